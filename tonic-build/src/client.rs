@@ -1,17 +1,23 @@
-use crate::generate_doc_comments;
+use crate::{generate_doc_comment, generate_doc_comments};
 use proc_macro2::TokenStream;
 use prost_build::{Method, Service};
 use quote::{format_ident, quote};
 
-pub(crate) fn generate(service: &Service, proto: &str) -> TokenStream {
+pub(crate) fn generate(
+    service: &Service,
+    proto: &str,
+    codec_path: &str,
+    attributes: TokenStream,
+) -> TokenStream {
     let service_ident = quote::format_ident!("{}Client", service.name);
-    let methods = generate_methods(service, proto);
+    let methods = generate_methods(service, proto, codec_path);
 
     let connect = generate_connect(&service_ident);
     let service_doc = generate_doc_comments(&service.comments.leading);
 
     quote! {
         #service_doc
+        #attributes
         pub struct #service_ident<T> {
             inner: tonic::client::Grpc<T>,
         }
@@ -24,11 +30,37 @@ pub(crate) fn generate(service: &Service, proto: &str) -> TokenStream {
               T::Error: Into<StdError>,
               <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
               <T::ResponseBody as HttpBody>::Data: Into<bytes::Bytes> + Send, {
+            /// Create a client that sends its requests through `inner`, e.g. a
+            /// `tonic::transport::Channel` or any other tower `Service` of HTTP requests.
             pub fn new(inner: T) -> Self {
                 let inner = tonic::client::Grpc::new(inner);
                 Self { inner }
             }
 
+            /// Limit the size of a decoded response message to `limit` bytes.
+            pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+                self.inner = self.inner.max_decoding_message_size(limit);
+                self
+            }
+
+            /// Limit the size of an encoded request message to `limit` bytes.
+            pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+                self.inner = self.inner.max_encoding_message_size(limit);
+                self
+            }
+
+            /// Set the scheme and authority of every request to those of `origin`.
+            pub fn origin(mut self, origin: http::Uri) -> Self {
+                self.inner = self.inner.origin(origin);
+                self
+            }
+
+            /// Authenticate every request with `credentials`, unless it carries its own.
+            pub fn credentials(mut self, credentials: impl tonic::CallCredentials) -> Self {
+                self.inner = self.inner.credentials(credentials);
+                self
+            }
+
             /// Check if the service is ready.
             pub async fn ready(&mut self) -> Result<(), tonic::Status> {
                 self.inner.ready().await.map_err(|e| {
@@ -49,6 +81,68 @@ pub(crate) fn generate(service: &Service, proto: &str) -> TokenStream {
     }
 }
 
+pub(crate) fn generate_api(service: &Service, proto: &str) -> TokenStream {
+    let client_ident = quote::format_ident!("{}Client", service.name);
+    let api_ident = quote::format_ident!("{}ClientApi", service.name);
+    let api_doc = generate_doc_comment(&format!(
+        "Generated trait containing the gRPC methods of {}, to be implemented by fakes in tests.",
+        client_ident
+    ));
+
+    let mut signatures = TokenStream::new();
+    let mut methods = TokenStream::new();
+
+    for method in &service.methods {
+        let ident = format_ident!("{}", method.name);
+        let (request, response) = crate::replace_wellknown(proto, method);
+
+        let request = if method.client_streaming {
+            quote!(tonic::RequestStream<#request>)
+        } else {
+            request
+        };
+        let response = if method.server_streaming {
+            quote!(tonic::codec::Streaming<#response>)
+        } else {
+            response
+        };
+
+        let signature = quote! {
+            async fn #ident(
+                &mut self,
+                request: tonic::Request<#request>,
+            ) -> Result<tonic::Response<#response>, tonic::Status>
+        };
+
+        signatures.extend(generate_doc_comments(&method.comments.leading));
+        signatures.extend(quote!(#signature;));
+        methods.extend(quote! {
+            #signature {
+                #client_ident::#ident(self, request).await
+            }
+        });
+    }
+
+    quote! {
+        #api_doc
+        #[async_trait]
+        pub trait #api_ident: Send + 'static {
+            #signatures
+        }
+
+        #[async_trait]
+        impl<T> #api_ident for #client_ident<T>
+        where T: tonic::client::GrpcService<tonic::body::BoxBody> + Send + 'static,
+              T::Future: Send,
+              T::ResponseBody: Body + HttpBody + Send + 'static,
+              T::Error: Into<StdError>,
+              <T::ResponseBody as HttpBody>::Error: Into<StdError> + Send,
+              <T::ResponseBody as HttpBody>::Data: Into<bytes::Bytes> + Send, {
+            #methods
+        }
+    }
+}
+
 #[cfg(feature = "transport")]
 fn generate_connect(service_ident: &syn::Ident) -> TokenStream {
     quote! {
@@ -71,8 +165,9 @@ fn generate_connect(_service_ident: &syn::Ident) -> TokenStream {
     TokenStream::new()
 }
 
-fn generate_methods(service: &Service, proto: &str) -> TokenStream {
+fn generate_methods(service: &Service, proto: &str, codec_path: &str) -> TokenStream {
     let mut stream = TokenStream::new();
+    let codec_name = syn::parse_str::<syn::Path>(codec_path).unwrap();
 
     for method in &service.methods {
         let path = format!(
@@ -83,10 +178,10 @@ fn generate_methods(service: &Service, proto: &str) -> TokenStream {
         stream.extend(generate_doc_comments(&method.comments.leading));
 
         let method = match (method.client_streaming, method.server_streaming) {
-            (false, false) => generate_unary(method, &proto, path),
-            (false, true) => generate_server_streaming(method, &proto, path),
-            (true, false) => generate_client_streaming(method, &proto, path),
-            (true, true) => generate_streaming(method, &proto, path),
+            (false, false) => generate_unary(method, &proto, path, &codec_name),
+            (false, true) => generate_server_streaming(method, &proto, path, &codec_name),
+            (true, false) => generate_client_streaming(method, &proto, path, &codec_name),
+            (true, true) => generate_streaming(method, &proto, path, &codec_name),
         };
 
         stream.extend(method);
@@ -95,7 +190,12 @@ fn generate_methods(service: &Service, proto: &str) -> TokenStream {
     stream
 }
 
-fn generate_unary(method: &Method, proto: &str, path: String) -> TokenStream {
+fn generate_unary(
+    method: &Method,
+    proto: &str,
+    path: String,
+    codec_name: &syn::Path,
+) -> TokenStream {
     let ident = format_ident!("{}", method.name);
     let (request, response) = crate::replace_wellknown(proto, &method);
 
@@ -105,14 +205,19 @@ fn generate_unary(method: &Method, proto: &str, path: String) -> TokenStream {
             request: impl tonic::IntoRequest<#request>,
         ) -> Result<tonic::Response<#response>, tonic::Status> {
            self.ready().await?;
-           let codec = tonic::codec::ProstCodec::default();
+           let codec = #codec_name::default();
            let path = http::uri::PathAndQuery::from_static(#path);
            self.inner.unary(request.into_request(), path, codec).await
         }
     }
 }
 
-fn generate_server_streaming(method: &Method, proto: &str, path: String) -> TokenStream {
+fn generate_server_streaming(
+    method: &Method,
+    proto: &str,
+    path: String,
+    codec_name: &syn::Path,
+) -> TokenStream {
     let ident = format_ident!("{}", method.name);
 
     let (request, response) = crate::replace_wellknown(proto, &method);
@@ -123,14 +228,19 @@ fn generate_server_streaming(method: &Method, proto: &str, path: String) -> Toke
             request: impl tonic::IntoRequest<#request>,
         ) -> Result<tonic::Response<tonic::codec::Streaming<#response>>, tonic::Status> {
            self.ready().await?;
-           let codec = tonic::codec::ProstCodec::default();
+           let codec = #codec_name::default();
            let path = http::uri::PathAndQuery::from_static(#path);
            self.inner.server_streaming(request.into_request(), path, codec).await
         }
     }
 }
 
-fn generate_client_streaming(method: &Method, proto: &str, path: String) -> TokenStream {
+fn generate_client_streaming(
+    method: &Method,
+    proto: &str,
+    path: String,
+    codec_name: &syn::Path,
+) -> TokenStream {
     let ident = format_ident!("{}", method.name);
 
     let (request, response) = crate::replace_wellknown(proto, &method);
@@ -141,14 +251,19 @@ fn generate_client_streaming(method: &Method, proto: &str, path: String) -> Toke
             request: impl tonic::IntoStreamingRequest<Message = #request>
         ) -> Result<tonic::Response<#response>, tonic::Status> {
            self.ready().await?;
-           let codec = tonic::codec::ProstCodec::default();
+           let codec = #codec_name::default();
            let path = http::uri::PathAndQuery::from_static(#path);
            self.inner.client_streaming(request.into_streaming_request(), path, codec).await
         }
     }
 }
 
-fn generate_streaming(method: &Method, proto: &str, path: String) -> TokenStream {
+fn generate_streaming(
+    method: &Method,
+    proto: &str,
+    path: String,
+    codec_name: &syn::Path,
+) -> TokenStream {
     let ident = format_ident!("{}", method.name);
 
     let (request, response) = crate::replace_wellknown(proto, &method);
@@ -159,7 +274,7 @@ fn generate_streaming(method: &Method, proto: &str, path: String) -> TokenStream
             request: impl tonic::IntoStreamingRequest<Message = #request>
         ) -> Result<tonic::Response<tonic::codec::Streaming<#response>>, tonic::Status> {
            self.ready().await?;
-           let codec = tonic::codec::ProstCodec::default();
+           let codec = #codec_name::default();
            let path = http::uri::PathAndQuery::from_static(#path);
            self.inner.streaming(request.into_streaming_request(), path, codec).await
         }
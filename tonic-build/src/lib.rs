@@ -4,8 +4,21 @@
 //! # Features
 //!
 //! - `rustfmt`: This feature enables the use of `rustfmt` to format the output code
-//! this makes the code readable and the error messages nice. This requires that `rustfmt`
-//! is installed. This is enabled by default.
+//!   this makes the code readable and the error messages nice. This requires that `rustfmt`
+//!   is installed. This is enabled by default.
+//! - `transport`: This feature generates a `connect` constructor for clients, which
+//!   creates a `tonic::transport::Channel`. Clients without it can be used with any
+//!   other service, e.g. when `tonic` is built without its `transport` feature. This is
+//!   enabled by default.
+//!
+//! # Generated names
+//!
+//! Besides the `client` and `server` modules, the code of a package has a `names`
+//! module with a module per service. It holds the fully qualified name of the
+//! service as `SERVICE_NAME` and the path of every method as a constant, e.g.
+//! `names::greeter::SAY_HELLO` for `/helloworld.Greeter/SayHello`. These are meant
+//! for middleware that matches on methods, such as authorization policies or
+//! metrics labels.
 //!
 //! # Required dependencies
 //!
@@ -69,15 +82,21 @@ use std::{
 };
 
 mod client;
+mod names;
 mod server;
 
 /// Service generator builder.
 #[derive(Debug, Clone)]
 pub struct Builder {
     build_client: bool,
+    build_client_api: bool,
     build_server: bool,
     field_attributes: Vec<(String, String)>,
     type_attributes: Vec<(String, String)>,
+    server_attributes: Vec<(String, String)>,
+    client_attributes: Vec<(String, String)>,
+    extern_paths: Vec<(String, String)>,
+    codec_path: String,
     out_dir: Option<PathBuf>,
     #[cfg(feature = "rustfmt")]
     format: bool,
@@ -85,12 +104,46 @@ pub struct Builder {
 
 impl Builder {
     /// Enable or disable gRPC client code generation.
+    ///
+    /// The clients of the services are generated into a `client` module.
+    /// Defaults to `true`, a crate that only implements the services can
+    /// disable it.
     pub fn build_client(mut self, enable: bool) -> Self {
         self.build_client = enable;
         self
     }
 
+    /// Enable or disable the generation of a client trait.
+    ///
+    /// For a service `Greeter`, this generates a `GreeterClientApi` trait in
+    /// the `client` module, with a method for every method of the service,
+    /// and implements it for `GreeterClient`. Application code can depend on
+    /// `dyn GreeterClientApi`, so that tests can substitute a fake that
+    /// implements the trait for a connection to a server. Defaults to
+    /// `false`.
+    ///
+    /// The methods of the trait take streaming requests as a
+    /// `tonic::RequestStream`, and fakes can create streaming responses via
+    /// `tonic::Streaming::from_stream`.
+    pub fn build_client_api(mut self, enable: bool) -> Self {
+        self.build_client_api = enable;
+        self
+    }
+
     /// Enable or disable gRPC server code generation.
+    ///
+    /// The server traits of the services and the servers wrapping them are
+    /// generated into a `server` module. Defaults to `true`, a crate that
+    /// only calls the services, like the SDK of an API, can disable it:
+    ///
+    /// ```rust,no_run
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///    tonic_build::configure()
+    ///         .build_server(false)
+    ///         .compile(&["proto/service.proto"], &["proto"])?;
+    ///    Ok(())
+    /// }
+    /// ```
     pub fn build_server(mut self, enable: bool) -> Self {
         self.build_server = enable;
         self
@@ -129,6 +182,92 @@ impl Builder {
         self
     }
 
+    /// Add additional attribute to the generated servers of matched services.
+    ///
+    /// `path` is matched like in [`Builder::type_attribute`], against the
+    /// fully qualified name of services, e.g. `.helloworld.Greeter`. The
+    /// attribute is added to the `GreeterServer` struct, not the `Greeter`
+    /// trait.
+    ///
+    /// ```rust,no_run
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///    tonic_build::configure()
+    ///         .server_attribute(".helloworld.Greeter", "#[deprecated]")
+    ///         .compile(&["proto/helloworld.proto"], &["proto"])?;
+    ///    Ok(())
+    /// }
+    /// ```
+    ///
+    /// [`Builder::type_attribute`]: struct.Builder.html#method.type_attribute
+    pub fn server_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
+        self.server_attributes
+            .push((path.as_ref().to_string(), attribute.as_ref().to_string()));
+        self
+    }
+
+    /// Add additional attribute to the generated clients of matched services.
+    ///
+    /// `path` is matched like for [`Builder::server_attribute`], the
+    /// attribute is added to the `GreeterClient` struct.
+    ///
+    /// [`Builder::server_attribute`]: struct.Builder.html#method.server_attribute
+    pub fn client_attribute<P: AsRef<str>, A: AsRef<str>>(mut self, path: P, attribute: A) -> Self {
+        self.client_attributes
+            .push((path.as_ref().to_string(), attribute.as_ref().to_string()));
+        self
+    }
+
+    /// Declare an externally provided Protobuf package or type.
+    ///
+    /// Messages of `proto_path` are not generated, the types at `rust_path`
+    /// are used instead, also by the generated clients and servers. Passed
+    /// directly to `prost_build::Config.extern_path`.
+    ///
+    /// ```rust,no_run
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///    tonic_build::configure()
+    ///         .extern_path(".common", "::common_protos")
+    ///         .compile(&["proto/service.proto"], &["proto"])?;
+    ///    Ok(())
+    /// }
+    /// ```
+    pub fn extern_path<P: AsRef<str>, R: AsRef<str>>(
+        mut self,
+        proto_path: P,
+        rust_path: R,
+    ) -> Self {
+        self.extern_paths.push((
+            proto_path.as_ref().to_string(),
+            rust_path.as_ref().to_string(),
+        ));
+        self
+    }
+
+    /// Set the path of the [`Codec`] used to encode and decode the messages of
+    /// the generated clients and servers.
+    ///
+    /// The codec must implement `Default`. Defaults to
+    /// `tonic::codec::ProstCodec`, the messages generated by `prost` can be
+    /// used with other codecs by deriving the traits they need, e.g. via
+    /// [`Builder::type_attribute`].
+    ///
+    /// ```rust,no_run
+    /// fn main() -> Result<(), Box<dyn std::error::Error>> {
+    ///    tonic_build::configure()
+    ///         .codec_path("crate::json::JsonCodec")
+    ///         .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+    ///         .compile(&["proto/service.proto"], &["proto"])?;
+    ///    Ok(())
+    /// }
+    /// ```
+    ///
+    /// [`Codec`]: https://docs.rs/tonic/*/tonic/codec/trait.Codec.html
+    /// [`Builder::type_attribute`]: struct.Builder.html#method.type_attribute
+    pub fn codec_path(mut self, codec_path: impl Into<String>) -> Self {
+        self.codec_path = codec_path.into();
+        self
+    }
+
     /// Compile the .proto files and execute code generation.
     pub fn compile<P: AsRef<Path>>(self, protos: &[P], includes: &[P]) -> io::Result<()> {
         let mut config = Config::new();
@@ -148,6 +287,9 @@ impl Builder {
         for (path, attr) in self.type_attributes.iter() {
             config.type_attribute(path, attr);
         }
+        for (proto_path, rust_path) in self.extern_paths.iter() {
+            config.extern_path(proto_path, rust_path);
+        }
         config.service_generator(Box::new(ServiceGenerator::new(self)));
 
         config.compile_protos(protos, includes)?;
@@ -169,10 +311,15 @@ impl Builder {
 pub fn configure() -> Builder {
     Builder {
         build_client: true,
+        build_client_api: false,
         build_server: true,
         out_dir: None,
         field_attributes: Vec::new(),
         type_attributes: Vec::new(),
+        server_attributes: Vec::new(),
+        client_attributes: Vec::new(),
+        extern_paths: Vec::new(),
+        codec_path: "tonic::codec::ProstCodec".to_string(),
         #[cfg(feature = "rustfmt")]
         format: true,
     }
@@ -217,6 +364,7 @@ fn fmt(out_dir: &str) {
 
 struct ServiceGenerator {
     builder: Builder,
+    names: TokenStream,
     clients: TokenStream,
     servers: TokenStream,
 }
@@ -225,6 +373,7 @@ impl ServiceGenerator {
     fn new(builder: Builder) -> Self {
         ServiceGenerator {
             builder,
+            names: TokenStream::default(),
             clients: TokenStream::default(),
             servers: TokenStream::default(),
         }
@@ -234,19 +383,42 @@ impl ServiceGenerator {
 impl prost_build::ServiceGenerator for ServiceGenerator {
     fn generate(&mut self, service: prost_build::Service, _buf: &mut String) {
         let path = "super";
+        let name = format!(".{}.{}", service.package, service.proto_name);
+
+        self.names.extend(names::generate(&service));
 
         if self.builder.build_server {
-            let server = server::generate(&service, path);
+            let attributes = generate_attributes(&self.builder.server_attributes, &name);
+            let server = server::generate(&service, path, &self.builder.codec_path, attributes);
             self.servers.extend(server);
         }
 
         if self.builder.build_client {
-            let client = client::generate(&service, path);
+            let attributes = generate_attributes(&self.builder.client_attributes, &name);
+            let client = client::generate(&service, path, &self.builder.codec_path, attributes);
             self.clients.extend(client);
+
+            if self.builder.build_client_api {
+                self.clients.extend(client::generate_api(&service, path));
+            }
         }
     }
 
     fn finalize(&mut self, buf: &mut String) {
+        if !self.names.is_empty() {
+            let names = std::mem::replace(&mut self.names, TokenStream::new());
+
+            let names = quote::quote! {
+                /// Generated names of services and the paths of their methods.
+                pub mod names {
+                    #names
+                }
+            };
+
+            let code = format!("{}", names);
+            buf.push_str(&code);
+        }
+
         if self.builder.build_client && !self.clients.is_empty() {
             let clients = &self.clients;
 
@@ -283,6 +455,35 @@ impl prost_build::ServiceGenerator for ServiceGenerator {
     }
 }
 
+// Generate the attributes whose path matches the fully qualified `name` of a
+// service
+fn generate_attributes(attributes: &[(String, String)], name: &str) -> TokenStream {
+    let mut stream = TokenStream::new();
+
+    for (path, attribute) in attributes {
+        if match_name(path, name) {
+            stream.extend(attribute.parse::<TokenStream>().expect("invalid attribute"));
+        }
+    }
+
+    stream
+}
+
+// Match a fully qualified `name` against a `path` like prost does: `.` matches
+// every name, a path starting with `.` matches the name or a package it is in,
+// and any other path matches the trailing segments of the name.
+fn match_name(path: &str, name: &str) -> bool {
+    if path == "." || path == name {
+        return true;
+    }
+
+    if path.starts_with('.') {
+        name.starts_with(path) && name[path.len()..].starts_with('.')
+    } else {
+        name.ends_with(path) && name[..name.len() - path.len()].ends_with('.')
+    }
+}
+
 // Generate a singular line of a doc comment
 fn generate_doc_comment(comment: &str) -> TokenStream {
     let mut doc_stream = TokenStream::new();
@@ -311,21 +512,23 @@ fn generate_doc_comments<T: AsRef<str>>(comments: &[T]) -> TokenStream {
 }
 
 fn replace_wellknown(proto_path: &str, method: &Method) -> (TokenStream, TokenStream) {
-    let request = if method.input_proto_type.starts_with(".google.protobuf") {
-        method.input_type.parse::<TokenStream>().unwrap()
-    } else {
-        syn::parse_str::<syn::Path>(&format!("{}::{}", proto_path, method.input_type))
-            .unwrap()
-            .to_token_stream()
-    };
+    let request = resolve_type(proto_path, &method.input_proto_type, &method.input_type);
+    let response = resolve_type(proto_path, &method.output_proto_type, &method.output_type);
+
+    (request, response)
+}
 
-    let response = if method.output_proto_type.starts_with(".google.protobuf") {
-        method.output_type.parse::<TokenStream>().unwrap()
+// Well known types and types of extern paths resolve to absolute paths, all
+// other types are relative to the module of the package.
+fn resolve_type(proto_path: &str, proto_type: &str, rust_type: &str) -> TokenStream {
+    if proto_type.starts_with(".google.protobuf")
+        || rust_type.starts_with("::")
+        || rust_type.starts_with("crate::")
+    {
+        rust_type.parse::<TokenStream>().unwrap()
     } else {
-        syn::parse_str::<syn::Path>(&format!("{}::{}", proto_path, method.output_type))
+        syn::parse_str::<syn::Path>(&format!("{}::{}", proto_path, rust_type))
             .unwrap()
             .to_token_stream()
-    };
-
-    (request, response)
+    }
 }
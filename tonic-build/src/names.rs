@@ -0,0 +1,48 @@
+use crate::generate_doc_comment;
+use heck::{ShoutySnakeCase, SnakeCase};
+use proc_macro2::{Ident, TokenStream};
+use prost_build::{Method, Service};
+use quote::{format_ident, quote};
+
+/// The module generated for `service`.
+pub(crate) fn module(service: &Service) -> Ident {
+    format_ident!("{}", service.name.to_snake_case())
+}
+
+/// The constant generated for the path of `method`.
+pub(crate) fn method(method: &Method) -> Ident {
+    format_ident!("{}", method.name.to_shouty_snake_case())
+}
+
+/// Generate a module with the fully qualified name of `service` and the
+/// paths of its methods.
+pub(crate) fn generate(service: &Service) -> TokenStream {
+    let module = module(service);
+    let service_name = format!("{}.{}", service.package, service.proto_name);
+    let module_doc = generate_doc_comment(&format!(
+        "Names of the `{}` service and the paths of its methods.",
+        service_name
+    ));
+
+    let mut methods = TokenStream::new();
+    for method in &service.methods {
+        let ident = self::method(method);
+        let path = format!("/{}/{}", service_name, method.proto_name);
+        let doc = generate_doc_comment(&format!("The path of the `{}` method.", method.proto_name));
+
+        methods.extend(quote! {
+            #doc
+            pub const #ident: &str = #path;
+        });
+    }
+
+    quote! {
+        #module_doc
+        pub mod #module {
+            /// The fully qualified name of the service.
+            pub const SERVICE_NAME: &str = #service_name;
+
+            #methods
+        }
+    }
+}
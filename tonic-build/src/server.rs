@@ -1,11 +1,16 @@
 use crate::{generate_doc_comment, generate_doc_comments};
-use proc_macro2::{Span, TokenStream};
+use proc_macro2::TokenStream;
 use prost_build::{Method, Service};
 use quote::quote;
-use syn::{Ident, Lit, LitStr};
+use syn::Ident;
 
-pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
-    let methods = generate_methods(&service, proto_path);
+pub(crate) fn generate(
+    service: &Service,
+    proto_path: &str,
+    codec_path: &str,
+    attributes: TokenStream,
+) -> TokenStream {
+    let methods = generate_methods(&service, proto_path, codec_path);
 
     let server_service = quote::format_ident!("{}Server", service.name);
     let server_trait = quote::format_ident!("{}", service.name);
@@ -13,8 +18,8 @@ pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
     let service_doc = generate_doc_comments(&service.comments.leading);
 
     // Transport based implementations
-    let path = format!("{}.{}", service.package, service.proto_name);
-    let transport = generate_transport(&server_service, &server_trait, &path);
+    let names = crate::names::module(service);
+    let transport = generate_transport(&server_service, &server_trait, &names);
 
     quote! {
         #generated_trait
@@ -22,14 +27,49 @@ pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
         #service_doc
         #[derive(Debug)]
         #[doc(hidden)]
+        #attributes
         pub struct #server_service<T: #server_trait> {
             inner: Arc<T>,
+            accept_gzip: bool,
+            send_gzip: bool,
+            max_decoding_message_size: Option<usize>,
+            max_encoding_message_size: Option<usize>,
         }
 
         impl<T: #server_trait> #server_service<T> {
             pub fn new(inner: T) -> Self {
                 let inner = Arc::new(inner);
-                Self { inner }
+                Self {
+                    inner,
+                    accept_gzip: false,
+                    send_gzip: false,
+                    max_decoding_message_size: None,
+                    max_encoding_message_size: None,
+                }
+            }
+
+            /// Accept requests whose messages are compressed with gzip.
+            pub fn accept_gzip(mut self) -> Self {
+                self.accept_gzip = true;
+                self
+            }
+
+            /// Compress response messages with gzip if the client accepts it.
+            pub fn send_gzip(mut self) -> Self {
+                self.send_gzip = true;
+                self
+            }
+
+            /// Limit the size of a decoded request message to `limit` bytes.
+            pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+                self.max_decoding_message_size = Some(limit);
+                self
+            }
+
+            /// Limit the size of an encoded response message to `limit` bytes.
+            pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+                self.max_encoding_message_size = Some(limit);
+                self
             }
         }
 
@@ -62,7 +102,13 @@ pub(crate) fn generate(service: &Service, proto_path: &str) -> TokenStream {
         impl<T: #server_trait> Clone for #server_service<T> {
             fn clone(&self) -> Self {
                 let inner = self.inner.clone();
-                Self { inner }
+                Self {
+                    inner,
+                    accept_gzip: self.accept_gzip,
+                    send_gzip: self.send_gzip,
+                    max_decoding_message_size: self.max_decoding_message_size,
+                    max_encoding_message_size: self.max_encoding_message_size,
+                }
             }
         }
 
@@ -163,13 +209,11 @@ fn generate_trait_methods(service: &Service, proto_path: &str) -> TokenStream {
 fn generate_transport(
     server_service: &syn::Ident,
     server_trait: &syn::Ident,
-    service_name: &str,
+    names: &syn::Ident,
 ) -> TokenStream {
-    let service_name = syn::LitStr::new(service_name, proc_macro2::Span::call_site());
-
     quote! {
         impl<T: #server_trait> tonic::transport::ServiceName for #server_service<T> {
-            const NAME: &'static str = #service_name;
+            const NAME: &'static str = super::names::#names::SERVICE_NAME;
         }
     }
 }
@@ -178,38 +222,46 @@ fn generate_transport(
 fn generate_transport(
     _server_service: &syn::Ident,
     _server_trait: &syn::Ident,
-    _service_name: &str,
+    _names: &syn::Ident,
 ) -> TokenStream {
     TokenStream::new()
 }
 
-fn generate_methods(service: &Service, proto_path: &str) -> TokenStream {
+fn generate_methods(service: &Service, proto_path: &str, codec_path: &str) -> TokenStream {
     let mut stream = TokenStream::new();
+    let names = crate::names::module(service);
 
     for method in &service.methods {
-        let path = format!(
-            "/{}.{}/{}",
-            service.package, service.proto_name, method.proto_name
-        );
-        let method_path = Lit::Str(LitStr::new(&path, Span::call_site()));
+        let method_path = crate::names::method(method);
         let ident = quote::format_ident!("{}", method.name);
         let server_trait = quote::format_ident!("{}", service.name);
+        let codec_name = syn::parse_str::<syn::Path>(codec_path).unwrap();
 
         let method_stream = match (method.client_streaming, method.server_streaming) {
-            (false, false) => generate_unary(method, ident, proto_path, server_trait),
+            (false, false) => generate_unary(method, ident, proto_path, server_trait, codec_name),
+
+            (false, true) => generate_server_streaming(
+                method,
+                ident.clone(),
+                proto_path,
+                server_trait,
+                codec_name,
+            ),
+            (true, false) => generate_client_streaming(
+                method,
+                ident.clone(),
+                proto_path,
+                server_trait,
+                codec_name,
+            ),
 
-            (false, true) => {
-                generate_server_streaming(method, ident.clone(), proto_path, server_trait)
-            }
-            (true, false) => {
-                generate_client_streaming(method, ident.clone(), proto_path, server_trait)
+            (true, true) => {
+                generate_streaming(method, ident.clone(), proto_path, server_trait, codec_name)
             }
-
-            (true, true) => generate_streaming(method, ident.clone(), proto_path, server_trait),
         };
 
         let method = quote! {
-            #method_path => {
+            super::names::#names::#method_path => {
                 #method_stream
             }
         };
@@ -224,6 +276,7 @@ fn generate_unary(
     method_ident: Ident,
     proto_path: &str,
     server_trait: Ident,
+    codec_name: syn::Path,
 ) -> TokenStream {
     let service_ident = quote::format_ident!("{}Svc", method.proto_name);
 
@@ -246,10 +299,15 @@ fn generate_unary(
         }
 
         let inner = self.inner.clone();
+        let (accept_gzip, send_gzip) = (self.accept_gzip, self.send_gzip);
+        let max_decoding_message_size = self.max_decoding_message_size;
+        let max_encoding_message_size = self.max_encoding_message_size;
         let fut = async move {
             let method = #service_ident(inner);
-            let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let codec = #codec_name::default();
+            let mut grpc = tonic::server::Grpc::new(codec)
+                .apply_compression_config(accept_gzip, send_gzip)
+                .apply_max_message_size_config(max_decoding_message_size, max_encoding_message_size);
             let res = grpc.unary(method, req).await;
             Ok(res)
         };
@@ -263,6 +321,7 @@ fn generate_server_streaming(
     method_ident: Ident,
     proto_path: &str,
     server_trait: Ident,
+    codec_name: syn::Path,
 ) -> TokenStream {
     let service_ident = quote::format_ident!("{}Svc", method.proto_name);
 
@@ -289,10 +348,15 @@ fn generate_server_streaming(
         }
 
         let inner = self.inner.clone();
+        let (accept_gzip, send_gzip) = (self.accept_gzip, self.send_gzip);
+        let max_decoding_message_size = self.max_decoding_message_size;
+        let max_encoding_message_size = self.max_encoding_message_size;
         let fut = async move {
             let method = #service_ident(inner);
-            let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let codec = #codec_name::default();
+            let mut grpc = tonic::server::Grpc::new(codec)
+                .apply_compression_config(accept_gzip, send_gzip)
+                .apply_max_message_size_config(max_decoding_message_size, max_encoding_message_size);
             let res = grpc.server_streaming(method, req).await;
             Ok(res)
         };
@@ -306,6 +370,7 @@ fn generate_client_streaming(
     method_ident: Ident,
     proto_path: &str,
     server_trait: Ident,
+    codec_name: syn::Path,
 ) -> TokenStream {
     let service_ident = quote::format_ident!("{}Svc", method.proto_name);
 
@@ -330,10 +395,15 @@ fn generate_client_streaming(
         }
 
         let inner = self.inner.clone();
+        let (accept_gzip, send_gzip) = (self.accept_gzip, self.send_gzip);
+        let max_decoding_message_size = self.max_decoding_message_size;
+        let max_encoding_message_size = self.max_encoding_message_size;
         let fut = async move {
             let method = #service_ident(inner);
-            let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let codec = #codec_name::default();
+            let mut grpc = tonic::server::Grpc::new(codec)
+                .apply_compression_config(accept_gzip, send_gzip)
+                .apply_max_message_size_config(max_decoding_message_size, max_encoding_message_size);
             let res = grpc.client_streaming(method, req).await;
             Ok(res)
         };
@@ -347,6 +417,7 @@ fn generate_streaming(
     method_ident: Ident,
     proto_path: &str,
     server_trait: Ident,
+    codec_name: syn::Path,
 ) -> TokenStream {
     let service_ident = quote::format_ident!("{}Svc", method.proto_name);
 
@@ -373,10 +444,15 @@ fn generate_streaming(
         }
 
         let inner = self.inner.clone();
+        let (accept_gzip, send_gzip) = (self.accept_gzip, self.send_gzip);
+        let max_decoding_message_size = self.max_decoding_message_size;
+        let max_encoding_message_size = self.max_encoding_message_size;
         let fut = async move {
             let method = #service_ident(inner);
-            let codec = tonic::codec::ProstCodec::default();
-            let mut grpc = tonic::server::Grpc::new(codec);
+            let codec = #codec_name::default();
+            let mut grpc = tonic::server::Grpc::new(codec)
+                .apply_compression_config(accept_gzip, send_gzip)
+                .apply_max_message_size_config(max_decoding_message_size, max_encoding_message_size);
             let res = grpc.streaming(method, req).await;
             Ok(res)
         };
@@ -1,6 +1,29 @@
 fn main() {
     tonic_build::compile_protos("proto/helloworld/helloworld.proto").unwrap();
-    tonic_build::compile_protos("proto/routeguide/route_guide.proto").unwrap();
     tonic_build::compile_protos("proto/echo/echo.proto").unwrap();
-    tonic_build::compile_protos("proto/google/pubsub/pubsub.proto").unwrap();
+
+    // the client is used through its trait, like by code that is tested with
+    // a fake client
+    tonic_build::configure()
+        .build_client_api(true)
+        .compile(
+            &["proto/routeguide/route_guide.proto"],
+            &["proto/routeguide"],
+        )
+        .unwrap();
+
+    // only the client of the Pub/Sub API is used
+    tonic_build::configure()
+        .build_server(false)
+        .compile(
+            &["proto/google/pubsub/pubsub.proto"],
+            &["proto/google/pubsub"],
+        )
+        .unwrap();
+
+    tonic_build::configure()
+        .codec_path("crate::codec::JsonCodec")
+        .type_attribute(".", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .compile(&["proto/json/json.proto"], &["proto/json"])
+        .unwrap();
 }
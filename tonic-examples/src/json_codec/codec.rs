@@ -0,0 +1,69 @@
+use bytes::{BufMut, Bytes, BytesMut};
+use serde::{de::DeserializeOwned, Serialize};
+use std::marker::PhantomData;
+use tonic::{
+    codec::{Codec, Decoder, Encoder},
+    Code, Status,
+};
+
+/// A codec sending messages as `application/grpc+json`, using the serde
+/// implementations of the generated messages.
+#[derive(Debug)]
+pub struct JsonCodec<T, U>(PhantomData<(T, U)>);
+
+impl<T, U> Default for JsonCodec<T, U> {
+    fn default() -> Self {
+        JsonCodec(PhantomData)
+    }
+}
+
+impl<T, U> Codec for JsonCodec<T, U>
+where
+    T: Serialize + Send + Sync + 'static,
+    U: DeserializeOwned + Send + Sync + 'static,
+{
+    const CONTENT_TYPE: &'static str = "application/grpc+json";
+
+    type Encode = T;
+    type Decode = U;
+
+    type Encoder = JsonEncoder<T>;
+    type Decoder = JsonDecoder<U>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        JsonEncoder(PhantomData)
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        JsonDecoder(PhantomData)
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonEncoder<T>(PhantomData<T>);
+
+impl<T: Serialize> Encoder for JsonEncoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        let json =
+            serde_json::to_vec(&item).map_err(|e| Status::new(Code::Internal, e.to_string()))?;
+
+        buf.reserve(json.len());
+        buf.put_slice(&json);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct JsonDecoder<U>(PhantomData<U>);
+
+impl<U: DeserializeOwned> Decoder for JsonDecoder<U> {
+    type Item = U;
+    type Error = Status;
+
+    fn decode(&mut self, buf: Bytes) -> Result<Self::Item, Self::Error> {
+        serde_json::from_slice(&buf).map_err(|e| Status::new(Code::Internal, e.to_string()))
+    }
+}
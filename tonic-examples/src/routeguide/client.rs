@@ -1,4 +1,3 @@
-use futures::stream;
 use rand::rngs::ThreadRng;
 use rand::Rng;
 use route_guide::{Point, Rectangle, RouteNote};
@@ -12,9 +11,9 @@ pub mod route_guide {
     tonic::include_proto!("routeguide");
 }
 
-use route_guide::client::RouteGuideClient;
+use route_guide::client::{RouteGuideClient, RouteGuideClientApi};
 
-async fn print_features(client: &mut RouteGuideClient<Channel>) -> Result<(), Box<dyn Error>> {
+async fn print_features(client: &mut dyn RouteGuideClientApi) -> Result<(), Box<dyn Error>> {
     let rectangle = Rectangle {
         lo: Some(Point {
             latitude: 400000000,
@@ -48,7 +47,7 @@ async fn run_record_route(client: &mut RouteGuideClient<Channel>) -> Result<(),
     }
 
     println!("Traversing {} points", points.len());
-    let request = Request::new(stream::iter(points));
+    let request = Request::from_messages(points);
 
     match client.record_route(request).await {
         Ok(response) => println!("SUMMARY: {:?}", response.into_inner()),
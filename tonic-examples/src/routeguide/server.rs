@@ -1,14 +1,12 @@
 mod data;
 
-use futures::{Stream, StreamExt};
+use futures::StreamExt;
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::pin::Pin;
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::sync::mpsc;
 use tonic::transport::Server;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, ResponseStream, Status};
 
 pub mod routeguide {
     tonic::include_proto!("routeguide");
@@ -40,7 +38,7 @@ impl server::RouteGuide for RouteGuide {
         Ok(response)
     }
 
-    type ListFeaturesStream = mpsc::Receiver<Result<Feature, Status>>;
+    type ListFeaturesStream = ResponseStream<Feature>;
 
     async fn list_features(
         &self,
@@ -48,21 +46,24 @@ impl server::RouteGuide for RouteGuide {
     ) -> Result<Response<Self::ListFeaturesStream>, Status> {
         println!("ListFeatures = {:?}", request);
 
-        let (mut tx, rx) = mpsc::channel(4);
+        let (mut tx, response) = Response::channel(4);
         let features = self.features.clone();
 
         tokio::spawn(async move {
             for feature in &features[..] {
                 if in_range(feature.location.as_ref().unwrap(), request.get_ref()) {
                     println!("  => send {:?}", feature);
-                    tx.send(Ok(feature.clone())).await.unwrap();
+                    if tx.send(Ok(feature.clone())).await.is_err() {
+                        println!(" /// client went away");
+                        return;
+                    }
                 }
             }
 
             println!(" /// done sending");
         });
 
-        Ok(Response::new(rx))
+        Ok(response)
     }
 
     async fn record_route(
@@ -108,8 +109,7 @@ impl server::RouteGuide for RouteGuide {
         Ok(Response::new(summary))
     }
 
-    type RouteChatStream =
-        Pin<Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + Sync + 'static>>;
+    type RouteChatStream = ResponseStream<RouteNote>;
 
     async fn route_chat(
         &self,
@@ -137,10 +137,7 @@ impl server::RouteGuide for RouteGuide {
             }
         };
 
-        Ok(Response::new(Box::pin(output)
-            as Pin<
-                Box<dyn Stream<Item = Result<RouteNote, Status>> + Send + Sync + 'static>,
-            >))
+        Ok(Response::from_stream(output))
     }
 }
 
@@ -12,11 +12,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let tls = ClientTlsConfig::with_rustls()
         .ca_certificate(ca)
-        .domain_name("example.com")
-        .clone();
+        .domain_name("example.com");
 
     let channel = Channel::from_static("http://[::1]:50051")
-        .tls_config(&tls)
+        .tls_config(tls)?
         .connect()
         .await?;
 
@@ -59,7 +59,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let server = EchoServer::default();
 
     Server::builder()
-        .tls_config(ServerTlsConfig::with_rustls().identity(identity))
+        .tls_config(ServerTlsConfig::with_rustls().identity(identity))?
         .clone()
         .add_service(pb::server::EchoServer::new(server))
         .serve(addr)
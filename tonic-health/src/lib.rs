@@ -0,0 +1,54 @@
+//! A `tonic` based gRPC healthcheck implementation.
+//!
+//! This implements the [gRPC health checking protocol], the `grpc.health.v1.Health`
+//! service that load balancers and orchestrators like Kubernetes or Envoy
+//! probe to find out whether a server can handle requests. Use
+//! [`server::health_reporter`] to create the service along with a
+//! [`server::HealthReporter`] through which the application updates the
+//! status of each of its services.
+//!
+//! [gRPC health checking protocol]: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+
+#![doc(html_root_url = "https://docs.rs/tonic-health/0.1.0-alpha.5")]
+#![warn(missing_debug_implementations, rust_2018_idioms, unreachable_pub)]
+
+use std::fmt::{Display, Formatter};
+
+/// Generated protobuf types from the `grpc.health.v1` package.
+pub mod proto {
+    #![allow(unreachable_pub, missing_docs, missing_debug_implementations)]
+    tonic::include_proto!("grpc.health.v1");
+}
+
+pub mod server;
+
+/// An enumeration of values representing gRPC service health.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServingStatus {
+    /// Unknown status
+    Unknown,
+    /// The service is currently up and serving requests.
+    Serving,
+    /// The service is currently down and not serving requests.
+    NotServing,
+}
+
+impl Display for ServingStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServingStatus::Unknown => f.write_str("Unknown"),
+            ServingStatus::Serving => f.write_str("Serving"),
+            ServingStatus::NotServing => f.write_str("NotServing"),
+        }
+    }
+}
+
+impl From<ServingStatus> for proto::health_check_response::ServingStatus {
+    fn from(s: ServingStatus) -> Self {
+        match s {
+            ServingStatus::Unknown => proto::health_check_response::ServingStatus::Unknown,
+            ServingStatus::Serving => proto::health_check_response::ServingStatus::Serving,
+            ServingStatus::NotServing => proto::health_check_response::ServingStatus::NotServing,
+        }
+    }
+}
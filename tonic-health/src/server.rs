@@ -0,0 +1,465 @@
+//! Contains all healthcheck based server utilities.
+
+use crate::proto::{
+    health_check_response,
+    server::{Health, HealthServer},
+    HealthCheckRequest, HealthCheckResponse,
+};
+use crate::ServingStatus;
+use futures_core::Stream;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::sync::watch;
+use tonic::{transport::ServiceName, Code, Request, Response, Status};
+
+/// The status of a service as seen by watchers.
+type StatusChannel = (watch::Sender<ServingStatus>, watch::Receiver<ServingStatus>);
+
+/// Creates a `HealthReporter` and a linked `HealthServer` pair. Together,
+/// these types can be used to serve the gRPC Health Checking service.
+///
+/// A `HealthReporter` is used to update the state of gRPC services.
+///
+/// A `HealthServer` is a Tonic gRPC server for the `grpc.health.v1.Health`,
+/// which can be added to a Tonic runtime using `add_service` on the runtime
+/// builder.
+///
+/// The overall health of the server, reported for the empty service name, is
+/// [`ServingStatus::Serving`] from the start.
+///
+/// ```
+/// # use tonic::transport::Server;
+/// let (reporter, health_server) = tonic_health::server::health_reporter();
+/// let router = Server::builder().add_service(health_server);
+/// ```
+///
+/// [`ServingStatus::Serving`]: ../enum.ServingStatus.html#variant.Serving
+pub fn health_reporter() -> (HealthReporter, HealthServer<HealthService>) {
+    let reporter = HealthReporter::new();
+    reporter.set_service_status("", ServingStatus::Serving);
+
+    let service = HealthService::new(reporter.clone());
+    (reporter, HealthServer::new(service))
+}
+
+/// A handle providing methods to update the health status of gRPC services.
+///
+/// Cloned handles update the same set of statuses, so the reporter can be
+/// handed to every part of the application that decides about health.
+#[derive(Clone)]
+pub struct HealthReporter {
+    statuses: Arc<RwLock<HashMap<String, StatusChannel>>>,
+    /// Notified whenever a service gets its first status, which the watches
+    /// of services that are not known yet wait for.
+    added: Arc<(watch::Sender<()>, watch::Receiver<()>)>,
+}
+
+impl HealthReporter {
+    fn new() -> Self {
+        HealthReporter {
+            statuses: Arc::new(RwLock::new(HashMap::new())),
+            added: Arc::new(watch::channel(())),
+        }
+    }
+
+    /// Sets the status of the service implemented by `S` to
+    /// [`ServingStatus::Serving`].
+    ///
+    /// [`ServingStatus::Serving`]: ../enum.ServingStatus.html#variant.Serving
+    pub fn set_serving<S: ServiceName>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::Serving);
+    }
+
+    /// Sets the status of the service implemented by `S` to
+    /// [`ServingStatus::NotServing`].
+    ///
+    /// [`ServingStatus::NotServing`]: ../enum.ServingStatus.html#variant.NotServing
+    pub fn set_not_serving<S: ServiceName>(&self) {
+        self.set_service_status(S::NAME, ServingStatus::NotServing);
+    }
+
+    /// Sets the status of the service with the fully qualified name
+    /// `service_name`, e.g. `helloworld.Greeter`, notifying all watchers of
+    /// that service if it changed.
+    pub fn set_service_status(&self, service_name: impl AsRef<str>, status: ServingStatus) {
+        let service_name = service_name.as_ref();
+        let mut statuses = self.statuses.write().unwrap();
+
+        match statuses.get(service_name) {
+            Some((tx, rx)) => {
+                if *rx.get_ref() != status {
+                    // one receiver is always kept in the map, so this can't fail
+                    let _ = tx.broadcast(status);
+                }
+            }
+            None => {
+                statuses.insert(service_name.to_string(), watch::channel(status));
+                // one receiver is always kept in the reporter, so this can't fail
+                let _ = self.added.0.broadcast(());
+            }
+        }
+    }
+
+    /// Puts the server into lame duck mode once `signal` resolves, then
+    /// resolves itself once `drain` elapsed.
+    ///
+    /// Lame duck mode sets every service that has a status, including the overall
+    /// health of the server, to [`ServingStatus::NotServing`], so load
+    /// balancers stop sending new requests to the server. The server itself
+    /// keeps serving every request for `drain`, old and new alike. Pass the
+    /// returned future to [`Router::serve_with_shutdown`] to shut down
+    /// gracefully once the drain is over.
+    ///
+    /// ```no_run
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # async fn run() -> Result<(), tonic::transport::Error> {
+    /// # let addr = "[::1]:50051".parse().unwrap();
+    /// let (reporter, health_server) = tonic_health::server::health_reporter();
+    /// let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    ///
+    /// let signal = async {
+    ///     rx.await.ok();
+    /// };
+    /// Server::builder()
+    ///     .add_service(health_server)
+    ///     .serve_with_shutdown(addr, reporter.lame_duck(signal, Duration::from_secs(30)))
+    ///     .await
+    /// # }
+    /// ```
+    ///
+    /// # Readiness probes
+    ///
+    /// A Kubernetes readiness probe that checks the health service only takes
+    /// the pod out of the endpoints of its services after `failureThreshold`
+    /// failed probes, `periodSeconds` apart. `drain` should be longer than
+    /// that, plus the time the load balancers take to pick up the change, or
+    /// requests still routed to the pod fail once it shut down. Liveness
+    /// probes must not check the health service, or the pod is restarted
+    /// while it drains.
+    ///
+    /// [`ServingStatus::NotServing`]: ../enum.ServingStatus.html#variant.NotServing
+    /// [`Router::serve_with_shutdown`]: https://docs.rs/tonic/*/tonic/transport/server/struct.Router.html#method.serve_with_shutdown
+    pub async fn lame_duck(self, signal: impl Future<Output = ()>, drain: Duration) {
+        signal.await;
+
+        let services = self
+            .statuses
+            .read()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        for service_name in services {
+            self.set_service_status(service_name, ServingStatus::NotServing);
+        }
+
+        tokio::timer::delay_for(drain).await;
+    }
+}
+
+impl fmt::Debug for HealthReporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let statuses = self.statuses.read().unwrap();
+        let statuses = statuses
+            .iter()
+            .map(|(name, (_, rx))| (name, *rx.get_ref()))
+            .collect::<HashMap<_, _>>();
+
+        f.debug_struct("HealthReporter")
+            .field("statuses", &statuses)
+            .finish()
+    }
+}
+
+/// A service providing implementations of gRPC health checking protocol.
+#[derive(Debug)]
+pub struct HealthService {
+    reporter: HealthReporter,
+}
+
+impl HealthService {
+    fn new(reporter: HealthReporter) -> Self {
+        HealthService { reporter }
+    }
+
+    fn service_health(&self, service_name: &str) -> Option<ServingStatus> {
+        let statuses = self.reporter.statuses.read().unwrap();
+        statuses.get(service_name).map(|(_, rx)| *rx.get_ref())
+    }
+
+    fn watch_service(&self, service_name: &str) -> Watched {
+        let statuses = self.reporter.statuses.read().unwrap();
+
+        match statuses.get(service_name) {
+            Some((_, rx)) => Watched::Known(rx.clone()),
+            // services that are not known yet share one channel rather than
+            // getting one each, so that watches don't fill up the map
+            None => Watched::Unknown {
+                service_name: service_name.to_string(),
+                reporter: self.reporter.clone(),
+                added: self.reporter.added.1.clone(),
+                reported: false,
+            },
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service_name = request.get_ref().service.as_str();
+
+        match self.service_health(service_name) {
+            Some(status) => Ok(Response::new(HealthCheckResponse {
+                status: health_check_response::ServingStatus::from(status) as i32,
+            })),
+            None => Err(Status::new(Code::NotFound, "service not registered")),
+        }
+    }
+
+    type WatchStream = WatchStream;
+
+    async fn watch(
+        &self,
+        request: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let watched = self.watch_service(request.get_ref().service.as_str());
+        Ok(Response::new(WatchStream { watched }))
+    }
+}
+
+/// The stream of status updates returned by `Watch`.
+///
+/// It starts with the current status of the service and yields every later
+/// change, `SERVICE_UNKNOWN` while no status was set for the service.
+#[derive(Debug)]
+pub struct WatchStream {
+    watched: Watched,
+}
+
+#[derive(Debug)]
+enum Watched {
+    Known(watch::Receiver<ServingStatus>),
+    /// A service without a status, which is looked up again whenever a
+    /// service gets its first status.
+    Unknown {
+        service_name: String,
+        reporter: HealthReporter,
+        added: watch::Receiver<()>,
+        /// Whether `SERVICE_UNKNOWN` was yielded already.
+        reported: bool,
+    },
+}
+
+impl Stream for WatchStream {
+    type Item = Result<HealthCheckResponse, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let status = loop {
+            match &mut self.watched {
+                Watched::Known(rx) => match futures_core::ready!(Pin::new(rx).poll_next(cx)) {
+                    Some(status) => break health_check_response::ServingStatus::from(status),
+                    None => return Poll::Ready(None),
+                },
+                Watched::Unknown { reported, .. } if !*reported => {
+                    *reported = true;
+                    break health_check_response::ServingStatus::ServiceUnknown;
+                }
+                Watched::Unknown {
+                    service_name,
+                    reporter,
+                    added,
+                    ..
+                } => {
+                    if futures_core::ready!(Pin::new(added).poll_next(cx)).is_none() {
+                        return Poll::Ready(None);
+                    }
+
+                    let statuses = reporter.statuses.read().unwrap();
+                    if let Some((_, rx)) = statuses.get(service_name.as_str()) {
+                        let rx = rx.clone();
+                        drop(statuses);
+                        self.watched = Watched::Known(rx);
+                    }
+                }
+            }
+        };
+
+        Poll::Ready(Some(Ok(HealthCheckResponse {
+            status: status as i32,
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::{client::HealthClient, health_check_response::ServingStatus as ProtoStatus};
+    use tonic::transport::{Channel, Server};
+
+    fn request(service: &str) -> Request<HealthCheckRequest> {
+        Request::new(HealthCheckRequest {
+            service: service.to_string(),
+        })
+    }
+
+    async fn next_status(stream: &mut WatchStream) -> ProtoStatus {
+        let response = futures_util::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        ProtoStatus::from_i32(response.status).unwrap()
+    }
+
+    fn is_pending(stream: &mut WatchStream) -> bool {
+        let mut next = futures_util::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx));
+        let waker = futures_util::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        Pin::new(&mut next).poll(&mut cx).is_pending()
+    }
+
+    fn service() -> (HealthReporter, HealthService) {
+        let (reporter, _) = health_reporter();
+        let service = HealthService::new(reporter.clone());
+        (reporter, service)
+    }
+
+    #[tokio::test]
+    async fn check() {
+        let (reporter, service) = service();
+
+        let response = service.check(request("")).await.unwrap();
+        assert_eq!(response.get_ref().status, ProtoStatus::Serving as i32);
+
+        let status = service.check(request("test.Svc")).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        reporter.set_service_status("test.Svc", ServingStatus::NotServing);
+        let response = service.check(request("test.Svc")).await.unwrap();
+        assert_eq!(response.get_ref().status, ProtoStatus::NotServing as i32);
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let (reporter, service) = service();
+        reporter.set_service_status("test.Svc", ServingStatus::Serving);
+
+        let mut stream = service
+            .watch(request("test.Svc"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(next_status(&mut stream).await, ProtoStatus::Serving);
+        assert!(is_pending(&mut stream));
+
+        // setting the same status again is not a change
+        reporter.set_service_status("test.Svc", ServingStatus::Serving);
+        assert!(is_pending(&mut stream));
+
+        reporter.set_service_status("test.Svc", ServingStatus::NotServing);
+        assert_eq!(next_status(&mut stream).await, ProtoStatus::NotServing);
+    }
+
+    #[tokio::test]
+    async fn watch_unknown_service() {
+        let (reporter, service) = service();
+
+        let mut stream = service
+            .watch(request("test.Svc"))
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(next_status(&mut stream).await, ProtoStatus::ServiceUnknown);
+
+        // a watch does not make the service known to checks
+        let status = service.check(request("test.Svc")).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        reporter.set_service_status("test.Svc", ServingStatus::Serving);
+        assert_eq!(next_status(&mut stream).await, ProtoStatus::Serving);
+    }
+
+    #[tokio::test]
+    async fn watch_unknown_services_is_bounded() {
+        let (reporter, service) = service();
+
+        let mut streams = Vec::new();
+        for i in 0..100 {
+            let name = format!("test.Svc{}", i);
+            let mut stream = service.watch(request(&name)).await.unwrap().into_inner();
+            assert_eq!(next_status(&mut stream).await, ProtoStatus::ServiceUnknown);
+            streams.push(stream);
+        }
+        // only the overall health of the server is known
+        assert_eq!(reporter.statuses.read().unwrap().len(), 1);
+
+        // another service becoming known is not a change
+        reporter.set_service_status("test.Other", ServingStatus::Serving);
+        assert!(is_pending(&mut streams[7]));
+
+        reporter.set_service_status("test.Svc7", ServingStatus::NotServing);
+        assert_eq!(next_status(&mut streams[7]).await, ProtoStatus::NotServing);
+        assert!(is_pending(&mut streams[8]));
+    }
+
+    async fn check_status(client: &mut HealthClient<Channel>, service: &str) -> ProtoStatus {
+        let response = client.check(request(service)).await.unwrap();
+        ProtoStatus::from_i32(response.get_ref().status).unwrap()
+    }
+
+    #[tokio::test]
+    async fn lame_duck() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let (reporter, health_server) = health_reporter();
+        reporter.set_service_status("test.Svc", ServingStatus::Serving);
+
+        let (signal_tx, signal) = tokio::sync::oneshot::channel::<()>();
+        let signal = async {
+            signal.await.ok();
+        };
+        let lame_duck = reporter.lame_duck(signal, Duration::from_millis(500));
+        let (done_tx, done) = tokio::sync::oneshot::channel();
+        tokio::spawn(async move {
+            let result = Server::builder()
+                .add_service(health_server)
+                .serve_with_shutdown(addr, lame_duck)
+                .await;
+            done_tx.send(result).unwrap();
+        });
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+        let endpoint = Channel::from_shared(format!("http://{}", addr)).unwrap();
+        let mut client = HealthClient::new(endpoint.connect().await.unwrap());
+        assert_eq!(check_status(&mut client, "").await, ProtoStatus::Serving);
+
+        signal_tx.send(()).unwrap();
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+        // health flips, while requests keep succeeding, also on new connections
+        assert_eq!(check_status(&mut client, "").await, ProtoStatus::NotServing);
+        assert_eq!(
+            check_status(&mut client, "test.Svc").await,
+            ProtoStatus::NotServing
+        );
+        let mut client = HealthClient::new(endpoint.connect().await.unwrap());
+        assert_eq!(check_status(&mut client, "").await, ProtoStatus::NotServing);
+
+        // the server shuts down once the drain is over
+        let done = tokio::timer::Timeout::new(done, Duration::from_secs(5));
+        done.await.expect("server did not stop").unwrap().unwrap();
+    }
+}
@@ -15,6 +15,12 @@ struct Opts {
 
     #[structopt(long)]
     use_tls: bool,
+
+    #[structopt(long = "server_host", default_value = "localhost")]
+    server_host: String,
+
+    #[structopt(long = "server_port", default_value = "10000")]
+    server_port: u16,
 }
 
 #[tokio::main]
@@ -25,20 +31,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let test_cases = matches.test_case;
 
-    let mut endpoint = Endpoint::from_static("http://localhost:10000")
+    let scheme = if matches.use_tls { "https" } else { "http" };
+    let uri = format!(
+        "{}://{}:{}",
+        scheme, matches.server_host, matches.server_port
+    );
+    let mut endpoint = Endpoint::from_shared(uri)?
         .timeout(Duration::from_secs(5))
-        .concurrency_limit(30)
-        .clone();
+        .concurrency_limit(30);
 
     if matches.use_tls {
         let pem = tokio::fs::read("tonic-interop/data/ca.pem").await?;
         let ca = Certificate::from_pem(pem);
 
-        endpoint.tls_config(
+        endpoint = endpoint.tls_config(
             ClientTlsConfig::with_openssl()
                 .ca_certificate(ca)
                 .domain_name("foo.test.google.fr"),
-        );
+        )?;
     }
 
     let channel = endpoint.connect().await?;
@@ -78,6 +88,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             Testcase::custom_metadata => {
                 client::custom_metadata(&mut client, &mut test_results).await
             }
+            Testcase::cancel_after_begin => {
+                client::cancel_after_begin(&mut client, &mut test_results).await
+            }
+            Testcase::cancel_after_first_response => {
+                client::cancel_after_first_response(&mut client, &mut test_results).await
+            }
+            Testcase::timeout_on_sleeping_server => {
+                client::timeout_on_sleeping_server(&mut client, &mut test_results).await
+            }
             _ => unimplemented!(),
         }
 
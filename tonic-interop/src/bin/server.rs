@@ -9,6 +9,9 @@ use tonic_interop::{server, MergeTrailers};
 struct Opts {
     #[structopt(long)]
     use_tls: bool,
+
+    #[structopt(long, default_value = "10000")]
+    port: u16,
 }
 
 #[tokio::main]
@@ -17,7 +20,7 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
 
     let matches = Opts::from_args();
 
-    let addr = "127.0.0.1:10000".parse().unwrap();
+    let addr = ([127, 0, 0, 1], matches.port).into();
 
     let mut builder = Server::builder();
 
@@ -26,10 +29,10 @@ async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
         let key = tokio::fs::read("tonic-interop/data/server1.key").await?;
 
         let identity = Identity::from_pem(cert, key);
-        builder.tls_config(ServerTlsConfig::with_openssl().identity(identity));
+        builder = builder.tls_config(ServerTlsConfig::with_openssl().identity(identity))?;
     }
 
-    builder.interceptor_fn(|svc, req| {
+    builder = builder.interceptor_fn(|svc, req| {
         let echo_header = req
             .headers()
             .get("x-grpc-test-echo-initial")
@@ -1,5 +1,6 @@
 use crate::{pb::client::*, pb::*, test_assert, TestAssertion};
 use futures_util::{future, stream, SinkExt, StreamExt};
+use std::time::Duration;
 use tokio::sync::mpsc;
 use tonic::transport::Channel;
 use tonic::{metadata::MetadataValue, Code, Request, Response, Status};
@@ -371,8 +372,8 @@ pub async fn custom_metadata(client: &mut TestClient, assertions: &mut Vec<TestA
     ));
     assertions.push(test_assert!(
         "metadata bin must match in unary",
-        response.metadata().get_bin(key2) == Some(&value2),
-        format!("result={:?}", response.metadata().get_bin(key1))
+        response.trailers().get_bin(key2) == Some(&value2),
+        format!("result={:?}", response.trailers().get_bin(key1))
     ));
 
     let response = client
@@ -397,6 +398,91 @@ pub async fn custom_metadata(client: &mut TestClient, assertions: &mut Vec<TestA
     ));
 }
 
+pub async fn cancel_after_begin(client: &mut TestClient, assertions: &mut Vec<TestAssertion>) {
+    // a request stream that never yields a message or ends
+    let call = client.streaming_input_call(Request::new(stream::pending()));
+
+    // a call is cancelled by dropping it, here right after it was started
+    let completed = match future::select(Box::pin(call), future::ready(())).await {
+        future::Either::Left((result, _)) => Some(result),
+        future::Either::Right(_) => None,
+    };
+
+    assertions.push(test_assert!(
+        "call must not complete before it is cancelled",
+        completed.is_none(),
+        format!("result={:?}", completed)
+    ));
+}
+
+pub async fn cancel_after_first_response(
+    client: &mut TestClient,
+    assertions: &mut Vec<TestAssertion>,
+) {
+    let (mut tx, rx) = mpsc::unbounded_channel();
+    tx.try_send(make_ping_pong_request(0)).unwrap();
+
+    let result = client.full_duplex_call(Request::new(rx)).await;
+
+    assertions.push(test_assert!(
+        "call must be successful",
+        result.is_ok(),
+        format!("result={:?}", result)
+    ));
+
+    if let Ok(mut response) = result.map(Response::into_inner) {
+        let first = response.next().await;
+
+        assertions.push(test_assert!(
+            "the first response payload size should match input",
+            match &first {
+                Some(Ok(first)) => crate::response_length(first) == RESPONSE_LENGTHS[0],
+                _ => false,
+            },
+            format!(
+                "first={:?}",
+                first.map(|r| r.map(|r| crate::response_length(&r)))
+            )
+        ));
+
+        // dropping the response stream resets the stream, cancelling the call
+        drop(response);
+        drop(tx);
+    }
+}
+
+pub async fn timeout_on_sleeping_server(
+    client: &mut TestClient,
+    assertions: &mut Vec<TestAssertion>,
+) {
+    let (mut tx, rx) = mpsc::unbounded_channel();
+    tx.try_send(StreamingOutputCallRequest {
+        payload: Some(crate::client_payload(27182)),
+        ..Default::default()
+    })
+    .unwrap();
+
+    let mut req = Request::new(rx);
+    req.set_timeout(Duration::from_millis(1));
+
+    // the deadline may elapse before or after the response headers arrive
+    let result = match client.full_duplex_call(req).await {
+        Ok(response) => response.into_inner().message().await.map(|_| ()),
+        Err(status) => Err(status),
+    };
+
+    assertions.push(test_assert!(
+        "call must fail with DEADLINE_EXCEEDED",
+        match &result {
+            Err(status) => status.code() == Code::DeadlineExceeded,
+            _ => false,
+        },
+        format!("result={:?}", result)
+    ));
+
+    drop(tx);
+}
+
 fn make_ping_pong_request(idx: usize) -> StreamingOutputCallRequest {
     let req_len = REQUEST_LENGTHS[idx];
     let resp_len = RESPONSE_LENGTHS[idx];
@@ -32,7 +32,8 @@ sleep 1
 ./target/debug/client \
  --test_case=empty_unary,large_unary,client_streaming,server_streaming,ping_pong,\
 empty_stream,status_code_and_message,special_status_message,unimplemented_method,\
-unimplemented_service,custom_metadata $ARG
+unimplemented_service,custom_metadata,cancel_after_begin,cancel_after_first_response,\
+timeout_on_sleeping_server $ARG
 
 echo ":; killing test server"; kill ${SERVER_PID};
 
@@ -47,7 +48,9 @@ trap 'echo ":; killing test server"; kill ${SERVER_PID};' EXIT
 
 sleep 1
 
+# timeout_on_sleeping_server is skipped: the tonic server only bounds the
+# handler by the deadline, not the response stream it returns.
 ./target/debug/client \
 --test_case=empty_unary,large_unary,client_streaming,server_streaming,ping_pong,\
 empty_stream,status_code_and_message,special_status_message,unimplemented_method,\
-unimplemented_service,custom_metadata $ARG
+unimplemented_service,custom_metadata,cancel_after_begin,cancel_after_first_response $ARG
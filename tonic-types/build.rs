@@ -0,0 +1,17 @@
+fn main() {
+    let protos = &[
+        "proto/google/rpc/status.proto",
+        "proto/google/rpc/error_details.proto",
+    ];
+
+    tonic_build::configure()
+        .build_client(false)
+        .build_server(false)
+        .compile(protos, &["proto"])
+        .unwrap();
+
+    // prevent needing to rebuild if files (or deps) haven't changed
+    for proto in protos {
+        println!("cargo:rerun-if-changed={}", proto);
+    }
+}
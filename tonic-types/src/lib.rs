@@ -0,0 +1,245 @@
+//! Standard gRPC error details for `tonic`.
+//!
+//! gRPC servers can attach structured details to an error status, like the
+//! fields of a request that failed validation or how long a client should
+//! wait before retrying. These are sent as a `google.rpc.Status` message in
+//! the `grpc-status-details-bin` trailer, which makes them interoperable
+//! with the [richer error model] of other gRPC implementations, e.g.
+//! grpc-go and grpc-java.
+//!
+//! [`StatusExt`] builds such a [`Status`] from a list of [`ErrorDetail`]s
+//! and extracts them from a received one:
+//!
+//! ```
+//! use tonic::{Code, Status};
+//! use tonic_types::{pb, ErrorDetail, StatusExt};
+//!
+//! let status = Status::with_error_details(
+//!     Code::InvalidArgument,
+//!     "request contains invalid arguments",
+//!     vec![pb::BadRequest {
+//!         field_violations: vec![pb::bad_request::FieldViolation {
+//!             field: "name".to_string(),
+//!             description: "name must not be empty".to_string(),
+//!         }],
+//!     }
+//!     .into()],
+//! );
+//!
+//! match &status.error_details().unwrap()[..] {
+//!     [ErrorDetail::BadRequest(bad_request)] => {
+//!         assert_eq!(bad_request.field_violations[0].field, "name");
+//!     }
+//!     _ => unreachable!(),
+//! }
+//! ```
+//!
+//! [richer error model]: https://grpc.io/docs/guides/error/#richer-error-model
+//! [`StatusExt`]: trait.StatusExt.html
+//! [`Status`]: https://docs.rs/tonic/*/tonic/struct.Status.html
+//! [`ErrorDetail`]: enum.ErrorDetail.html
+
+#![doc(html_root_url = "https://docs.rs/tonic-types/0.1.0-alpha.5")]
+#![warn(missing_debug_implementations, rust_2018_idioms, unreachable_pub)]
+
+use bytes::{Bytes, BytesMut};
+use prost::{DecodeError, Message};
+use prost_types::Any;
+use tonic::{Code, Status};
+
+/// Generated protobuf types from the `google.rpc` package.
+pub mod pb {
+    #![allow(unreachable_pub, missing_docs, missing_debug_implementations)]
+    tonic::include_proto!("google.rpc");
+}
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/google.rpc.";
+
+/// A standard error detail of a `google.rpc.Status`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ErrorDetail {
+    /// The cause of the error with structured details.
+    ErrorInfo(pb::ErrorInfo),
+    /// When the client can retry a failed request.
+    RetryInfo(pb::RetryInfo),
+    /// Additional debugging info.
+    DebugInfo(pb::DebugInfo),
+    /// How a quota check failed.
+    QuotaFailure(pb::QuotaFailure),
+    /// What preconditions have failed.
+    PreconditionFailure(pb::PreconditionFailure),
+    /// Violations in a client request.
+    BadRequest(pb::BadRequest),
+    /// Metadata about the request that clients can attach to a bug report.
+    RequestInfo(pb::RequestInfo),
+    /// The resource that is being accessed.
+    ResourceInfo(pb::ResourceInfo),
+    /// Links to documentation or for performing an out of band action.
+    Help(pb::Help),
+    /// A localized error message that is safe to return to the user.
+    LocalizedMessage(pb::LocalizedMessage),
+    /// A detail that is not one of the standard `google.rpc` messages, kept
+    /// as it was received.
+    Other(Any),
+}
+
+macro_rules! error_details {
+    ($($name:ident),*) => {
+        impl ErrorDetail {
+            fn into_any(self) -> Any {
+                match self {
+                    $(ErrorDetail::$name(detail) => Any {
+                        type_url: format!("{}{}", TYPE_URL_PREFIX, stringify!($name)),
+                        value: encode(&detail),
+                    },)*
+                    ErrorDetail::Other(any) => any,
+                }
+            }
+
+            fn from_any(any: Any) -> Result<Self, DecodeError> {
+                let name = match any.type_url.get(..TYPE_URL_PREFIX.len()) {
+                    Some(prefix) if prefix == TYPE_URL_PREFIX => &any.type_url[prefix.len()..],
+                    _ => return Ok(ErrorDetail::Other(any)),
+                };
+
+                let detail = match name {
+                    $(stringify!($name) => ErrorDetail::$name(Message::decode(&any.value[..])?),)*
+                    _ => ErrorDetail::Other(any),
+                };
+                Ok(detail)
+            }
+        }
+
+        $(
+            impl From<pb::$name> for ErrorDetail {
+                fn from(detail: pb::$name) -> Self {
+                    ErrorDetail::$name(detail)
+                }
+            }
+        )*
+    };
+}
+
+error_details!(
+    ErrorInfo,
+    RetryInfo,
+    DebugInfo,
+    QuotaFailure,
+    PreconditionFailure,
+    BadRequest,
+    RequestInfo,
+    ResourceInfo,
+    Help,
+    LocalizedMessage
+);
+
+fn encode(message: &impl Message) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(message.encoded_len());
+    // a `Vec` grows as needed, so encoding can't run out of space
+    message.encode(&mut buf).unwrap();
+    buf
+}
+
+/// Extension methods on [`Status`] for the standard gRPC error details.
+///
+/// [`Status`]: https://docs.rs/tonic/*/tonic/struct.Status.html
+pub trait StatusExt {
+    /// Create a new `Status` with the associated code and message, carrying
+    /// `details` in a `google.rpc.Status` message.
+    fn with_error_details(
+        code: Code,
+        message: impl Into<String>,
+        details: impl IntoIterator<Item = ErrorDetail>,
+    ) -> Status;
+
+    /// Extract the error details of this `Status`.
+    ///
+    /// A `Status` without details has none, an error is returned if its
+    /// details are not a valid `google.rpc.Status` message.
+    fn error_details(&self) -> Result<Vec<ErrorDetail>, DecodeError>;
+}
+
+impl StatusExt for Status {
+    fn with_error_details(
+        code: Code,
+        message: impl Into<String>,
+        details: impl IntoIterator<Item = ErrorDetail>,
+    ) -> Status {
+        let message = message.into();
+        let status = pb::Status {
+            code: code as i32,
+            message: message.clone(),
+            details: details.into_iter().map(ErrorDetail::into_any).collect(),
+        };
+
+        let mut buf = BytesMut::with_capacity(status.encoded_len());
+        // the buffer has room for the whole message
+        status.encode(&mut buf).unwrap();
+
+        Status::with_details(code, message, Bytes::from(buf))
+    }
+
+    fn error_details(&self) -> Result<Vec<ErrorDetail>, DecodeError> {
+        if self.details().is_empty() {
+            return Ok(Vec::new());
+        }
+
+        pb::Status::decode(self.details())?
+            .details
+            .into_iter()
+            .map(ErrorDetail::from_any)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_details_roundtrip() {
+        let retry_info = pb::RetryInfo {
+            retry_delay: Some(prost_types::Duration {
+                seconds: 5,
+                nanos: 0,
+            }),
+        };
+        let other = Any {
+            type_url: "type.googleapis.com/example.Detail".to_string(),
+            value: vec![1, 2, 3],
+        };
+
+        let status = Status::with_error_details(
+            Code::Unavailable,
+            "try again later",
+            vec![retry_info.clone().into(), ErrorDetail::Other(other.clone())],
+        );
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "try again later");
+
+        let details = pb::Status::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::Unavailable as i32);
+        assert_eq!(details.message, "try again later");
+        assert_eq!(
+            details.details[0].type_url,
+            "type.googleapis.com/google.rpc.RetryInfo"
+        );
+
+        assert_eq!(
+            status.error_details().unwrap(),
+            vec![
+                ErrorDetail::RetryInfo(retry_info),
+                ErrorDetail::Other(other)
+            ]
+        );
+    }
+
+    #[test]
+    fn no_error_details() {
+        let status = Status::new(Code::Internal, "oops");
+        assert_eq!(status.error_details().unwrap(), vec![]);
+
+        let status = Status::with_details(Code::Internal, "oops", Bytes::from_static(b"\xff"));
+        assert!(status.error_details().is_err());
+    }
+}
@@ -0,0 +1,493 @@
+//! A response cache for idempotent unary calls.
+//!
+//! [`Cache`] wraps the service a client sends its requests through, e.g. a
+//! [`Channel`], and answers repeated identical requests from memory instead of
+//! sending them to the server again.
+//!
+//! Which requests are cached is decided by a key function that is given the
+//! request, with its body buffered into [`Bytes`]. Returning `None` sends the
+//! request to the server without caching it. Two requests with the same key
+//! are considered identical, so the key should include everything the response
+//! depends on: usually the path and the body, and any metadata such as the
+//! identity of the caller that changes the result.
+//!
+//! ```
+//! # use tonic::client::CacheLayer;
+//! # use std::time::Duration;
+//! let layer = CacheLayer::new(Duration::from_secs(30), |req: &http::Request<bytes::Bytes>| {
+//!     Some((req.uri().path().to_string(), req.body().clone()))
+//! });
+//! ```
+//!
+//! # Requirements
+//!
+//! Only cache idempotent, side effect free methods: a cached call never
+//! reaches the server. Since the whole request body is buffered before the
+//! key function runs, the cache must only be applied to services used for
+//! unary calls. Create a separate client over the uncached channel for
+//! streaming methods.
+//!
+//! Only successful responses, with a `grpc-status` of `OK`, are stored.
+//!
+//! # Invalidation
+//!
+//! A response is served from the cache for `ttl` after it was received, then
+//! the next identical request goes to the server again. Entries can be dropped
+//! earlier with [`Cache::invalidate`] or [`Cache::clear`]. Expired entries are
+//! removed lazily, at the latest once per `ttl`, so memory is bounded by the
+//! number of distinct requests within the last `ttl`.
+//!
+//! [`Channel`]: ../transport/struct.Channel.html
+//! [`Bytes`]: https://docs.rs/bytes/0.4/bytes/struct.Bytes.html
+
+use crate::{body::BoxBody, Code, Status};
+use bytes::{Buf, Bytes, BytesMut};
+use futures_util::future::poll_fn;
+use http::{HeaderMap, Request, Response};
+use http_body::Body as HttpBody;
+use hyper::Chunk;
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    hash::Hash,
+    mem,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower::layer::Layer;
+use tower_service::Service;
+use tracing::trace;
+
+/// Creates [`Cache`] services sharing one cache.
+pub struct CacheLayer<K, F> {
+    store: Arc<Store<K>>,
+    key: Arc<F>,
+}
+
+/// A service that caches the responses of the inner service.
+///
+/// See the [module level documentation](index.html) for details.
+pub struct Cache<S, K, F> {
+    inner: S,
+    store: Arc<Store<K>>,
+    key: Arc<F>,
+}
+
+/// The body of a response returned by [`Cache`].
+pub struct CacheBody<B> {
+    kind: Kind<B>,
+}
+
+enum Kind<B> {
+    Inner(B),
+    Cached {
+        data: Option<Bytes>,
+        trailers: Option<HeaderMap>,
+    },
+}
+
+struct Store<K> {
+    ttl: Duration,
+    state: Mutex<Entries<K>>,
+}
+
+struct Entries<K> {
+    entries: HashMap<K, Entry>,
+    last_sweep: Instant,
+}
+
+#[derive(Clone)]
+struct Entry {
+    expires: Instant,
+    status: http::StatusCode,
+    headers: HeaderMap,
+    data: Bytes,
+    trailers: Option<HeaderMap>,
+}
+
+impl<K, F> CacheLayer<K, F>
+where
+    K: Hash + Eq,
+    F: Fn(&Request<Bytes>) -> Option<K>,
+{
+    /// Create a layer caching responses for `ttl`, keyed by `key`.
+    pub fn new(ttl: Duration, key: F) -> Self {
+        CacheLayer {
+            store: Arc::new(Store::new(ttl)),
+            key: Arc::new(key),
+        }
+    }
+}
+
+impl<S, K, F> Layer<S> for CacheLayer<K, F> {
+    type Service = Cache<S, K, F>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Cache {
+            inner,
+            store: self.store.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<S, K, F> Cache<S, K, F>
+where
+    K: Hash + Eq,
+    F: Fn(&Request<Bytes>) -> Option<K>,
+{
+    /// Wrap `inner`, caching its responses for `ttl`, keyed by `key`.
+    pub fn new(inner: S, ttl: Duration, key: F) -> Self {
+        CacheLayer::new(ttl, key).layer(inner)
+    }
+
+    /// Drop the cached response for `key`, if any.
+    pub fn invalidate(&self, key: &K) {
+        self.store.state.lock().unwrap().entries.remove(key);
+    }
+
+    /// Drop all cached responses.
+    pub fn clear(&self) {
+        self.store.state.lock().unwrap().entries.clear();
+    }
+}
+
+impl<K: Hash + Eq> Store<K> {
+    fn new(ttl: Duration) -> Self {
+        Store {
+            ttl,
+            state: Mutex::new(Entries {
+                entries: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    fn get(&self, key: &K) -> Option<Entry> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+
+        match state.entries.get(key) {
+            Some(entry) if now < entry.expires => Some(entry.clone()),
+            Some(_) => {
+                state.entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, key: K, mut entry: Entry) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let Entries {
+            entries,
+            last_sweep,
+        } = &mut *state;
+
+        if now >= *last_sweep + self.ttl {
+            entries.retain(|_, entry| now < entry.expires);
+            *last_sweep = now;
+        }
+
+        entry.expires = now + self.ttl;
+        entries.insert(key, entry);
+    }
+}
+
+impl<S, K, F, ResBody> Service<Request<BoxBody>> for Cache<S, K, F>
+where
+    S: Service<Request<BoxBody>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<crate::Error>,
+    ResBody: HttpBody + Send + Unpin + 'static,
+    ResBody::Data: Into<Bytes>,
+    ResBody::Error: Into<crate::Error>,
+    K: Hash + Eq + Send + 'static,
+    F: Fn(&Request<Bytes>) -> Option<K> + Send + Sync + 'static,
+{
+    type Response = Response<CacheBody<ResBody>>;
+    type Error = crate::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        // the inner service was driven to readiness, so keep that instance
+        // for this request and leave a fresh clone for the next one
+        let clone = self.inner.clone();
+        let mut inner = mem::replace(&mut self.inner, clone);
+        let store = self.store.clone();
+        let key = self.key.clone();
+
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let request = Request::from_parts(parts, buffer(body).await?);
+
+            let key = match key(&request) {
+                Some(key) => key,
+                None => {
+                    let response = inner.call(request.map(from_bytes)).await;
+                    return Ok(response.map_err(Into::into)?.map(CacheBody::inner));
+                }
+            };
+
+            if let Some(entry) = store.get(&key) {
+                trace!("serving response from cache");
+                return Ok(entry.into_response());
+            }
+
+            let response = inner
+                .call(request.map(from_bytes))
+                .await
+                .map_err(Into::into)?;
+            let (parts, mut body) = response.into_parts();
+
+            let mut data = BytesMut::new();
+            while let Some(chunk) = poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+                let chunk: Bytes = chunk.map_err(Into::into)?.into();
+                data.extend_from_slice(&chunk[..]);
+            }
+            let trailers = poll_fn(|cx| Pin::new(&mut body).poll_trailers(cx))
+                .await
+                .map_err(Into::into)?;
+
+            let entry = Entry {
+                expires: Instant::now(),
+                status: parts.status,
+                headers: parts.headers,
+                data: data.freeze(),
+                trailers,
+            };
+
+            if entry.is_ok() {
+                store.insert(key, entry.clone());
+            }
+
+            Ok(entry.into_response())
+        })
+    }
+}
+
+impl<S: Clone, K, F> Clone for Cache<S, K, F> {
+    fn clone(&self) -> Self {
+        Cache {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl<K, F> Clone for CacheLayer<K, F> {
+    fn clone(&self) -> Self {
+        CacheLayer {
+            store: self.store.clone(),
+            key: self.key.clone(),
+        }
+    }
+}
+
+impl Entry {
+    /// Whether the response carries a `grpc-status` of `OK`.
+    fn is_ok(&self) -> bool {
+        let status = self
+            .trailers
+            .as_ref()
+            .and_then(Status::from_header_map)
+            .or_else(|| Status::from_header_map(&self.headers));
+
+        self.status == http::StatusCode::OK && status.map(|s| s.code() == Code::Ok).unwrap_or(false)
+    }
+
+    fn into_response<B>(self) -> Response<CacheBody<B>> {
+        let mut response = Response::new(CacheBody {
+            kind: Kind::Cached {
+                data: Some(self.data).filter(|data| !data.is_empty()),
+                trailers: self.trailers,
+            },
+        });
+
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers;
+
+        response
+    }
+}
+
+async fn buffer(mut body: BoxBody) -> Result<Bytes, crate::Error> {
+    let mut bytes = BytesMut::new();
+
+    while let Some(buf) = poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+        let mut buf = buf?;
+        while buf.has_remaining() {
+            let n = buf.bytes().len();
+            bytes.extend_from_slice(buf.bytes());
+            buf.advance(n);
+        }
+    }
+
+    Ok(bytes.freeze())
+}
+
+fn from_bytes(bytes: Bytes) -> BoxBody {
+    BoxBody::map_from(hyper::Body::from(bytes))
+}
+
+impl<B> CacheBody<B> {
+    fn inner(body: B) -> Self {
+        CacheBody {
+            kind: Kind::Inner(body),
+        }
+    }
+}
+
+impl<B> HttpBody for CacheBody<B>
+where
+    B: HttpBody + Unpin,
+    B::Data: Into<Bytes>,
+    B::Error: Into<crate::Error>,
+{
+    type Data = Chunk;
+    type Error = crate::Error;
+
+    fn is_end_stream(&self) -> bool {
+        match &self.kind {
+            Kind::Inner(body) => body.is_end_stream(),
+            Kind::Cached { data, trailers } => data.is_none() && trailers.is_none(),
+        }
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        match &mut self.kind {
+            Kind::Inner(body) => Pin::new(body).poll_data(cx).map(|data| {
+                data.map(|data| data.map(|d| Chunk::from(d.into())).map_err(Into::into))
+            }),
+            Kind::Cached { data, .. } => Poll::Ready(data.take().map(|d| Ok(Chunk::from(d)))),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        match &mut self.kind {
+            Kind::Inner(body) => Pin::new(body).poll_trailers(cx).map_err(Into::into),
+            Kind::Cached { trailers, .. } => Poll::Ready(Ok(trailers.take())),
+        }
+    }
+}
+
+impl<S: fmt::Debug, K, F> fmt::Debug for Cache<S, K, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("inner", &self.inner)
+            .field("ttl", &self.store.ttl)
+            .finish()
+    }
+}
+
+impl<K, F> fmt::Debug for CacheLayer<K, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheLayer")
+            .field("ttl", &self.store.ttl)
+            .finish()
+    }
+}
+
+impl<B> fmt::Debug for CacheBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheBody").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Clone, Default)]
+    struct Counter {
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl Service<Request<BoxBody>> for Counter {
+        type Response = Response<hyper::Body>;
+        type Error = crate::Error;
+        type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<BoxBody>) -> Self::Future {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            let response = Response::builder()
+                .header("grpc-status", "0")
+                .body(hyper::Body::from(format!("response {}", n)))
+                .unwrap();
+
+            future::ok(response)
+        }
+    }
+
+    fn path_and_body(request: &Request<Bytes>) -> Option<(String, Bytes)> {
+        Some((request.uri().path().to_string(), request.body().clone()))
+    }
+
+    async fn call<S>(svc: &mut S, body: &'static str) -> Bytes
+    where
+        S: Service<Request<BoxBody>, Response = Response<CacheBody<hyper::Body>>>,
+        S::Error: fmt::Debug,
+    {
+        let request = Request::builder()
+            .uri("/test.Svc/Get")
+            .body(from_bytes(Bytes::from_static(body.as_bytes())))
+            .unwrap();
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        let mut body = svc.call(request).await.unwrap().into_body();
+
+        let chunk = poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await;
+        chunk.unwrap().unwrap().into()
+    }
+
+    #[tokio::test]
+    async fn identical_call_is_served_from_cache() {
+        let counter = Counter::default();
+        let ttl = Duration::from_millis(100);
+        let mut svc = Cache::new(counter.clone(), ttl, path_and_body);
+
+        assert_eq!(call(&mut svc, "a").await, "response 0");
+        assert_eq!(call(&mut svc, "a").await, "response 0");
+        assert_eq!(call(&mut svc, "b").await, "response 1");
+        assert_eq!(counter.calls.load(Ordering::SeqCst), 2);
+
+        tokio::timer::delay_for(ttl).await;
+
+        assert_eq!(call(&mut svc, "a").await, "response 2");
+    }
+
+    #[tokio::test]
+    async fn requests_without_key_are_not_cached() {
+        let counter = Counter::default();
+        let mut svc = Cache::new(
+            counter.clone(),
+            Duration::from_secs(60),
+            |_: &Request<Bytes>| None::<()>,
+        );
+
+        assert_eq!(call(&mut svc, "a").await, "response 0");
+        assert_eq!(call(&mut svc, "a").await, "response 1");
+    }
+}
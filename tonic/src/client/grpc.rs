@@ -1,8 +1,9 @@
 use crate::{
     body::{Body, BoxBody},
     client::GrpcService,
-    codec::{encode_client, Codec, Streaming},
-    Code, Request, Response, Status,
+    codec::{encode_client, Codec, CompressionEncoding, Streaming},
+    credentials::Credentials,
+    CallCredentials, Code, Request, Response, Status,
 };
 use bytes::Bytes;
 use futures_core::Stream;
@@ -12,7 +13,8 @@ use http::{
     uri::{Parts, PathAndQuery, Uri},
 };
 use http_body::Body as HttpBody;
-use std::fmt;
+use std::{fmt, sync::Arc};
+use tracing::Span;
 
 /// A gRPC client dispatcher.
 ///
@@ -26,15 +28,91 @@ use std::fmt;
 /// the conventions explained in the [gRPC protocol definition] under `Path →`. An
 /// example of this path could look like `/greeter.Greeter/SayHello`.
 ///
+/// The span that is current when a request method is called is entered
+/// while the request messages are encoded and the response messages are
+/// decoded, even though that work happens within the transport.
+///
+/// The size of encoded request and decoded response messages is unbounded
+/// unless limited via [`Grpc::max_encoding_message_size`] and
+/// [`Grpc::max_decoding_message_size`].
+///
+/// Requests are authenticated with the [`CallCredentials`] set via
+/// [`Grpc::credentials`], unless they carry their own.
+///
+/// The inner service can be any tower `Service` of HTTP requests, not just a
+/// [`Channel`]: e.g. a hyper client or connection, a stack of middleware, or
+/// a transport of its own for targets such as wasm, in which case tonic can
+/// be used without its `transport` feature. Requests only carry the path of
+/// the method, so such services need an [`Grpc::origin`] unless they set the
+/// scheme and authority themselves.
+///
+/// [`Channel`]: ../transport/struct.Channel.html
 /// [gRPC protocol definition]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md#requests
 pub struct Grpc<T> {
     inner: T,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
+    credentials: Option<Credentials>,
+    origin: Option<Uri>,
 }
 
 impl<T> Grpc<T> {
     /// Creates a new gRPC client with the provided [`GrpcService`].
     pub fn new(inner: T) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+            credentials: None,
+            origin: None,
+        }
+    }
+
+    /// Limit the size of a decoded response message to `limit` bytes.
+    ///
+    /// A response carrying a larger message fails with a `RESOURCE_EXHAUSTED`
+    /// status. For compressed messages the limit applies to both the
+    /// compressed and the decompressed size.
+    pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Limit the size of an encoded request message to `limit` bytes.
+    ///
+    /// A larger request message is not sent; the request fails with a
+    /// `RESOURCE_EXHAUSTED` status instead.
+    pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
+        self
+    }
+
+    /// Authenticate every request with `credentials`, unless it carries its
+    /// own set via [`Request::set_credentials`].
+    ///
+    /// [`Request::set_credentials`]: ../struct.Request.html#method.set_credentials
+    pub fn credentials(mut self, credentials: impl CallCredentials) -> Self {
+        self.credentials = Some(Credentials(Arc::new(credentials)));
+        self
+    }
+
+    /// Set the scheme and authority of every request to those of `origin`,
+    /// e.g. `http://example.com`.
+    ///
+    /// A [`Channel`] sets them itself, but other services may require them.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `origin` lacks a scheme or an authority.
+    ///
+    /// [`Channel`]: ../transport/struct.Channel.html
+    pub fn origin(mut self, origin: Uri) -> Self {
+        assert!(
+            origin.scheme_part().is_some() && origin.authority_part().is_some(),
+            "origin must have a scheme and an authority"
+        );
+        self.origin = Some(origin);
+        self
     }
 
     /// Check if the inner [`GrpcService`] is able to accept a  new request.
@@ -81,12 +159,12 @@ impl<T> Grpc<T> {
         T::ResponseBody: Body + HttpBody + Send + 'static,
         <T::ResponseBody as HttpBody>::Error: Into<crate::Error>,
         <T::ResponseBody as HttpBody>::Data: Into<Bytes>,
-        S: Stream<Item = M1> + Send + Sync + 'static,
+        S: Stream<Item = M1> + Send + 'static,
         C: Codec<Encode = M1, Decode = M2>,
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let (mut parts, body) = self.streaming(request, path, codec).await?.into_parts();
+        let (parts, body, extensions) = self.streaming(request, path, codec).await?.into_parts();
 
         futures_util::pin_mut!(body);
 
@@ -95,11 +173,12 @@ impl<T> Grpc<T> {
             .await?
             .ok_or_else(|| Status::new(Code::Internal, "Missing response message."))?;
 
+        let mut response = Response::from_parts(parts, message, extensions);
         if let Some(trailers) = body.trailers().await? {
-            parts.merge(trailers);
+            *response.trailers_mut() = trailers;
         }
 
-        Ok(Response::from_parts(parts, message))
+        Ok(response)
     }
 
     /// Send a server side streaming gRPC request.
@@ -134,22 +213,33 @@ impl<T> Grpc<T> {
         T::ResponseBody: Body + HttpBody + Send + 'static,
         <T::ResponseBody as HttpBody>::Data: Into<Bytes>,
         <T::ResponseBody as HttpBody>::Error: Into<crate::Error>,
-        S: Stream<Item = M1> + Send + Sync + 'static,
+        S: Stream<Item = M1> + Send + 'static,
         C: Codec<Encode = M1, Decode = M2>,
         M1: Send + Sync + 'static,
         M2: Send + Sync + 'static,
     {
-        let mut parts = Parts::default();
+        let mut parts = match &self.origin {
+            Some(origin) => origin.clone().into_parts(),
+            None => Parts::default(),
+        };
         parts.path_and_query = Some(path);
 
-        let uri = Uri::from_parts(parts).expect("path_and_query only is valid Uri");
+        let uri = Uri::from_parts(parts).expect("origin and path_and_query are a valid Uri");
+        let span = Span::current();
+        let max_encoding_message_size = self.max_encoding_message_size;
 
         let request = request
-            .map(|s| encode_client(codec.encoder(), s))
+            .map(|s| encode_client(codec.encoder(), s, span.clone(), max_encoding_message_size))
             .map(BoxBody::new);
 
         let mut request = request.into_http(uri);
 
+        if let Some(credentials) = &self.credentials {
+            if request.extensions().get::<Credentials>().is_none() {
+                request.extensions_mut().insert(credentials.clone());
+            }
+        }
+
         // Add the gRPC related HTTP headers
         request
             .headers_mut()
@@ -158,7 +248,7 @@ impl<T> Grpc<T> {
         // Set the content type
         request
             .headers_mut()
-            .insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+            .insert(CONTENT_TYPE, HeaderValue::from_static(C::CONTENT_TYPE));
 
         let response = self
             .inner
@@ -181,11 +271,22 @@ impl<T> Grpc<T> {
             true
         };
 
+        // compressed responses are always decoded, whether they were asked
+        // for via `grpc-accept-encoding` or not
+        let encoding = CompressionEncoding::from_encoding_header(response.headers(), true)?;
+
         let response = response.map(|body| {
             if expect_additional_trailers {
-                Streaming::new_response(codec.decoder(), body, status_code)
+                Streaming::new_response(
+                    codec.decoder(),
+                    body,
+                    status_code,
+                    span,
+                    encoding,
+                    self.max_decoding_message_size,
+                )
             } else {
-                Streaming::new_empty(codec.decoder(), body)
+                Streaming::new_empty(codec.decoder(), body, span)
             }
         });
 
@@ -197,6 +298,10 @@ impl<T: Clone> Clone for Grpc<T> {
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            max_decoding_message_size: self.max_decoding_message_size,
+            max_encoding_message_size: self.max_encoding_message_size,
+            credentials: self.credentials.clone(),
+            origin: self.origin.clone(),
         }
     }
 }
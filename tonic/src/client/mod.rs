@@ -7,8 +7,13 @@
 //! This client is generally used by some code generation tool to provide stubs
 //! for the gRPC service. Thusly, they are a bit cumbersome to use by hand.
 
+#[cfg(feature = "transport")]
+mod cache;
 mod grpc;
 mod service;
 
+#[cfg(feature = "transport")]
+#[cfg_attr(docsrs, doc(cfg(feature = "transport")))]
+pub use self::cache::{Cache, CacheBody, CacheLayer};
 pub use self::grpc::Grpc;
 pub use self::service::GrpcService;
@@ -0,0 +1,476 @@
+use crate::Status;
+#[cfg(feature = "transport")]
+use crate::{body::BytesBuf, Code};
+use bytes::BytesMut;
+#[cfg(feature = "transport")]
+use bytes::{Buf, BufMut, IntoBuf};
+#[cfg(feature = "transport")]
+use futures_util::ready;
+use http::{HeaderMap, HeaderValue};
+#[cfg(feature = "transport")]
+use http_body::Body;
+#[cfg(feature = "transport")]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tracing::trace;
+
+pub(crate) const ENCODING_HEADER: &str = "grpc-encoding";
+pub(crate) const ACCEPT_ENCODING_HEADER: &str = "grpc-accept-encoding";
+
+const IDENTITY: &str = "identity";
+const GZIP: &str = "gzip";
+
+/// The compression settings of one side of a call.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct CompressionConfig {
+    /// Compress outgoing messages with gzip.
+    ///
+    /// A server only does so if the client accepts gzip.
+    pub(crate) send_gzip: bool,
+    /// Accept gzip compressed incoming messages.
+    pub(crate) accept_gzip: bool,
+}
+
+impl CompressionConfig {
+    /// The value of the `grpc-accept-encoding` header listing the encodings
+    /// that are accepted.
+    pub(crate) fn accept_encoding_header(&self) -> HeaderValue {
+        if self.accept_gzip {
+            HeaderValue::from_static("gzip,identity")
+        } else {
+            HeaderValue::from_static(IDENTITY)
+        }
+    }
+}
+
+/// A compression encoding supported by tonic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CompressionEncoding {
+    Gzip,
+}
+
+impl CompressionEncoding {
+    /// Determine how the incoming messages are encoded from the
+    /// `grpc-encoding` header.
+    ///
+    /// Fails with an `UNIMPLEMENTED` status if the encoding is not accepted.
+    pub(crate) fn from_encoding_header(
+        headers: &HeaderMap,
+        accept_gzip: bool,
+    ) -> Result<Option<Self>, Status> {
+        let encoding = match headers.get(ENCODING_HEADER) {
+            Some(encoding) => encoding,
+            None => return Ok(None),
+        };
+
+        match encoding.as_bytes() {
+            b"identity" => Ok(None),
+            b"gzip" if accept_gzip => Ok(Some(CompressionEncoding::Gzip)),
+            _ => {
+                trace!(message = "unsupported message encoding", ?encoding);
+                Err(Status::unimplemented(format!(
+                    "Content is compressed with `{}` which isn't supported",
+                    String::from_utf8_lossy(encoding.as_bytes())
+                )))
+            }
+        }
+    }
+
+    /// Pick the encoding for outgoing messages from the encodings the peer
+    /// lists in its `grpc-accept-encoding` header.
+    pub(crate) fn from_accept_encoding_header(
+        headers: &HeaderMap,
+        send_gzip: bool,
+    ) -> Option<Self> {
+        if !send_gzip {
+            return None;
+        }
+
+        let accepted = headers.get(ACCEPT_ENCODING_HEADER)?.to_str().ok()?;
+
+        accepted
+            .split(',')
+            .map(str::trim)
+            .find(|encoding| *encoding == GZIP)
+            .map(|_| CompressionEncoding::Gzip)
+    }
+
+    pub(crate) fn into_header_value(self) -> HeaderValue {
+        match self {
+            CompressionEncoding::Gzip => HeaderValue::from_static(GZIP),
+        }
+    }
+}
+
+/// Compress `src` into `dst`.
+pub(crate) fn compress(encoding: CompressionEncoding, src: &[u8], dst: &mut BytesMut) {
+    match encoding {
+        CompressionEncoding::Gzip => gzip::compress(src, dst),
+    }
+}
+
+/// Decompress `src` into `dst`.
+///
+/// Fails with a `RESOURCE_EXHAUSTED` status if the decompressed message is
+/// larger than `max_size`.
+pub(crate) fn decompress(
+    encoding: CompressionEncoding,
+    src: &[u8],
+    dst: &mut BytesMut,
+    max_size: Option<usize>,
+) -> Result<(), Status> {
+    match encoding {
+        CompressionEncoding::Gzip => gzip::decompress(src, dst, max_size),
+    }
+}
+
+/// A body of gRPC frames that compresses the messages of `inner` that are not
+/// compressed yet.
+#[cfg(feature = "transport")]
+pub(crate) struct CompressBody<B> {
+    inner: B,
+    encoding: CompressionEncoding,
+    buf: BytesMut,
+}
+
+#[cfg(feature = "transport")]
+impl<B> CompressBody<B> {
+    pub(crate) fn new(inner: B, encoding: CompressionEncoding) -> Self {
+        Self {
+            inner,
+            encoding,
+            buf: BytesMut::new(),
+        }
+    }
+
+    /// Take the next complete frame out of the buffer, compressing it if
+    /// necessary.
+    fn next_frame(&mut self) -> Option<BytesBuf> {
+        if self.buf.len() < 5 {
+            return None;
+        }
+
+        let mut len = [0; 4];
+        len.copy_from_slice(&self.buf[1..5]);
+        let len = u32::from_be_bytes(len) as usize;
+
+        if self.buf.len() < len + 5 {
+            return None;
+        }
+
+        let frame = self.buf.split_to(len + 5);
+        if frame[0] != 0 {
+            return Some(frame.freeze().into_buf());
+        }
+
+        let mut compressed = BytesMut::new();
+        compress(self.encoding, &frame[5..], &mut compressed);
+
+        let mut buf = BytesMut::with_capacity(compressed.len() + 5);
+        buf.put_u8(1);
+        buf.put_u32_be(compressed.len() as u32);
+        buf.put(compressed);
+
+        trace!(len = buf.len() - 5, compressed = true, "encoded message");
+        Some(buf.freeze().into_buf())
+    }
+}
+
+#[cfg(feature = "transport")]
+impl<B> Body for CompressBody<B>
+where
+    B: Body<Data = BytesBuf, Error = Status> + Unpin,
+{
+    type Data = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.buf.is_empty() && self.inner.is_end_stream()
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        loop {
+            if let Some(frame) = self.next_frame() {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+
+            match ready!(Pin::new(&mut self.inner).poll_data(cx)) {
+                Some(Ok(data)) => {
+                    self.buf.reserve(data.remaining());
+                    self.buf.put(data);
+                }
+                Some(Err(status)) => return Poll::Ready(Some(Err(status))),
+                None if self.buf.is_empty() => return Poll::Ready(None),
+                None => {
+                    return Poll::Ready(Some(Err(Status::new(
+                        Code::Internal,
+                        "Unexpected EOF encoding stream.",
+                    ))))
+                }
+            }
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+}
+
+mod gzip {
+    use crate::{Code, Status};
+    use bytes::{BufMut, BytesMut};
+    use miniz_oxide::{
+        deflate::compress_to_vec,
+        inflate::{decompress_to_vec_with_limit, TINFLStatus},
+    };
+
+    const MAGIC: [u8; 2] = [0x1f, 0x8b];
+    const DEFLATE: u8 = 8;
+    const LEVEL: u8 = 6;
+
+    const FHCRC: u8 = 1 << 1;
+    const FEXTRA: u8 = 1 << 2;
+    const FNAME: u8 = 1 << 3;
+    const FCOMMENT: u8 = 1 << 4;
+
+    pub(super) fn compress(src: &[u8], dst: &mut BytesMut) {
+        let deflated = compress_to_vec(src, LEVEL);
+
+        dst.reserve(deflated.len() + 18);
+        // magic, method, flags, mtime, extra flags and an unknown OS
+        dst.put_slice(&MAGIC);
+        dst.put_slice(&[DEFLATE, 0, 0, 0, 0, 0, 0, 255]);
+        dst.put_slice(&deflated);
+        dst.put_u32_le(crc32(src));
+        dst.put_u32_le(src.len() as u32);
+    }
+
+    pub(super) fn decompress(
+        src: &[u8],
+        dst: &mut BytesMut,
+        max_size: Option<usize>,
+    ) -> Result<(), Status> {
+        let start = header_len(src).ok_or_else(|| invalid("invalid gzip header"))?;
+        if src.len() < start + 8 {
+            return Err(invalid("truncated gzip stream"));
+        }
+        let end = src.len() - 8;
+
+        let max_size = max_size.unwrap_or(usize::MAX);
+        let inflated = decompress_to_vec_with_limit(&src[start..end], max_size).map_err(|e| {
+            if e.status == TINFLStatus::HasMoreOutput {
+                Status::new(
+                    Code::ResourceExhausted,
+                    format!(
+                        "Error, decompressed message length too large: the limit is: {} bytes",
+                        max_size
+                    ),
+                )
+            } else {
+                invalid("invalid deflate stream")
+            }
+        })?;
+
+        let mut crc = [0; 4];
+        crc.copy_from_slice(&src[end..end + 4]);
+        let mut size = [0; 4];
+        size.copy_from_slice(&src[end + 4..]);
+
+        if u32::from_le_bytes(crc) != crc32(&inflated)
+            || u32::from_le_bytes(size) != inflated.len() as u32
+        {
+            return Err(invalid("gzip checksum mismatch"));
+        }
+
+        dst.extend_from_slice(&inflated);
+        Ok(())
+    }
+
+    /// The length of the gzip header at the start of `src`.
+    fn header_len(src: &[u8]) -> Option<usize> {
+        if src.len() < 10 || src[..2] != MAGIC || src[2] != DEFLATE {
+            return None;
+        }
+
+        let flags = src[3];
+        let mut len = 10;
+
+        if flags & FEXTRA != 0 {
+            let extra = *src.get(len)? as usize | (*src.get(len + 1)? as usize) << 8;
+            len += 2 + extra;
+        }
+        if flags & FNAME != 0 {
+            len += src.get(len..)?.iter().position(|b| *b == 0)? + 1;
+        }
+        if flags & FCOMMENT != 0 {
+            len += src.get(len..)?.iter().position(|b| *b == 0)? + 1;
+        }
+        if flags & FHCRC != 0 {
+            len += 2;
+        }
+
+        Some(len)
+    }
+
+    fn invalid(message: &str) -> Status {
+        Status::new(
+            Code::Internal,
+            format!("Failed to decompress message: {}", message),
+        )
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0, |crc, b| {
+            CRC32_TABLE[((crc ^ *b as u32) & 0xff) as usize] ^ (crc >> 8)
+        })
+    }
+
+    const CRC32_TABLE: [u32; 256] = crc32_table();
+
+    const fn crc32_table() -> [u32; 256] {
+        let mut table = [0; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 {
+                    0xedb8_8320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn roundtrip() {
+            let data = b"hello hello hello hello hello".repeat(100);
+
+            let mut compressed = BytesMut::new();
+            compress(&data, &mut compressed);
+            assert!(compressed.len() < data.len());
+
+            let mut decompressed = BytesMut::new();
+            decompress(&compressed, &mut decompressed, None).unwrap();
+            assert_eq!(&decompressed[..], &data[..]);
+        }
+
+        #[test]
+        fn limit() {
+            let data = vec![0u8; 1024];
+
+            let mut compressed = BytesMut::new();
+            compress(&data, &mut compressed);
+
+            let mut decompressed = BytesMut::new();
+            decompress(&compressed, &mut decompressed, Some(1024)).unwrap();
+
+            let err = decompress(&compressed, &mut BytesMut::new(), Some(1023)).unwrap_err();
+            assert_eq!(err.code(), Code::ResourceExhausted);
+        }
+
+        #[test]
+        fn check_value() {
+            assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        }
+
+        #[test]
+        fn corrupt() {
+            let mut compressed = BytesMut::new();
+            compress(b"hello", &mut compressed);
+            let last = compressed.len() - 5;
+            compressed[last] ^= 1;
+
+            let err = decompress(&compressed, &mut BytesMut::new(), None).unwrap_err();
+            assert_eq!(err.code(), Code::Internal);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "transport"))]
+mod tests {
+    use super::*;
+    use crate::body::BoxBody;
+    use futures_util::future::poll_fn;
+
+    /// A body that yields `data` in chunks of `chunk` bytes.
+    struct Chunked {
+        data: BytesMut,
+        chunk: usize,
+    }
+
+    impl Body for Chunked {
+        type Data = BytesBuf;
+        type Error = Status;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            if self.data.is_empty() {
+                return Poll::Ready(None);
+            }
+
+            let len = self.chunk.min(self.data.len());
+            Poll::Ready(Some(Ok(self.data.split_to(len).freeze().into_buf())))
+        }
+
+        fn poll_trailers(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(None))
+        }
+    }
+
+    #[tokio::test]
+    async fn compress_body_compresses_frames() {
+        let message = b"hello hello hello hello hello hello";
+
+        let mut data = BytesMut::with_capacity(message.len() + 5);
+        data.put_u8(0);
+        data.put_u32_be(message.len() as u32);
+        data.put_slice(message);
+
+        let inner = BoxBody::new(Chunked { data, chunk: 3 });
+        let mut body = CompressBody::new(inner, CompressionEncoding::Gzip);
+
+        let frame = poll_fn(|cx| Pin::new(&mut body).poll_data(cx))
+            .await
+            .unwrap()
+            .unwrap();
+        let frame = frame.bytes();
+        assert_eq!(frame[0], 1);
+
+        let mut decompressed = BytesMut::new();
+        decompress(
+            CompressionEncoding::Gzip,
+            &frame[5..],
+            &mut decompressed,
+            None,
+        )
+        .unwrap();
+        assert_eq!(&decompressed[..], &message[..]);
+
+        assert!(poll_fn(|cx| Pin::new(&mut body).poll_data(cx))
+            .await
+            .is_none());
+    }
+}
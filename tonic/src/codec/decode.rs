@@ -1,30 +1,52 @@
-use super::Decoder;
+use super::{
+    compression::{decompress, CompressionEncoding},
+    Decoder, SyncStream,
+};
 use crate::{body::BoxBody, metadata::MetadataMap, Code, Status};
-use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use bytes::{BufMut, Bytes, BytesMut};
 use futures_core::Stream;
 use futures_util::{future, ready};
 use http::StatusCode;
 use http_body::Body;
 use std::{
     fmt,
+    marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
 };
-use tracing::{debug, trace};
+use tracing::{debug, trace, Span};
 
 const BUFFER_SIZE: usize = 8 * 1024;
 
+type MessageStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
 /// Streaming requests and responses.
 ///
 /// This will wrap some inner [`Body`] and [`Decoder`] and provide an interface
 /// to fetch the message stream and trailing metadata
+///
+/// Messages are decoded as they are fetched, and data is only read from the
+/// body once no complete message is left to decode. Data that was not read
+/// yet holds on to the HTTP/2 flow-control window, so what a peer can send
+/// ahead of a slow reader is bounded by the stream and connection window
+/// sizes, which may hold many small messages, plus the message being read.
 pub struct Streaming<T> {
     decoder: Box<dyn Decoder<Item = T, Error = Status> + Send + Sync + 'static>,
     body: BoxBody,
     state: State,
     direction: Direction,
+    /// The data that was received but not decoded yet, when it is made up of
+    /// several data frames.
     buf: BytesMut,
+    /// The data that was received but not decoded yet, when it is the rest of
+    /// a single data frame. Only one of `buf` and `chunk` holds data.
+    chunk: Bytes,
+    decompress_buf: BytesMut,
+    encoding: Option<CompressionEncoding>,
+    max_message_size: Option<usize>,
     trailers: Option<MetadataMap>,
+    span: Span,
+    messages: Option<MessageStream<T>>,
 }
 
 impl<T> Unpin for Streaming<T> {}
@@ -43,37 +65,71 @@ enum Direction {
 }
 
 impl<T> Streaming<T> {
-    pub(crate) fn new_response<B, D>(decoder: D, body: B, status_code: StatusCode) -> Self
+    pub(crate) fn new_response<B, D>(
+        decoder: D,
+        body: B,
+        status_code: StatusCode,
+        span: Span,
+        encoding: Option<CompressionEncoding>,
+        max_message_size: Option<usize>,
+    ) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
         B::Error: Into<crate::Error>,
         D: Decoder<Item = T, Error = Status> + Send + Sync + 'static,
     {
-        Self::new(decoder, body, Direction::Response(status_code))
+        Self::new(
+            decoder,
+            body,
+            Direction::Response(status_code),
+            span,
+            encoding,
+            max_message_size,
+        )
     }
 
-    pub(crate) fn new_empty<B, D>(decoder: D, body: B) -> Self
+    pub(crate) fn new_empty<B, D>(decoder: D, body: B, span: Span) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
         B::Error: Into<crate::Error>,
         D: Decoder<Item = T, Error = Status> + Send + Sync + 'static,
     {
-        Self::new(decoder, body, Direction::EmptyResponse)
+        Self::new(decoder, body, Direction::EmptyResponse, span, None, None)
     }
 
-    pub(crate) fn new_request<B, D>(decoder: D, body: B) -> Self
+    pub(crate) fn new_request<B, D>(
+        decoder: D,
+        body: B,
+        span: Span,
+        encoding: Option<CompressionEncoding>,
+        max_message_size: Option<usize>,
+    ) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
         B::Error: Into<crate::Error>,
         D: Decoder<Item = T, Error = Status> + Send + Sync + 'static,
     {
-        Self::new(decoder, body, Direction::Request)
+        Self::new(
+            decoder,
+            body,
+            Direction::Request,
+            span,
+            encoding,
+            max_message_size,
+        )
     }
 
-    fn new<B, D>(decoder: D, body: B, direction: Direction) -> Self
+    fn new<B, D>(
+        decoder: D,
+        body: B,
+        direction: Direction,
+        span: Span,
+        encoding: Option<CompressionEncoding>,
+        max_message_size: Option<usize>,
+    ) -> Self
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes>,
@@ -86,11 +142,70 @@ impl<T> Streaming<T> {
             state: State::ReadHeader,
             direction,
             buf: BytesMut::with_capacity(BUFFER_SIZE),
+            chunk: Bytes::new(),
+            decompress_buf: BytesMut::new(),
+            encoding,
+            max_message_size,
             trailers: None,
+            span,
+            messages: None,
         }
     }
 }
 
+impl<T: 'static> Streaming<T> {
+    /// Create a stream that yields the messages of `stream` instead of
+    /// decoding them from a body.
+    ///
+    /// This is meant for tests, e.g. to call a server trait method that
+    /// takes a streaming request, or to return a streaming response from a
+    /// fake client. The stream has no trailers.
+    ///
+    /// ```rust
+    /// # use tonic::{Status, Streaming};
+    /// # use futures_util::stream;
+    /// # async fn dox() -> Result<(), Status> {
+    /// let mut messages = Streaming::from_stream(stream::iter(vec![Ok(1), Ok(2)]));
+    ///
+    /// assert_eq!(messages.message().await?, Some(1));
+    /// assert_eq!(messages.message().await?, Some(2));
+    /// assert_eq!(messages.message().await?, None);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, Status>> + Send + 'static,
+    {
+        Self {
+            decoder: Box::new(NoDecoder(PhantomData)),
+            body: BoxBody::empty(),
+            state: State::ReadHeader,
+            direction: Direction::Request,
+            buf: BytesMut::new(),
+            chunk: Bytes::new(),
+            decompress_buf: BytesMut::new(),
+            encoding: None,
+            max_message_size: None,
+            trailers: None,
+            span: Span::none(),
+            messages: Some(Box::pin(SyncStream::new(stream))),
+        }
+    }
+}
+
+/// The decoder of a `Streaming` whose messages are not decoded.
+struct NoDecoder<T>(PhantomData<fn() -> T>);
+
+impl<T> Decoder for NoDecoder<T> {
+    type Item = T;
+    type Error = Status;
+
+    fn decode(&mut self, _: Bytes) -> Result<T, Status> {
+        unreachable!("messages of a stream are not decoded")
+    }
+}
+
 impl<T> Streaming<T> {
     /// Fetch the next message from this stream.
     /// ```rust
@@ -153,21 +268,48 @@ impl<T> Streaming<T> {
         Ok(map.map(MetadataMap::from_headers))
     }
 
+    /// Append a data frame to the data that was not decoded yet.
+    fn push_chunk(&mut self, data: Bytes) {
+        // a frame that starts with a message is kept as it is, so that
+        // messages it holds in full are decoded without copying them
+        if self.buf.is_empty() && self.chunk.is_empty() {
+            self.chunk = data;
+            return;
+        }
+
+        // a message spans several frames, which are copied together
+        let chunk = std::mem::replace(&mut self.chunk, Bytes::new());
+        let len = chunk.len() + data.len();
+        if len > self.buf.remaining_mut() {
+            let amt = if len > BUFFER_SIZE { len } else { BUFFER_SIZE };
+
+            self.buf.reserve(amt);
+        }
+
+        self.buf.put(chunk);
+        self.buf.put(data);
+    }
+
     fn decode_chunk(&mut self) -> Result<Option<T>, Status> {
-        let mut buf = (&self.buf[..]).into_buf();
+        let data: &[u8] = if self.buf.is_empty() {
+            &self.chunk
+        } else {
+            &self.buf
+        };
 
         if let State::ReadHeader = self.state {
-            if buf.remaining() < 5 {
+            if data.len() < 5 {
                 return Ok(None);
             }
 
-            let is_compressed = match buf.get_u8() {
+            let is_compressed = match data[0] {
                 0 => false,
+                1 if self.encoding.is_some() => true,
                 1 => {
-                    trace!("message compressed, compression not supported yet");
+                    trace!("message compressed without a grpc-encoding");
                     return Err(Status::new(
-                        Code::Unimplemented,
-                        "Message compressed, compression not supported yet.".to_string(),
+                        Code::Internal,
+                        "Message compressed but no grpc-encoding was specified.".to_string(),
                     ));
                 }
                 f => {
@@ -178,7 +320,21 @@ impl<T> Streaming<T> {
                     ));
                 }
             };
-            let len = buf.get_u32_be() as usize;
+            let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+
+            // reject oversized messages before buffering them
+            if let Some(limit) = self.max_message_size {
+                if len > limit {
+                    trace!(len, limit, "message too large");
+                    return Err(Status::new(
+                        Code::ResourceExhausted,
+                        format!(
+                            "Error, decoded message length too large: found {} bytes, the limit is: {} bytes",
+                            len, limit
+                        ),
+                    ));
+                }
+            }
 
             self.state = State::ReadBody {
                 compression: is_compressed,
@@ -186,26 +342,41 @@ impl<T> Streaming<T> {
             }
         }
 
-        if let State::ReadBody { len, .. } = &self.state {
+        if let State::ReadBody { len, compression } = &self.state {
             // if we haven't read enough of the message then return and keep
             // reading
-            if buf.remaining() < *len || self.buf.len() < *len + 5 {
+            if data.len() < *len + 5 {
                 return Ok(None);
             }
 
-            // advance past the header
-            self.buf.advance(5);
+            // split the message off past the header
+            let message = if self.buf.is_empty() {
+                self.chunk.advance(5);
+                self.chunk.split_to(*len)
+            } else {
+                self.buf.advance(5);
+                self.buf.split_to(*len).freeze()
+            };
 
-            match self.decoder.decode(&mut self.buf) {
-                Ok(Some(msg)) => {
-                    self.state = State::ReadHeader;
-                    return Ok(Some(msg));
-                }
-                Ok(None) => return Ok(None),
-                Err(e) => {
-                    return Err(e);
+            let _enter = self.span.enter();
+            let message = match self.encoding {
+                Some(encoding) if *compression => {
+                    self.decompress_buf.clear();
+                    decompress(
+                        encoding,
+                        &message,
+                        &mut self.decompress_buf,
+                        self.max_message_size,
+                    )?;
+                    self.decompress_buf.take().freeze()
                 }
-            }
+                _ => message,
+            };
+
+            let msg = self.decoder.decode(message)?;
+            trace!(len, compressed = *compression, "decoded message");
+            self.state = State::ReadHeader;
+            return Ok(Some(msg));
         }
 
         Ok(None)
@@ -216,6 +387,10 @@ impl<T> Stream for Streaming<T> {
     type Item = Result<T, Status>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(messages) = &mut self.messages {
+            return messages.as_mut().poll_next(cx);
+        }
+
         loop {
             // FIXME: implement the ability to poll trailers when we _know_ that
             // the consumer of this stream will only poll for the first message.
@@ -238,21 +413,12 @@ impl<T> Stream for Streaming<T> {
             };
 
             if let Some(data) = chunk {
-                if data.remaining() > self.buf.remaining_mut() {
-                    let amt = if data.remaining() > BUFFER_SIZE {
-                        data.remaining()
-                    } else {
-                        BUFFER_SIZE
-                    };
-
-                    self.buf.reserve(amt);
-                }
-
-                self.buf.put(data);
+                let position = data.position() as usize;
+                let mut data = data.into_inner();
+                data.advance(position);
+                self.push_chunk(data);
             } else {
-                // FIXME: improve buf usage.
-                let buf1 = (&self.buf[..]).into_buf();
-                if buf1.has_remaining() {
+                if !self.buf.is_empty() || !self.chunk.is_empty() {
                     trace!("unexpected EOF decoding stream");
                     Err(Status::new(
                         Code::Internal,
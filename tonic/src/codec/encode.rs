@@ -1,3 +1,4 @@
+use super::compression::{compress, CompressionEncoding};
 use crate::{body::BytesBuf, Code, Status};
 use bytes::{BufMut, BytesMut, IntoBuf};
 use futures_core::{Stream, TryStream};
@@ -5,66 +6,121 @@ use futures_util::{ready, StreamExt, TryStreamExt};
 use http::HeaderMap;
 use http_body::Body;
 use pin_project::pin_project;
+use std::fmt;
 use std::pin::Pin;
+use std::sync::{Mutex, PoisonError};
 use std::task::{Context, Poll};
 use tokio_codec::Encoder;
+use tracing::{trace, Span};
 
 const BUFFER_SIZE: usize = 8 * 1024;
 
 pub(crate) fn encode_server<T, U>(
     encoder: T,
     source: U,
+    span: Span,
+    compression: Option<CompressionEncoding>,
+    max_message_size: Option<usize>,
 ) -> EncodeBody<impl Stream<Item = Result<BytesBuf, Status>>>
 where
     T: Encoder<Error = Status> + Send + Sync + 'static,
     T::Item: Send + Sync,
     U: Stream<Item = Result<T::Item, Status>> + Send + Sync + 'static,
 {
-    let stream = encode(encoder, source).into_stream();
+    let stream = encode(encoder, source, span, compression, max_message_size).into_stream();
     EncodeBody::new_server(stream)
 }
 
 pub(crate) fn encode_client<T, U>(
     encoder: T,
     source: U,
+    span: Span,
+    max_message_size: Option<usize>,
 ) -> EncodeBody<impl Stream<Item = Result<BytesBuf, Status>>>
 where
     T: Encoder<Error = Status> + Send + Sync + 'static,
     T::Item: Send + Sync,
-    U: Stream<Item = T::Item> + Send + Sync + 'static,
+    U: Stream<Item = T::Item> + Send + 'static,
 {
-    let stream = encode(encoder, source.map(|x| Ok(x))).into_stream();
+    let source = SyncStream::new(source).map(Ok);
+    let stream = encode(encoder, source, span, None, max_message_size).into_stream();
     EncodeBody::new_client(stream)
 }
 
-fn encode<T, U>(mut encoder: T, source: U) -> impl TryStream<Ok = BytesBuf, Error = Status>
+/// A stream that is `Sync` even if the stream it wraps is not.
+///
+/// Request and response bodies must be `Sync`, but the streams of messages
+/// they are encoded from, like those of `async` blocks holding a non-`Sync`
+/// value across an `.await`, often are not. Since the wrapped stream can only
+/// be polled through a mutable reference, it is never shared between threads.
+pub(crate) struct SyncStream<S> {
+    inner: Mutex<Pin<Box<S>>>,
+}
+
+impl<S> SyncStream<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        SyncStream {
+            inner: Mutex::new(Box::pin(inner)),
+        }
+    }
+}
+
+impl<S: Stream> Stream for SyncStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        // a mutable reference needs no locking, and a panic while polling
+        // leaves nothing inconsistent behind
+        let inner = self.inner.get_mut().unwrap_or_else(PoisonError::into_inner);
+        inner.as_mut().poll_next(cx)
+    }
+}
+
+impl<S> fmt::Debug for SyncStream<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SyncStream").finish()
+    }
+}
+
+/// Encode each message of `source` into its own gRPC frame.
+///
+/// The provided `span` is entered while each message is encoded so that
+/// serialization work is attributed to the request it belongs to. If
+/// `compression` is set, every message is compressed with it. A message
+/// larger than `max_message_size` before compression is not sent and fails
+/// the stream with a `RESOURCE_EXHAUSTED` status instead.
+fn encode<T, U>(
+    mut encoder: T,
+    source: U,
+    span: Span,
+    compression: Option<CompressionEncoding>,
+    max_message_size: Option<usize>,
+) -> impl TryStream<Ok = BytesBuf, Error = Status>
 where
     T: Encoder<Error = Status>,
     U: Stream<Item = Result<T::Item, Status>>,
 {
     async_stream::stream! {
         let mut buf = BytesMut::with_capacity(BUFFER_SIZE);
+        let mut uncompressed = BytesMut::new();
         futures_util::pin_mut!(source);
 
         loop {
             match source.next().await {
                 Some(Ok(item)) => {
-                    buf.reserve(5);
-                    unsafe {
-                        buf.advance_mut(5);
-                    }
-                    encoder.encode(item, &mut buf).map_err(drop).unwrap();
-
-                    // now that we know length, we can write the header
-                    let len = buf.len() - 5;
-                    assert!(len <= std::u32::MAX as usize);
-                    {
-                        let mut cursor = std::io::Cursor::new(&mut buf[..5]);
-                        cursor.put_u8(0); // byte must be 0, reserve doesn't auto-zero
-                        cursor.put_u32_be(len as u32);
-                    }
+                    // the guard must not be held across the yield below
+                    let encoded = {
+                        let _enter = span.enter();
+                        encode_item(&mut encoder, item, &mut buf, &mut uncompressed, compression, max_message_size)
+                    };
 
-                    yield Ok(buf.split_to(len + 5).freeze().into_buf());
+                    match encoded {
+                        Ok(len) => yield Ok(buf.split_to(len).freeze().into_buf()),
+                        Err(status) => {
+                            yield Err(status);
+                            break;
+                        }
+                    }
                 },
                 Some(Err(status)) => yield Err(status),
                 None => break,
@@ -73,6 +129,65 @@ where
     }
 }
 
+/// Encode a single message into a gRPC frame at the end of `buf`, returning
+/// the length of the whole frame.
+fn encode_item<T>(
+    encoder: &mut T,
+    item: T::Item,
+    buf: &mut BytesMut,
+    uncompressed: &mut BytesMut,
+    compression: Option<CompressionEncoding>,
+    max_message_size: Option<usize>,
+) -> Result<usize, Status>
+where
+    T: Encoder<Error = Status>,
+{
+    buf.reserve(5);
+    unsafe {
+        buf.advance_mut(5);
+    }
+
+    let message_len = match compression {
+        Some(encoding) => {
+            uncompressed.clear();
+            encoder.encode(item, uncompressed).map_err(drop).unwrap();
+            compress(encoding, uncompressed, buf);
+            uncompressed.len()
+        }
+        None => {
+            encoder.encode(item, buf).map_err(drop).unwrap();
+            buf.len() - 5
+        }
+    };
+
+    if let Some(limit) = max_message_size {
+        if message_len > limit {
+            trace!(len = message_len, limit, "message too large");
+            buf.clear();
+            return Err(Status::new(
+                Code::ResourceExhausted,
+                format!(
+                    "Error, encoded message length too large: found {} bytes, the limit is: {} bytes",
+                    message_len, limit
+                ),
+            ));
+        }
+    }
+
+    // now that we know length, we can write the header
+    let len = buf.len() - 5;
+    assert!(len <= u32::MAX as usize);
+    {
+        let mut cursor = std::io::Cursor::new(&mut buf[..5]);
+        // byte must be set, reserve doesn't auto-zero
+        cursor.put_u8(compression.is_some() as u8);
+        cursor.put_u32_be(len as u32);
+    }
+
+    trace!(len, compressed = compression.is_some(), "encoded message");
+    Ok(len + 5)
+}
+
 #[derive(Debug)]
 enum Role {
     Client,
@@ -85,6 +200,7 @@ pub(crate) struct EncodeBody<S> {
     #[pin]
     inner: S,
     error: Option<Status>,
+    trailers: Option<HeaderMap>,
     role: Role,
 }
 
@@ -96,6 +212,7 @@ where
         Self {
             inner,
             error: None,
+            trailers: None,
             role: Role::Client,
         }
     }
@@ -104,9 +221,16 @@ where
         Self {
             inner,
             error: None,
+            trailers: None,
             role: Role::Server,
         }
     }
+
+    /// Send `trailers` along with the status that ends a server response.
+    pub(crate) fn trailers(mut self, trailers: HeaderMap) -> Self {
+        self.trailers = Some(trailers);
+        self
+    }
 }
 
 impl<S> Body for EncodeBody<S>
@@ -152,7 +276,10 @@ where
                     Status::new(Code::Ok, "")
                 };
 
-                Poll::Ready(Ok(Some(status.to_header_map()?)))
+                let mut trailers = self_proj.trailers.take().unwrap_or_default();
+                trailers.extend(status.to_header_map()?);
+
+                Poll::Ready(Ok(Some(trailers)))
             }
         }
     }
@@ -3,25 +3,47 @@
 //! This module contains the generic `Codec` trait and a protobuf codec
 //! based on prost.
 
+mod compression;
 mod decode;
 mod encode;
 #[cfg(feature = "prost")]
 mod prost;
+mod raw;
+mod transform;
 
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "transport")]
+pub(crate) use self::compression::CompressBody;
+pub(crate) use self::compression::{
+    CompressionConfig, CompressionEncoding, ACCEPT_ENCODING_HEADER, ENCODING_HEADER,
+};
 pub use self::decode::Streaming;
-pub(crate) use self::encode::{encode_client, encode_server};
+pub(crate) use self::encode::{encode_client, encode_server, SyncStream};
 #[cfg(feature = "prost")]
 #[cfg_attr(docsrs, doc(cfg(feature = "prost")))]
 pub use self::prost::ProstCodec;
-pub use tokio_codec::{Decoder, Encoder};
+pub use self::raw::{BytesCodec, BytesDecoder, BytesEncoder};
+pub use self::transform::{Transform, TransformCodec, TransformDecoder};
+pub use tokio_codec::Encoder;
 
 use crate::Status;
+use bytes::Bytes;
 
 /// Trait that knows how to encode and decode gRPC messages.
 pub trait Codec: Default {
+    /// The content type of the messages, `application/grpc` optionally
+    /// followed by `+` and a subtype naming their format, e.g.
+    /// `application/grpc+json`.
+    ///
+    /// It is sent in the `content-type` header of requests and responses. A
+    /// server rejects requests with a different content type, see
+    /// [`server::Grpc`].
+    ///
+    /// [`server::Grpc`]: ../server/struct.Grpc.html
+    const CONTENT_TYPE: &'static str = "application/grpc";
+
     /// The encodable message.
     type Encode: Send + 'static;
     /// The decodable message.
@@ -37,3 +59,18 @@ pub trait Codec: Default {
     /// Fetch the decoder.
     fn decoder(&mut self) -> Self::Decoder;
 }
+
+/// Trait that knows how to decode a gRPC message.
+///
+/// The message is framed by tonic and handed to the decoder once it was
+/// received in full. When it arrived in a single HTTP/2 data frame, `buf`
+/// shares the memory of that frame instead of being copied out of it.
+pub trait Decoder {
+    /// The decoded message.
+    type Item;
+    /// The error returned if the message cannot be decoded.
+    type Error;
+
+    /// Decode a message from its payload.
+    fn decode(&mut self, buf: Bytes) -> Result<Self::Item, Self::Error>;
+}
@@ -1,6 +1,6 @@
 use super::{Codec, Decoder, Encoder};
 use crate::{Code, Status};
-use bytes::{BufMut, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use prost::Message;
 use std::marker::PhantomData;
 
@@ -64,10 +64,8 @@ impl<U: Message + Default> Decoder for ProstDecoder<U> {
     type Item = U;
     type Error = Status;
 
-    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        Message::decode(buf.take())
-            .map(Option::Some)
-            .map_err(from_decode_error)
+    fn decode(&mut self, buf: Bytes) -> Result<Self::Item, Self::Error> {
+        Message::decode(buf).map_err(from_decode_error)
     }
 }
 
@@ -0,0 +1,71 @@
+use super::{Codec, Decoder, Encoder};
+use crate::Status;
+use bytes::{BufMut, Bytes, BytesMut};
+
+/// A [`Codec`] that passes the payloads of messages through as [`Bytes`]
+/// without encoding or decoding them.
+///
+/// This is meant for proxies, which forward messages without knowing their
+/// types. Decoded payloads share the memory of the data frames they arrived
+/// in whenever a message did not span several frames.
+///
+/// ```
+/// # use tonic::codec::{BytesCodec, Codec, Decoder};
+/// # use bytes::Bytes;
+/// let mut decoder = BytesCodec::default().decoder();
+/// let payload = decoder.decode(Bytes::from_static(b"\x08\x01")).unwrap();
+///
+/// assert_eq!(&payload[..], b"\x08\x01");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct BytesCodec {
+    _p: (),
+}
+
+impl Codec for BytesCodec {
+    type Encode = Bytes;
+    type Decode = Bytes;
+
+    type Encoder = BytesEncoder;
+    type Decoder = BytesDecoder;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        BytesEncoder { _p: () }
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        BytesDecoder { _p: () }
+    }
+}
+
+/// A [`Encoder`] that writes payloads as they are.
+#[derive(Debug, Clone, Default)]
+pub struct BytesEncoder {
+    _p: (),
+}
+
+impl Encoder for BytesEncoder {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
+        buf.reserve(item.len());
+        buf.put(item);
+        Ok(())
+    }
+}
+
+/// A [`Decoder`] that returns payloads as they are.
+#[derive(Debug, Clone, Default)]
+pub struct BytesDecoder {
+    _p: (),
+}
+
+impl Decoder for BytesDecoder {
+    type Item = Bytes;
+    type Error = Status;
+
+    fn decode(&mut self, buf: Bytes) -> Result<Self::Item, Self::Error> {
+        Ok(buf)
+    }
+}
@@ -1,9 +1,9 @@
 use super::{
     encode_server,
     prost::{ProstDecoder, ProstEncoder},
-    Streaming,
+    BytesDecoder, CompressionEncoding, Streaming,
 };
-use crate::Status;
+use crate::{test_util::Recorder, Code, Status};
 use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
 use http_body::Body;
 use prost::Message;
@@ -12,6 +12,7 @@ use std::{
     pin::Pin,
     task::{Context, Poll},
 };
+use tracing::Span;
 
 #[derive(Clone, PartialEq, prost::Message)]
 struct Msg {
@@ -41,7 +42,7 @@ async fn decode() {
         count: 0,
     };
 
-    let mut stream = Streaming::new_request(decoder, body);
+    let mut stream = Streaming::new_request(decoder, body, Span::none(), None, None);
 
     let mut i = 0usize;
     while let Some(msg) = stream.message().await.unwrap() {
@@ -51,6 +52,60 @@ async fn decode() {
     assert_eq!(i, 1);
 }
 
+fn frame_payloads(payloads: &[Vec<u8>]) -> Bytes {
+    let mut buf = BytesMut::new();
+    for payload in payloads {
+        buf.reserve(payload.len() + 5);
+        buf.put_u8(0);
+        buf.put_u32_be(payload.len() as u32);
+        buf.put_slice(payload);
+    }
+    buf.freeze()
+}
+
+#[tokio::test]
+async fn decode_single_frame_without_copy() {
+    let payloads = vec![vec![1u8; 100], vec![2u8; 200]];
+    let data = frame_payloads(&payloads);
+    let frame = data.as_ptr() as usize..data.as_ptr() as usize + data.len();
+
+    let body = MockBody {
+        partial_len: data.len(),
+        data,
+        count: 0,
+    };
+    let decoder = BytesDecoder::default();
+    let mut stream = Streaming::new_request(decoder, body, Span::none(), None, None);
+
+    for payload in &payloads {
+        let msg = stream.message().await.unwrap().unwrap();
+        assert_eq!(&msg[..], &payload[..]);
+        assert!(frame.contains(&(msg.as_ptr() as usize)));
+    }
+    assert!(stream.message().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn decode_across_frames() {
+    let payloads = vec![vec![1u8; 100], vec![2u8; 200], vec![3u8; 50]];
+    let data = frame_payloads(&payloads);
+
+    // the first frame ends in the middle of the second message
+    let body = MockBody {
+        data,
+        partial_len: 150,
+        count: 0,
+    };
+    let decoder = BytesDecoder::default();
+    let mut stream = Streaming::new_request(decoder, body, Span::none(), None, None);
+
+    for payload in &payloads {
+        let msg = stream.message().await.unwrap().unwrap();
+        assert_eq!(&msg[..], &payload[..]);
+    }
+    assert!(stream.message().await.unwrap().is_none());
+}
+
 #[tokio::test]
 async fn encode() {
     let encoder = ProstEncoder::<Msg>::default();
@@ -61,13 +116,219 @@ async fn encode() {
     let messages = std::iter::repeat(Ok::<_, Status>(msg)).take(10000);
     let source = futures_util::stream::iter(messages);
 
-    let body = encode_server(encoder, source);
+    let body = encode_server(encoder, source, Span::none(), None, None);
+
+    futures_util::pin_mut!(body);
+
+    while let Some(r) = body.next().await {
+        r.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn gzip_roundtrip() {
+    let encoder = ProstEncoder::<Msg>::default();
+
+    let messages = (0..2).map(|_| {
+        Ok::<_, Status>(Msg {
+            data: vec![0u8; 10000],
+        })
+    });
+    let source = futures_util::stream::iter(messages);
+
+    let body = encode_server(
+        encoder,
+        source,
+        Span::none(),
+        Some(CompressionEncoding::Gzip),
+        None,
+    );
+    futures_util::pin_mut!(body);
+
+    let mut buf = BytesMut::new();
+    while let Some(frame) = body.next().await {
+        let frame = frame.unwrap();
+        // the compressed flag of each message is set
+        assert_eq!(frame.bytes()[0], 1);
+        buf.extend_from_slice(frame.bytes());
+    }
+    assert!(buf.len() < 10000);
+
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: 7,
+        count: 0,
+    };
+    let decoder = ProstDecoder::<Msg>::default();
+    let mut stream = Streaming::new_request(
+        decoder,
+        body,
+        Span::none(),
+        Some(CompressionEncoding::Gzip),
+        None,
+    );
+
+    let mut i = 0usize;
+    while let Some(msg) = stream.message().await.unwrap() {
+        assert_eq!(msg.data, vec![0u8; 10000]);
+        i += 1;
+    }
+    assert_eq!(i, 2);
+}
+
+#[tokio::test]
+async fn compressed_message_without_encoding() {
+    let mut buf = BytesMut::with_capacity(5);
+    buf.put_u8(1);
+    buf.put_u32_be(0);
+
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: 5,
+        count: 0,
+    };
+    let decoder = ProstDecoder::<Msg>::default();
+    let mut stream = Streaming::new_request(decoder, body, Span::none(), None, None);
+
+    let status = stream.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::Internal);
+}
+
+#[tokio::test]
+async fn decode_message_too_large() {
+    let msg = Msg {
+        data: vec![0u8; 1024],
+    };
+
+    let mut buf = BytesMut::with_capacity(msg.encoded_len() + 5);
+    buf.put_u8(0);
+    buf.put_u32_be(msg.encoded_len() as u32);
+    msg.encode(&mut buf).unwrap();
+
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: 5,
+        count: 0,
+    };
+    let decoder = ProstDecoder::<Msg>::default();
+    let mut stream = Streaming::new_request(decoder, body, Span::none(), None, Some(1024));
+
+    let status = stream.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn decompressed_message_too_large() {
+    let encoder = ProstEncoder::<Msg>::default();
+    let messages = vec![Ok::<_, Status>(Msg {
+        data: vec![0u8; 10000],
+    })];
+    let source = futures_util::stream::iter(messages);
+
+    let body = encode_server(
+        encoder,
+        source,
+        Span::none(),
+        Some(CompressionEncoding::Gzip),
+        None,
+    );
+    futures_util::pin_mut!(body);
 
+    let mut buf = BytesMut::new();
+    while let Some(frame) = body.next().await {
+        buf.extend_from_slice(frame.unwrap().bytes());
+    }
+    // the compressed message fits, only the decompressed one does not
+    assert!(buf.len() < 1024);
+
+    let body = MockBody {
+        data: buf.freeze(),
+        partial_len: 5,
+        count: 0,
+    };
+    let decoder = ProstDecoder::<Msg>::default();
+    let mut stream = Streaming::new_request(
+        decoder,
+        body,
+        Span::none(),
+        Some(CompressionEncoding::Gzip),
+        Some(1024),
+    );
+
+    let status = stream.message().await.unwrap_err();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn encode_message_too_large() {
+    let encoder = ProstEncoder::<Msg>::default();
+    let messages = vec![
+        Ok::<_, Status>(Msg { data: vec![0u8; 8] }),
+        Ok(Msg {
+            data: vec![0u8; 1024],
+        }),
+        Ok(Msg { data: vec![0u8; 8] }),
+    ];
+    let source = futures_util::stream::iter(messages);
+
+    let body = encode_server(encoder, source, Span::none(), None, Some(1024));
+    futures_util::pin_mut!(body);
+
+    body.next().await.unwrap().unwrap();
+    // no further messages are sent after the oversized one
+    assert!(body.next().await.is_none());
+
+    let trailers = futures_util::future::poll_fn(|cx| body.as_mut().poll_trailers(cx))
+        .await
+        .unwrap()
+        .unwrap();
+    let status = Status::from_header_map(&trailers).unwrap();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+}
+
+#[tokio::test]
+async fn streaming_from_stream() {
+    let messages = vec![Ok(Msg { data: vec![1] }), Err(Status::unimplemented(""))];
+    let mut stream = Streaming::from_stream(futures_util::stream::iter(messages));
+
+    assert_eq!(stream.message().await.unwrap().unwrap().data, vec![1]);
+    assert_eq!(
+        stream.message().await.unwrap_err().code(),
+        Code::Unimplemented
+    );
+    assert!(stream.message().await.unwrap().is_none());
+    assert!(stream.trailers().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn encode_enters_request_span() {
+    let recorder = Recorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let span = tracing::info_span!("request");
+
+    let encoder = ProstEncoder::<Msg>::default();
+    let messages = (0..3).map(|_| Ok::<_, Status>(Msg { data: vec![0u8; 8] }));
+    let source = futures_util::stream::iter(messages);
+
+    let body = encode_server(encoder, source, span, None, None);
     futures_util::pin_mut!(body);
 
+    // poll the body outside of the span, like the transport does
     while let Some(r) = body.next().await {
         r.unwrap();
     }
+
+    let encoded = recorder
+        .events()
+        .into_iter()
+        .filter(|event| event.target.ends_with("codec::encode"))
+        .collect::<Vec<_>>();
+
+    assert_eq!(encoded.len(), 3);
+    for event in encoded {
+        assert_eq!(event.span, Some("request"));
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,108 @@
+use super::{Codec, Decoder};
+use crate::Status;
+use bytes::Bytes;
+
+/// A typed transform applied to every decoded message.
+///
+/// The transform sees each message after it has been decoded and before it
+/// is handed to the handler, which makes it a good place for input
+/// normalization like trimming strings or filling in defaults. Returning an
+/// error fails the request with that status.
+///
+/// The transform is generic over the message type `M`, which is the
+/// [`Codec::Decode`] type of the codec it is applied to via
+/// [`TransformCodec`]. A single type may implement `Transform` for several
+/// message types to be shared across methods.
+///
+/// ```
+/// # use tonic::{codec::Transform, Status};
+/// #[derive(Debug, Default, Clone)]
+/// struct Trim;
+///
+/// impl Transform<String> for Trim {
+///     fn transform(&self, message: String) -> Result<String, Status> {
+///         Ok(message.trim().to_string())
+///     }
+/// }
+/// ```
+pub trait Transform<M>: Default + Clone + Send + Sync + 'static {
+    /// Transform a decoded message.
+    fn transform(&self, message: M) -> Result<M, Status>;
+}
+
+/// A [`Codec`] that passes every decoded message through a [`Transform`].
+///
+/// Encoding is left to the inner codec untouched.
+///
+/// ```
+/// # #[cfg(feature = "prost")]
+/// # fn dox() {
+/// # use tonic::{codec::{ProstCodec, Transform, TransformCodec}, Status};
+/// # #[derive(Debug, Default, Clone)]
+/// # struct Trim;
+/// # impl Transform<String> for Trim {
+/// #     fn transform(&self, message: String) -> Result<String, Status> {
+/// #         Ok(message.trim().to_string())
+/// #     }
+/// # }
+/// let codec = TransformCodec::new(ProstCodec::<String, String>::default(), Trim);
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct TransformCodec<C, F> {
+    inner: C,
+    transform: F,
+}
+
+impl<C, F> TransformCodec<C, F> {
+    /// Wrap `inner`, transforming its decoded messages with `transform`.
+    pub fn new(inner: C, transform: F) -> Self {
+        Self { inner, transform }
+    }
+}
+
+impl<C, F> Codec for TransformCodec<C, F>
+where
+    C: Codec,
+    F: Transform<C::Decode>,
+{
+    const CONTENT_TYPE: &'static str = C::CONTENT_TYPE;
+
+    type Encode = C::Encode;
+    type Decode = C::Decode;
+
+    type Encoder = C::Encoder;
+    type Decoder = TransformDecoder<C::Decoder, F>;
+
+    fn encoder(&mut self) -> Self::Encoder {
+        self.inner.encoder()
+    }
+
+    fn decoder(&mut self) -> Self::Decoder {
+        TransformDecoder {
+            inner: self.inner.decoder(),
+            transform: self.transform.clone(),
+        }
+    }
+}
+
+/// A [`Decoder`] that applies a [`Transform`] to the messages of its inner
+/// decoder.
+#[derive(Debug, Clone)]
+pub struct TransformDecoder<D, F> {
+    inner: D,
+    transform: F,
+}
+
+impl<D, F> Decoder for TransformDecoder<D, F>
+where
+    D: Decoder<Error = Status>,
+    F: Transform<D::Item>,
+{
+    type Item = D::Item;
+    type Error = Status;
+
+    fn decode(&mut self, buf: Bytes) -> Result<Self::Item, Self::Error> {
+        self.transform.transform(self.inner.decode(buf)?)
+    }
+}
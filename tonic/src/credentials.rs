@@ -0,0 +1,190 @@
+use crate::{
+    metadata::{Ascii, MetadataMap, MetadataValue},
+    Code, Status,
+};
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+/// The metadata produced by [`CallCredentials::request_metadata`].
+///
+/// [`CallCredentials::request_metadata`]: trait.CallCredentials.html#tymethod.request_metadata
+pub type RequestMetadata =
+    Pin<Box<dyn Future<Output = Result<MetadataMap, Status>> + Send + 'static>>;
+
+/// Credentials that authenticate every call, like the per-RPC credentials
+/// of grpc-go and grpc-java.
+///
+/// The credentials produce the metadata of each call asynchronously, e.g. a
+/// JWT or an OAuth2 access token that is fetched from a token endpoint or the
+/// GCE metadata server and refreshed when it is about to expire. They are
+/// set on a single request via [`Request::set_credentials`], or on every
+/// request of a client via [`Grpc::credentials`], and applied by a
+/// [`Channel`] right before the request is sent. If they fail, the call fails
+/// with their [`Status`].
+///
+/// Credentials are only sent over channels with TLS, unless they
+/// [allow insecure channels](#method.allow_insecure); other calls fail with
+/// an `UNAUTHENTICATED` status.
+///
+/// Closures taking the method of the call implement this trait:
+///
+/// ```
+/// # use tonic::{metadata::{MetadataMap, MetadataValue}, CallCredentials, Status};
+/// async fn fetch_token() -> Result<String, Status> {
+///     Ok("some-secret-token".to_string())
+/// }
+///
+/// let credentials = |_method: &str| async {
+///     let token = fetch_token().await?;
+///     let mut metadata = MetadataMap::new();
+///     metadata.insert(
+///         "authorization",
+///         MetadataValue::from_str(&format!("Bearer {}", token)).unwrap(),
+///     );
+///     Ok(metadata)
+/// };
+/// # fn check(_: impl CallCredentials) {}
+/// # check(credentials);
+/// ```
+///
+/// [`Request::set_credentials`]: struct.Request.html#method.set_credentials
+/// [`Grpc::credentials`]: client/struct.Grpc.html#method.credentials
+/// [`Channel`]: transport/struct.Channel.html
+/// [`Status`]: struct.Status.html
+pub trait CallCredentials: Send + Sync + 'static {
+    /// Produce the metadata to add to a call of `method`, the path of the
+    /// request, e.g. `/helloworld.Greeter/SayHello`.
+    fn request_metadata(&self, method: &str) -> RequestMetadata;
+
+    /// Whether the credentials may be sent over a channel without TLS.
+    ///
+    /// This is `false` by default.
+    fn allow_insecure(&self) -> bool {
+        false
+    }
+}
+
+impl<F, Fut> CallCredentials for F
+where
+    F: Fn(&str) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<MetadataMap, Status>> + Send + 'static,
+{
+    fn request_metadata(&self, method: &str) -> RequestMetadata {
+        Box::pin(self(method))
+    }
+}
+
+impl fmt::Debug for dyn CallCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallCredentials").finish()
+    }
+}
+
+/// [`CallCredentials`] that send a fixed OAuth2 access token in the
+/// `authorization` metadata.
+///
+/// ```
+/// # use tonic::{AccessToken, Request};
+/// let mut request = Request::new(());
+/// request.set_credentials(AccessToken::new("some-secret-token").unwrap());
+/// ```
+///
+/// [`CallCredentials`]: trait.CallCredentials.html
+#[derive(Clone)]
+pub struct AccessToken {
+    header: MetadataValue<Ascii>,
+}
+
+impl AccessToken {
+    /// Create credentials sending `Bearer <token>`.
+    ///
+    /// Fails if the token is not a valid metadata value.
+    pub fn new(token: &str) -> Result<Self, Status> {
+        let header = MetadataValue::from_str(&format!("Bearer {}", token))
+            .map_err(|_| Status::new(Code::InvalidArgument, "invalid access token"))?;
+        Ok(AccessToken { header })
+    }
+}
+
+impl CallCredentials for AccessToken {
+    fn request_metadata(&self, _method: &str) -> RequestMetadata {
+        let mut metadata = MetadataMap::new();
+        metadata.insert("authorization", self.header.clone());
+        Box::pin(futures_util::future::ok(metadata))
+    }
+}
+
+impl fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // the token is a secret
+        f.debug_struct("AccessToken").finish()
+    }
+}
+
+/// The credentials of a request, see [`Request::set_credentials`].
+///
+/// [`Request::set_credentials`]: struct.Request.html#method.set_credentials
+#[derive(Clone)]
+pub(crate) struct Credentials(pub(crate) Arc<dyn CallCredentials>);
+
+impl Credentials {
+    /// Add the metadata of the credentials to `request`, which is sent over a
+    /// channel with TLS if `secure` is true.
+    #[cfg_attr(not(feature = "transport"), allow(dead_code))]
+    pub(crate) async fn apply<T>(
+        self,
+        mut request: http::Request<T>,
+        secure: bool,
+    ) -> Result<http::Request<T>, Status> {
+        if !secure && !self.0.allow_insecure() {
+            return Err(Status::new(
+                Code::Unauthenticated,
+                "call credentials require a channel with TLS",
+            ));
+        }
+
+        let method = request.uri().path().to_string();
+        let metadata = self.0.request_metadata(&method).await?;
+        request
+            .headers_mut()
+            .extend(metadata.into_sanitized_headers());
+
+        Ok(request)
+    }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn applies_metadata() {
+        let credentials = Credentials(Arc::new(AccessToken::new("secret").unwrap()));
+
+        let req = http::Request::builder()
+            .uri("/test.Svc/Method")
+            .body(())
+            .unwrap();
+        let req = credentials.apply(req, true).await.unwrap();
+        assert_eq!(req.headers()["authorization"], "Bearer secret");
+    }
+
+    #[tokio::test]
+    async fn fails_with_status() {
+        let credentials = Credentials(Arc::new(|_method: &str| async {
+            Err(Status::new(Code::Unavailable, "token endpoint is down"))
+        }));
+
+        let status = credentials
+            .apply(http::Request::new(()), true)
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "token endpoint is down");
+    }
+}
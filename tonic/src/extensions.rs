@@ -0,0 +1,73 @@
+/// A type map of request or response scoped context.
+///
+/// Values are keyed by their type, so [`Extensions::get`] always returns the
+/// type it is asked for and there are no string keys that could collide or be
+/// mistyped. Use a dedicated type per kind of context, e.g. a newtype around a
+/// `String` rather than the `String` itself.
+///
+/// Extensions are local only: they are never sent to the peer. On the server
+/// they are taken from the extensions of the underlying `http::Request`, so
+/// values inserted by an interceptor or a tower layer are available to the
+/// handler. On the client they are moved into the `http::Request` handed to the
+/// channel, where tower layers can read them.
+///
+/// Responses work the same way in the other direction: a handler's response
+/// extensions are moved into the `http::Response` returned by the server, and
+/// on the client the extensions of the `http::Response` returned by the
+/// channel end up in the response.
+///
+/// ```
+/// # use tonic::Request;
+/// #[derive(Debug, PartialEq)]
+/// struct TenantId(u64);
+///
+/// let mut request = Request::new(());
+/// request.extensions_mut().insert(TenantId(7));
+///
+/// assert_eq!(request.extensions().get::<TenantId>(), Some(&TenantId(7)));
+/// assert_eq!(request.extensions().get::<u64>(), None);
+/// ```
+#[derive(Debug, Default)]
+pub struct Extensions {
+    inner: http::Extensions,
+}
+
+impl Extensions {
+    /// Create an empty `Extensions`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, returning the previous value of the same type if any.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, val: T) -> Option<T> {
+        self.inner.insert(val)
+    }
+
+    /// Get a reference to the value of type `T`.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.inner.get()
+    }
+
+    /// Get a mutable reference to the value of type `T`.
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.inner.get_mut()
+    }
+
+    /// Remove the value of type `T`, returning it if it was present.
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.inner.remove()
+    }
+
+    /// Remove all values.
+    pub fn clear(&mut self) {
+        self.inner.clear()
+    }
+
+    pub(crate) fn from_http(inner: http::Extensions) -> Self {
+        Extensions { inner }
+    }
+
+    pub(crate) fn into_http(self) -> http::Extensions {
+        self.inner
+    }
+}
@@ -0,0 +1,184 @@
+//! Application level heartbeats for streaming RPCs.
+//!
+//! HTTP/2 pings can be stripped or answered by intermediaries, so they do not
+//! prove that the peer application is still alive. These helpers instead
+//! exchange heartbeats as regular gRPC messages on a streaming RPC.
+//!
+//! There is no dedicated heartbeat type: a heartbeat is any message of the
+//! stream's own message type that both sides agree to treat as one, usually a
+//! variant of a `oneof` or a message with a flag set. The sender interleaves
+//! heartbeats into its outbound stream via [`with_heartbeats`] and the
+//! receiver drops them from the inbound stream via [`filter_heartbeats`], so
+//! handlers only ever see application messages.
+//!
+//! ```
+//! # use futures_util::stream;
+//! # use std::time::Duration;
+//! # use tonic::heartbeat::{filter_heartbeats, with_heartbeats};
+//! # #[derive(Debug, PartialEq)]
+//! # enum Message { Heartbeat, Data(u32) }
+//! # let outbound = stream::empty::<Message>();
+//! # let inbound = stream::empty::<Result<Message, tonic::Status>>();
+//! // send a heartbeat whenever the outbound stream was idle for 5 seconds
+//! let outbound = with_heartbeats(outbound, Duration::from_secs(5), || Message::Heartbeat);
+//!
+//! // and drop the heartbeats sent by the peer
+//! let inbound = filter_heartbeats(inbound, |message| *message == Message::Heartbeat);
+//! ```
+
+use futures_core::Stream;
+use pin_project::pin_project;
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::timer::{delay, Delay};
+
+/// Interleave heartbeat messages into `stream`.
+///
+/// A message created by `heartbeat` is yielded whenever `stream` has not
+/// yielded a message for `interval`. Heartbeats stop once `stream` ends.
+pub fn with_heartbeats<S, F>(stream: S, interval: Duration, heartbeat: F) -> Heartbeats<S, F>
+where
+    S: Stream,
+    F: FnMut() -> S::Item,
+{
+    Heartbeats {
+        stream,
+        heartbeat,
+        interval,
+        delay: delay(Instant::now() + interval),
+    }
+}
+
+/// Drop heartbeat messages from an inbound stream.
+///
+/// Every successfully decoded message for which `is_heartbeat` returns `true`
+/// is skipped, all other messages and errors are passed through.
+pub fn filter_heartbeats<S, T, E, P>(stream: S, is_heartbeat: P) -> FilterHeartbeats<S, P>
+where
+    S: Stream<Item = Result<T, E>>,
+    P: FnMut(&T) -> bool,
+{
+    FilterHeartbeats {
+        stream,
+        is_heartbeat,
+    }
+}
+
+/// Stream returned by [`with_heartbeats`].
+#[pin_project]
+pub struct Heartbeats<S, F> {
+    #[pin]
+    stream: S,
+    heartbeat: F,
+    interval: Duration,
+    delay: Delay,
+}
+
+impl<S, F> Stream for Heartbeats<S, F>
+where
+    S: Stream,
+    F: FnMut() -> S::Item,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let me = self.project();
+
+        if let Poll::Ready(item) = me.stream.poll_next(cx) {
+            me.delay.reset(Instant::now() + *me.interval);
+            return Poll::Ready(item);
+        }
+
+        match Pin::new(&mut *me.delay).poll(cx) {
+            Poll::Ready(()) => {
+                me.delay.reset(Instant::now() + *me.interval);
+                Poll::Ready(Some((me.heartbeat)()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<S, F> fmt::Debug for Heartbeats<S, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Heartbeats")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// Stream returned by [`filter_heartbeats`].
+#[pin_project]
+pub struct FilterHeartbeats<S, P> {
+    #[pin]
+    stream: S,
+    is_heartbeat: P,
+}
+
+impl<S, T, E, P> Stream for FilterHeartbeats<S, P>
+where
+    S: Stream<Item = Result<T, E>>,
+    P: FnMut(&T) -> bool,
+{
+    type Item = Result<T, E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut me = self.project();
+
+        loop {
+            match futures_util::ready!(me.stream.as_mut().poll_next(cx)) {
+                Some(Ok(message)) if (me.is_heartbeat)(&message) => continue,
+                item => return Poll::Ready(item),
+            }
+        }
+    }
+}
+
+impl<S, P> fmt::Debug for FilterHeartbeats<S, P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FilterHeartbeats").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Status;
+    use futures_util::{future, stream, StreamExt};
+    use tokio::timer::delay_for;
+
+    #[derive(Debug, PartialEq)]
+    enum Message {
+        Heartbeat,
+        Data(u32),
+    }
+
+    #[tokio::test]
+    async fn heartbeats_on_idle_stream() {
+        let (tx, rx) = futures_channel::mpsc::unbounded();
+        let outbound = with_heartbeats(rx, Duration::from_millis(10), || Message::Heartbeat);
+
+        let send = async move {
+            // stay idle for a few heartbeat intervals before sending any data
+            delay_for(Duration::from_millis(45)).await;
+            tx.unbounded_send(Message::Data(1)).unwrap();
+        };
+
+        let (sent, ()) = future::join(outbound.collect::<Vec<_>>(), send).await;
+        let heartbeats = sent.iter().filter(|m| **m == Message::Heartbeat).count();
+        assert!(heartbeats >= 2, "only {} heartbeats were sent", heartbeats);
+        assert_eq!(sent.last(), Some(&Message::Data(1)));
+
+        let inbound = stream::iter(sent.into_iter().map(Ok::<_, Status>));
+        let received = filter_heartbeats(inbound, |m| *m == Message::Heartbeat)
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(received, vec![Message::Data(1)]);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::{Request, Status};
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+type InterceptorFn =
+    Arc<dyn Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static>;
+type AsyncInterceptorFn =
+    Arc<dyn Fn(Request<()>) -> BoxFuture<Result<Request<()>, Status>> + Send + Sync + 'static>;
+
+/// Represents a gRPC interceptor.
+///
+/// An interceptor is called with the metadata and extensions of each request
+/// before it is sent by a client or handled by a server. It can inspect and
+/// modify them, e.g. to attach an `authorization` token or a request id, or
+/// reject the request by returning a [`Status`], which then becomes the
+/// result of the call without the request going any further.
+///
+/// The message of the request is not available to an interceptor, it is
+/// always `()`.
+///
+/// ```
+/// # use tonic::{metadata::MetadataValue, Code, Interceptor, Request, Status};
+/// let interceptor = Interceptor::new(|mut req: Request<()>| {
+///     match req.metadata().get("authorization") {
+///         Some(token) if token == "Bearer secret" => {}
+///         _ => return Err(Status::new(Code::Unauthenticated, "invalid token")),
+///     }
+///
+///     req.metadata_mut()
+///         .insert("x-authenticated", MetadataValue::from_static("true"));
+///     Ok(req)
+/// });
+/// ```
+///
+/// [`Status`]: struct.Status.html
+#[derive(Clone)]
+pub struct Interceptor {
+    f: InterceptorFn,
+}
+
+impl Interceptor {
+    /// Create a new `Interceptor` from the provided function.
+    pub fn new(
+        f: impl Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+    ) -> Self {
+        Interceptor { f: Arc::new(f) }
+    }
+
+    /// Run the interceptor on the metadata and extensions of `req`.
+    #[cfg_attr(not(feature = "transport"), allow(dead_code))]
+    pub(crate) fn call<T>(&self, req: http::Request<T>) -> Result<http::Request<T>, Status> {
+        let (mut parts, body) = req.into_parts();
+
+        let headers = std::mem::replace(&mut parts.headers, http::HeaderMap::new());
+        let extensions = std::mem::replace(&mut parts.extensions, http::Extensions::new());
+        let request = Request::from_parts(headers, extensions);
+
+        let (headers, extensions) = (self.f)(request)?.into_parts();
+        parts.headers = headers;
+        parts.extensions = extensions;
+
+        Ok(http::Request::from_parts(parts, body))
+    }
+}
+
+impl<F> From<F> for Interceptor
+where
+    F: Fn(Request<()>) -> Result<Request<()>, Status> + Send + Sync + 'static,
+{
+    fn from(f: F) -> Self {
+        Interceptor::new(f)
+    }
+}
+
+impl fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interceptor").finish()
+    }
+}
+
+/// Represents an asynchronous gRPC interceptor.
+///
+/// This is the asynchronous counterpart of [`Interceptor`] for clients: the
+/// returned future is awaited before the request is sent, so it can do I/O
+/// first, e.g. fetch or refresh an OAuth2 token to put into the
+/// `authorization` metadata.
+///
+/// ```
+/// # use tonic::{metadata::MetadataValue, AsyncInterceptor, Code, Request, Status};
+/// async fn fetch_token() -> Result<String, Status> {
+///     Ok("Bearer some-secret-token".to_string())
+/// }
+///
+/// let interceptor = AsyncInterceptor::new(|mut req: Request<()>| async move {
+///     let token = fetch_token().await?;
+///     let token = MetadataValue::from_str(&token)
+///         .map_err(|_| Status::new(Code::Unauthenticated, "invalid token"))?;
+///     req.metadata_mut().insert("authorization", token);
+///     Ok(req)
+/// });
+/// ```
+///
+/// [`Interceptor`]: struct.Interceptor.html
+#[derive(Clone)]
+pub struct AsyncInterceptor {
+    f: AsyncInterceptorFn,
+}
+
+impl AsyncInterceptor {
+    /// Create a new `AsyncInterceptor` from the provided function.
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Request<()>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Request<()>, Status>> + Send + 'static,
+    {
+        let f = move |req| Box::pin(f(req)) as BoxFuture<_>;
+        AsyncInterceptor { f: Arc::new(f) }
+    }
+
+    /// Run the interceptor on the metadata and extensions of `req`.
+    #[cfg_attr(not(feature = "transport"), allow(dead_code))]
+    pub(crate) fn call<T>(
+        &self,
+        req: http::Request<T>,
+    ) -> impl Future<Output = Result<http::Request<T>, Status>> {
+        let (mut parts, body) = req.into_parts();
+
+        let headers = std::mem::replace(&mut parts.headers, http::HeaderMap::new());
+        let extensions = std::mem::replace(&mut parts.extensions, http::Extensions::new());
+        let intercepted = (self.f)(Request::from_parts(headers, extensions));
+
+        async move {
+            let (headers, extensions) = intercepted.await?.into_parts();
+            parts.headers = headers;
+            parts.extensions = extensions;
+
+            Ok(http::Request::from_parts(parts, body))
+        }
+    }
+}
+
+impl fmt::Debug for AsyncInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AsyncInterceptor").finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{metadata::MetadataValue, Code};
+
+    #[test]
+    fn modifies_request() {
+        let interceptor = Interceptor::new(|mut req: Request<()>| {
+            assert_eq!(req.extensions().get::<u32>(), Some(&7));
+            req.metadata_mut()
+                .insert("x-request-id", MetadataValue::from_static("abc"));
+            req.extensions_mut().insert("tenant");
+            Ok(req)
+        });
+
+        let mut req = http::Request::builder()
+            .method("POST")
+            .uri("/test.Svc/Method")
+            .header("te", "trailers")
+            .body("body")
+            .unwrap();
+        req.extensions_mut().insert(7u32);
+
+        let req = interceptor.call(req).unwrap();
+
+        assert_eq!(req.method(), http::Method::POST);
+        assert_eq!(req.uri(), "/test.Svc/Method");
+        assert_eq!(req.headers()["te"], "trailers");
+        assert_eq!(req.headers()["x-request-id"], "abc");
+        assert_eq!(req.extensions().get::<&str>(), Some(&"tenant"));
+        assert_eq!(*req.body(), "body");
+    }
+
+    #[test]
+    fn rejects_request() {
+        let interceptor =
+            Interceptor::new(|_| Err(Status::new(Code::PermissionDenied, "not allowed")));
+
+        let status = interceptor.call(http::Request::new(())).unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "not allowed");
+    }
+
+    #[tokio::test]
+    async fn async_modifies_request() {
+        let interceptor = AsyncInterceptor::new(|mut req: Request<()>| async move {
+            tokio::timer::delay_for(std::time::Duration::from_millis(1)).await;
+            req.metadata_mut()
+                .insert("authorization", MetadataValue::from_static("Bearer token"));
+            Ok(req)
+        });
+
+        let req = http::Request::builder()
+            .uri("/test.Svc/Method")
+            .body("body")
+            .unwrap();
+        let req = interceptor.call(req).await.unwrap();
+
+        assert_eq!(req.uri(), "/test.Svc/Method");
+        assert_eq!(req.headers()["authorization"], "Bearer token");
+        assert_eq!(*req.body(), "body");
+    }
+}
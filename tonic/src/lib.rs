@@ -17,13 +17,25 @@
 //! # Feature Flags
 //!
 //! - `transport`: Enables the fully featured, batteries included client and server
-//! implementation based on [`hyper`], [`tower`] and [`tokio`]. Enabled by default.
+//!   implementation based on [`hyper`], [`tower`] and [`tokio`]. Enabled by default.
+//!   Without it, the codecs, statuses and the generic [`client`] and [`server`] remain
+//!   available and depend on neither a runtime nor sockets, so generated clients can
+//!   send requests through any other service, e.g. in a browser on `wasm32`.
 //! - `codegen`: Enables all the required exports and optional dependencies required
-//! for [`tonic-build`]. Enabled by default.
+//!   for [`tonic-build`]. Enabled by default.
 //! - `openssl`: Enables the `openssl` based tls options for the `transport` feature`. Not
-//! enabled by default.
+//!   enabled by default.
 //! - `rustls`: Enables the `ruslts` based tls options for the `transport` feature`. Not
-//! enabled by default.
+//!   enabled by default.
+//! - `tls-roots`: Enables trusting the platform's native root certificates with `rustls`,
+//!   via [`ClientTlsConfig::native_roots`]. Implies `rustls`. Not enabled by default.
+//! - `tls-webpki-roots`: Enables trusting the bundled Mozilla root certificates of
+//!   `webpki-roots` with `rustls`, via [`ClientTlsConfig::webpki_roots`]. Implies `rustls`.
+//!   Not enabled by default.
+//! - `trace-frames`: Emits a `TRACE` level event for every HTTP/2 frame read or written
+//!   by the `transport` client and server, for debugging interop issues. Not enabled by default.
+//! - `grpc-web`: Enables accepting [grpc-web] requests from browsers in the `transport`
+//!   server. Not enabled by default.
 //! - `prost`: Enables the [`prost`] based gRPC [`Codec`] implementation.
 //!
 //! # Structure
@@ -46,6 +58,7 @@
 //! between [`rustls`] and [`openssl`] for the tls backend.
 //!
 //! [gRPC]: https://grpc.io
+//! [grpc-web]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
 //! [`tonic`]: https://github.com/hyperium/tonic
 //! [`tokio`]: https://docs.rs/tokio
 //! [`prost`]: https://docs.rs/prost
@@ -57,8 +70,11 @@
 //! [`Channel`]: transport/struct.Channel.html
 //! [`Server`]: transport/struct.Server.html
 //! [`rustls`]: https://docs.rs/rustls
+//! [`ClientTlsConfig::native_roots`]: transport/struct.ClientTlsConfig.html#method.native_roots
+//! [`ClientTlsConfig::webpki_roots`]: transport/struct.ClientTlsConfig.html#method.webpki_roots
 //! [`openssl`]: https://www.openssl.org
 //! [`client`]: client/index.html
+//! [`server`]: server/index.html
 //! [`transport`]: transport/index.html
 
 #![recursion_limit = "256"]
@@ -79,6 +95,9 @@
 pub mod body;
 pub mod client;
 pub mod codec;
+#[cfg(feature = "transport")]
+#[cfg_attr(docsrs, doc(cfg(feature = "transport")))]
+pub mod heartbeat;
 pub mod metadata;
 pub mod server;
 
@@ -86,10 +105,15 @@ pub mod server;
 #[cfg_attr(docsrs, doc(cfg(feature = "transport")))]
 pub mod transport;
 
+mod credentials;
+mod extensions;
+mod interceptor;
 mod macros;
 mod request;
 mod response;
 mod status;
+#[cfg(test)]
+mod test_util;
 
 /// A re-export of [`async-trait`](https://docs.rs/async-trait) for use with codegen.
 #[cfg(feature = "codegen")]
@@ -98,8 +122,11 @@ pub use async_trait::async_trait;
 
 #[doc(inline)]
 pub use codec::Streaming;
-pub use request::{IntoRequest, IntoStreamingRequest, Request};
-pub use response::Response;
+pub use credentials::{AccessToken, CallCredentials, RequestMetadata};
+pub use extensions::Extensions;
+pub use interceptor::{AsyncInterceptor, Interceptor};
+pub use request::{IntoRequest, IntoStreamingRequest, Request, RequestStream};
+pub use response::{Response, ResponseStream};
 pub use status::{Code, Status};
 
 pub(crate) type Error = Box<dyn std::error::Error + Send + Sync>;
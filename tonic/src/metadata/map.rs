@@ -197,6 +197,18 @@ pub struct OccupiedEntry<'a, VE: ValueEncoding> {
 // ===== impl MetadataMap =====
 
 impl MetadataMap {
+    // Headers that are set by the gRPC protocol itself and must not be
+    // provided through custom metadata.
+    const GRPC_RESERVED_HEADERS: [&'static str; 7] = [
+        "te",
+        "content-type",
+        "grpc-encoding",
+        "grpc-message",
+        "grpc-message-type",
+        "grpc-status",
+        "grpc-status-details-bin",
+    ];
+
     /// Create an empty `MetadataMap`.
     ///
     /// The map will be created without any capacity. This function will not
@@ -237,6 +249,18 @@ impl MetadataMap {
         self.headers
     }
 
+    /// Convert this `MetadataMap` into the headers of an outgoing request or
+    /// response, dropping the headers reserved by the gRPC protocol.
+    ///
+    /// These are set by tonic itself, a value coming from user metadata (e.g.
+    /// metadata copied from an incoming request) would otherwise clobber them.
+    pub(crate) fn into_sanitized_headers(mut self) -> http::HeaderMap {
+        for header in &Self::GRPC_RESERVED_HEADERS {
+            self.headers.remove(*header);
+        }
+        self.headers
+    }
+
     /// Create an empty `MetadataMap` with the specified capacity.
     ///
     /// The returned map will allocate internal storage in order to hold about
@@ -2488,6 +2512,20 @@ mod tests {
         assert_eq!(map.get("x-host").unwrap(), "example.com");
     }
 
+    #[test]
+    fn test_into_sanitized_headers_drops_reserved_headers() {
+        let mut map = MetadataMap::new();
+        map.insert("x-host", "example.com".parse().unwrap());
+        map.insert("content-type", "text/plain".parse().unwrap());
+        map.insert("grpc-status", "0".parse().unwrap());
+        map.insert_bin("grpc-status-details-bin", MetadataValue::from_bytes(b"x"));
+
+        let http_map = map.into_sanitized_headers();
+
+        assert_eq!(http_map.len(), 1);
+        assert_eq!(http_map.get("x-host").unwrap(), "example.com");
+    }
+
     #[test]
     fn test_to_headers_encoding() {
         use crate::Code;
@@ -1,11 +1,73 @@
-use crate::metadata::MetadataMap;
+#[cfg(feature = "transport")]
+use crate::transport::{CancellationToken, Certificate, ConnectionInfo};
+use crate::{
+    credentials::Credentials,
+    metadata::{MetadataMap, MetadataValue},
+    CallCredentials, Extensions,
+};
 use futures_core::Stream;
+use futures_util::stream;
+#[cfg(feature = "transport")]
+use std::net::SocketAddr;
+use std::{
+    fmt,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Marks a request to wait for the channel to become ready, see
+/// [`Request::set_wait_for_ready`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct WaitForReady;
+
+/// A boxed stream of request messages.
+///
+/// The client traits that `tonic-build` can generate take streaming requests
+/// of this type, since their methods cannot be generic over the stream.
+///
+/// ```rust
+/// # use tonic::{Request, RequestStream};
+/// # use futures_util::stream;
+/// # pub struct Point {}
+/// let request = Request::new(RequestStream::new(stream::iter(vec![Point {}, Point {}])));
+/// ```
+pub struct RequestStream<T> {
+    inner: Pin<Box<dyn Stream<Item = T> + Send + 'static>>,
+}
+
+impl<T> RequestStream<T> {
+    /// Box the stream of messages `stream`.
+    pub fn new(stream: impl Stream<Item = T> + Send + 'static) -> Self {
+        RequestStream {
+            inner: Box::pin(stream),
+        }
+    }
+}
+
+impl<T> Stream for RequestStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.inner.as_mut().poll_next(cx)
+    }
+}
+
+impl<T> fmt::Debug for RequestStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestStream").finish()
+    }
+}
 
 /// A gRPC request and metadata from an RPC call.
 #[derive(Debug)]
 pub struct Request<T> {
     metadata: MetadataMap,
     message: T,
+    extensions: Extensions,
 }
 
 /// Trait implemented by RPC request types.
@@ -73,11 +135,16 @@ pub trait IntoRequest<T>: sealed::Sealed {
 /// let messages = vec![Point {}, Point {}];
 ///
 /// client.record_route(Request::new(stream::iter(messages.clone())));
+/// client.record_route(Request::from_messages(messages.clone()));
 /// client.record_route(stream::iter(messages));
 /// ```
+///
+/// The stream only needs to be `Send`, so it may be an `async` stream that
+/// holds non-`Sync` values, or the receiving half of a channel fed by another
+/// task.
 pub trait IntoStreamingRequest: sealed::Sealed {
     /// The RPC request stream type
-    type Stream: Stream<Item = Self::Message> + Send + Sync + 'static;
+    type Stream: Stream<Item = Self::Message> + Send + 'static;
 
     /// The RPC request type
     type Message;
@@ -102,6 +169,7 @@ impl<T> Request<T> {
         Request {
             metadata: MetadataMap::new(),
             message,
+            extensions: Extensions::new(),
         }
     }
 
@@ -121,10 +189,142 @@ impl<T> Request<T> {
     }
 
     /// Get a mutable reference to the request metadata.
+    ///
+    /// Headers reserved by the gRPC protocol, like `content-type` or
+    /// `grpc-status`, are not sent even if they are set here.
     pub fn metadata_mut(&mut self) -> &mut MetadataMap {
         &mut self.metadata
     }
 
+    /// Get a reference to the request extensions.
+    ///
+    /// See [`Extensions`] for what is stored there and how it is propagated.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Get a mutable reference to the request extensions.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// The address of the peer that sent this request.
+    ///
+    /// This is set for requests received by a tonic [`Server`], from the
+    /// [`ConnectionInfo`] of their connection. It is `None` for connections
+    /// without an address, e.g. over a Unix domain socket, and for requests
+    /// that were not received by a server.
+    ///
+    /// ```rust
+    /// # use tonic::{Code, Request, Status};
+    /// fn audit(request: &Request<()>) -> Result<(), Status> {
+    ///     let peer = request
+    ///         .remote_addr()
+    ///         .ok_or_else(|| Status::new(Code::PermissionDenied, "unknown peer"))?;
+    ///     println!("request from {}", peer.ip());
+    ///     Ok(())
+    /// }
+    /// ```
+    ///
+    /// [`Server`]: transport/struct.Server.html
+    /// [`ConnectionInfo`]: transport/struct.ConnectionInfo.html
+    #[cfg(feature = "transport")]
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.extensions
+            .get::<ConnectionInfo>()
+            .and_then(ConnectionInfo::remote_addr)
+    }
+
+    /// The certificate chain presented by the peer that sent this request,
+    /// if it was received by a tonic [`Server`] over TLS with client
+    /// authentication.
+    ///
+    /// The first certificate is the peer's own certificate.
+    ///
+    /// [`Server`]: transport/struct.Server.html
+    #[cfg(feature = "transport")]
+    pub fn peer_certificates(&self) -> Option<&[Certificate]> {
+        self.extensions
+            .get::<ConnectionInfo>()
+            .and_then(ConnectionInfo::peer_certificates)
+    }
+
+    /// The token that signals when this request is cancelled, see
+    /// [`CancellationToken`].
+    ///
+    /// This is set for requests received by a tonic [`Server`], and `None`
+    /// for requests that were not.
+    ///
+    /// [`CancellationToken`]: transport/struct.CancellationToken.html
+    /// [`Server`]: transport/struct.Server.html
+    #[cfg(feature = "transport")]
+    pub fn cancellation_token(&self) -> Option<CancellationToken> {
+        self.extensions.get::<CancellationToken>().cloned()
+    }
+
+    /// Set the deadline of this request, sent to the server in the
+    /// `grpc-timeout` header.
+    ///
+    /// A tonic server cancels the request once `deadline` has elapsed after
+    /// receiving it, and responds with a `DEADLINE_EXCEEDED` status. This is
+    /// independent of [`Endpoint::timeout`], which applies to every request
+    /// of a channel and is only enforced by the client.
+    ///
+    /// ```rust
+    /// # use tonic::Request;
+    /// # use std::time::Duration;
+    /// let mut request = Request::new(());
+    /// request.set_timeout(Duration::from_secs(30));
+    ///
+    /// assert_eq!(request.metadata().get("grpc-timeout").unwrap(), "30000000u");
+    /// ```
+    ///
+    /// [`Endpoint::timeout`]: transport/struct.Endpoint.html#method.timeout
+    pub fn set_timeout(&mut self, deadline: Duration) {
+        let value = MetadataValue::from_str(&duration_to_grpc_timeout(deadline)).unwrap();
+        self.metadata_mut().insert(GRPC_TIMEOUT_HEADER, value);
+    }
+
+    /// Set whether this request waits for the channel to become ready.
+    ///
+    /// By default, a request issued while a [`Channel`] is backing off before
+    /// reconnecting fails right away with `UNAVAILABLE`. A request that waits
+    /// for ready is queued instead and sent once the channel has reconnected,
+    /// or fails with `DEADLINE_EXCEEDED` if its [timeout] elapses first.
+    ///
+    /// ```rust
+    /// # use tonic::Request;
+    /// let mut request = Request::new(());
+    /// request.set_wait_for_ready(true);
+    /// ```
+    ///
+    /// [`Channel`]: transport/struct.Channel.html
+    /// [timeout]: #method.set_timeout
+    pub fn set_wait_for_ready(&mut self, enabled: bool) {
+        if enabled {
+            self.extensions.insert(WaitForReady);
+        } else {
+            self.extensions.remove::<WaitForReady>();
+        }
+    }
+
+    /// Authenticate this request with `credentials`, overriding the
+    /// credentials of the client.
+    ///
+    /// The credentials are applied by a [`Channel`], see [`CallCredentials`].
+    ///
+    /// ```rust
+    /// # use tonic::{AccessToken, Request};
+    /// let mut request = Request::new(());
+    /// request.set_credentials(AccessToken::new("some-secret-token").unwrap());
+    /// ```
+    ///
+    /// [`Channel`]: transport/struct.Channel.html
+    /// [`CallCredentials`]: trait.CallCredentials.html
+    pub fn set_credentials(&mut self, credentials: impl CallCredentials) {
+        self.extensions.insert(Credentials(Arc::new(credentials)));
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         self.message
@@ -134,6 +334,7 @@ impl<T> Request<T> {
         Request {
             metadata: MetadataMap::from_headers(parts.headers),
             message,
+            extensions: Extensions::from_http(parts.extensions),
         }
     }
 
@@ -149,11 +350,19 @@ impl<T> Request<T> {
         *request.version_mut() = http::Version::HTTP_2;
         *request.method_mut() = http::Method::POST;
         *request.uri_mut() = uri;
-        *request.headers_mut() = self.metadata.into_headers();
+        *request.headers_mut() = self.metadata.into_sanitized_headers();
+        *request.extensions_mut() = self.extensions.into_http();
 
         request
     }
 
+    /// Split the request into the headers of its metadata and its extensions,
+    /// dropping the message.
+    #[cfg_attr(not(feature = "transport"), allow(dead_code))]
+    pub(crate) fn into_parts(self) -> (http::HeaderMap, http::Extensions) {
+        (self.metadata.into_headers(), self.extensions.into_http())
+    }
+
     #[doc(hidden)]
     pub fn map<F, U>(self, f: F) -> Request<U>
     where
@@ -164,6 +373,34 @@ impl<T> Request<T> {
         Request {
             metadata: self.metadata,
             message,
+            extensions: self.extensions,
+        }
+    }
+}
+
+impl<I: Iterator> Request<stream::Iter<I>> {
+    /// Create a streaming request that sends the given messages, and then
+    /// ends the stream.
+    ///
+    /// ```rust
+    /// # use tonic::Request;
+    /// # pub struct Point {}
+    /// let request = Request::from_messages(vec![Point {}, Point {}]);
+    /// ```
+    pub fn from_messages(messages: impl IntoIterator<IntoIter = I>) -> Self {
+        Request::new(stream::iter(messages))
+    }
+}
+
+impl Request<()> {
+    /// Create a request without a message out of the headers and extensions of
+    /// an HTTP request.
+    #[cfg_attr(not(feature = "transport"), allow(dead_code))]
+    pub(crate) fn from_parts(headers: http::HeaderMap, extensions: http::Extensions) -> Self {
+        Request {
+            metadata: MetadataMap::from_headers(headers),
+            message: (),
+            extensions: Extensions::from_http(extensions),
         }
     }
 }
@@ -182,7 +419,7 @@ impl<T> IntoRequest<T> for Request<T> {
 
 impl<T> IntoStreamingRequest for T
 where
-    T: Stream + Send + Sync + 'static,
+    T: Stream + Send + 'static,
 {
     type Stream = T;
     type Message = T::Item;
@@ -194,7 +431,7 @@ where
 
 impl<T> IntoStreamingRequest for Request<T>
 where
-    T: Stream + Send + Sync + 'static,
+    T: Stream + Send + 'static,
 {
     type Stream = T;
     type Message = T::Item;
@@ -204,8 +441,72 @@ where
     }
 }
 
+/// Format `duration` as a `grpc-timeout` header value, in the most precise
+/// unit that fits into the eight digits allowed for it.
+pub(crate) fn duration_to_grpc_timeout(duration: Duration) -> String {
+    const MAX_VALUE: u128 = 99_999_999;
+
+    let units = [
+        ('n', duration.as_nanos()),
+        ('u', duration.as_micros()),
+        ('m', duration.as_millis()),
+        ('S', u128::from(duration.as_secs())),
+        ('M', u128::from(duration.as_secs() / 60)),
+        ('H', u128::from(duration.as_secs() / 60 / 60)),
+    ];
+
+    let (unit, value) = units
+        .iter()
+        .find(|(_, value)| *value <= MAX_VALUE)
+        .copied()
+        // the longest timeout that can be sent is about 11,400 years
+        .unwrap_or(('H', MAX_VALUE));
+    format!("{}{}", value, unit)
+}
+
 impl<T> sealed::Sealed for T {}
 
 mod sealed {
     pub trait Sealed {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grpc_timeout_header() {
+        let timeout = duration_to_grpc_timeout;
+
+        assert_eq!(timeout(Duration::from_nanos(42)), "42n");
+        assert_eq!(timeout(Duration::from_millis(50)), "50000000n");
+        assert_eq!(timeout(Duration::from_millis(100)), "100000u");
+        assert_eq!(timeout(Duration::from_secs(200)), "200000m");
+        assert_eq!(
+            timeout(Duration::from_secs(60 * 60 * 24 * 365)),
+            "31536000S"
+        );
+        assert_eq!(timeout(Duration::from_secs(u64::MAX)), "99999999H");
+    }
+
+    #[tokio::test]
+    async fn from_messages() {
+        use futures_util::StreamExt;
+
+        let request = Request::from_messages(vec![1, 2, 3]);
+        let messages = request.into_inner().collect::<Vec<_>>().await;
+        assert_eq!(messages, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn streaming_request_without_sync() {
+        use futures_util::StreamExt;
+        use std::cell::Cell;
+
+        fn assert_streaming(_: impl IntoStreamingRequest<Message = u32>) {}
+
+        // a `Cell` is `Send` but not `Sync`
+        let offset = Cell::new(1);
+        assert_streaming(stream::iter(vec![1, 2]).map(move |n| n + offset.get()));
+    }
+}
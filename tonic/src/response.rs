@@ -1,10 +1,24 @@
-use crate::metadata::MetadataMap;
+use crate::{codec::SyncStream, metadata::MetadataMap, Extensions, Status};
+use futures_core::Stream;
+use std::pin::Pin;
+#[cfg(feature = "transport")]
+use tokio::sync::mpsc;
+
+/// A boxed stream of response messages, returned by [`Response::from_stream`].
+///
+/// It can be used as the response stream type of any streaming method of a
+/// generated server trait.
+///
+/// [`Response::from_stream`]: struct.Response.html#method.from_stream
+pub type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync>>;
 
 /// A gRPC response and metadata from an RPC call.
 #[derive(Debug)]
 pub struct Response<T> {
     metadata: MetadataMap,
     message: T,
+    extensions: Extensions,
+    trailers: MetadataMap,
 }
 
 impl<T> Response<T> {
@@ -24,6 +38,8 @@ impl<T> Response<T> {
         Response {
             metadata: MetadataMap::new(),
             message,
+            extensions: Extensions::new(),
+            trailers: MetadataMap::new(),
         }
     }
 
@@ -43,21 +59,62 @@ impl<T> Response<T> {
     }
 
     /// Get a mutable reference to the response metadata.
+    ///
+    /// Headers reserved by the gRPC protocol, like `content-type` or
+    /// `grpc-status`, are not sent even if they are set here.
     pub fn metadata_mut(&mut self) -> &mut MetadataMap {
         &mut self.metadata
     }
 
+    /// Get a reference to the trailing metadata.
+    ///
+    /// A client receives the trailing metadata of a unary or client streaming
+    /// call here, separate from the initial metadata of [`Response::metadata`].
+    /// The trailing metadata of a streaming response is received at its end,
+    /// via [`Streaming::trailers`].
+    ///
+    /// [`Streaming::trailers`]: struct.Streaming.html#method.trailers
+    pub fn trailers(&self) -> &MetadataMap {
+        &self.trailers
+    }
+
+    /// Get a mutable reference to the trailing metadata.
+    ///
+    /// A server sends it after the response messages, along with the
+    /// `grpc-status`. Headers reserved by the gRPC protocol are not sent even
+    /// if they are set here.
+    pub fn trailers_mut(&mut self) -> &mut MetadataMap {
+        &mut self.trailers
+    }
+
+    /// Get a reference to the response extensions.
+    ///
+    /// See [`Extensions`] for what is stored there and how it is propagated.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Get a mutable reference to the response extensions.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
     /// Consumes `self`, returning the message
     pub fn into_inner(self) -> T {
         self.message
     }
 
-    pub(crate) fn into_parts(self) -> (MetadataMap, T) {
-        (self.metadata, self.message)
+    pub(crate) fn into_parts(self) -> (MetadataMap, T, Extensions) {
+        (self.metadata, self.message, self.extensions)
     }
 
-    pub(crate) fn from_parts(metadata: MetadataMap, message: T) -> Self {
-        Self { metadata, message }
+    pub(crate) fn from_parts(metadata: MetadataMap, message: T, extensions: Extensions) -> Self {
+        Self {
+            metadata,
+            message,
+            extensions,
+            trailers: MetadataMap::new(),
+        }
     }
 
     pub(crate) fn from_http(res: http::Response<T>) -> Self {
@@ -65,6 +122,8 @@ impl<T> Response<T> {
         Response {
             metadata: MetadataMap::from_headers(head.headers),
             message,
+            extensions: Extensions::from_http(head.extensions),
+            trailers: MetadataMap::new(),
         }
     }
 
@@ -72,7 +131,8 @@ impl<T> Response<T> {
         let mut res = http::Response::new(self.message);
 
         *res.version_mut() = http::Version::HTTP_2;
-        *res.headers_mut() = self.metadata.into_headers();
+        *res.headers_mut() = self.metadata.into_sanitized_headers();
+        *res.extensions_mut() = self.extensions.into_http();
 
         res
     }
@@ -86,6 +146,67 @@ impl<T> Response<T> {
         Response {
             metadata: self.metadata,
             message,
+            extensions: self.extensions,
+            trailers: self.trailers,
         }
     }
 }
+
+impl<T: 'static> Response<ResponseStream<T>> {
+    /// Create a streaming response that sends the messages of `stream`.
+    ///
+    /// An `Err` item ends the response with its status. Unlike the response
+    /// stream types of generated server traits, `stream` does not need to be
+    /// `Sync`, so it may be an `async` stream that holds non-`Sync` values.
+    ///
+    /// ```rust
+    /// # use tonic::{Response, ResponseStream, Status};
+    /// # use futures_util::stream;
+    /// # pub struct Feature {}
+    /// fn list_features() -> Result<Response<ResponseStream<Feature>>, Status> {
+    ///     let features = vec![Ok(Feature {}), Ok(Feature {})];
+    ///     Ok(Response::from_stream(stream::iter(features)))
+    /// }
+    /// ```
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<T, Status>> + Send + 'static,
+    {
+        Response::new(Box::pin(SyncStream::new(stream)))
+    }
+
+    /// Create a streaming response that sends the messages sent on the
+    /// returned channel, which holds up to `buffer` messages.
+    ///
+    /// Sending waits while the buffer is full, so a task producing messages
+    /// faster than the client reads them is held back. The response ends
+    /// once every sender is dropped, and sending fails once the client went
+    /// away.
+    ///
+    /// ```rust
+    /// # use tonic::{Response, ResponseStream, Status};
+    /// # pub struct Feature {}
+    /// fn list_features() -> Result<Response<ResponseStream<Feature>>, Status> {
+    ///     let (mut tx, response) = Response::channel(4);
+    ///
+    ///     tokio::spawn(async move {
+    ///         for _ in 0..10 {
+    ///             if tx.send(Ok(Feature {})).await.is_err() {
+    ///                 break;
+    ///             }
+    ///         }
+    ///     });
+    ///
+    ///     Ok(response)
+    /// }
+    /// ```
+    #[cfg(feature = "transport")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "transport")))]
+    pub fn channel(buffer: usize) -> (mpsc::Sender<Result<T, Status>>, Self)
+    where
+        T: Send,
+    {
+        let (tx, rx) = mpsc::channel(buffer);
+        (tx, Response::from_stream(rx))
+    }
+}
@@ -1,7 +1,13 @@
 use crate::{
     body::BoxBody,
-    codec::{encode_server, Codec, Streaming},
-    server::{ClientStreamingService, ServerStreamingService, StreamingService, UnaryService},
+    codec::{
+        encode_server, Codec, CompressionConfig, CompressionEncoding, Streaming,
+        ACCEPT_ENCODING_HEADER, ENCODING_HEADER,
+    },
+    metadata::MetadataMap,
+    server::{
+        ClientStreamingService, RawService, ServerStreamingService, StreamingService, UnaryService,
+    },
     Code, Request, Response, Status,
 };
 use bytes::Bytes;
@@ -9,6 +15,7 @@ use futures_core::TryStream;
 use futures_util::{future, stream, TryStreamExt};
 use http_body::Body;
 use std::fmt;
+use tracing::Span;
 
 /// A gRPC Server handler.
 ///
@@ -19,8 +26,46 @@ use std::fmt;
 /// Each request handler method accepts some service that implements the
 /// corresponding service trait and a http request that contains some body that
 /// implements some [`Body`].
+///
+/// Response streams are polled lazily: the next message is only pulled from
+/// the handler's stream once the transport asks for more data to send, which
+/// it does only while the peer's HTTP/2 flow control window has capacity. A
+/// handler producing faster than the client reads is therefore backpressured
+/// and at most one encoded message is buffered beyond the flow control window,
+/// so no extra bound on outgoing messages is needed. The window is configured
+/// on the client via `initial_stream_window_size` and
+/// `initial_connection_window_size`.
+///
+/// If the http request carries a [`tracing::Span`] in its extensions, that
+/// span is entered while the request messages are decoded and the response
+/// messages are encoded. This attributes (de)serialization work to the
+/// request; a span can be attached by an interceptor via
+/// `request.extensions_mut().insert(span)`.
+///
+/// Requests are only accepted with the [`Codec::CONTENT_TYPE`] of the codec
+/// or a plain `application/grpc`, which is also the content type of the
+/// responses. Other requests are answered with an HTTP `415 Unsupported
+/// Media Type` response carrying an `INTERNAL` status, without calling the
+/// handler.
+///
+/// Messages are not compressed unless enabled via [`Grpc::accept_gzip`] and
+/// [`Grpc::send_gzip`]. A request whose `grpc-encoding` header names an
+/// encoding that is not accepted is not passed to the handler; it is answered
+/// with an `UNIMPLEMENTED` status and a `grpc-accept-encoding` header listing
+/// the accepted encodings, as required by the [gRPC compression spec].
+///
+/// The size of decoded request and encoded response messages is unbounded
+/// unless limited via [`Grpc::max_decoding_message_size`] and
+/// [`Grpc::max_encoding_message_size`].
+///
+/// [`Codec::CONTENT_TYPE`]: ../codec/trait.Codec.html#associatedconstant.CONTENT_TYPE
+/// [`tracing::Span`]: https://docs.rs/tracing/0.1/tracing/struct.Span.html
+/// [gRPC compression spec]: https://github.com/grpc/grpc/blob/master/doc/compression.md
 pub struct Grpc<T> {
     codec: T,
+    compression: CompressionConfig,
+    max_decoding_message_size: Option<usize>,
+    max_encoding_message_size: Option<usize>,
 }
 
 impl<T> Grpc<T>
@@ -30,7 +75,64 @@ where
 {
     /// Creates a new gRPC client with the provided [`Codec`].
     pub fn new(codec: T) -> Self {
-        Self { codec }
+        Self {
+            codec,
+            compression: CompressionConfig::default(),
+            max_decoding_message_size: None,
+            max_encoding_message_size: None,
+        }
+    }
+
+    /// Accept requests whose messages are compressed with gzip.
+    pub fn accept_gzip(mut self) -> Self {
+        self.compression.accept_gzip = true;
+        self
+    }
+
+    /// Compress response messages with gzip if the client accepts it, as
+    /// indicated by its `grpc-accept-encoding` header.
+    pub fn send_gzip(mut self) -> Self {
+        self.compression.send_gzip = true;
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn apply_compression_config(mut self, accept_gzip: bool, send_gzip: bool) -> Self {
+        self.compression = CompressionConfig {
+            send_gzip,
+            accept_gzip,
+        };
+        self
+    }
+
+    /// Limit the size of a decoded request message to `limit` bytes.
+    ///
+    /// A request carrying a larger message fails with a `RESOURCE_EXHAUSTED`
+    /// status. For compressed messages the limit applies to both the
+    /// compressed and the decompressed size.
+    pub fn max_decoding_message_size(mut self, limit: usize) -> Self {
+        self.max_decoding_message_size = Some(limit);
+        self
+    }
+
+    /// Limit the size of an encoded response message to `limit` bytes.
+    ///
+    /// A larger response message is not sent; the response fails with a
+    /// `RESOURCE_EXHAUSTED` status instead.
+    pub fn max_encoding_message_size(mut self, limit: usize) -> Self {
+        self.max_encoding_message_size = Some(limit);
+        self
+    }
+
+    #[doc(hidden)]
+    pub fn apply_max_message_size_config(
+        mut self,
+        max_decoding_message_size: Option<usize>,
+        max_encoding_message_size: Option<usize>,
+    ) -> Self {
+        self.max_decoding_message_size = max_decoding_message_size;
+        self.max_encoding_message_size = max_encoding_message_size;
+        self
     }
 
     /// Handle a single unary gRPC request.
@@ -45,13 +147,23 @@ where
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        let request = match self.map_request_unary(req).await {
+        let encoding = match self.accept_request(&req) {
+            Ok(encoding) => encoding,
+            Err(response) => return *response,
+        };
+        let response_encoding = self.response_encoding(&req);
+
+        let span = request_span(&req);
+
+        let request = match self.map_request_unary(req, span.clone(), encoding).await {
             Ok(r) => r,
             Err(status) => {
                 return self
-                    .map_response::<stream::Once<future::Ready<Result<T::Encode, Status>>>>(Err(
-                        status,
-                    ));
+                    .map_response::<stream::Once<future::Ready<Result<T::Encode, Status>>>>(
+                        Err(status),
+                        span,
+                        None,
+                    );
             }
         };
 
@@ -60,7 +172,7 @@ where
             .await
             .map(|r| r.map(|m| stream::once(future::ok(m))));
 
-        self.map_response(response)
+        self.map_response(response, span, response_encoding)
     }
 
     /// Handle a server side streaming request.
@@ -76,16 +188,24 @@ where
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        let request = match self.map_request_unary(req).await {
+        let encoding = match self.accept_request(&req) {
+            Ok(encoding) => encoding,
+            Err(response) => return *response,
+        };
+        let response_encoding = self.response_encoding(&req);
+
+        let span = request_span(&req);
+
+        let request = match self.map_request_unary(req, span.clone(), encoding).await {
             Ok(r) => r,
             Err(status) => {
-                return self.map_response::<S::ResponseStream>(Err(status));
+                return self.map_response::<S::ResponseStream>(Err(status), span, None);
             }
         };
 
         let response = service.call(request).await;
 
-        self.map_response(response)
+        self.map_response(response, span, response_encoding)
     }
 
     /// Handle a client side streaming gRPC request.
@@ -100,12 +220,19 @@ where
         B::Data: Into<Bytes> + Send + 'static,
         B::Error: Into<crate::Error> + Send + 'static,
     {
-        let request = self.map_request_streaming(req);
+        let encoding = match self.accept_request(&req) {
+            Ok(encoding) => encoding,
+            Err(response) => return *response,
+        };
+        let response_encoding = self.response_encoding(&req);
+
+        let span = request_span(&req);
+        let request = self.map_request_streaming(req, span.clone(), encoding);
         let response = service
             .call(request)
             .await
             .map(|r| r.map(|m| stream::once(future::ok(m))));
-        self.map_response(response)
+        self.map_response(response, span, response_encoding)
     }
 
     /// Handle a bi-directional streaming gRPC request.
@@ -121,14 +248,63 @@ where
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        let request = self.map_request_streaming(req);
+        let encoding = match self.accept_request(&req) {
+            Ok(encoding) => encoding,
+            Err(response) => return *response,
+        };
+        let response_encoding = self.response_encoding(&req);
+
+        let span = request_span(&req);
+        let request = self.map_request_streaming(req, span.clone(), encoding);
         let response = service.call(request).await;
-        self.map_response(response)
+        self.map_response(response, span, response_encoding)
+    }
+
+    /// Handle a gRPC request with a handler that builds the http response
+    /// itself.
+    ///
+    /// The request messages are decoded as usual, but the response returned by
+    /// the handler is sent as-is. Tonic does not set the content type, encode
+    /// or frame the body, or add a `grpc-status`, so the handler is responsible
+    /// for producing a valid gRPC response: a `content-type` of
+    /// `application/grpc`, length-prefixed messages in the body and a
+    /// `grpc-status` in the headers or trailers. If the handler returns an
+    /// error it is sent as a regular gRPC error response.
+    pub async fn raw<S, B>(
+        &mut self,
+        mut service: S,
+        req: http::Request<B>,
+    ) -> http::Response<BoxBody>
+    where
+        S: RawService<T::Decode>,
+        B: Body + Send + Sync + 'static,
+        B::Data: Into<Bytes> + Send,
+        B::Error: Into<crate::Error> + Send,
+    {
+        let encoding = match self.accept_request(&req) {
+            Ok(encoding) => encoding,
+            Err(response) => return *response,
+        };
+
+        let span = request_span(&req);
+        let request = self.map_request_streaming(req, span.clone(), encoding);
+
+        match service.call(request).await {
+            Ok(response) => response,
+            Err(status) => self
+                .map_response::<stream::Once<future::Ready<Result<T::Encode, Status>>>>(
+                    Err(status),
+                    span,
+                    None,
+                ),
+        }
     }
 
     async fn map_request_unary<B>(
         &mut self,
         request: http::Request<B>,
+        span: Span,
+        encoding: Option<CompressionEncoding>,
     ) -> Result<Request<T::Decode>, Status>
     where
         B: Body + Send + Sync + 'static,
@@ -136,7 +312,13 @@ where
         B::Error: Into<crate::Error> + Send,
     {
         let (parts, body) = request.into_parts();
-        let stream = Streaming::new_request(self.codec.decoder(), body);
+        let stream = Streaming::new_request(
+            self.codec.decoder(),
+            body,
+            span,
+            encoding,
+            self.max_decoding_message_size,
+        );
 
         futures_util::pin_mut!(stream);
 
@@ -157,50 +339,161 @@ where
     fn map_request_streaming<B>(
         &mut self,
         request: http::Request<B>,
+        span: Span,
+        encoding: Option<CompressionEncoding>,
     ) -> Request<Streaming<T::Decode>>
     where
         B: Body + Send + Sync + 'static,
         B::Data: Into<Bytes> + Send,
         B::Error: Into<crate::Error> + Send,
     {
-        Request::from_http(request.map(|body| Streaming::new_request(self.codec.decoder(), body)))
+        let max_message_size = self.max_decoding_message_size;
+        Request::from_http(request.map(|body| {
+            Streaming::new_request(self.codec.decoder(), body, span, encoding, max_message_size)
+        }))
     }
 
     fn map_response<B>(
         &mut self,
         response: Result<crate::Response<B>, Status>,
+        span: Span,
+        encoding: Option<CompressionEncoding>,
     ) -> http::Response<BoxBody>
     where
         B: TryStream<Ok = T::Encode, Error = Status> + Send + Sync + 'static,
     {
         match response {
-            Ok(r) => {
+            Ok(mut r) => {
+                let trailers = std::mem::replace(r.trailers_mut(), MetadataMap::new());
                 let (mut parts, body) = r.into_http().into_parts();
 
                 // Set the content type
                 parts.headers.insert(
                     http::header::CONTENT_TYPE,
-                    http::header::HeaderValue::from_static("application/grpc"),
+                    http::header::HeaderValue::from_static(T::CONTENT_TYPE),
                 );
 
-                let body = encode_server(self.codec.encoder(), body.into_stream());
+                if let Some(encoding) = encoding {
+                    parts
+                        .headers
+                        .insert(ENCODING_HEADER, encoding.into_header_value());
+                }
+
+                let body = encode_server(
+                    self.codec.encoder(),
+                    body.into_stream(),
+                    span,
+                    encoding,
+                    self.max_encoding_message_size,
+                )
+                .trailers(trailers.into_sanitized_headers());
 
                 http::Response::from_parts(parts, BoxBody::new(body))
             }
-            Err(status) => {
-                let (mut parts, _body) = Response::new(()).into_http().into_parts();
+            Err(status) => error_response(status),
+        }
+    }
 
-                parts.headers.insert(
-                    http::header::CONTENT_TYPE,
-                    http::header::HeaderValue::from_static("application/grpc"),
-                );
+    /// Check that the request can be handled, returning the encoding of its
+    /// messages.
+    fn accept_request<B>(
+        &self,
+        request: &http::Request<B>,
+    ) -> Result<Option<CompressionEncoding>, Box<http::Response<BoxBody>>> {
+        self.check_content_type(request)?;
+        self.request_encoding(request)
+    }
 
-                status.add_header(&mut parts.headers).unwrap();
+    /// Reject requests whose content type the codec can't decode.
+    fn check_content_type<B>(
+        &self,
+        request: &http::Request<B>,
+    ) -> Result<(), Box<http::Response<BoxBody>>> {
+        let content_type = match request.headers().get(http::header::CONTENT_TYPE) {
+            Some(content_type) => content_type,
+            None => return Ok(()),
+        };
 
-                http::Response::from_parts(parts, BoxBody::empty())
+        match content_type.to_str() {
+            Ok(content_type) if is_compatible_content_type(content_type, T::CONTENT_TYPE) => Ok(()),
+            _ => {
+                let status = Status::new(
+                    Code::Internal,
+                    format!(
+                        "Unsupported content-type {:?}, expected {}",
+                        content_type,
+                        T::CONTENT_TYPE
+                    ),
+                );
+                let mut response = error_response(status);
+                *response.status_mut() = http::StatusCode::UNSUPPORTED_MEDIA_TYPE;
+                Err(Box::new(response))
             }
         }
     }
+
+    /// Determine the encoding of the request messages, rejecting requests
+    /// compressed with an encoding that is not accepted.
+    fn request_encoding<B>(
+        &self,
+        request: &http::Request<B>,
+    ) -> Result<Option<CompressionEncoding>, Box<http::Response<BoxBody>>> {
+        CompressionEncoding::from_encoding_header(request.headers(), self.compression.accept_gzip)
+            .map_err(|status| {
+                let mut response = error_response(status);
+                response.headers_mut().insert(
+                    ACCEPT_ENCODING_HEADER,
+                    self.compression.accept_encoding_header(),
+                );
+                Box::new(response)
+            })
+    }
+
+    /// Determine the encoding of the response messages.
+    fn response_encoding<B>(&self, request: &http::Request<B>) -> Option<CompressionEncoding> {
+        CompressionEncoding::from_accept_encoding_header(
+            request.headers(),
+            self.compression.send_gzip,
+        )
+    }
+}
+
+/// Whether a request of `content_type` can be decoded by a codec of
+/// `codec_content_type`.
+///
+/// A plain `application/grpc` is accepted by every codec, and a codec without
+/// a subtype also accepts `application/grpc+proto`, the protobuf default.
+fn is_compatible_content_type(content_type: &str, codec_content_type: &str) -> bool {
+    const GRPC: &str = "application/grpc";
+
+    // parameters like `charset` don't matter to the messages
+    let content_type = content_type.split(';').next().unwrap_or_default().trim();
+
+    content_type.eq_ignore_ascii_case(codec_content_type)
+        || content_type.eq_ignore_ascii_case(GRPC)
+        || (codec_content_type == GRPC
+            && content_type.eq_ignore_ascii_case("application/grpc+proto"))
+}
+
+fn error_response(status: Status) -> http::Response<BoxBody> {
+    let (mut parts, _body) = Response::new(()).into_http().into_parts();
+
+    parts.headers.insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static("application/grpc"),
+    );
+
+    status.add_header(&mut parts.headers).unwrap();
+
+    http::Response::from_parts(parts, BoxBody::empty())
+}
+
+fn request_span<B>(request: &http::Request<B>) -> Span {
+    request
+        .extensions()
+        .get::<Span>()
+        .cloned()
+        .unwrap_or_else(Span::none)
 }
 
 impl<T> fmt::Debug for Grpc<T> {
@@ -208,3 +501,364 @@ impl<T> fmt::Debug for Grpc<T> {
         f.debug_struct("Grpc").finish()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{ProstCodec, Transform, TransformCodec};
+    use bytes::{Buf, BufMut, BytesMut};
+    use http::HeaderValue;
+    use prost::Message;
+    use std::pin::Pin;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Greeting {
+        #[prost(string, tag = "1")]
+        name: String,
+    }
+
+    #[derive(Debug, Default, Clone)]
+    struct Trim;
+
+    impl Transform<Greeting> for Trim {
+        fn transform(&self, mut message: Greeting) -> Result<Greeting, Status> {
+            message.name = message.name.trim().to_string();
+            Ok(message)
+        }
+    }
+
+    fn framed(message: &Greeting) -> hyper::Body {
+        let mut buf = BytesMut::with_capacity(message.encoded_len() + 5);
+        buf.put_u8(0);
+        buf.put_u32_be(message.encoded_len() as u32);
+        message.encode(&mut buf).unwrap();
+        hyper::Body::from(buf.freeze())
+    }
+
+    #[tokio::test]
+    async fn transform_runs_before_handler() {
+        let codec = TransformCodec::new(ProstCodec::<Greeting, Greeting>::default(), Trim);
+        let mut grpc = Grpc::new(codec);
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            let name = req.into_inner().name;
+            assert_eq!(name, "tonic");
+            future::ok::<_, Status>(Response::new(Greeting { name }))
+        });
+
+        let body = framed(&Greeting {
+            name: "  tonic \n".to_string(),
+        });
+        let response = grpc.unary(handler, http::Request::new(body)).await;
+
+        assert!(Status::from_header_map(response.headers()).is_none());
+    }
+
+    #[tokio::test]
+    async fn raw_response_is_sent_as_is() {
+        let mut grpc = Grpc::new(ProstCodec::<(), ()>::default());
+
+        let handler = tower::service_fn(|_req: Request<Streaming<()>>| {
+            let response = http::Response::builder()
+                .header("content-type", "application/grpc+custom")
+                .header("grpc-status", "0")
+                .header("x-raw", "1")
+                .body(BoxBody::empty())
+                .unwrap();
+
+            future::ok::<_, Status>(response)
+        });
+
+        let response = grpc
+            .raw(handler, http::Request::new(hyper::Body::empty()))
+            .await;
+        let headers = response.headers();
+
+        assert_eq!(
+            headers.get("content-type"),
+            Some(&HeaderValue::from_static("application/grpc+custom"))
+        );
+        assert_eq!(headers.get("x-raw"), Some(&HeaderValue::from_static("1")));
+    }
+
+    #[tokio::test]
+    async fn raw_error_is_a_grpc_response() {
+        let mut grpc = Grpc::new(ProstCodec::<(), ()>::default());
+
+        let handler = tower::service_fn(|_req: Request<Streaming<()>>| {
+            future::err::<http::Response<BoxBody>, _>(Status::unimplemented("nope"))
+        });
+
+        let response = grpc
+            .raw(handler, http::Request::new(hyper::Body::empty()))
+            .await;
+        let status = Status::from_header_map(response.headers()).unwrap();
+
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert_eq!(status.message(), "nope");
+    }
+
+    #[tokio::test]
+    async fn trailers_are_sent_with_status() {
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default());
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            let mut response = Response::new(req.into_inner());
+            let trailers = response.trailers_mut();
+            trailers.insert("x-next-page", "2".parse().unwrap());
+            trailers.insert("grpc-status", "13".parse().unwrap());
+            future::ok::<_, Status>(response)
+        });
+
+        let request = http::Request::new(framed(&Greeting::default()));
+        let mut body = grpc.unary(handler, request).await.into_body();
+
+        while let Some(data) = future::poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+            data.unwrap();
+        }
+        let trailers = body.trailers().await.unwrap().unwrap();
+
+        assert_eq!(trailers.get("x-next-page").unwrap(), "2");
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+    }
+
+    #[derive(Debug, Default)]
+    struct JsonCodec(ProstCodec<Greeting, Greeting>);
+
+    impl Codec for JsonCodec {
+        const CONTENT_TYPE: &'static str = "application/grpc+json";
+
+        type Encode = Greeting;
+        type Decode = Greeting;
+        type Encoder = <ProstCodec<Greeting, Greeting> as Codec>::Encoder;
+        type Decoder = <ProstCodec<Greeting, Greeting> as Codec>::Decoder;
+
+        fn encoder(&mut self) -> Self::Encoder {
+            self.0.encoder()
+        }
+
+        fn decoder(&mut self) -> Self::Decoder {
+            self.0.decoder()
+        }
+    }
+
+    async fn call_with_content_type<C>(codec: C, content_type: &str) -> http::Response<BoxBody>
+    where
+        C: Codec<Encode = Greeting, Decode = Greeting>,
+    {
+        let mut grpc = Grpc::new(codec);
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            future::ok::<_, Status>(Response::new(req.into_inner()))
+        });
+
+        let request = http::Request::builder()
+            .header("content-type", content_type)
+            .body(framed(&Greeting::default()))
+            .unwrap();
+        grpc.unary(handler, request).await
+    }
+
+    #[tokio::test]
+    async fn content_type_is_negotiated() {
+        let prost = ProstCodec::<Greeting, Greeting>::default;
+
+        for &content_type in &["application/grpc", "application/grpc+proto"] {
+            let response = call_with_content_type(prost(), content_type).await;
+            assert_eq!(response.status(), http::StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/grpc");
+        }
+
+        for &content_type in &[
+            "application/grpc+json",
+            "application/grpc+JSON; charset=utf-8",
+        ] {
+            let response = call_with_content_type(JsonCodec::default(), content_type).await;
+            assert_eq!(response.status(), http::StatusCode::OK);
+            assert_eq!(response.headers()["content-type"], "application/grpc+json");
+        }
+    }
+
+    #[tokio::test]
+    async fn unsupported_content_type_is_rejected() {
+        let prost = ProstCodec::<Greeting, Greeting>::default;
+
+        for &content_type in &["application/json", "application/grpc+json"] {
+            let response = call_with_content_type(prost(), content_type).await;
+            assert_eq!(response.status(), http::StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+            let status = Status::from_header_map(response.headers()).unwrap();
+            assert_eq!(status.code(), Code::Internal);
+        }
+    }
+
+    #[tokio::test]
+    async fn unsupported_encoding_is_rejected() {
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default());
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            future::ok::<_, Status>(Response::new(req.into_inner()))
+        });
+
+        let request = http::Request::builder()
+            .header("grpc-encoding", "gzip")
+            .body(framed(&Greeting::default()))
+            .unwrap();
+        let response = grpc.unary(handler, request).await;
+        let status = Status::from_header_map(response.headers()).unwrap();
+
+        assert_eq!(status.code(), Code::Unimplemented);
+        assert_eq!(
+            response.headers().get("grpc-accept-encoding"),
+            Some(&HeaderValue::from_static("identity"))
+        );
+    }
+
+    #[tokio::test]
+    async fn identity_encoding_is_accepted() {
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default());
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            future::ok::<_, Status>(Response::new(req.into_inner()))
+        });
+
+        let request = http::Request::builder()
+            .header("grpc-encoding", "identity")
+            .body(framed(&Greeting::default()))
+            .unwrap();
+        let response = grpc.unary(handler, request).await;
+
+        assert!(Status::from_header_map(response.headers()).is_none());
+    }
+
+    /// The gzip compressed frame of `message`.
+    async fn gzip_framed(message: Greeting) -> hyper::Body {
+        let encoder = ProstCodec::<Greeting, Greeting>::default().encoder();
+        let source = stream::iter(vec![Ok(message)]);
+        let body = encode_server(
+            encoder,
+            source,
+            Span::none(),
+            Some(CompressionEncoding::Gzip),
+            None,
+        );
+        futures_util::pin_mut!(body);
+
+        let mut buf = BytesMut::new();
+        while let Some(frame) = body.next().await {
+            buf.extend_from_slice(frame.unwrap().bytes());
+        }
+        hyper::Body::from(buf.freeze())
+    }
+
+    #[tokio::test]
+    async fn gzip_is_accepted_and_sent() {
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default())
+            .accept_gzip()
+            .send_gzip();
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            assert_eq!(req.get_ref().name, "tonic");
+            future::ok::<_, Status>(Response::new(req.into_inner()))
+        });
+
+        let body = gzip_framed(Greeting {
+            name: "tonic".to_string(),
+        })
+        .await;
+        let request = http::Request::builder()
+            .header("grpc-encoding", "gzip")
+            .header("grpc-accept-encoding", "gzip,identity")
+            .body(body)
+            .unwrap();
+        let response = grpc.unary(handler, request).await;
+
+        assert!(Status::from_header_map(response.headers()).is_none());
+        assert_eq!(
+            response.headers().get("grpc-encoding"),
+            Some(&HeaderValue::from_static("gzip"))
+        );
+
+        let body = response.into_body();
+        futures_util::pin_mut!(body);
+        let frame = body.next().await.unwrap().unwrap();
+        assert_eq!(frame.bytes()[0], 1);
+    }
+
+    #[tokio::test]
+    async fn gzip_is_not_sent_unless_accepted() {
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default()).send_gzip();
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            future::ok::<_, Status>(Response::new(req.into_inner()))
+        });
+
+        let response = grpc
+            .unary(handler, http::Request::new(framed(&Greeting::default())))
+            .await;
+
+        assert!(response.headers().get("grpc-encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn oversized_request_is_rejected() {
+        let mut grpc =
+            Grpc::new(ProstCodec::<Greeting, Greeting>::default()).max_decoding_message_size(8);
+
+        let handler = tower::service_fn(|_: Request<Greeting>| -> future::Ready<_> {
+            panic!("handler must not be called")
+        });
+
+        let request = http::Request::new(framed(&Greeting {
+            name: "a name that is too long".to_string(),
+        }));
+        let response = grpc.unary(handler, request).await;
+
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn extensions_reach_handler() {
+        #[derive(Debug, PartialEq)]
+        struct Tenant(&'static str);
+
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default());
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            assert_eq!(req.extensions().get::<Tenant>(), Some(&Tenant("acme")));
+            future::ok::<_, Status>(Response::new(req.into_inner()))
+        });
+
+        // what an interceptor does with the http request before routing it
+        let mut request = http::Request::new(framed(&Greeting::default()));
+        request.extensions_mut().insert(Tenant("acme"));
+
+        let response = grpc.unary(handler, request).await;
+
+        assert!(Status::from_header_map(response.headers()).is_none());
+    }
+
+    #[tokio::test]
+    async fn extensions_reach_http_response() {
+        #[derive(Debug, PartialEq)]
+        struct Claims(&'static str);
+
+        let mut grpc = Grpc::new(ProstCodec::<Greeting, Greeting>::default());
+
+        let handler = tower::service_fn(|req: Request<Greeting>| {
+            let mut response = Response::new(req.into_inner());
+            response.extensions_mut().insert(Claims("admin"));
+            future::ok::<_, Status>(response)
+        });
+
+        let request = http::Request::new(framed(&Greeting::default()));
+        let response = grpc.unary(handler, request).await;
+
+        // where a tower layer wrapping the server picks them up
+        assert_eq!(
+            response.extensions().get::<Claims>(),
+            Some(&Claims("admin"))
+        );
+    }
+}
@@ -13,5 +13,5 @@ mod service;
 
 pub use self::grpc::Grpc;
 pub use self::service::{
-    ClientStreamingService, ServerStreamingService, StreamingService, UnaryService,
+    ClientStreamingService, RawService, ServerStreamingService, StreamingService, UnaryService,
 };
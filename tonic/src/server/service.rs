@@ -1,4 +1,4 @@
-use crate::{Request, Response, Status, Streaming};
+use crate::{body::BoxBody, Request, Response, Status, Streaming};
 use futures_core::Stream;
 use std::future::Future;
 use tower_service::Service;
@@ -120,3 +120,27 @@ where
         Service::call(self, request)
     }
 }
+
+/// A specialization of tower_service::Service for handlers that build the
+/// http response themselves.
+///
+/// Existing tower_service::Service implementations with the correct form will
+/// automatically implement `RawService`.
+pub trait RawService<R> {
+    /// Response future
+    type Future: Future<Output = Result<http::Response<BoxBody>, Status>>;
+
+    /// Call the service
+    fn call(&mut self, request: Request<Streaming<R>>) -> Self::Future;
+}
+
+impl<T, M1> RawService<M1> for T
+where
+    T: Service<Request<Streaming<M1>>, Response = http::Response<BoxBody>, Error = crate::Status>,
+{
+    type Future = T::Future;
+
+    fn call(&mut self, request: Request<Streaming<M1>>) -> Self::Future {
+        Service::call(self, request)
+    }
+}
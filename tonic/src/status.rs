@@ -71,6 +71,28 @@ impl Status {
         }
     }
 
+    /// Create a new `ResourceExhausted` status with the associated message.
+    pub fn resource_exhausted(message: impl Into<String>) -> Status {
+        Status {
+            code: Code::ResourceExhausted,
+            message: message.into(),
+            details: Bytes::new(),
+        }
+    }
+
+    /// Create a new `Status` with the associated code, message and binary
+    /// details, which are sent in the `grpc-status-details-bin` header.
+    ///
+    /// The details are usually an encoded `google.rpc.Status` message, which
+    /// the `tonic-types` crate can build from standard error details.
+    pub fn with_details(code: Code, message: impl Into<String>, details: Bytes) -> Status {
+        Status {
+            code,
+            message: message.into(),
+            details,
+        }
+    }
+
     #[cfg_attr(not(feature = "h2"), allow(dead_code))]
     pub(crate) fn from_error(err: &(dyn Error + 'static)) -> Status {
         Status::try_from_error(err).unwrap_or_else(|| Status::new(Code::Unknown, err.to_string()))
@@ -155,7 +177,13 @@ impl Status {
                 .unwrap_or_else(|| Ok(String::new()));
             let details = header_map
                 .get(GRPC_STATUS_DETAILS_HEADER)
-                .map(|h| Bytes::from(h.as_bytes()))
+                .and_then(|h| match base64::decode(h.as_bytes()) {
+                    Ok(details) => Some(Bytes::from(details)),
+                    Err(err) => {
+                        warn!("Error decoding status details header: {}", err);
+                        None
+                    }
+                })
                 .unwrap_or_else(Bytes::new);
             match error_message {
                 Ok(message) => Status {
@@ -220,10 +248,11 @@ impl Status {
         }
 
         if !self.details.is_empty() {
+            let details = base64::encode_config(&self.details[..], base64::STANDARD_NO_PAD);
+
             header_map.insert(
                 GRPC_STATUS_DETAILS_HEADER,
-                HeaderValue::from_shared(self.details.clone())
-                    .map_err(invalid_header_value_byte)?,
+                HeaderValue::from_shared(details.into()).map_err(invalid_header_value_byte)?,
             );
         }
 
@@ -505,4 +534,20 @@ mod tests {
         assert_eq!(Code::from(-1), Code::Unknown);
         assert_eq!(Code::from(Code::__NonExhaustive as i32), Code::Unknown);
     }
+
+    #[test]
+    fn details_header_roundtrip() {
+        let status = Status::with_details(
+            Code::InvalidArgument,
+            "invalid",
+            Bytes::from(&[0, 1, 255][..]),
+        );
+
+        let header_map = status.to_header_map().unwrap();
+        assert_eq!(header_map[GRPC_STATUS_DETAILS_HEADER], "AAH/");
+
+        let status = Status::from_header_map(&header_map).unwrap();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.details(), &[0, 1, 255]);
+    }
 }
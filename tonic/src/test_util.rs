@@ -0,0 +1,185 @@
+//! Helpers shared by the unit tests.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+use tracing::{
+    field::{Field, Visit},
+    span,
+    subscriber::Subscriber,
+    Event, Metadata,
+};
+use tracing_core::span::Current;
+#[cfg(feature = "transport")]
+use {
+    futures_util::future,
+    std::{
+        io,
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tokio::io::{AsyncRead, AsyncWrite},
+};
+
+/// An event recorded by a [`Recorder`].
+#[derive(Debug, Clone)]
+pub(crate) struct RecordedEvent {
+    pub(crate) target: &'static str,
+    /// The name of the span that was entered when the event was emitted.
+    pub(crate) span: Option<&'static str>,
+    /// The fields of the event other than its message, by name.
+    pub(crate) fields: Vec<(String, String)>,
+}
+
+/// A `Subscriber` that keeps track of the entered span and records every
+/// event and every field recorded on a span after it was created.
+///
+/// Clones share what they record, so one of them can be set as the default
+/// subscriber while another one is inspected.
+#[derive(Clone, Default)]
+pub(crate) struct Recorder {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    spans: Mutex<Vec<&'static Metadata<'static>>>,
+    stack: Mutex<Vec<span::Id>>,
+    events: Mutex<Vec<RecordedEvent>>,
+    span_fields: Mutex<Vec<(String, String)>>,
+}
+
+impl Recorder {
+    /// The events recorded so far.
+    pub(crate) fn events(&self) -> Vec<RecordedEvent> {
+        self.inner.events.lock().unwrap().clone()
+    }
+
+    /// The fields recorded on spans after they were created, by name.
+    pub(crate) fn span_fields(&self) -> Vec<(String, String)> {
+        self.inner.span_fields.lock().unwrap().clone()
+    }
+
+    fn metadata(&self, id: &span::Id) -> &'static Metadata<'static> {
+        self.inner.spans.lock().unwrap()[id.into_u64() as usize - 1]
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+        let mut spans = self.inner.spans.lock().unwrap();
+        spans.push(span.metadata());
+        span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _span: &span::Id, values: &span::Record<'_>) {
+        values.record(&mut Fields(&mut self.inner.span_fields.lock().unwrap()));
+    }
+
+    fn record_follows_from(&self, _span: &span::Id, _follows: &span::Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let current = self.inner.stack.lock().unwrap().last().cloned();
+
+        let mut fields = Vec::new();
+        event.record(&mut Fields(&mut fields));
+        fields.retain(|(name, _)| name != "message");
+
+        self.inner.events.lock().unwrap().push(RecordedEvent {
+            target: event.metadata().target(),
+            span: current.map(|id| self.metadata(&id).name()),
+            fields,
+        });
+    }
+
+    fn enter(&self, span: &span::Id) {
+        self.inner.stack.lock().unwrap().push(span.clone());
+    }
+
+    fn exit(&self, _span: &span::Id) {
+        self.inner.stack.lock().unwrap().pop();
+    }
+
+    fn current_span(&self) -> Current {
+        match self.inner.stack.lock().unwrap().last() {
+            Some(id) => Current::new(id.clone(), self.metadata(id)),
+            None => Current::none(),
+        }
+    }
+}
+
+struct Fields<'a>(&'a mut Vec<(String, String)>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value)));
+    }
+}
+
+/// An HTTP/2 frame with the given header fields and payload.
+#[cfg(feature = "transport")]
+pub(crate) fn frame(ty: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+    let len = (payload.len() as u32).to_be_bytes();
+    let mut frame = vec![len[1], len[2], len[3], ty, flags];
+    frame.extend_from_slice(&stream_id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// A connection that records the bytes written to it and has nothing to
+/// read.
+#[cfg(feature = "transport")]
+#[derive(Debug, Default)]
+pub(crate) struct WriteRecorder {
+    pub(crate) written: Vec<u8>,
+}
+
+#[cfg(feature = "transport")]
+impl AsyncRead for WriteRecorder {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "transport")]
+impl AsyncWrite for WriteRecorder {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.written.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Write all of `buf` to `io`, which must accept it in a single write.
+#[cfg(feature = "transport")]
+pub(crate) async fn write(io: &mut (impl AsyncWrite + Unpin), buf: &[u8]) {
+    let n = future::poll_fn(|cx| Pin::new(&mut *io).poll_write(cx, buf))
+        .await
+        .unwrap();
+    assert_eq!(n, buf.len());
+}
@@ -0,0 +1,78 @@
+use std::future::Future;
+use tokio::sync::watch;
+
+/// Signals that a request received by a [`Server`] was cancelled, obtained
+/// via [`Request::cancellation_token`].
+///
+/// A request is cancelled when the client resets its stream, the connection
+/// is closed, or its deadline elapses before the response was sent. Handlers
+/// that hand off work to other tasks, e.g. to produce the messages of a
+/// streaming response, can use this to stop that work early. A request whose
+/// response was sent completely, or whose handler failed, is not cancelled.
+///
+/// ```
+/// # use tonic::{Code, Request, Response, ResponseStream, Status};
+/// # pub struct Feature {}
+/// # fn lookup(_: usize) -> Feature { Feature {} }
+/// fn list_features(request: Request<()>) -> Result<Response<ResponseStream<Feature>>, Status> {
+///     let token = request
+///         .cancellation_token()
+///         .ok_or_else(|| Status::new(Code::Internal, "not received by a server"))?;
+///     let (mut tx, response) = Response::channel(4);
+///
+///     tokio::spawn(async move {
+///         for i in 0..100 {
+///             if token.is_cancelled() {
+///                 break;
+///             }
+///
+///             let feature = lookup(i);
+///             if tx.send(Ok(feature)).await.is_err() {
+///                 break;
+///             }
+///         }
+///     });
+///
+///     Ok(response)
+/// }
+/// ```
+///
+/// [`Server`]: struct.Server.html
+/// [`Request::cancellation_token`]: ../struct.Request.html#method.cancellation_token
+#[derive(Debug, Clone)]
+pub struct CancellationToken {
+    rx: watch::Receiver<bool>,
+}
+
+impl CancellationToken {
+    pub(crate) fn new() -> (watch::Sender<bool>, Self) {
+        let (tx, rx) = watch::channel(false);
+        (tx, CancellationToken { rx })
+    }
+
+    /// Whether the request was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.rx.get_ref()
+    }
+
+    /// Wait until the request is cancelled.
+    ///
+    /// This never completes for a request that finished without being
+    /// cancelled.
+    pub fn cancelled(&self) -> impl Future<Output = ()> + Send + 'static {
+        let mut rx = self.rx.clone();
+
+        async move {
+            loop {
+                if *rx.get_ref() {
+                    return;
+                }
+
+                if rx.recv().await.is_none() {
+                    // the request finished
+                    futures_util::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+}
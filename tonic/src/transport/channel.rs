@@ -1,49 +1,187 @@
 //! Client implementation and builder.
 
 use super::{
-    service::{Connection, ServiceList},
-    Endpoint,
+    channelz::{self, CallGuard, ChannelNode},
+    service::{
+        Backpressure, Connection, Connector, DynamicServiceList, NotReady, Requeue, Retry,
+        ServiceList,
+    },
+    Addresses, Certificate, Endpoint, Resolver, RetryPolicy,
+};
+use crate::{
+    body::BoxBody,
+    client::GrpcService,
+    codec::{
+        CompressBody, CompressionConfig, CompressionEncoding, ACCEPT_ENCODING_HEADER,
+        ENCODING_HEADER,
+    },
+    credentials::Credentials,
+    request::WaitForReady,
+    AsyncInterceptor, Interceptor, Status,
 };
-use crate::{body::BoxBody, client::GrpcService};
 use bytes::Bytes;
+use futures_core::Stream;
+use futures_util::{future, stream, StreamExt, TryFutureExt};
 use http::{
     uri::{InvalidUriBytes, Uri},
     Request, Response,
 };
 use std::{
+    collections::HashSet,
     fmt,
     future::Future,
+    hash::Hash,
+    net::SocketAddr,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     task::{Context, Poll},
 };
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    sync::watch,
+};
 use tower::{
     buffer::{self, Buffer},
     discover::Discover,
-    util::{BoxService, Either},
+    layer::Layer,
+    util::{BoxService, Either, Oneshot},
     Service,
 };
 use tower_balance::p2c::Balance;
+use tower_make::MakeConnection;
+use tracing::debug;
+
+pub use tower::discover::Change;
+
+type Svc = Either<Connection, ClientService>;
+
+type BoxFuture =
+    Pin<Box<dyn Future<Output = Result<Response<hyper::Body>, crate::Error>> + Send + 'static>>;
 
-type Svc = Either<Connection, BoxService<Request<BoxBody>, Response<hyper::Body>, crate::Error>>;
+type RetryFuture =
+    <Retry<Backpressure<Buffer<Svc, Request<BoxBody>>>> as Service<Request<BoxBody>>>::Future;
 
-const DEFAULT_BUFFER_SIZE: usize = 1024;
+pub(super) type ClientService = BoxService<Request<BoxBody>, Response<hyper::Body>, crate::Error>;
+pub(super) type ClientLayer =
+    Arc<dyn Layer<ClientService, Service = ClientService> + Send + Sync + 'static>;
+pub(super) type HeaderInterceptor = Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync + 'static>;
+
+pub(super) const DEFAULT_BUFFER_SIZE: usize = 1024;
 
 /// A default batteries included `transport` channel.
 ///
 /// This provides a fully featured http2 gRPC client based on [`hyper::Client`]
 /// and `tower` services.
+///
+/// # Tracing
+///
+/// When a request is dispatched onto a connection the following fields are
+/// recorded on the span that was current when the request was issued, if the
+/// span declares them (e.g. as `tracing::field::Empty`):
+///
+/// - `grpc.endpoint`: the URI of the endpoint the request was sent to, which
+///   for a balanced channel identifies the selected endpoint.
+/// - `grpc.connectivity_state`: the state of the connection at dispatch time,
+///   either `ready` or `transient_failure` while the channel is backing off
+///   before reconnecting.
 #[derive(Clone)]
 pub struct Channel {
-    svc: Buffer<Svc, Request<BoxBody>>,
-    interceptor_headers: Option<Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync + 'static>>,
+    svc: Backpressure<Buffer<Svc, Request<BoxBody>>>,
+    interceptor_headers: Option<HeaderInterceptor>,
+    interceptor: Option<Interceptor>,
+    async_interceptor: Option<AsyncInterceptor>,
+    secure: bool,
+    connection_info: Option<Arc<Mutex<Option<ConnectionInfo>>>>,
+    connectivity_state: Option<watch::Receiver<ConnectivityState>>,
+    reset_backoff: Option<Arc<AtomicBool>>,
+    compression: CompressionConfig,
+    /// Whether to compress request messages, shared by all clones.
+    send_gzip: Arc<AtomicBool>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    channelz: Option<Arc<ChannelNode>>,
+}
+
+/// Information about the connection a [`Channel`] has established.
+///
+/// This is captured every time the underlying connection is (re)established
+/// and can be fetched via [`Channel::connection_info`].
+///
+/// On the server, every request carries the `ConnectionInfo` of the
+/// connection it arrived on in its extensions. With a client CA configured
+/// via [`ServerTlsConfig::client_ca_root`], its [`peer_certificates`] are the
+/// verified certificates of the client.
+///
+/// ```
+/// # use tonic::{transport::ConnectionInfo, Code, Request, Status};
+/// fn client_cert(request: &Request<()>) -> Result<&[u8], Status> {
+///     request
+///         .extensions()
+///         .get::<ConnectionInfo>()
+///         .and_then(|info| info.peer_certificates())
+///         .and_then(|certs| certs.first())
+///         .map(|cert| cert.get_ref())
+///         .ok_or_else(|| Status::new(Code::Unauthenticated, "no client certificate"))
+/// }
+/// ```
+///
+/// [`ServerTlsConfig::client_ca_root`]: struct.ServerTlsConfig.html#method.client_ca_root
+/// [`peer_certificates`]: #method.peer_certificates
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    pub(crate) remote_addr: Option<SocketAddr>,
+    pub(crate) alpn_protocol: Option<Vec<u8>>,
+    pub(crate) peer_certificates: Option<Vec<Certificate>>,
+}
+
+/// The connectivity state of a [`Channel`], as fetched via [`Channel::state`].
+///
+/// The states follow the gRPC connectivity semantics. Since a channel only
+/// (re)connects while it is used, its state only changes while requests are
+/// issued on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConnectivityState {
+    /// The channel is not connected and has not tried to connect yet, e.g. a
+    /// channel created via [`Endpoint::connect_lazy`] before its first
+    /// request, or after its connection was lost.
+    ///
+    /// [`Endpoint::connect_lazy`]: struct.Endpoint.html#method.connect_lazy
+    Idle,
+    /// The channel is establishing a connection.
+    Connecting,
+    /// The channel is connected and requests are sent right away.
+    Ready,
+    /// The last connection attempt failed and the channel backs off before
+    /// the next one. Requests fail with `UNAVAILABLE` in this state, unless
+    /// they are set to [wait for ready].
+    ///
+    /// [wait for ready]: ../struct.Request.html#method.set_wait_for_ready
+    TransientFailure,
+    /// The channel was shut down and no longer sends requests.
+    Shutdown,
 }
 
 /// A future that resolves to an HTTP response.
 ///
 /// This is returned by the `Service::call` on [`Channel`].
 pub struct ResponseFuture {
-    inner: buffer::future::ResponseFuture<<Svc as Service<Request<BoxBody>>>::Future>,
+    inner: Inner,
+    /// Counts the call in channelz once it is finished.
+    call: Option<CallGuard>,
+}
+
+enum Inner {
+    Call(buffer::future::ResponseFuture<<Svc as Service<Request<BoxBody>>>::Future>),
+    /// Waiting for the async interceptor before sending the request.
+    Intercept(BoxFuture),
+    /// Sending the request once the channel is ready.
+    WaitForReady(BoxFuture),
+    /// Sending the request, retrying it if it fails.
+    Retry(RetryFuture),
+    /// The request was rejected by the interceptor.
+    Rejected(Option<Status>),
 }
 
 impl Channel {
@@ -79,60 +217,403 @@ impl Channel {
     /// This creates a [`Channel`] that will load balance accross all the
     /// provided endpoints.
     pub fn balance_list(list: impl Iterator<Item = Endpoint>) -> Self {
-        let list = list.collect::<Vec<_>>();
+        let mut list = list.collect::<Vec<_>>();
 
-        let buffer_size = list
-            .iter()
-            .next()
-            .and_then(|e| e.buffer_size)
-            .unwrap_or(DEFAULT_BUFFER_SIZE);
+        // channel wide settings are taken from the first endpoint
+        let channelz = list.first().and_then(register_channelz);
+        for endpoint in &mut list {
+            endpoint.channelz_channel = channelz.clone();
+        }
+        let settings = list.first().cloned();
+        let discover = ServiceList::new(list);
 
-        let interceptor_headers = list
-            .iter()
-            .next()
-            .and_then(|e| e.interceptor_headers.clone());
+        Self::balance(discover, settings.as_ref())
+    }
 
-        let discover = ServiceList::new(list);
+    /// Balance over a changing set of [`Endpoint`]'s.
+    ///
+    /// This creates a [`Channel`] that will load balance across the endpoints
+    /// inserted by `changes`, e.g. as a service discovery mechanism resolves
+    /// the replicas of a backend. Every endpoint is identified by a key, which
+    /// is used to remove it again with [`Change::Remove`]. Inserting an
+    /// endpoint under a key that is already in use replaces it.
+    ///
+    /// Endpoints are connected to in the background and only receive requests
+    /// once connected. An endpoint that fails to connect is dropped and has to
+    /// be inserted again. Requests issued while no endpoint is connected wait
+    /// for one. When `changes` ends, the channel keeps balancing over the
+    /// endpoints it has.
+    ///
+    /// Per endpoint settings such as timeouts and TLS apply to each endpoint's
+    /// connection. Channel wide settings such as the buffer size use their
+    /// defaults.
+    ///
+    /// ```
+    /// # use tonic::transport::{channel::Change, Channel, Endpoint};
+    /// # #[tokio::main]
+    /// # async fn main() {
+    /// let (tx, rx) = futures_channel::mpsc::unbounded();
+    /// let channel = Channel::balance_stream(rx);
+    ///
+    /// tx.unbounded_send(Change::Insert("a", Endpoint::from_static("http://[::1]:50051")))
+    ///     .unwrap();
+    /// tx.unbounded_send(Change::Remove("a")).unwrap();
+    /// # drop(channel);
+    /// # }
+    /// ```
+    pub fn balance_stream<K, S>(changes: S) -> Self
+    where
+        K: Hash + Eq + Clone + Send + Unpin + fmt::Debug + 'static,
+        S: Stream<Item = Change<K, Endpoint>> + Send + 'static,
+    {
+        let discover = DynamicServiceList::new(Box::pin(changes));
+
+        Self::balance(discover, None)
+    }
+
+    /// Get information about the currently established connection.
+    ///
+    /// This includes the remote address and, when TLS is in use, the negotiated
+    /// ALPN protocol and the peer's certificate chain. The information is
+    /// refreshed whenever the channel reconnects.
+    ///
+    /// Returns `None` for channels created via [`Channel::balance_list`] or
+    /// [`Channel::balance_stream`] since they are backed by more than one
+    /// connection.
+    pub fn connection_info(&self) -> Option<ConnectionInfo> {
+        self.connection_info
+            .as_ref()
+            .and_then(|info| info.lock().unwrap().clone())
+    }
+
+    /// Get the current connectivity state of the channel.
+    ///
+    /// Returns `None` for channels created via [`Channel::balance_list`] or
+    /// [`Channel::balance_stream`] since they are backed by more than one
+    /// connection.
+    pub fn state(&self) -> Option<ConnectivityState> {
+        self.connectivity_state
+            .as_ref()
+            .map(|state| *state.get_ref())
+    }
+
+    /// Wait for the connectivity state of the channel to change from
+    /// `current`, returning the new state.
+    ///
+    /// This returns right away if the state already differs from `current`.
+    /// As with [`Channel::state`], this returns `None` for balanced channels.
+    ///
+    /// ```no_run
+    /// # use tonic::transport::{Channel, ConnectivityState};
+    /// # async fn watch(channel: Channel) {
+    /// let mut state = channel.state().unwrap();
+    /// while state != ConnectivityState::Shutdown {
+    ///     state = channel.wait_for_state_change(state).await.unwrap();
+    ///     println!("channel is now {:?}", state);
+    /// }
+    /// # }
+    /// ```
+    pub async fn wait_for_state_change(
+        &self,
+        current: ConnectivityState,
+    ) -> Option<ConnectivityState> {
+        let mut states = self.connectivity_state.clone()?;
+
+        if *states.get_ref() != current {
+            return Some(*states.get_ref());
+        }
 
-        Self::balance(discover, buffer_size, interceptor_headers)
+        loop {
+            match states.recv().await {
+                Some(state) if state != current => return Some(state),
+                Some(_) => {}
+                None => return Some(ConnectivityState::Shutdown),
+            }
+        }
     }
 
-    pub(crate) async fn connect(endpoint: Endpoint) -> Result<Self, super::Error> {
-        let buffer_size = endpoint.buffer_size.clone().unwrap_or(DEFAULT_BUFFER_SIZE);
-        let interceptor_headers = endpoint.interceptor_headers.clone();
+    /// Reset the reconnect backoff of the channel.
+    ///
+    /// If the channel is backing off before reconnecting, see
+    /// [`Endpoint::reconnect_backoff`], the next request makes a new
+    /// connection attempt right away instead of failing with `UNAVAILABLE`.
+    /// The backoff then starts again from its initial delay. This is useful
+    /// e.g. once the network is known to be back up.
+    ///
+    /// This has no effect on channels created via [`Channel::balance_list`] or
+    /// [`Channel::balance_stream`].
+    ///
+    /// [`Endpoint::reconnect_backoff`]: struct.Endpoint.html#method.reconnect_backoff
+    pub fn reset_connect_backoff(&self) {
+        if let Some(reset) = &self.reset_backoff {
+            reset.store(true, Ordering::SeqCst);
+        }
+    }
 
-        let svc = Connection::new(endpoint)
+    /// Set whether to compress request messages with gzip.
+    ///
+    /// This changes the setting made via [`Endpoint::send_gzip`] for the
+    /// requests issued after this call on this channel and all of its clones,
+    /// without reconnecting. Requests in flight keep the setting they were
+    /// issued with.
+    ///
+    /// ```
+    /// # use tonic::transport::Channel;
+    /// # fn set(channel: Channel) {
+    /// // e.g. once the network turned out to be metered
+    /// channel.set_compression(true);
+    /// # }
+    /// ```
+    ///
+    /// [`Endpoint::send_gzip`]: struct.Endpoint.html#method.send_gzip
+    pub fn set_compression(&self, send_gzip: bool) {
+        self.send_gzip.store(send_gzip, Ordering::SeqCst);
+    }
+
+    pub(crate) async fn connect(mut endpoint: Endpoint) -> Result<Self, super::Error> {
+        endpoint.channelz_channel = register_channelz(&endpoint);
+        let connector = Connector::http(&endpoint);
+        let svc = Connection::new(endpoint.clone(), connector)
             .await
             .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))?;
 
-        let svc = Buffer::new(Either::A(svc), buffer_size);
+        Ok(Self::new(svc, endpoint))
+    }
+
+    pub(crate) async fn connect_resolved(
+        endpoint: Endpoint,
+        resolver: Arc<dyn Resolver>,
+    ) -> Result<Self, super::Error> {
+        let mut addresses = resolver.resolve(&endpoint.uri);
+        let first = match addresses.next().await {
+            Some(Ok(first)) => first,
+            Some(Err(error)) => {
+                return Err(super::Error::from_source(super::ErrorKind::Client, error))
+            }
+            None => Vec::new(),
+        };
 
-        Ok(Channel {
+        let addresses = stream::once(future::ok(first)).chain(addresses);
+        Ok(Self::resolved(endpoint, Box::pin(addresses)))
+    }
+
+    /// Balance over the addresses of `endpoint` yielded by `addresses`.
+    pub(crate) fn resolved(mut endpoint: Endpoint, addresses: Addresses) -> Self {
+        endpoint.channelz_channel = register_channelz(&endpoint);
+
+        // every address is connected to as an endpoint of its own, which
+        // keeps the origin and TLS settings of `endpoint`
+        let scheme = endpoint.uri.scheme_str().unwrap_or("http").to_string();
+        let mut template = endpoint.clone();
+        template.origin = Some(endpoint.origin_uri().clone());
+        template.resolver = None;
+
+        let mut current = HashSet::new();
+        let changes = addresses
+            .filter_map(|addresses| {
+                future::ready(match addresses {
+                    Ok(addresses) => Some(addresses),
+                    Err(error) => {
+                        debug!(message = "failed to resolve endpoint", %error);
+                        None
+                    }
+                })
+            })
+            .map(move |addresses| {
+                let addresses = addresses.into_iter().collect::<HashSet<_>>();
+                let mut changes = current
+                    .difference(&addresses)
+                    .map(|addr| Change::Remove(*addr))
+                    .collect::<Vec<_>>();
+                for addr in addresses.difference(&current) {
+                    let mut endpoint = template.clone();
+                    endpoint.uri = format!("{}://{}", scheme, addr)
+                        .parse()
+                        .expect("valid endpoint URI");
+                    changes.push(Change::Insert(*addr, endpoint));
+                }
+
+                current = addresses;
+                stream::iter(changes)
+            })
+            .flatten();
+
+        Self::balance(DynamicServiceList::new(Box::pin(changes)), Some(&endpoint))
+    }
+
+    pub(crate) fn connect_lazy(mut endpoint: Endpoint) -> Self {
+        endpoint.channelz_channel = register_channelz(&endpoint);
+        let connector = Connector::http(&endpoint);
+        let svc = Connection::lazy(endpoint.clone(), connector);
+        Self::new(svc, endpoint)
+    }
+
+    pub(crate) async fn connect_with_connector<C>(
+        mut endpoint: Endpoint,
+        connector: C,
+    ) -> Result<Self, super::Error>
+    where
+        C: MakeConnection<Uri> + Send + 'static,
+        C::Connection: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        endpoint.channelz_channel = register_channelz(&endpoint);
+        let connector = Connector::new(connector, |_| None, &endpoint);
+        let svc = Connection::new(endpoint.clone(), connector)
+            .await
+            .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))?;
+
+        Ok(Self::new(svc, endpoint))
+    }
+
+    fn new(svc: Connection, endpoint: Endpoint) -> Self {
+        let secure = endpoint.secure();
+        let buffer_size = endpoint.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE);
+        let buffer_full_policy = endpoint.buffer_full_policy;
+        let buffer_full_status = endpoint.buffer_full_status;
+        let interceptor_headers = endpoint.interceptor_headers;
+        let interceptor = endpoint.interceptor;
+        let async_interceptor = endpoint.async_interceptor;
+        let compression = endpoint.compression;
+        let retry_policy = endpoint.retry_policy;
+        let channelz = endpoint.channelz_channel;
+        let connection_info = Some(svc.connection_info());
+        let connectivity_state = Some(svc.connectivity_state());
+        let reset_backoff = Some(svc.reset_backoff());
+
+        let svc = if endpoint.layers.is_empty() {
+            Either::A(svc)
+        } else {
+            Either::B(apply_layers(&endpoint.layers, BoxService::new(svc)))
+        };
+        let svc = Buffer::new(svc, buffer_size);
+        let svc = Backpressure::new(svc, buffer_full_policy, buffer_full_status);
+
+        Channel {
             svc,
             interceptor_headers,
-        })
+            interceptor,
+            async_interceptor,
+            secure,
+            connection_info,
+            connectivity_state,
+            reset_backoff,
+            compression,
+            send_gzip: Arc::new(AtomicBool::new(compression.send_gzip)),
+            retry_policy,
+            channelz,
+        }
     }
 
-    pub(crate) fn balance<D>(
-        discover: D,
-        buffer_size: usize,
-        interceptor_headers: Option<Arc<dyn Fn(&mut http::HeaderMap) + Send + Sync + 'static>>,
-    ) -> Self
+    /// Balance over the endpoints of `discover`, applying the channel wide
+    /// settings of `settings` or the defaults if there is none.
+    pub(crate) fn balance<D>(discover: D, settings: Option<&Endpoint>) -> Self
     where
         D: Discover<Service = Connection> + Unpin + Send + 'static,
         D::Error: Into<crate::Error>,
         D::Key: Send + Clone,
     {
+        let buffer_size = settings
+            .and_then(|e| e.buffer_size)
+            .unwrap_or(DEFAULT_BUFFER_SIZE);
+        let buffer_full_policy = settings.map(|e| e.buffer_full_policy).unwrap_or_default();
+        let buffer_full_status = settings.and_then(|e| e.buffer_full_status.clone());
+        let compression = settings.map(|e| e.compression).unwrap_or_default();
+
         let svc = Balance::from_entropy(discover);
 
         let svc = BoxService::new(svc);
+        let svc = match settings {
+            Some(settings) => apply_layers(&settings.layers, svc),
+            None => svc,
+        };
         let svc = Buffer::new(Either::B(svc), buffer_size);
+        let svc = Backpressure::new(svc, buffer_full_policy, buffer_full_status);
 
         Channel {
             svc,
-            interceptor_headers,
+            interceptor_headers: settings.and_then(|e| e.interceptor_headers.clone()),
+            interceptor: settings.and_then(|e| e.interceptor.clone()),
+            async_interceptor: settings.and_then(|e| e.async_interceptor.clone()),
+            secure: settings.is_some_and(Endpoint::secure),
+            connection_info: None,
+            connectivity_state: None,
+            reset_backoff: None,
+            compression,
+            send_gzip: Arc::new(AtomicBool::new(compression.send_gzip)),
+            retry_policy: settings.and_then(|e| e.retry_policy.clone()),
+            channelz: settings.and_then(|e| e.channelz_channel.clone()),
+        }
+    }
+}
+
+/// Register the channel created from `endpoint` with channelz, if the
+/// endpoint is set to.
+fn register_channelz(endpoint: &Endpoint) -> Option<Arc<ChannelNode>> {
+    let channelz = endpoint.channelz.as_ref()?;
+    Some(channelz.register_channel(endpoint.uri.to_string()))
+}
+
+/// Send `request`, sending it again whenever it fails because the channel is
+/// backing off before reconnecting.
+fn requeue(
+    svc: Backpressure<Buffer<Svc, Request<BoxBody>>>,
+    retry_policy: Option<Arc<RetryPolicy>>,
+    request: Request<BoxBody>,
+) -> BoxFuture {
+    let svc = Requeue::new(svc);
+    match retry_policy {
+        Some(policy) => Box::pin(Oneshot::new(Retry::new(svc, policy), request)),
+        None => Box::pin(Oneshot::new(svc, request)),
+    }
+}
+
+fn wait_for_ready(request: &Request<BoxBody>) -> bool {
+    request.extensions().get::<WaitForReady>().is_some()
+}
+
+/// Fail a request that was issued while the channel was backing off with its
+/// status, dropping the request that is kept in the error.
+fn not_ready_into_status(error: crate::Error) -> crate::Error {
+    match error.downcast::<NotReady<Request<BoxBody>>>() {
+        Ok(not_ready) => not_ready.into_status().into(),
+        Err(error) => error,
+    }
+}
+
+/// Wrap `svc` in `layers`, the first of which ends up outermost.
+fn apply_layers(layers: &[ClientLayer], svc: ClientService) -> ClientService {
+    layers.iter().rev().fold(svc, |svc, layer| layer.layer(svc))
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(remote_addr: Option<SocketAddr>) -> Self {
+        Self {
+            remote_addr,
+            alpn_protocol: None,
+            peer_certificates: None,
         }
     }
+
+    /// The address of the remote peer.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The protocol negotiated via ALPN, if TLS is in use.
+    ///
+    /// For a successfully established TLS connection this is always `h2`.
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_ref().map(|p| &p[..])
+    }
+
+    /// The certificate chain presented by the peer, if TLS is in use.
+    ///
+    /// The first certificate is the peer's own certificate.
+    pub fn peer_certificates(&self) -> Option<&[Certificate]> {
+        self.peer_certificates.as_ref().map(|c| &c[..])
+    }
 }
 
 impl GrpcService<BoxBody> for Channel {
@@ -146,12 +627,99 @@ impl GrpcService<BoxBody> for Channel {
     }
 
     fn call(&mut self, mut request: Request<BoxBody>) -> Self::Future {
+        let tracked = self.channelz.as_ref().map(|node| node.calls().track());
+
         if let Some(interceptor) = self.interceptor_headers.clone() {
             interceptor(request.headers_mut());
         }
 
+        if let Some(interceptor) = &self.interceptor {
+            request = match interceptor.call(request) {
+                Ok(request) => request,
+                Err(status) => {
+                    return ResponseFuture {
+                        inner: Inner::Rejected(Some(status)),
+                        call: tracked,
+                    }
+                }
+            };
+        }
+
+        if self.send_gzip.load(Ordering::SeqCst) {
+            let encoding = CompressionEncoding::Gzip;
+            request
+                .headers_mut()
+                .insert(ENCODING_HEADER, encoding.into_header_value());
+            request = request.map(|body| BoxBody::new(CompressBody::new(body, encoding)));
+        }
+
+        if self.compression.accept_gzip {
+            request.headers_mut().insert(
+                ACCEPT_ENCODING_HEADER,
+                self.compression.accept_encoding_header(),
+            );
+        }
+
+        let credentials = request.extensions_mut().remove::<Credentials>();
+        if self.async_interceptor.is_some() || credentials.is_some() {
+            let interceptor = self.async_interceptor.clone();
+            let secure = self.secure;
+            let intercepted = async move {
+                let request = match interceptor {
+                    Some(interceptor) => interceptor.call(request).await?,
+                    None => request,
+                };
+                match credentials {
+                    Some(credentials) => credentials.apply(request, secure).await,
+                    None => Ok(request),
+                }
+            };
+            // the request can only be buffered once it is intercepted, by
+            // then this channel may have been used for other requests
+            let svc = self.svc.clone();
+            let retry_policy = self.retry_policy.clone();
+            let call = intercepted.err_into().and_then(move |request| {
+                if wait_for_ready(&request) {
+                    return future::Either::Left(requeue(svc, retry_policy, request));
+                }
+
+                future::Either::Right(match retry_policy {
+                    Some(policy) => {
+                        future::Either::Left(Oneshot::new(Retry::new(svc, policy), request))
+                    }
+                    None => future::Either::Right(Oneshot::new(svc, request)),
+                })
+            });
+
+            return ResponseFuture {
+                inner: Inner::Intercept(Box::pin(call)),
+                call: tracked,
+            };
+        }
+
+        if wait_for_ready(&request) {
+            let call = requeue(self.svc.clone(), self.retry_policy.clone(), request);
+            return ResponseFuture {
+                inner: Inner::WaitForReady(call),
+                call: tracked,
+            };
+        }
+
+        if let Some(policy) = &self.retry_policy {
+            // every attempt is buffered on its own, the slot reserved by
+            // `poll_ready` is kept for the next request
+            let mut retry = Retry::new(self.svc.clone(), policy.clone());
+            return ResponseFuture {
+                inner: Inner::Retry(Service::call(&mut retry, request)),
+                call: tracked,
+            };
+        }
+
         let inner = GrpcService::call(&mut self.svc, request);
-        ResponseFuture { inner }
+        ResponseFuture {
+            inner: Inner::Call(inner),
+            call: tracked,
+        }
     }
 }
 
@@ -159,8 +727,24 @@ impl Future for ResponseFuture {
     type Output = Result<Response<hyper::Body>, super::Error>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let val = futures_util::ready!(Pin::new(&mut self.inner).poll(cx))
-            .map_err(|e| super::Error::from_source(super::ErrorKind::Client, e))?;
+        let val = match &mut self.inner {
+            Inner::Call(inner) => futures_util::ready!(Pin::new(inner).poll(cx)),
+            Inner::Intercept(inner) => futures_util::ready!(inner.as_mut().poll(cx)),
+            Inner::WaitForReady(inner) => futures_util::ready!(inner.as_mut().poll(cx)),
+            Inner::Retry(inner) => futures_util::ready!(inner.as_mut().poll(cx)),
+            Inner::Rejected(status) => {
+                let status = status.take().expect("polled after completion");
+                Err(status.into())
+            }
+        };
+
+        if let Some(call) = self.call.take() {
+            call.finish(channelz::call_succeeded(&val));
+        }
+
+        let val = val.map_err(|e| {
+            super::Error::from_source(super::ErrorKind::Client, not_ready_into_status(e))
+        })?;
         Ok(val).into()
     }
 }
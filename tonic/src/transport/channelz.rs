@@ -0,0 +1,599 @@
+//! Runtime introspection of channels and servers, modeled after the
+//! [channelz] service of gRPC.
+//!
+//! [channelz]: https://github.com/grpc/proposal/blob/master/A14-channelz.md
+
+use super::{ConnectivityState, RpcStats, StatsHandler};
+use crate::Code;
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    time::SystemTime,
+};
+use tokio::sync::watch;
+
+/// A registry of the channels and servers of a process, along with their
+/// connections and call counts.
+///
+/// Channels register with it via [`Endpoint::channelz`] and servers via
+/// [`Server::channelz`]. Every channel has a subchannel per endpoint it
+/// connects to, every subchannel and server has a socket per connection.
+/// Entries are removed once the channel is dropped, the server stops or the
+/// connection is closed.
+///
+/// ```
+/// # use tonic::transport::{Channelz, Endpoint, Server};
+/// let channelz = Channelz::new();
+///
+/// let endpoint = Endpoint::from_static("http://[::1]:50051").channelz(channelz.clone());
+/// let server = Server::builder().channelz(channelz.clone());
+///
+/// for channel in channelz.channels() {
+///     println!("{} is {:?}", channel.target(), channel.state());
+/// }
+/// ```
+///
+/// Calls are counted as they are sent and received, so a client call that
+/// fails with a status sent in the trailers of its response is counted as
+/// succeeded by channels and subchannels. Only transport errors and statuses
+/// sent without a response body count as failed there. Servers count calls
+/// by the status they answer with.
+///
+/// [`Endpoint::channelz`]: struct.Endpoint.html#method.channelz
+/// [`Server::channelz`]: struct.Server.html#method.channelz
+#[derive(Clone, Default)]
+pub struct Channelz {
+    registry: Arc<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: AtomicU64,
+    channels: Children<ChannelNode>,
+    servers: Children<ServerNode>,
+}
+
+impl Registry {
+    fn next_id(&self) -> u64 {
+        self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+impl Channelz {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The channels that are registered, in the order they were created.
+    pub fn channels(&self) -> Vec<ChannelInfo> {
+        self.registry
+            .channels
+            .get()
+            .iter()
+            .map(|node| node.info())
+            .collect()
+    }
+
+    /// The servers that are registered, in the order they started serving.
+    pub fn servers(&self) -> Vec<ServerInfo> {
+        self.registry
+            .servers
+            .get()
+            .iter()
+            .map(|node| node.info())
+            .collect()
+    }
+
+    pub(crate) fn register_channel(&self, target: String) -> Arc<ChannelNode> {
+        let node = Arc::new(ChannelNode {
+            id: self.registry.next_id(),
+            target,
+            calls: Arc::default(),
+            subchannels: Children::default(),
+            registry: self.registry.clone(),
+        });
+        self.registry.channels.add(&node);
+        node
+    }
+
+    pub(crate) fn register_server(&self) -> Arc<ServerNode> {
+        let node = Arc::new(ServerNode {
+            id: self.registry.next_id(),
+            calls: CallMetrics::default(),
+            sockets: Children::default(),
+            registry: self.registry.clone(),
+        });
+        self.registry.servers.add(&node);
+        node
+    }
+}
+
+impl fmt::Debug for Channelz {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channelz").finish()
+    }
+}
+
+/// A snapshot of a channel, as returned by [`Channelz::channels`].
+///
+/// [`Channelz::channels`]: struct.Channelz.html#method.channels
+#[derive(Debug, Clone)]
+pub struct ChannelInfo {
+    id: u64,
+    target: String,
+    state: ConnectivityState,
+    calls: CallCounts,
+    subchannels: Vec<SubchannelInfo>,
+}
+
+impl ChannelInfo {
+    /// The id of the channel, unique within its registry.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The URI of the endpoint the channel was created from, or of the first
+    /// endpoint of a balanced channel.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The connectivity state of the channel, which is `Ready` if any of its
+    /// subchannels is, and otherwise follows the most promising state of
+    /// its subchannels.
+    pub fn state(&self) -> ConnectivityState {
+        self.state
+    }
+
+    /// The calls issued on the channel.
+    pub fn calls(&self) -> &CallCounts {
+        &self.calls
+    }
+
+    /// The subchannels of the channel, one per endpoint it connects to.
+    pub fn subchannels(&self) -> &[SubchannelInfo] {
+        &self.subchannels
+    }
+}
+
+/// A snapshot of the connection of a channel to one of its endpoints.
+#[derive(Debug, Clone)]
+pub struct SubchannelInfo {
+    id: u64,
+    target: String,
+    state: ConnectivityState,
+    calls: CallCounts,
+    sockets: Vec<SocketInfo>,
+}
+
+impl SubchannelInfo {
+    /// The id of the subchannel, unique within its registry.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The URI of the endpoint the subchannel connects to.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The connectivity state of the subchannel.
+    pub fn state(&self) -> ConnectivityState {
+        self.state
+    }
+
+    /// The calls sent on the subchannel, counting every attempt of a retried
+    /// call.
+    pub fn calls(&self) -> &CallCounts {
+        &self.calls
+    }
+
+    /// The connections of the subchannel that are open, at most one unless
+    /// a previous connection is still closing.
+    pub fn sockets(&self) -> &[SocketInfo] {
+        &self.sockets
+    }
+}
+
+/// A snapshot of a server, as returned by [`Channelz::servers`].
+///
+/// [`Channelz::servers`]: struct.Channelz.html#method.servers
+#[derive(Debug, Clone)]
+pub struct ServerInfo {
+    id: u64,
+    calls: CallCounts,
+    sockets: Vec<SocketInfo>,
+}
+
+impl ServerInfo {
+    /// The id of the server, unique within its registry.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The calls received by the server. A call succeeded if it was
+    /// answered with `OK`.
+    pub fn calls(&self) -> &CallCounts {
+        &self.calls
+    }
+
+    /// The connections accepted by the server that are open.
+    pub fn sockets(&self) -> &[SocketInfo] {
+        &self.sockets
+    }
+}
+
+/// A snapshot of a connection of a subchannel or server.
+#[derive(Debug, Clone)]
+pub struct SocketInfo {
+    id: u64,
+    remote_addr: Option<SocketAddr>,
+    streams: CallCounts,
+}
+
+impl SocketInfo {
+    /// The id of the socket, unique within its registry.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The address of the peer, if the connection has one.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    /// The HTTP/2 streams opened on the connection. A stream succeeded if
+    /// both sides ended it, and failed if either side reset it.
+    pub fn streams(&self) -> &CallCounts {
+        &self.streams
+    }
+}
+
+/// The number of calls or streams that were started and finished.
+///
+/// Those that were started but neither succeeded nor failed are still in
+/// progress.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CallCounts {
+    started: u64,
+    succeeded: u64,
+    failed: u64,
+    last_started: Option<SystemTime>,
+}
+
+impl CallCounts {
+    /// The number of calls that were started.
+    pub fn started(&self) -> u64 {
+        self.started
+    }
+
+    /// The number of calls that succeeded.
+    pub fn succeeded(&self) -> u64 {
+        self.succeeded
+    }
+
+    /// The number of calls that failed, including cancelled calls.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+
+    /// When the last call was started, if any was.
+    pub fn last_started(&self) -> Option<SystemTime> {
+        self.last_started
+    }
+}
+
+/// A list of nodes that are removed once their owner drops them.
+struct Children<T>(Mutex<Vec<Weak<T>>>);
+
+impl<T> Children<T> {
+    fn add(&self, node: &Arc<T>) {
+        let mut nodes = self.0.lock().unwrap();
+        nodes.retain(|node| node.strong_count() > 0);
+        nodes.push(Arc::downgrade(node));
+    }
+
+    fn get(&self) -> Vec<Arc<T>> {
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect()
+    }
+}
+
+impl<T> Default for Children<T> {
+    fn default() -> Self {
+        Children(Mutex::new(Vec::new()))
+    }
+}
+
+pub(crate) struct ChannelNode {
+    id: u64,
+    target: String,
+    calls: Arc<CallMetrics>,
+    subchannels: Children<SubchannelNode>,
+    registry: Arc<Registry>,
+}
+
+impl ChannelNode {
+    pub(crate) fn calls(&self) -> &Arc<CallMetrics> {
+        &self.calls
+    }
+
+    pub(crate) fn add_subchannel(&self, target: String) -> Arc<SubchannelNode> {
+        let node = Arc::new(SubchannelNode {
+            id: self.registry.next_id(),
+            target,
+            state: Mutex::new(None),
+            calls: Arc::default(),
+            sockets: Children::default(),
+            registry: self.registry.clone(),
+        });
+        self.subchannels.add(&node);
+        node
+    }
+
+    fn info(&self) -> ChannelInfo {
+        let subchannels = self
+            .subchannels
+            .get()
+            .iter()
+            .map(|node| node.info())
+            .collect::<Vec<_>>();
+
+        ChannelInfo {
+            id: self.id,
+            target: self.target.clone(),
+            state: aggregate_state(subchannels.iter().map(SubchannelInfo::state)),
+            calls: self.calls.counts(),
+            subchannels,
+        }
+    }
+}
+
+/// The state of a channel, given the states of its subchannels.
+fn aggregate_state(states: impl Iterator<Item = ConnectivityState>) -> ConnectivityState {
+    let rank = |state| match state {
+        ConnectivityState::Ready => 0,
+        ConnectivityState::Connecting => 1,
+        ConnectivityState::Idle => 2,
+        ConnectivityState::TransientFailure => 3,
+        ConnectivityState::Shutdown => 4,
+    };
+
+    states
+        .min_by_key(|state| rank(*state))
+        .unwrap_or(ConnectivityState::Idle)
+}
+
+pub(crate) struct SubchannelNode {
+    id: u64,
+    target: String,
+    state: Mutex<Option<watch::Receiver<ConnectivityState>>>,
+    calls: Arc<CallMetrics>,
+    sockets: Children<SocketNode>,
+    registry: Arc<Registry>,
+}
+
+impl SubchannelNode {
+    pub(crate) fn calls(&self) -> &Arc<CallMetrics> {
+        &self.calls
+    }
+
+    /// Report the connectivity state of the subchannel through `state`.
+    pub(crate) fn set_state(&self, state: watch::Receiver<ConnectivityState>) {
+        *self.state.lock().unwrap() = Some(state);
+    }
+
+    pub(crate) fn add_socket(&self, remote_addr: Option<SocketAddr>) -> Arc<SocketNode> {
+        let node = Arc::new(SocketNode::new(self.registry.next_id(), remote_addr));
+        self.sockets.add(&node);
+        node
+    }
+
+    fn info(&self) -> SubchannelInfo {
+        let state = match &*self.state.lock().unwrap() {
+            Some(state) => *state.get_ref(),
+            None => ConnectivityState::Connecting,
+        };
+
+        SubchannelInfo {
+            id: self.id,
+            target: self.target.clone(),
+            state,
+            calls: self.calls.counts(),
+            sockets: self.sockets.get().iter().map(|node| node.info()).collect(),
+        }
+    }
+}
+
+pub(crate) struct ServerNode {
+    id: u64,
+    calls: CallMetrics,
+    sockets: Children<SocketNode>,
+    registry: Arc<Registry>,
+}
+
+impl ServerNode {
+    pub(crate) fn add_socket(&self, remote_addr: Option<SocketAddr>) -> Arc<SocketNode> {
+        let node = Arc::new(SocketNode::new(self.registry.next_id(), remote_addr));
+        self.sockets.add(&node);
+        node
+    }
+
+    fn info(&self) -> ServerInfo {
+        ServerInfo {
+            id: self.id,
+            calls: self.calls.counts(),
+            sockets: self.sockets.get().iter().map(|node| node.info()).collect(),
+        }
+    }
+}
+
+impl StatsHandler for ServerNode {
+    fn begin(&self, _method: &str) {
+        self.calls.start();
+    }
+
+    fn end(&self, stats: &RpcStats<'_>) {
+        self.calls.finish(stats.code() == Some(Code::Ok));
+    }
+}
+
+pub(crate) struct SocketNode {
+    id: u64,
+    remote_addr: Option<SocketAddr>,
+    streams: CallMetrics,
+}
+
+impl SocketNode {
+    fn new(id: u64, remote_addr: Option<SocketAddr>) -> Self {
+        SocketNode {
+            id,
+            remote_addr,
+            streams: CallMetrics::default(),
+        }
+    }
+
+    pub(crate) fn streams(&self) -> &CallMetrics {
+        &self.streams
+    }
+
+    fn info(&self) -> SocketInfo {
+        SocketInfo {
+            id: self.id,
+            remote_addr: self.remote_addr,
+            streams: self.streams.counts(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct CallMetrics {
+    started: AtomicU64,
+    succeeded: AtomicU64,
+    failed: AtomicU64,
+    last_started: Mutex<Option<SystemTime>>,
+}
+
+impl CallMetrics {
+    pub(crate) fn start(&self) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        *self.last_started.lock().unwrap() = Some(SystemTime::now());
+    }
+
+    pub(crate) fn finish(&self, succeeded: bool) {
+        if succeeded {
+            self.succeeded.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Start a call that is finished through the returned guard, or counted
+    /// as failed if the guard is dropped first.
+    pub(crate) fn track(self: &Arc<Self>) -> CallGuard {
+        self.start();
+        CallGuard(Some(self.clone()))
+    }
+
+    fn counts(&self) -> CallCounts {
+        CallCounts {
+            started: self.started.load(Ordering::Relaxed),
+            succeeded: self.succeeded.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            last_started: *self.last_started.lock().unwrap(),
+        }
+    }
+}
+
+pub(crate) struct CallGuard(Option<Arc<CallMetrics>>);
+
+impl CallGuard {
+    pub(crate) fn finish(mut self, succeeded: bool) {
+        if let Some(metrics) = self.0.take() {
+            metrics.finish(succeeded);
+        }
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        if let Some(metrics) = self.0.take() {
+            metrics.finish(false);
+        }
+    }
+}
+
+/// Whether a client call succeeded, as far as can be told from the headers
+/// of its response.
+pub(crate) fn call_succeeded<B, E>(result: &Result<http::Response<B>, E>) -> bool {
+    match result {
+        Ok(response) => response
+            .headers()
+            .get("grpc-status")
+            .is_none_or(|status| status == "0"),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_removed_once_dropped() {
+        let channelz = Channelz::new();
+        let channel = channelz.register_channel("http://[::1]:50051".into());
+        let subchannel = channel.add_subchannel("http://[::1]:50051".into());
+        let socket = subchannel.add_socket(None);
+
+        let channels = channelz.channels();
+        assert_eq!(channels.len(), 1);
+        assert_eq!(channels[0].subchannels().len(), 1);
+        assert_eq!(channels[0].subchannels()[0].sockets()[0].id(), socket.id);
+
+        drop(socket);
+        assert!(channelz.channels()[0].subchannels()[0].sockets().is_empty());
+
+        drop(subchannel);
+        drop(channel);
+        assert!(channelz.channels().is_empty());
+    }
+
+    #[test]
+    fn dropped_calls_fail() {
+        let metrics = Arc::new(CallMetrics::default());
+        metrics.track().finish(true);
+        drop(metrics.track());
+        let _pending = metrics.track();
+
+        let counts = metrics.counts();
+        assert_eq!(counts.started(), 3);
+        assert_eq!(counts.succeeded(), 1);
+        assert_eq!(counts.failed(), 1);
+        assert!(counts.last_started().is_some());
+    }
+
+    #[test]
+    fn channel_state_follows_subchannels() {
+        use ConnectivityState::*;
+
+        assert_eq!(aggregate_state(vec![].into_iter()), Idle);
+        assert_eq!(
+            aggregate_state(vec![TransientFailure, Ready, Connecting].into_iter()),
+            Ready
+        );
+        assert_eq!(
+            aggregate_state(vec![TransientFailure, Idle].into_iter()),
+            Idle
+        );
+    }
+}
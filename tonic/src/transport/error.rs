@@ -1,6 +1,23 @@
-use std::{error, fmt};
+use super::service::ConnectFailed;
+use std::{error, fmt, io};
 
 /// Error's that originate from the client or server;
+///
+/// The cause of an error is available via [`source`], and its class via the
+/// `is_*` methods, e.g. to decide whether to retry a call or to count
+/// failures by class:
+///
+/// ```
+/// # fn dox(error: tonic::transport::Error) {
+/// if error.is_timeout() {
+///     // the endpoint might just be slow, try again later
+/// } else if error.is_connect() {
+///     // the endpoint is down or unreachable
+/// }
+/// # }
+/// ```
+///
+/// [`source`]: #method.source
 pub struct Error {
     kind: ErrorKind,
     source: Option<crate::Error>,
@@ -13,12 +30,141 @@ impl Error {
             source: Some(source),
         }
     }
+
+    /// Returns true if establishing a connection failed, e.g. because it was
+    /// refused, the host could not be resolved, the TLS handshake failed or
+    /// the connect timeout elapsed.
+    pub fn is_connect(&self) -> bool {
+        self.any_source(|error| is_stage(error, Stage::Connect))
+    }
+
+    /// Returns true if the host of the endpoint could not be resolved.
+    pub fn is_dns(&self) -> bool {
+        self.any_source(|error| is_stage(error, Stage::Dns))
+    }
+
+    /// Returns true if the TLS handshake failed, or if a TLS config is
+    /// invalid.
+    pub fn is_tls(&self) -> bool {
+        match self.kind {
+            ErrorKind::InvalidCaCertificate
+            | ErrorKind::InvalidIdentity
+            | ErrorKind::InvalidDomainName => true,
+            _ => self.any_source(|error| is_stage(error, Stage::Tls)),
+        }
+    }
+
+    /// Returns true if the [request timeout] or the [connect timeout] of the
+    /// endpoint elapsed.
+    ///
+    /// [request timeout]: struct.Endpoint.html#method.timeout
+    /// [connect timeout]: struct.Endpoint.html#method.connect_timeout
+    pub fn is_timeout(&self) -> bool {
+        self.any_source(|error| {
+            error.is::<tower::timeout::error::Elapsed>()
+                || error.is::<tokio::timer::timeout::Elapsed>()
+                || error
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|error| error.kind() == io::ErrorKind::TimedOut)
+        })
+    }
+
+    /// Whether `f` holds for any error in the source chain.
+    fn any_source(&self, f: impl Fn(&(dyn error::Error + 'static)) -> bool) -> bool {
+        let mut sources = Vec::new();
+        sources.extend(
+            self.source
+                .as_ref()
+                .map(|e| &**e as &(dyn error::Error + 'static)),
+        );
+
+        while let Some(error) = sources.pop() {
+            if f(error) {
+                return true;
+            }
+
+            // the connect error of a lazy channel is kept beside its status
+            if let Some(failed) = error.downcast_ref::<ConnectFailed>() {
+                sources.push(failed.connect_error());
+            }
+
+            // `io::Error::source` skips the error it wraps
+            match error.downcast_ref::<io::Error>() {
+                Some(error) => {
+                    sources.extend(error.get_ref().map(|e| e as &(dyn error::Error + 'static)))
+                }
+                None => sources.extend(error.source()),
+            }
+        }
+
+        false
+    }
 }
 
+/// An error that occurred while establishing a connection, marked with the
+/// stage it occurred in so that [`Error`] can classify it.
 #[derive(Debug)]
+pub(crate) struct ConnectError {
+    stage: Stage,
+    source: crate::Error,
+}
+
+/// A stage of establishing a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Stage {
+    /// Any stage, the connection as a whole.
+    Connect,
+    /// Resolving the host.
+    Dns,
+    /// The TLS handshake.
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    Tls,
+}
+
+impl ConnectError {
+    pub(crate) fn new(stage: Stage, source: impl Into<crate::Error>) -> Self {
+        ConnectError {
+            stage,
+            source: source.into(),
+        }
+    }
+}
+
+fn is_stage(error: &(dyn error::Error + 'static), stage: Stage) -> bool {
+    error
+        .downcast_ref::<ConnectError>()
+        .is_some_and(|error| error.stage == stage)
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.stage {
+            Stage::Connect => write!(f, "failed to connect: {}", self.source),
+            Stage::Dns => write!(f, "failed to resolve host: {}", self.source),
+            Stage::Tls => write!(f, "TLS handshake failed: {}", self.source),
+        }
+    }
+}
+
+impl error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&*self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
 pub(crate) enum ErrorKind {
     Client,
     Server,
+    /// A CA certificate of a TLS config could not be used.
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    InvalidCaCertificate,
+    /// The identity of a TLS config could not be used.
+    #[cfg_attr(not(feature = "tls"), allow(dead_code))]
+    InvalidIdentity,
+    /// The domain name of a TLS config is not a valid DNS name.
+    #[cfg_attr(not(feature = "rustls"), allow(dead_code))]
+    InvalidDomainName,
 }
 
 impl fmt::Debug for Error {
@@ -0,0 +1,191 @@
+use super::{Certificate, Identity};
+use futures_core::Stream;
+use futures_util::stream;
+use std::{fmt, path::PathBuf, pin::Pin, time::Duration};
+use tokio::{fs, timer::delay_for};
+
+/// A stream of the identities of a server, returned by
+/// [`IdentityProvider::identities`].
+///
+/// [`IdentityProvider::identities`]: trait.IdentityProvider.html#tymethod.identities
+pub type Identities =
+    Pin<Box<dyn Stream<Item = Result<ServerIdentity, crate::Error>> + Send + 'static>>;
+
+/// Provides the identity of a TLS server, set via
+/// [`ServerTlsConfig::identity_provider`].
+///
+/// The server waits for the first identity before it accepts any connection.
+/// Every later identity is used for the handshakes of new connections, while
+/// established connections, and the streams on them, are kept. This allows
+/// rotating certificates without restarting the server.
+///
+/// An identity that fails to load is logged and the previous one is kept.
+///
+/// ```
+/// # use tonic::transport::{Identities, Identity, IdentityProvider, ServerIdentity};
+/// # use futures_util::stream;
+/// /// Always provides the same identity.
+/// struct Static(Identity);
+///
+/// impl IdentityProvider for Static {
+///     fn identities(&self) -> Identities {
+///         let identity = ServerIdentity::new(self.0.clone());
+///         Box::pin(stream::iter(vec![Ok(identity)]))
+///     }
+/// }
+/// ```
+///
+/// [`ServerTlsConfig::identity_provider`]: struct.ServerTlsConfig.html#method.identity_provider
+pub trait IdentityProvider: Send + Sync + 'static {
+    /// The identities of the server, starting with the current one.
+    fn identities(&self) -> Identities;
+}
+
+impl fmt::Debug for dyn IdentityProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityProvider").finish()
+    }
+}
+
+/// The identity of a server and the certificate against which to validate
+/// client certificates, yielded by an [`IdentityProvider`].
+///
+/// [`IdentityProvider`]: trait.IdentityProvider.html
+#[derive(Debug, Clone)]
+pub struct ServerIdentity {
+    pub(crate) identity: Identity,
+    pub(crate) client_ca_root: Option<Certificate>,
+}
+
+impl ServerIdentity {
+    /// Create a server identity that validates client certificates against
+    /// the [`ServerTlsConfig::client_ca_root`], if any.
+    ///
+    /// [`ServerTlsConfig::client_ca_root`]: struct.ServerTlsConfig.html#method.client_ca_root
+    pub fn new(identity: Identity) -> Self {
+        ServerIdentity {
+            identity,
+            client_ca_root: None,
+        }
+    }
+
+    /// Sets a certificate against which to validate client TLS certificates.
+    pub fn client_ca_root(mut self, cert: Certificate) -> Self {
+        self.client_ca_root = Some(cert);
+        self
+    }
+}
+
+/// An [`IdentityProvider`] that reads the certificate and key of a server
+/// from PEM files, and reads them again periodically.
+///
+/// A new identity is only provided when the contents of the files changed,
+/// e.g. after cert-manager renewed a certificate mounted from a Kubernetes
+/// secret.
+///
+/// ```
+/// # use tonic::transport::{FileIdentityProvider, ServerTlsConfig};
+/// # use std::time::Duration;
+/// # #[cfg(feature = "rustls")]
+/// # fn dox() {
+/// let identities = FileIdentityProvider::new("/etc/tls/tls.crt", "/etc/tls/tls.key")
+///     .client_ca_root("/etc/tls/ca.crt")
+///     .interval(Duration::from_secs(300));
+///
+/// let tls = ServerTlsConfig::with_rustls().identity_provider(identities);
+/// # }
+/// ```
+///
+/// [`IdentityProvider`]: trait.IdentityProvider.html
+#[derive(Debug, Clone)]
+pub struct FileIdentityProvider {
+    cert: PathBuf,
+    key: PathBuf,
+    client_ca_root: Option<PathBuf>,
+    interval: Duration,
+}
+
+impl FileIdentityProvider {
+    /// Create a provider that reads the certificate and key at the given
+    /// paths every minute.
+    pub fn new(cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        FileIdentityProvider {
+            cert: cert.into(),
+            key: key.into(),
+            client_ca_root: None,
+            interval: Duration::from_secs(60),
+        }
+    }
+
+    /// Sets the path of a certificate against which to validate client TLS
+    /// certificates, read along with the identity.
+    pub fn client_ca_root(mut self, path: impl Into<PathBuf>) -> Self {
+        self.client_ca_root = Some(path.into());
+        self
+    }
+
+    /// Set the interval between reads.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    async fn read(&self) -> Result<Files, crate::Error> {
+        let client_ca_root = match &self.client_ca_root {
+            Some(path) => Some(fs::read(path).await?),
+            None => None,
+        };
+
+        Ok(Files {
+            cert: fs::read(&self.cert).await?,
+            key: fs::read(&self.key).await?,
+            client_ca_root,
+        })
+    }
+}
+
+impl IdentityProvider for FileIdentityProvider {
+    fn identities(&self) -> Identities {
+        let provider = self.clone();
+
+        let identities = stream::unfold((true, None), move |(first, last)| {
+            let provider = provider.clone();
+            async move {
+                if !first {
+                    delay_for(provider.interval).await;
+                }
+
+                loop {
+                    match provider.read().await {
+                        Ok(files) if last.as_ref() != Some(&files) => {
+                            let identity = files.identity();
+                            return Some((Ok(identity), (false, Some(files))));
+                        }
+                        Ok(_) => {}
+                        Err(error) => return Some((Err(error), (false, last))),
+                    }
+                    delay_for(provider.interval).await;
+                }
+            }
+        });
+
+        Box::pin(identities)
+    }
+}
+
+/// The contents of the files of a `FileIdentityProvider`.
+#[derive(PartialEq)]
+struct Files {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+    client_ca_root: Option<Vec<u8>>,
+}
+
+impl Files {
+    fn identity(&self) -> ServerIdentity {
+        ServerIdentity {
+            identity: Identity::from_pem(&self.cert, &self.key),
+            client_ca_root: self.client_ca_root.as_ref().map(Certificate::from_pem),
+        }
+    }
+}
@@ -31,7 +31,7 @@
 //! let mut channel = Channel::from_static("https://example.com")
 //!     .tls_config(ClientTlsConfig::with_rustls()
 //!         .ca_certificate(Certificate::from_pem(&cert))
-//!         .domain_name("example.com".to_string()))
+//!         .domain_name("example.com".to_string()))?
 //!     .timeout(Duration::from_secs(5))
 //!     .rate_limit(5, Duration::from_secs(1))
 //!     .concurrency_limit(256)
@@ -75,7 +75,7 @@
 //!
 //! Server::builder()
 //!     .tls_config(ServerTlsConfig::with_rustls()
-//!         .identity(Identity::from_pem(&cert, &key)))
+//!         .identity(Identity::from_pem(&cert, &key)))?
 //!     .concurrency_limit_per_connection(256)
 //!     .interceptor_fn(|svc, req| {
 //!         println!("Request: {:?}", req);
@@ -95,23 +95,42 @@
 pub mod channel;
 pub mod server;
 
+mod cancel;
+mod channelz;
 mod endpoint;
 mod error;
+#[cfg(feature = "tls")]
+mod identity;
+mod resolver;
 mod service;
+mod stats;
 mod tls;
 
+#[cfg(test)]
+mod tests;
+
+pub use self::cancel::CancellationToken;
 #[doc(inline)]
-pub use self::channel::Channel;
-pub use self::endpoint::Endpoint;
+pub use self::channel::{Channel, ConnectionInfo, ConnectivityState};
+pub use self::channelz::{
+    CallCounts, ChannelInfo, Channelz, ServerInfo, SocketInfo, SubchannelInfo,
+};
+pub use self::endpoint::{BufferFullPolicy, Endpoint, EndpointConfig, Proxy, RetryPolicy};
 pub use self::error::Error;
+pub use self::resolver::{Addresses, DnsResolver, Resolver};
 #[doc(inline)]
 pub use self::server::{Server, ServiceName};
+pub use self::stats::{RpcStats, StatsHandler};
 pub use self::tls::{Certificate, Identity};
 pub use hyper::Body;
 
 #[cfg(feature = "tls")]
 pub use self::endpoint::ClientTlsConfig;
 #[cfg(feature = "tls")]
+pub use self::identity::{FileIdentityProvider, Identities, IdentityProvider, ServerIdentity};
+#[cfg(feature = "grpc-web")]
+pub use self::server::GrpcWebConfig;
+#[cfg(feature = "tls")]
 pub use self::server::ServerTlsConfig;
 
-pub(crate) use self::error::ErrorKind;
+pub(crate) use self::error::{ConnectError, ErrorKind, Stage};
@@ -0,0 +1,135 @@
+use futures_core::Stream;
+use futures_util::stream;
+use http::Uri;
+use hyper::client::connect::dns::{GaiResolver, Name, Resolve};
+use std::{fmt, io, net::SocketAddr, pin::Pin, time::Duration};
+use tokio::timer::delay_for;
+
+/// A stream of the addresses of an endpoint, returned by [`Resolver::resolve`].
+///
+/// Every item is the complete set of addresses at that time.
+///
+/// [`Resolver::resolve`]: trait.Resolver.html#tymethod.resolve
+pub type Addresses =
+    Pin<Box<dyn Stream<Item = Result<Vec<SocketAddr>, crate::Error>> + Send + 'static>>;
+
+/// Resolves the addresses of an [`Endpoint`], set via [`Endpoint::resolver`].
+///
+/// A channel created from an endpoint with a resolver balances over the
+/// addresses the resolver yields. It connects to every address that is
+/// added and drops the connections to addresses that are removed, e.g. as a
+/// service discovery mechanism updates the replicas of a backend. The
+/// `:authority` of requests and the TLS server name still default to the
+/// host of the endpoint's URI.
+///
+/// A failed resolution is logged and leaves the addresses as they are.
+///
+/// ```
+/// # use tonic::transport::{Addresses, Resolver};
+/// # use futures_util::stream;
+/// # use http::Uri;
+/// /// Always resolves to the same addresses.
+/// struct Static(Vec<std::net::SocketAddr>);
+///
+/// impl Resolver for Static {
+///     fn resolve(&self, _uri: &Uri) -> Addresses {
+///         Box::pin(stream::iter(vec![Ok(self.0.clone())]))
+///     }
+/// }
+/// ```
+///
+/// [`Endpoint`]: struct.Endpoint.html
+/// [`Endpoint::resolver`]: struct.Endpoint.html#method.resolver
+pub trait Resolver: Send + Sync + 'static {
+    /// Resolve the addresses of the endpoint at `uri`.
+    fn resolve(&self, uri: &Uri) -> Addresses;
+}
+
+impl fmt::Debug for dyn Resolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resolver").finish()
+    }
+}
+
+/// A [`Resolver`] that looks up the A and AAAA records of the host of an
+/// endpoint, and looks them up again periodically.
+///
+/// The lookups run on a thread pool, off the task that uses the channel. The
+/// port of the addresses is the port of the endpoint's URI, or the default
+/// port of its scheme.
+///
+/// ```
+/// # use tonic::transport::{DnsResolver, Endpoint};
+/// # use std::time::Duration;
+/// # let builder = Endpoint::from_static("https://example.com");
+/// builder.resolver(DnsResolver::new().interval(Duration::from_secs(10)));
+/// ```
+///
+/// [`Resolver`]: trait.Resolver.html
+#[derive(Debug, Clone)]
+pub struct DnsResolver {
+    interval: Duration,
+}
+
+impl DnsResolver {
+    /// Create a resolver that looks up addresses every 30 seconds.
+    pub fn new() -> Self {
+        DnsResolver {
+            interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Set the interval between lookups.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+}
+
+impl Default for DnsResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver for DnsResolver {
+    fn resolve(&self, uri: &Uri) -> Addresses {
+        let host = uri
+            .host()
+            .unwrap_or_default()
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        let port = uri.port_u16().unwrap_or_else(|| match uri.scheme_str() {
+            Some("https") => 443,
+            _ => 80,
+        });
+        let interval = self.interval;
+
+        let lookups = stream::unfold(true, move |first| {
+            let host = host.clone();
+            async move {
+                if !first {
+                    delay_for(interval).await;
+                }
+                Some((lookup(host, port).await, false))
+            }
+        });
+
+        Box::pin(lookups)
+    }
+}
+
+async fn lookup(host: String, port: u16) -> Result<Vec<SocketAddr>, crate::Error> {
+    // IP addresses are not looked up
+    if let Ok(ip) = host.parse() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let name: Name = host
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let addrs = GaiResolver::new().resolve(name).await?;
+
+    Ok(addrs.map(|ip| SocketAddr::new(ip, port)).collect())
+}
@@ -1,41 +1,61 @@
 //! Server implementation and builder.
 
-use super::service::{layer_fn, BoxedIo, Or, Routes, ServiceBuilderExt};
+#[cfg(feature = "grpc-web")]
+use super::service::GrpcWeb;
+use super::service::{
+    layer_fn, overloaded_status, AddExtension, BoxedIo, CancelOnDrop, EchoMetadata,
+    GlobalConcurrencyLimit, GrpcTimeout, Intercept, KeepAliveConfig, LifetimeConfig, LoadShed, Or,
+    PeerRateLimit, PeerRateLimiter, Routes, ServiceBuilderExt, Stats, TcpConfig,
+    DEFAULT_KEEP_ALIVE_TIMEOUT,
+};
 #[cfg(feature = "tls")]
 use super::{
     service::TlsAcceptor,
     tls::{Identity, TlsProvider},
-    Certificate,
+    Certificate, Identities, IdentityProvider, ServerIdentity,
 };
-use crate::body::BoxBody;
+use super::{stats::BothHandlers, Channelz, StatsHandler};
+use crate::{body::BoxBody, Status};
 use futures_core::Stream;
-use futures_util::{future, ready, try_future::MapErr, TryFutureExt, TryStreamExt};
-use http::{Request, Response};
-use hyper::{
-    server::{accept::Accept, conn},
-    Body,
+use futures_util::{
+    future::{self, Either},
+    ready, stream,
+    try_future::MapErr,
+    StreamExt, TryFutureExt, TryStreamExt,
 };
+#[cfg(feature = "grpc-web")]
+use http::HeaderValue;
+use http::{header::HeaderName, Request, Response};
+use hyper::Body;
 use std::{
     fmt,
     future::Future,
+    io,
     net::SocketAddr,
     pin::Pin,
-    sync::Arc,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
-    // time::Duration,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+    sync::{oneshot, watch},
+    timer::Delay,
 };
+use tokio_sync::semaphore::Semaphore;
 use tower::{
     layer::{util::Stack, Layer},
     limit::concurrency::ConcurrencyLimitLayer,
-    // timeout::TimeoutLayer,
-    Service,
-    ServiceBuilder,
+    util::Oneshot,
+    Service, ServiceBuilder,
 };
-#[cfg(feature = "tls")]
-use tracing::error;
+use tracing::{debug, error};
 
 type BoxService = tower::util::BoxService<Request<Body>, Response<BoxBody>, crate::Error>;
 type Interceptor = Arc<dyn Layer<BoxService, Service = BoxService> + Send + Sync + 'static>;
+/// Creates the service that answers requests for unknown services.
+type Fallback = Arc<dyn Fn() -> BoxService + Send + Sync + 'static>;
 
 /// A default batteries included `transport` server.
 ///
@@ -45,16 +65,46 @@ type Interceptor = Arc<dyn Layer<BoxService, Service = BoxService> + Send + Sync
 /// a very good out of the box http2 server for use with tonic but is also a
 /// reference implementation that should be a good starting point for anyone
 /// wanting to create a more complex and/or specific implementation.
+///
+/// # Cancellation
+///
+/// When the client resets a request, its connection is closed, or its
+/// deadline elapses, the server drops the future of its handler and the
+/// stream of its response, so neither is polled again. Work that a handler
+/// spawned is not stopped by this, it can observe the cancellation through
+/// the [`CancellationToken`] of the request.
+///
+/// [`CancellationToken`]: struct.CancellationToken.html
 #[derive(Default, Clone)]
 pub struct Server {
     interceptor: Option<Interceptor>,
+    intercept: Option<crate::Interceptor>,
+    layers: Vec<Interceptor>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
+    channelz: Option<Channelz>,
+    fallback: Option<Fallback>,
     concurrency_limit: Option<usize>,
-    // timeout: Option<Duration>,
+    global_concurrency_limit: Option<usize>,
+    load_shed: bool,
+    load_shed_status: Option<Status>,
+    peer_rate_limit: Option<Arc<PeerRateLimiter>>,
+    peer_rate_limit_status: Option<Status>,
+    echo_metadata: Option<Arc<Vec<HeaderName>>>,
+    timeout: Option<Duration>,
     #[cfg(feature = "tls")]
     tls: Option<TlsAcceptor>,
+    #[cfg(feature = "tls")]
+    tls_reload: Option<ServerTlsConfig>,
+    #[cfg(feature = "grpc-web")]
+    grpc_web: Option<Arc<GrpcWebConfig>>,
     init_stream_window_size: Option<u32>,
     init_connection_window_size: Option<u32>,
     max_concurrent_streams: Option<u32>,
+    http2_keep_alive_interval: Option<Duration>,
+    http2_keep_alive_timeout: Option<Duration>,
+    shutdown_grace_period: Option<Duration>,
+    lifetime: LifetimeConfig,
+    tcp: TcpConfig,
 }
 
 /// A stack based `Service` router.
@@ -62,6 +112,7 @@ pub struct Server {
 pub struct Router<A, B> {
     server: Server,
     routes: Routes<A, B, Request<Body>>,
+    service_names: Vec<&'static str>,
 }
 
 /// A trait to provide a static reference to the service's
@@ -82,9 +133,44 @@ impl Server {
 
 impl Server {
     /// Configure TLS for this server.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the config has no identity, or if its identity or client CA
+    /// certificate can't be parsed. The error says which of them is invalid.
+    /// The identities of an [identity provider] are only checked once they
+    /// are provided.
+    ///
+    /// [identity provider]: struct.ServerTlsConfig.html#method.identity_provider
     #[cfg(feature = "tls")]
-    pub fn tls_config(&mut self, tls_config: &ServerTlsConfig) -> &mut Self {
-        self.tls = Some(tls_config.tls_acceptor().unwrap());
+    pub fn tls_config(mut self, tls_config: ServerTlsConfig) -> Result<Self, super::Error> {
+        // the acceptor of a reloaded identity is only built once serving
+        if tls_config.identity_provider.is_some() {
+            self.tls = None;
+            self.tls_reload = Some(tls_config);
+        } else {
+            self.tls = Some(tls_config.tls_acceptor()?);
+            self.tls_reload = None;
+        }
+        Ok(self)
+    }
+
+    /// Accept [grpc-web] requests, so that browsers can call the server's
+    /// services directly.
+    ///
+    /// This makes the server accept HTTP/1.1 connections besides HTTP/2,
+    /// as browsers may use either.
+    ///
+    /// ```
+    /// # use tonic::transport::{GrpcWebConfig, Server};
+    /// # let builder = Server::builder();
+    /// builder.grpc_web_config(GrpcWebConfig::new().allow_origin("https://example.com"));
+    /// ```
+    ///
+    /// [grpc-web]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
+    #[cfg(feature = "grpc-web")]
+    pub fn grpc_web_config(mut self, grpc_web_config: GrpcWebConfig) -> Self {
+        self.grpc_web = Some(Arc::new(grpc_web_config));
         self
     }
 
@@ -93,28 +179,166 @@ impl Server {
     /// ```
     /// # use tonic::transport::Server;
     /// # use tower_service::Service;
-    /// # let mut builder = Server::builder();
+    /// # let builder = Server::builder();
     /// builder.concurrency_limit_per_connection(32);
     /// ```
-    pub fn concurrency_limit_per_connection(&mut self, limit: usize) -> &mut Self {
+    pub fn concurrency_limit_per_connection(mut self, limit: usize) -> Self {
         self.concurrency_limit = Some(limit);
         self
     }
 
-    // FIXME: tower-timeout currentlly uses `From` instead of `Into` for the error
-    // so our services do not align.
-    // pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
-    //     self.timeout = Some(timeout);
-    //     self
-    // }
+    /// Set the concurrency limit applied to inbound requests across all
+    /// connections.
+    ///
+    /// Unlike [`Server::concurrency_limit_per_connection`], this limit is
+    /// shared by every connection of the server. A request holds its slot
+    /// until its response has been produced.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # let builder = Server::builder();
+    /// builder.global_concurrency_limit(256);
+    /// ```
+    pub fn global_concurrency_limit(mut self, limit: usize) -> Self {
+        self.global_concurrency_limit = Some(limit);
+        self
+    }
+
+    /// Reject requests that exceed the concurrency limits instead of queueing
+    /// them, with the status set via [`Server::load_shed_status`].
+    ///
+    /// By default a request over [`Server::concurrency_limit_per_connection`]
+    /// or [`Server::global_concurrency_limit`] waits until a slot frees up.
+    /// With load shedding enabled it is rejected right away, so clients can
+    /// back off or try another server.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # let builder = Server::builder();
+    /// builder.global_concurrency_limit(256).load_shed(true);
+    /// ```
+    pub fn load_shed(mut self, enabled: bool) -> Self {
+        self.load_shed = enabled;
+        self
+    }
+
+    /// Set the status returned for requests rejected by load shedding.
+    ///
+    /// Default is `RESOURCE_EXHAUSTED` with the message `server overloaded`.
+    ///
+    /// ```
+    /// # use tonic::{transport::Server, Code, Status};
+    /// # let builder = Server::builder();
+    /// builder.load_shed_status(Status::new(Code::Unavailable, "try again later"));
+    /// ```
+    pub fn load_shed_status(mut self, status: Status) -> Self {
+        self.load_shed_status = Some(status);
+        self
+    }
+
+    /// Set a rate limit applied to each peer, keyed by its remote IP address.
+    ///
+    /// Every peer gets a bucket of `limit` requests that is refilled every
+    /// `duration`, independent of all other peers. Requests beyond that are
+    /// rejected without reaching the service, with the status set via
+    /// [`Server::peer_rate_limit_status`].
+    ///
+    /// The limit is shared by all connections from the same address. Buckets of
+    /// peers that have not sent a request for a full `duration` are evicted, so
+    /// only peers active within the last `duration` are kept in memory.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.rate_limit_per_peer(100, Duration::from_secs(1));
+    /// ```
+    pub fn rate_limit_per_peer(mut self, limit: u64, duration: Duration) -> Self {
+        self.peer_rate_limit = Some(Arc::new(PeerRateLimiter::new(limit, duration)));
+        self
+    }
+
+    /// Set the status returned for requests rejected by the per peer rate
+    /// limit.
+    ///
+    /// Default is `RESOURCE_EXHAUSTED` with the message
+    /// `peer rate limit exceeded`.
+    ///
+    /// ```
+    /// # use tonic::{transport::Server, Code, Status};
+    /// # let builder = Server::builder();
+    /// builder.peer_rate_limit_status(Status::new(Code::Unavailable, "try again later"));
+    /// ```
+    pub fn peer_rate_limit_status(mut self, status: Status) -> Self {
+        self.peer_rate_limit_status = Some(status);
+        self
+    }
+
+    /// Echo the given request metadata keys into the response trailers.
+    ///
+    /// For every request, the values of these keys are copied into the
+    /// trailers of its response, e.g. to return a client supplied request id
+    /// for correlation without changing any handler. Keys missing from a
+    /// request are skipped. Responses without trailers, which carry their
+    /// `grpc-status` in the headers, get the values added to the headers.
+    ///
+    /// Keys that are not known up front can be parsed with
+    /// [`HeaderName::from_bytes`], which reports invalid keys as an error.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use http::header::HeaderName;
+    /// # let builder = Server::builder();
+    /// builder.echo_metadata(vec![HeaderName::from_static("x-request-id")]);
+    /// ```
+    ///
+    /// [`HeaderName::from_bytes`]: https://docs.rs/http/0.1/http/header/struct.HeaderName.html#method.from_bytes
+    pub fn echo_metadata<I>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = HeaderName>,
+    {
+        let keys = keys.into_iter().collect();
+        self.echo_metadata = Some(Arc::new(keys));
+        self
+    }
+
+    /// Limit how long the handler of a request and the stream of its response
+    /// may run.
+    ///
+    /// Once `timeout` elapsed, the request is answered with a
+    /// `DEADLINE_EXCEEDED` status and its handler is dropped, cancelling its
+    /// [`CancellationToken`]. A response stream that is still running by then
+    /// is dropped and ends with `DEADLINE_EXCEEDED` trailers instead. A shorter deadline sent by the client via the
+    /// `grpc-timeout` header takes precedence. By default only the deadline
+    /// of the client applies.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.timeout(Duration::from_secs(30));
+    /// ```
+    ///
+    /// [`CancellationToken`]: struct.CancellationToken.html
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
 
     /// Sets the [`SETTINGS_INITIAL_WINDOW_SIZE`][spec] option for HTTP2
     /// stream-level flow control.
     ///
+    /// Request data is only read as a handler fetches messages from a
+    /// [`Streaming`] request, so this bounds the bytes a client can send
+    /// ahead of a slow handler, however many messages they hold, along with
+    /// [`Server::initial_connection_window_size`] for all the streams of a
+    /// connection.
+    ///
     /// Default is 65,535
     ///
     /// [spec]: https://http2.github.io/http2-spec/#SETTINGS_INITIAL_WINDOW_SIZE
-    pub fn initial_stream_window_size(&mut self, sz: impl Into<Option<u32>>) -> &mut Self {
+    /// [`Streaming`]: ../struct.Streaming.html
+    pub fn initial_stream_window_size(mut self, sz: impl Into<Option<u32>>) -> Self {
         self.init_stream_window_size = sz.into();
         self
     }
@@ -122,7 +346,7 @@ impl Server {
     /// Sets the max connection-level flow control for HTTP2
     ///
     /// Default is 65,535
-    pub fn initial_connection_window_size(&mut self, sz: impl Into<Option<u32>>) -> &mut Self {
+    pub fn initial_connection_window_size(mut self, sz: impl Into<Option<u32>>) -> Self {
         self.init_connection_window_size = sz.into();
         self
     }
@@ -133,23 +357,180 @@ impl Server {
     /// Default is no limit (`None`).
     ///
     /// [spec]: https://http2.github.io/http2-spec/#SETTINGS_MAX_CONCURRENT_STREAMS
-    pub fn max_concurrent_streams(&mut self, max: impl Into<Option<u32>>) -> &mut Self {
+    pub fn max_concurrent_streams(mut self, max: impl Into<Option<u32>>) -> Self {
         self.max_concurrent_streams = max.into();
         self
     }
 
+    /// Send HTTP/2 `PING` frames at this interval to keep connections alive.
+    ///
+    /// A ping is only sent after nothing was received from the client for
+    /// `interval`, whether or not the connection has open streams. If nothing
+    /// is received within [`Server::http2_keep_alive_timeout`] after a ping,
+    /// the connection is closed. This detects clients that went away without
+    /// closing their connection.
+    ///
+    /// By default no pings are sent.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.http2_keep_alive_interval(Duration::from_secs(60));
+    /// ```
+    pub fn http2_keep_alive_interval(mut self, interval: Duration) -> Self {
+        self.http2_keep_alive_interval = Some(interval);
+        self
+    }
+
+    /// Set how long to wait for a response to a keepalive ping before closing
+    /// the connection.
+    ///
+    /// This has no effect unless [`Server::http2_keep_alive_interval`] is set.
+    /// Default is 20 seconds.
+    pub fn http2_keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.http2_keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Close connections that had no open streams for `idle`.
+    ///
+    /// The client is told to not open new streams on the connection with a
+    /// `GOAWAY` frame first, so requests that race with it are not lost.
+    /// This releases the connections of clients that never close them. By
+    /// default idle connections are kept open.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.max_connection_idle(Duration::from_secs(300));
+    /// ```
+    pub fn max_connection_idle(mut self, idle: Duration) -> Self {
+        self.lifetime.max_idle = Some(idle);
+        self
+    }
+
+    /// Close connections once they were open for `age`.
+    ///
+    /// Like [`Server::max_connection_idle`], the client is told to not open
+    /// new streams on the connection, which is closed once the streams open
+    /// on it ended. This makes clients reconnect every now and then, so that
+    /// e.g. a load balancer can spread them over new server instances. A
+    /// random jitter of up to 10% is added to `age` to spread out reconnects.
+    /// By default connections are kept open regardless of their age.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder
+    ///     .max_connection_age(Duration::from_secs(30 * 60))
+    ///     .max_connection_age_grace(Duration::from_secs(60));
+    /// ```
+    ///
+    /// [`Server::max_connection_idle`]: struct.Server.html#method.max_connection_idle
+    pub fn max_connection_age(mut self, age: Duration) -> Self {
+        self.lifetime.max_age = Some(age);
+        self
+    }
+
+    /// Limit how long the streams of a connection past its
+    /// [`Server::max_connection_age`] may take to end.
+    ///
+    /// The connection is closed once `grace` elapsed, failing the requests
+    /// still in flight on it. By default the server waits for all streams to
+    /// end.
+    ///
+    /// [`Server::max_connection_age`]: struct.Server.html#method.max_connection_age
+    pub fn max_connection_age_grace(mut self, grace: Duration) -> Self {
+        self.lifetime.max_age_grace = Some(grace);
+        self
+    }
+
+    /// Set the `TCP_NODELAY` option on accepted connections.
+    ///
+    /// This disables Nagle's algorithm, so small messages like unary requests
+    /// are sent right away instead of being delayed to be coalesced with
+    /// later writes. Default is `false`.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # let builder = Server::builder();
+    /// builder.tcp_nodelay(true);
+    /// ```
+    pub fn tcp_nodelay(mut self, enabled: bool) -> Self {
+        self.tcp.nodelay = enabled;
+        self
+    }
+
+    /// Enable TCP keepalive probes on accepted connections, sent after they were
+    /// idle for `interval`.
+    ///
+    /// Unlike HTTP/2 keepalive pings, these are handled by the operating
+    /// system. By default TCP keepalive is disabled.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.tcp_keepalive(Duration::from_secs(60));
+    /// ```
+    pub fn tcp_keepalive(mut self, interval: Duration) -> Self {
+        self.tcp.keepalive = Some(interval);
+        self
+    }
+
+    /// Set the size of the send buffer (`SO_SNDBUF`) of accepted connections.
+    ///
+    /// By default the operating system's default is used.
+    pub fn tcp_send_buffer_size(mut self, size: usize) -> Self {
+        self.tcp.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the receive buffer (`SO_RCVBUF`) of accepted connections.
+    ///
+    /// By default the operating system's default is used.
+    pub fn tcp_recv_buffer_size(mut self, size: usize) -> Self {
+        self.tcp.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Limit how long a graceful shutdown waits for in-flight requests.
+    ///
+    /// Once the shutdown signal passed to [`Router::serve_with_shutdown`]
+    /// resolves, the server waits at most `period` for the requests in flight
+    /// to complete. The connections still open after that are closed, failing
+    /// the requests on them.
+    ///
+    /// By default the server waits for all requests to complete.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.shutdown_grace_period(Duration::from_secs(30));
+    /// ```
+    ///
+    /// [`Router::serve_with_shutdown`]: struct.Router.html#method.serve_with_shutdown
+    pub fn shutdown_grace_period(mut self, period: Duration) -> Self {
+        self.shutdown_grace_period = Some(period);
+        self
+    }
+
     /// Intercept the execution of gRPC methods.
     ///
     /// ```
     /// # use tonic::transport::Server;
     /// # use tower_service::Service;
-    /// # let mut builder = Server::builder();
+    /// # let builder = Server::builder();
     /// builder.interceptor_fn(|svc, req| {
     ///     println!("request={:?}", req);
     ///     svc.call(req)
     /// });
     /// ```
-    pub fn interceptor_fn<F, Out>(&mut self, f: F) -> &mut Self
+    pub fn interceptor_fn<F, Out>(mut self, f: F) -> Self
     where
         F: Fn(&mut BoxService, Request<Body>) -> Out + Send + Sync + 'static,
         Out: Future<Output = Result<Response<BoxBody>, crate::Error>> + Send + 'static,
@@ -164,6 +545,135 @@ impl Server {
         self
     }
 
+    /// Add a tower [`Layer`] around the services of this server.
+    ///
+    /// Layers wrap the whole server, e.g. to add tracing, metrics or
+    /// authentication, and see every request, including those rejected by
+    /// interceptors and limits configured on this builder. They are applied
+    /// per connection. When called more than once, the first layer added is
+    /// the outermost.
+    ///
+    /// ```
+    /// # use tonic::transport::Server;
+    /// # use std::time::Duration;
+    /// # let builder = Server::builder();
+    /// builder.layer(tower::timeout::TimeoutLayer::new(Duration::from_secs(30)));
+    /// ```
+    ///
+    /// [`Layer`]: https://docs.rs/tower-layer/*/tower_layer/trait.Layer.html
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxService> + Send + Sync + 'static,
+        L::Service: Service<Request<Body>, Response = Response<BoxBody>> + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<crate::Error> + Send,
+    {
+        let layer = Stack::new(layer, layer_fn(|svc| BoxService::new(Svc(svc))));
+        self.layers.push(Arc::new(layer));
+        self
+    }
+
+    /// Report the statistics of every request to a [`StatsHandler`].
+    ///
+    /// Independent of this, every request is traced in a `grpc_request`
+    /// span with the fields `grpc.method`, `grpc.code`, `grpc.latency_ms`,
+    /// `grpc.messages_received` and `grpc.messages_sent`. The span is
+    /// entered while the handler is called and while messages are encoded
+    /// and decoded.
+    ///
+    /// ```
+    /// # use tonic::transport::{RpcStats, Server};
+    /// # let builder = Server::builder();
+    /// builder.stats_handler(|stats: &RpcStats<'_>| {
+    ///     println!("{} finished with {:?}", stats.method(), stats.code());
+    /// });
+    /// ```
+    ///
+    /// [`StatsHandler`]: trait.StatsHandler.html
+    pub fn stats_handler(mut self, handler: impl StatsHandler) -> Self {
+        self.stats_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Register the server with [`Channelz`] while it serves.
+    ///
+    /// The server is listed with the calls it received and a socket for
+    /// every open connection. This works alongside a [`StatsHandler`].
+    ///
+    /// ```
+    /// # use tonic::transport::{Channelz, Server};
+    /// # let builder = Server::builder();
+    /// let channelz = Channelz::new();
+    /// builder.channelz(channelz.clone());
+    /// ```
+    ///
+    /// [`Channelz`]: struct.Channelz.html
+    /// [`StatsHandler`]: trait.StatsHandler.html
+    pub fn channelz(mut self, channelz: Channelz) -> Self {
+        self.channelz = Some(channelz);
+        self
+    }
+
+    /// Run an [`Interceptor`] on the metadata and extensions of every request
+    /// before it is routed to a service.
+    ///
+    /// A request the interceptor rejects is answered with the returned
+    /// [`Status`] and never reaches the service.
+    ///
+    /// ```
+    /// # use tonic::{transport::Server, Code, Request, Status};
+    /// # let builder = Server::builder();
+    /// builder.interceptor(|req: Request<()>| match req.metadata().get("x-tenant") {
+    ///     Some(_) => Ok(req),
+    ///     None => Err(Status::new(Code::InvalidArgument, "missing tenant")),
+    /// });
+    /// ```
+    ///
+    /// [`Interceptor`]: ../struct.Interceptor.html
+    /// [`Status`]: ../struct.Status.html
+    pub fn interceptor(mut self, interceptor: impl Into<crate::Interceptor>) -> Self {
+        self.intercept = Some(interceptor.into());
+        self
+    }
+
+    /// Answer requests for services that were not added to the router with
+    /// `svc`, instead of with an empty `UNIMPLEMENTED` response.
+    ///
+    /// This can return an `UNIMPLEMENTED` status with details, or serve
+    /// other requests on the same port, e.g. a health check path, as long as
+    /// the client speaks HTTP/2. The fallback sees requests after the
+    /// interceptors and layers of the server, like the services do.
+    ///
+    /// ```
+    /// # use tonic::{body::BoxBody, transport::Server};
+    /// # use futures_util::future;
+    /// # let builder = Server::builder();
+    /// builder.fallback(tower::service_fn(|req: http::Request<hyper::Body>| {
+    ///     let status = match req.uri().path() {
+    ///         "/healthz" => 200,
+    ///         _ => 404,
+    ///     };
+    ///     let res = http::Response::builder().status(status).body(BoxBody::empty());
+    ///     future::ready(res)
+    /// }));
+    /// ```
+    pub fn fallback<S>(mut self, svc: S) -> Self
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+    {
+        // every request is answered by a clone of `svc`, which only needs to
+        // be `Send` to be shared
+        let svc = Mutex::new(svc);
+        let fallback: Fallback = Arc::new(move || {
+            let svc = svc.lock().unwrap().clone();
+            BoxService::new(Svc(svc))
+        });
+        self.fallback = Some(fallback);
+        self
+    }
+
     /// Create a router with the `S` typed service as the first service.
     ///
     /// This will clone the `Server` builder and create a router that will
@@ -181,59 +691,263 @@ impl Server {
         Router::new(self.clone(), svc)
     }
 
-    pub(crate) async fn serve<S>(self, addr: SocketAddr, svc: S) -> Result<(), super::Error>
+    pub(crate) async fn serve<S, F>(
+        self,
+        addr: SocketAddr,
+        svc: S,
+        signal: Option<F>,
+    ) -> Result<(), super::Error>
     where
         S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
         S::Future: Send + 'static,
         S::Error: Into<crate::Error> + Send,
+        F: Future<Output = ()>,
+    {
+        let listener = TcpListener::bind(addr).await.map_err(map_err)?;
+        self.serve_with_listener(listener, svc, signal).await
+    }
+
+    pub(crate) async fn serve_with_listener<S, F>(
+        self,
+        listener: TcpListener,
+        svc: S,
+        signal: Option<F>,
+    ) -> Result<(), super::Error>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+        F: Future<Output = ()>,
+    {
+        let tcp_config = self.tcp;
+        let incoming = async_stream::try_stream! {
+            let mut tcp = TcpIncoming::new(listener);
+
+            while let Some((io, remote_addr)) = tcp.try_next().await? {
+                if let Err(error) = tcp_config.apply_to_stream(&io) {
+                    debug!(message = "Unable to set TCP socket options.", %error);
+                }
+                yield (io, Some(remote_addr));
+            }
+        };
+
+        self.serve_incoming(svc, incoming, signal).await
+    }
+
+    pub(crate) async fn serve_with_incoming<S, I, IO, IE>(
+        self,
+        svc: S,
+        incoming: I,
+    ) -> Result<(), super::Error>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+        I: Stream<Item = Result<IO, IE>> + Send,
+        IO: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        IE: Into<crate::Error>,
+    {
+        let incoming = incoming.map_ok(|io| (io, None)).map_err(Into::into);
+        self.serve_incoming(svc, incoming, None::<future::Pending<()>>)
+            .await
+    }
+
+    /// Serve connections from `incoming`, which yields each connection along
+    /// with the address of its peer, if it has one, until `signal` resolves.
+    ///
+    /// Without a `signal` the server runs until `incoming` ends.
+    async fn serve_incoming<S, I, IO, F>(
+        self,
+        svc: S,
+        incoming: I,
+        signal: Option<F>,
+    ) -> Result<(), super::Error>
+    where
+        S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<crate::Error> + Send,
+        I: Stream<Item = Result<(IO, Option<SocketAddr>), crate::Error>> + Send,
+        IO: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        F: Future<Output = ()>,
     {
         let interceptor = self.interceptor.clone();
+        let intercept = self.intercept.clone();
+        let layers = Arc::new(self.layers.clone());
+        let channelz = self.channelz.as_ref().map(Channelz::register_server);
+        let stats_handler = match (self.stats_handler.clone(), channelz.clone()) {
+            (Some(handler), Some(channelz)) => Some(Arc::new(BothHandlers(handler, channelz)) as _),
+            (handler, None) => handler,
+            (None, Some(channelz)) => Some(channelz as _),
+        };
         let concurrency_limit = self.concurrency_limit;
+        let global_concurrency_limit = self
+            .global_concurrency_limit
+            .map(|limit| Arc::new(Semaphore::new(limit)));
+        let load_shed = self.load_shed;
+        let load_shed_status = self.load_shed_status.clone();
+        let peer_rate_limit = self.peer_rate_limit.clone();
+        let peer_rate_limit_status = self.peer_rate_limit_status.clone();
+        let echo_metadata = self.echo_metadata.clone();
+        #[cfg(feature = "grpc-web")]
+        let grpc_web = self.grpc_web.clone();
+        #[cfg(feature = "grpc-web")]
+        let http2_only = grpc_web.is_none();
+        #[cfg(not(feature = "grpc-web"))]
+        let http2_only = true;
         let init_connection_window_size = self.init_connection_window_size;
         let init_stream_window_size = self.init_stream_window_size;
         let max_concurrent_streams = self.max_concurrent_streams;
-        // let timeout = self.timeout.clone();
+        let keep_alive = self
+            .http2_keep_alive_interval
+            .map(|interval| KeepAliveConfig {
+                interval,
+                timeout: self
+                    .http2_keep_alive_timeout
+                    .unwrap_or(DEFAULT_KEEP_ALIVE_TIMEOUT),
+                while_idle: true,
+            });
+        let timeout = self.timeout;
+
+        let shutdown_grace_period = self.shutdown_grace_period;
+
+        let lifetime = Some(self.lifetime).filter(LifetimeConfig::is_bounded);
+
+        // connections are only ever closed by force after a grace period
+        let (force_close_tx, force_close) = watch::channel(false);
+        let force_close = shutdown_grace_period.map(|_| force_close);
+        let prepare = move |io: BoxedIo, remote_addr| {
+            let socket = channelz
+                .as_ref()
+                .map(|server| server.add_socket(remote_addr));
+            with_remote_addr(io, remote_addr)
+                .with_socket(socket)
+                .with_keep_alive(keep_alive)
+                .with_lifetime(lifetime)
+                .with_force_close(force_close.clone())
+        };
+
+        #[cfg(feature = "tls")]
+        let (mut tls, identities) = match &self.tls_reload {
+            Some(config) => {
+                let (acceptor, identities) = config.first_identity().await.map_err(map_err)?;
+                (Some(acceptor), identities)
+            }
+            None => (self.tls.clone(), Box::pin(stream::pending()) as Identities),
+        };
+        #[cfg(feature = "tls")]
+        let tls_reload = self.tls_reload.clone();
+
+        let incoming = Box::pin(incoming)
+            .map(Event::Accepted)
+            .chain(stream::once(future::ready(Event::Closed)));
+        #[cfg(feature = "tls")]
+        let incoming = stream::select(incoming, identities.map(Event::Identity));
 
         let incoming = hyper::server::accept::from_stream(async_stream::try_stream! {
-            let mut tcp = TcpIncoming::bind(addr)?;
+            let mut incoming = incoming;
+
+            while let Some(event) = incoming.next().await {
+                let (io, remote_addr) = match event {
+                    Event::Accepted(accepted) => accepted?,
+                    #[cfg(feature = "tls")]
+                    Event::Identity(identity) => {
+                        // new handshakes use the new identity, established
+                        // connections are kept
+                        let config = tls_reload.as_ref().unwrap();
+                        let acceptor = identity
+                            .and_then(|identity| Ok(config.reloaded_acceptor(identity)?));
+                        match acceptor {
+                            Ok(acceptor) => {
+                                debug!("reloaded the TLS identity");
+                                tls = Some(acceptor);
+                            }
+                            Err(error) => {
+                                error!(message = "Unable to reload the TLS identity.", %error);
+                            }
+                        }
+                        continue;
+                    }
+                    Event::Closed => break,
+                };
 
-            while let Some(stream) = tcp.try_next().await? {
                 #[cfg(feature = "tls")]
                 {
-                    if let Some(tls) = &self.tls {
-                        let io = match tls.connect(stream.into_inner()).await {
+                    if let Some(tls) = &tls {
+                        let io = match tls.connect(io).await {
                             Ok(io) => io,
                             Err(error) => {
                                 error!(message = "Unable to accept incoming connection.", %error);
                                 continue
                             },
                         };
-                        yield BoxedIo::new(io);
+                        yield prepare(io, remote_addr);
                         continue;
                     }
                 }
 
-                yield BoxedIo::new(stream);
+                yield prepare(BoxedIo::new(io), remote_addr);
             }
         });
 
         let svc = MakeSvc {
             inner: svc,
             interceptor,
+            intercept,
+            layers,
+            stats_handler,
             concurrency_limit,
-            // timeout,
+            global_concurrency_limit,
+            load_shed,
+            load_shed_status,
+            peer_rate_limit,
+            peer_rate_limit_status,
+            echo_metadata,
+            #[cfg(feature = "grpc-web")]
+            grpc_web,
+            timeout,
         };
 
-        hyper::Server::builder(incoming)
-            .http2_only(true)
+        let server = hyper::Server::builder(incoming)
+            .http2_only(http2_only)
             .http2_initial_connection_window_size(init_connection_window_size)
             .http2_initial_stream_window_size(init_stream_window_size)
             .http2_max_concurrent_streams(max_concurrent_streams)
-            .serve(svc)
-            .await
-            .map_err(map_err)?;
+            .serve(svc);
+
+        let signal = match signal {
+            Some(signal) => signal,
+            None => return server.await.map_err(map_err),
+        };
 
-        Ok(())
+        let (signaled_tx, signaled) = oneshot::channel();
+        let server = server.with_graceful_shutdown(async move {
+            signal.await;
+            debug!("shutting down, waiting for in-flight requests");
+            let _ = signaled_tx.send(());
+        });
+
+        let grace_period = match shutdown_grace_period {
+            Some(period) => period,
+            None => return server.await.map_err(map_err),
+        };
+
+        let grace = async move {
+            // the server stopped on its own if the signal never fired
+            match signaled.await {
+                Ok(()) => tokio::timer::delay_for(grace_period).await,
+                Err(_) => future::pending().await,
+            }
+        };
+
+        futures_util::pin_mut!(server, grace);
+        match future::select(server, grace).await {
+            Either::Left((result, _)) => result.map_err(map_err),
+            Either::Right(((), server)) => {
+                debug!("shutdown grace period is over, closing connections");
+                let _ = force_close_tx.broadcast(true);
+                server.await.map_err(map_err)
+            }
+        }
     }
 }
 
@@ -248,16 +962,14 @@ impl<S> Router<S, Unimplemented> {
         S::Future: Send + 'static,
         S::Error: Into<crate::Error> + Send,
     {
-        let svc_name = <S as ServiceName>::NAME;
-        let svc_route = format!("/{}", svc_name);
-        let pred = move |req: &Request<Body>| {
-            let path = req.uri().path();
-
-            path.starts_with(&svc_route)
+        let pred = route::<S>();
+        let unimplemented = Unimplemented {
+            fallback: server.fallback.clone(),
         };
         Self {
             server,
-            routes: Routes::new(pred, svc, Unimplemented::default()),
+            routes: Routes::new(pred, svc, unimplemented),
+            service_names: vec![S::NAME],
         }
     }
 }
@@ -272,6 +984,11 @@ where
     B::Error: Into<crate::Error> + Send,
 {
     /// Add a new service to this router.
+    ///
+    /// Requests are routed to the service whose [`ServiceName`] matches the
+    /// service part of their `:path`, services added later take precedence.
+    ///
+    /// [`ServiceName`]: trait.ServiceName.html
     pub fn add_service<S>(self, svc: S) -> Router<S, Or<A, B, Request<Body>>>
     where
         S: Service<Request<Body>, Response = Response<BoxBody>>
@@ -282,18 +999,31 @@ where
         S::Future: Send + 'static,
         S::Error: Into<crate::Error> + Send,
     {
-        let Self { routes, server } = self;
+        let Self {
+            routes,
+            server,
+            mut service_names,
+        } = self;
 
-        let svc_name = <S as ServiceName>::NAME;
-        let svc_route = format!("/{}", svc_name);
-        let pred = move |req: &Request<Body>| {
-            let path = req.uri().path();
+        let routes = routes.push(route::<S>(), svc);
+        service_names.push(S::NAME);
 
-            path.starts_with(&svc_route)
-        };
-        let routes = routes.push(pred, svc);
+        Router {
+            server,
+            routes,
+            service_names,
+        }
+    }
 
-        Router { server, routes }
+    /// The [`ServiceName`]s of the services added to this router, in the
+    /// order they were added.
+    ///
+    /// This is e.g. the list of services to report to a health or
+    /// reflection service.
+    ///
+    /// [`ServiceName`]: trait.ServiceName.html
+    pub fn service_names(&self) -> &[&'static str] {
+        &self.service_names
     }
 
     /// Consume this [`Server`] creating a future that will execute the server
@@ -301,8 +1031,112 @@ where
     ///
     /// [`Server`]: struct.Server.html
     pub async fn serve(self, addr: SocketAddr) -> Result<(), super::Error> {
-        self.server.serve(addr, self.routes).await
+        self.server
+            .serve(addr, self.routes, None::<future::Pending<()>>)
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will execute the server
+    /// on [`tokio`]'s default executor until `signal` resolves.
+    ///
+    /// Once `signal` resolves, the server stops accepting connections and
+    /// sends a `GOAWAY` frame on every open connection, so clients stop
+    /// sending new requests on it. The future completes once the requests in
+    /// flight are done and the connections are closed, or once the
+    /// [`Server::shutdown_grace_period`] is over.
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`Server::shutdown_grace_period`]: struct.Server.html#method.shutdown_grace_period
+    pub async fn serve_with_shutdown<F>(
+        self,
+        addr: SocketAddr,
+        signal: F,
+    ) -> Result<(), super::Error>
+    where
+        F: Future<Output = ()>,
+    {
+        self.server.serve(addr, self.routes, Some(signal)).await
+    }
+
+    /// Consume this [`Server`] creating a future that will serve the
+    /// connections accepted by `listener`.
+    ///
+    /// Binding the listener before serving lets the caller learn its
+    /// address, e.g. after binding port 0 for an ephemeral port, and makes
+    /// the server reachable right away: clients connecting before the server
+    /// accepts their connection wait in the listen backlog.
+    ///
+    /// [`Server`]: struct.Server.html
+    pub async fn serve_with_listener(self, listener: TcpListener) -> Result<(), super::Error> {
+        self.server
+            .serve_with_listener(listener, self.routes, None::<future::Pending<()>>)
+            .await
+    }
+
+    /// Consume this [`Server`] creating a future that will serve the
+    /// connections accepted by `listener` until `signal` resolves.
+    ///
+    /// See [`Router::serve_with_listener`] and [`Router::serve_with_shutdown`].
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`Router::serve_with_listener`]: #method.serve_with_listener
+    /// [`Router::serve_with_shutdown`]: #method.serve_with_shutdown
+    pub async fn serve_with_listener_and_shutdown<F>(
+        self,
+        listener: TcpListener,
+        signal: F,
+    ) -> Result<(), super::Error>
+    where
+        F: Future<Output = ()>,
+    {
+        self.server
+            .serve_with_listener(listener, self.routes, Some(signal))
+            .await
     }
+
+    /// Consume this [`Server`] creating a future that will serve the
+    /// connections yielded by `incoming` instead of binding a TCP listener.
+    ///
+    /// Any `AsyncRead + AsyncWrite` transport can be served this way, such as
+    /// Unix domain sockets or in-process pipes for tests that should not
+    /// bind a port. The server completes once `incoming` is exhausted. These
+    /// connections have no remote address, so per peer settings like
+    /// [`Server::rate_limit_per_peer`] do not apply to them.
+    ///
+    /// [`Server`]: struct.Server.html
+    /// [`Server::rate_limit_per_peer`]: struct.Server.html#method.rate_limit_per_peer
+    pub async fn serve_with_incoming<I, IO, IE>(self, incoming: I) -> Result<(), super::Error>
+    where
+        I: Stream<Item = Result<IO, IE>> + Send,
+        IO: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+        IE: Into<crate::Error>,
+    {
+        self.server.serve_with_incoming(self.routes, incoming).await
+    }
+}
+
+/// Matches requests whose `:path` is `/{NAME}/{method}` for the service `S`.
+///
+/// The trailing slash keeps a service from claiming the requests of another
+/// service whose name it is a prefix of, like `pkg.Svc` and `pkg.SvcV2`.
+fn route<S: ServiceName>() -> impl Fn(&Request<Body>) -> bool + Send + Sync + 'static {
+    let svc_route = format!("/{}/", S::NAME);
+    move |req: &Request<Body>| req.uri().path().starts_with(&svc_route)
+}
+
+fn with_remote_addr(io: BoxedIo, remote_addr: Option<SocketAddr>) -> BoxedIo {
+    match remote_addr {
+        Some(addr) => io.with_remote_addr(addr),
+        None => io,
+    }
+}
+
+/// What the accept loop of a server waits for.
+enum Event<IO> {
+    Accepted(Result<(IO, Option<SocketAddr>), crate::Error>),
+    #[cfg(feature = "tls")]
+    Identity(Result<ServerIdentity, crate::Error>),
+    Closed,
 }
 
 fn map_err(e: impl Into<crate::Error>) -> super::Error {
@@ -326,6 +1160,7 @@ pub struct ServerTlsConfig {
     openssl_raw: Option<openssl1::ssl::SslAcceptor>,
     #[cfg(feature = "rustls")]
     rustls_raw: Option<tokio_rustls::rustls::ServerConfig>,
+    identity_provider: Option<Arc<dyn IdentityProvider>>,
 }
 
 #[cfg(feature = "tls")]
@@ -333,6 +1168,7 @@ impl fmt::Debug for ServerTlsConfig {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ServerTlsConfig")
             .field("provider", &self.provider)
+            .field("identity_provider", &self.identity_provider)
             .finish()
     }
 }
@@ -362,26 +1198,54 @@ impl ServerTlsConfig {
             openssl_raw: None,
             #[cfg(feature = "rustls")]
             rustls_raw: None,
+            identity_provider: None,
         }
     }
 
     /// Sets the [`Identity`] of the server.
-    pub fn identity(&mut self, identity: Identity) -> &mut Self {
+    pub fn identity(mut self, identity: Identity) -> Self {
         self.identity = Some(identity);
         self
     }
 
     /// Sets a certificate against which to validate client TLS certificates.
-    pub fn client_ca_root(&mut self, cert: Certificate) -> &mut Self {
+    pub fn client_ca_root(mut self, cert: Certificate) -> Self {
         self.client_ca_root = Some(cert);
         self
     }
 
+    /// Reload the identity of the server from the given provider, e.g. from
+    /// files that are renewed periodically with [`FileIdentityProvider`].
+    ///
+    /// This overrides the [`identity`] of the config. The
+    /// [`client_ca_root`] of the config is used for identities that don't
+    /// have their own.
+    ///
+    /// ```
+    /// # use tonic::transport::{FileIdentityProvider, Server, ServerTlsConfig};
+    /// # #[cfg(feature = "rustls")]
+    /// # fn dox() -> Result<(), tonic::transport::Error> {
+    /// let tls = ServerTlsConfig::with_rustls()
+    ///     .identity_provider(FileIdentityProvider::new("tls.crt", "tls.key"));
+    ///
+    /// let builder = Server::builder().tls_config(tls)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`FileIdentityProvider`]: struct.FileIdentityProvider.html
+    /// [`identity`]: #method.identity
+    /// [`client_ca_root`]: #method.client_ca_root
+    pub fn identity_provider(mut self, provider: impl IdentityProvider) -> Self {
+        self.identity_provider = Some(Arc::new(provider));
+        self
+    }
+
     /// Use options specified by the given `SslAcceptor` to configure TLS.
     ///
     /// This overrides all other TLS options set via other means.
     #[cfg(feature = "openssl")]
-    pub fn openssl_connector(&mut self, acceptor: openssl1::ssl::SslAcceptor) -> &mut Self {
+    pub fn openssl_connector(mut self, acceptor: openssl1::ssl::SslAcceptor) -> Self {
         self.openssl_raw = Some(acceptor);
         self
     }
@@ -390,61 +1254,193 @@ impl ServerTlsConfig {
     ///
     /// This overrides all other TLS options set via other means.
     #[cfg(feature = "rustls")]
-    pub fn rustls_server_config(
-        &mut self,
-        config: tokio_rustls::rustls::ServerConfig,
-    ) -> &mut Self {
+    pub fn rustls_server_config(mut self, config: tokio_rustls::rustls::ServerConfig) -> Self {
         self.rustls_raw = Some(config);
         self
     }
 
-    fn tls_acceptor(&self) -> Result<TlsAcceptor, crate::Error> {
+    fn tls_acceptor(&self) -> Result<TlsAcceptor, super::Error> {
+        self.tls_acceptor_with(self.identity.clone(), self.client_ca_root.clone())
+    }
+
+    /// Build the acceptor for an identity of the identity provider.
+    fn reloaded_acceptor(&self, identity: ServerIdentity) -> Result<TlsAcceptor, super::Error> {
+        let client_ca_root = identity
+            .client_ca_root
+            .or_else(|| self.client_ca_root.clone());
+        self.tls_acceptor_with(Some(identity.identity), client_ca_root)
+    }
+
+    /// Wait for the first identity of the identity provider, returning its
+    /// acceptor and the later identities.
+    async fn first_identity(&self) -> Result<(TlsAcceptor, Identities), crate::Error> {
+        let mut identities = self.identity_provider.as_ref().unwrap().identities();
+        let identity = identities
+            .next()
+            .await
+            .ok_or("the identity provider provided no identity")??;
+
+        Ok((self.reloaded_acceptor(identity)?, identities))
+    }
+
+    fn tls_acceptor_with(
+        &self,
+        identity: Option<Identity>,
+        client_ca_root: Option<Certificate>,
+    ) -> Result<TlsAcceptor, super::Error> {
+        let identity = || {
+            identity.ok_or_else(|| {
+                super::Error::from_source(
+                    super::ErrorKind::InvalidIdentity,
+                    "no identity is set".into(),
+                )
+            })
+        };
         match self.provider {
             #[cfg(feature = "openssl")]
             TlsProvider::OpenSsl => match &self.openssl_raw {
-                None => TlsAcceptor::new_with_openssl_identity(
-                    self.identity.clone().unwrap(),
-                    self.client_ca_root.clone(),
-                ),
+                None => TlsAcceptor::new_with_openssl_identity(identity()?, client_ca_root),
                 Some(acceptor) => TlsAcceptor::new_with_openssl_raw(acceptor.clone()),
             },
             #[cfg(feature = "rustls")]
             TlsProvider::Rustls => match &self.rustls_raw {
-                None => TlsAcceptor::new_with_rustls_identity(
-                    self.identity.clone().unwrap(),
-                    self.client_ca_root.clone(),
-                ),
+                None => TlsAcceptor::new_with_rustls_identity(identity()?, client_ca_root),
                 Some(config) => TlsAcceptor::new_with_rustls_raw(config.clone()),
             },
         }
     }
 }
 
-#[derive(Debug)]
+/// Configures how a server handles [grpc-web] requests.
+///
+/// By default no cross-origin requests are allowed, so only a page served
+/// from the same origin as the server can call it.
+///
+/// [grpc-web]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
+#[cfg(feature = "grpc-web")]
+#[derive(Clone, Debug, Default)]
+pub struct GrpcWebConfig {
+    allowed_origins: AllowedOrigins,
+    pub(crate) max_age: Option<Duration>,
+}
+
+#[cfg(feature = "grpc-web")]
+#[derive(Clone, Debug)]
+enum AllowedOrigins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+#[cfg(feature = "grpc-web")]
+impl Default for AllowedOrigins {
+    fn default() -> Self {
+        AllowedOrigins::List(Vec::new())
+    }
+}
+
+#[cfg(feature = "grpc-web")]
+impl GrpcWebConfig {
+    /// Creates a new `GrpcWebConfig` that doesn't allow cross-origin requests.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow cross-origin requests from `origin`, e.g. `https://example.com`.
+    ///
+    /// # Panics
+    ///
+    /// If `origin` is not a valid header value.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        let origin = HeaderValue::from_str(origin).expect("invalid origin");
+        match &mut self.allowed_origins {
+            AllowedOrigins::Any => {}
+            AllowedOrigins::List(origins) => origins.push(origin),
+        }
+        self
+    }
+
+    /// Allow cross-origin requests from any origin.
+    pub fn allow_any_origin(mut self) -> Self {
+        self.allowed_origins = AllowedOrigins::Any;
+        self
+    }
+
+    /// Set how long browsers may cache the response to a CORS preflight
+    /// request.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub(crate) fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        match &self.allowed_origins {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(origins) => origins.contains(origin),
+        }
+    }
+}
+
+/// Accepts the connections of a TCP listener along with the addresses of
+/// their peers.
+///
+/// Like hyper's `AddrIncoming`, errors of single connections are skipped,
+/// while other errors, like running out of file descriptors, pause accepting
+/// connections for a second instead of ending the server.
 struct TcpIncoming {
-    inner: conn::AddrIncoming,
+    inner: Pin<Box<dyn Stream<Item = io::Result<TcpStream>> + Send>>,
+    delay: Option<Delay>,
 }
 
 impl TcpIncoming {
-    fn bind(addr: SocketAddr) -> Result<Self, crate::Error> {
-        let inner = conn::AddrIncoming::bind(&addr).map_err(Box::new)?;
-
-        Ok(Self { inner })
+    fn new(listener: TcpListener) -> Self {
+        Self {
+            inner: Box::pin(listener.incoming()),
+            delay: None,
+        }
     }
 }
 
 impl Stream for TcpIncoming {
-    type Item = Result<conn::AddrStream, crate::Error>;
+    type Item = Result<(TcpStream, SocketAddr), crate::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        match ready!(Accept::poll_accept(Pin::new(&mut self.inner), cx)) {
-            Some(Ok(s)) => Poll::Ready(Some(Ok(s))),
-            Some(Err(e)) => Poll::Ready(Some(Err(e.into()))),
-            None => Poll::Ready(None),
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(Pin::new(delay).poll(cx));
+                self.delay = None;
+            }
+
+            let error = match ready!(self.inner.as_mut().poll_next(cx)) {
+                Some(Ok(stream)) => match stream.peer_addr() {
+                    Ok(remote_addr) => return Poll::Ready(Some(Ok((stream, remote_addr)))),
+                    Err(error) => error,
+                },
+                Some(Err(error)) => error,
+                None => return Poll::Ready(None),
+            };
+
+            match error.kind() {
+                io::ErrorKind::ConnectionRefused
+                | io::ErrorKind::ConnectionAborted
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::NotConnected => {
+                    debug!(message = "Accepted connection already errored.", %error);
+                }
+                _ => {
+                    error!(message = "Unable to accept incoming connection.", %error);
+                    self.delay = Some(tokio::timer::delay_for(Duration::from_secs(1)));
+                }
+            }
         }
     }
 }
 
+impl fmt::Debug for TcpIncoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TcpIncoming").finish()
+    }
+}
+
 #[derive(Debug)]
 struct Svc<S>(S);
 
@@ -468,12 +1464,23 @@ where
 
 struct MakeSvc<S> {
     interceptor: Option<Interceptor>,
+    intercept: Option<crate::Interceptor>,
+    layers: Arc<Vec<Interceptor>>,
+    stats_handler: Option<Arc<dyn StatsHandler>>,
     concurrency_limit: Option<usize>,
-    // timeout: Option<Duration>,
+    global_concurrency_limit: Option<Arc<Semaphore>>,
+    load_shed: bool,
+    load_shed_status: Option<Status>,
+    peer_rate_limit: Option<Arc<PeerRateLimiter>>,
+    peer_rate_limit_status: Option<Status>,
+    echo_metadata: Option<Arc<Vec<HeaderName>>>,
+    #[cfg(feature = "grpc-web")]
+    grpc_web: Option<Arc<GrpcWebConfig>>,
+    timeout: Option<Duration>,
     inner: S,
 }
 
-impl<S, T> Service<T> for MakeSvc<S>
+impl<'a, S> Service<&'a BoxedIo> for MakeSvc<S>
 where
     S: Service<Request<Body>, Response = Response<BoxBody>> + Clone + Send + 'static,
     S::Future: Send + 'static,
@@ -488,16 +1495,36 @@ where
         Ok(()).into()
     }
 
-    fn call(&mut self, _: T) -> Self::Future {
+    fn call(&mut self, io: &'a BoxedIo) -> Self::Future {
         let interceptor = self.interceptor.clone();
+        let intercept = self.intercept.clone();
+        let layers = self.layers.clone();
+        let stats_handler = self.stats_handler.clone();
         let svc = self.inner.clone();
         let concurrency_limit = self.concurrency_limit;
-        // let timeout = self.timeout.clone();
+        let global_concurrency_limit = self.global_concurrency_limit.clone();
+        // the status of shed requests, if requests are shed
+        let load_shed = if self.load_shed {
+            Some(
+                self.load_shed_status
+                    .clone()
+                    .unwrap_or_else(overloaded_status),
+            )
+        } else {
+            None
+        };
+        let peer_rate_limit = self.peer_rate_limit.clone();
+        let peer_rate_limit_status = self.peer_rate_limit_status.clone();
+        let echo_metadata = self.echo_metadata.clone();
+        #[cfg(feature = "grpc-web")]
+        let grpc_web = self.grpc_web.clone();
+        let peer = io.remote_addr().map(|addr| addr.ip());
+        let info = io.connection_info().clone();
+        let timeout = self.timeout;
 
         Box::pin(async move {
             let svc = ServiceBuilder::new()
                 .optional_layer(concurrency_limit.map(ConcurrencyLimitLayer::new))
-                // .optional_layer(timeout.map(TimeoutLayer::new))
                 .service(svc);
 
             let svc = if let Some(interceptor) = interceptor {
@@ -507,33 +1534,96 @@ where
                 BoxService::new(Svc(svc))
             };
 
-            Ok(svc)
+            let svc = match global_concurrency_limit {
+                Some(semaphore) => BoxService::new(GlobalConcurrencyLimit::new(
+                    svc,
+                    semaphore,
+                    load_shed.clone(),
+                )),
+                None => svc,
+            };
+
+            let svc = match load_shed {
+                Some(status) => BoxService::new(LoadShed::new(svc, status)),
+                None => svc,
+            };
+
+            let svc = match intercept {
+                Some(intercept) => BoxService::new(Intercept::new(svc, intercept)),
+                None => svc,
+            };
+
+            let svc = match peer_rate_limit {
+                Some(limiter) => BoxService::new(PeerRateLimit::new(
+                    svc,
+                    limiter,
+                    peer,
+                    peer_rate_limit_status,
+                )),
+                None => svc,
+            };
+
+            let svc = BoxService::new(CancelOnDrop::new(svc));
+            let svc = BoxService::new(GrpcTimeout::new(svc, timeout));
+
+            let svc = match echo_metadata {
+                Some(keys) => BoxService::new(EchoMetadata::new(svc, keys)),
+                None => svc,
+            };
+
+            // the first layer added is the outermost
+            let svc = layers.iter().rev().fold(svc, |svc, layer| layer.layer(svc));
+
+            let svc = BoxService::new(Stats::new(svc, stats_handler));
+
+            #[cfg(feature = "grpc-web")]
+            let svc = match grpc_web {
+                Some(config) => BoxService::new(GrpcWeb::new(svc, config)),
+                None => svc,
+            };
+
+            Ok(BoxService::new(AddExtension::new(svc, info)))
         })
     }
 }
 
-#[derive(Default, Clone, Debug)]
+#[derive(Default, Clone)]
 #[doc(hidden)]
 pub struct Unimplemented {
-    _p: (),
+    fallback: Option<Fallback>,
 }
 
 impl Service<Request<Body>> for Unimplemented {
     type Response = Response<BoxBody>;
     type Error = crate::Error;
-    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+    type Future = Either<
+        future::Ready<Result<Self::Response, Self::Error>>,
+        Oneshot<BoxService, Request<Body>>,
+    >;
 
     fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         Ok(()).into()
     }
 
-    fn call(&mut self, _req: Request<Body>) -> Self::Future {
-        future::ok(
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if let Some(fallback) = &self.fallback {
+            return Either::Right(Oneshot::new(fallback(), req));
+        }
+
+        Either::Left(future::ok(
             http::Response::builder()
                 .status(200)
                 .header("grpc-status", "12")
                 .body(BoxBody::empty())
                 .unwrap(),
-        )
+        ))
+    }
+}
+
+impl fmt::Debug for Unimplemented {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Unimplemented")
+            .field("fallback", &self.fallback.is_some())
+            .finish()
     }
 }
@@ -0,0 +1,35 @@
+use http::Request;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// Inserts a clone of `value` into the extensions of every request.
+#[derive(Debug, Clone)]
+pub(crate) struct AddExtension<S, T> {
+    inner: S,
+    value: T,
+}
+
+impl<S, T> AddExtension<S, T> {
+    pub(crate) fn new(inner: S, value: T) -> Self {
+        AddExtension { inner, value }
+    }
+}
+
+impl<S, T, ReqBody> Service<Request<ReqBody>> for AddExtension<S, T>
+where
+    S: Service<Request<ReqBody>>,
+    T: Clone + Send + Sync + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        req.extensions_mut().insert(self.value.clone());
+        self.inner.call(req)
+    }
+}
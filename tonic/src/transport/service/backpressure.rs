@@ -0,0 +1,173 @@
+use crate::{transport::BufferFullPolicy, Status};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::timer::{delay_for, Delay};
+use tower_service::Service;
+use tracing::trace;
+
+/// Applies a [`BufferFullPolicy`] to a buffered service.
+///
+/// The inner service is expected to be a `Buffer`, which stays not ready for
+/// as long as its queue is full. Rejected requests fail with `status`.
+#[derive(Debug)]
+pub(crate) struct Backpressure<S> {
+    inner: S,
+    policy: BufferFullPolicy,
+    status: Status,
+    delay: Option<Delay>,
+}
+
+impl<S> Backpressure<S> {
+    pub(crate) fn new(inner: S, policy: BufferFullPolicy, status: Option<Status>) -> Self {
+        Backpressure {
+            inner,
+            policy,
+            status: status.unwrap_or_else(buffer_full),
+            delay: None,
+        }
+    }
+}
+
+impl<S, Request> Service<Request> for Backpressure<S>
+where
+    S: Service<Request, Error = crate::Error>,
+{
+    type Response = S::Response;
+    type Error = crate::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Poll::Ready(r) = self.inner.poll_ready(cx) {
+            self.delay = None;
+            return Poll::Ready(r);
+        }
+
+        trace!("poll_ready; buffer full");
+
+        match self.policy {
+            BufferFullPolicy::Wait => Poll::Pending,
+            BufferFullPolicy::FailFast => Poll::Ready(Err(self.status.clone().into())),
+            BufferFullPolicy::WaitTimeout(timeout) => {
+                let delay = self.delay.get_or_insert_with(|| delay_for(timeout));
+
+                match Pin::new(delay).poll(cx) {
+                    Poll::Ready(()) => {
+                        self.delay = None;
+                        Poll::Ready(Err(self.status.clone().into()))
+                    }
+                    Poll::Pending => Poll::Pending,
+                }
+            }
+        }
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        self.inner.call(request)
+    }
+}
+
+impl<S: Clone> Clone for Backpressure<S> {
+    fn clone(&self) -> Self {
+        Backpressure::new(self.inner.clone(), self.policy, Some(self.status.clone()))
+    }
+}
+
+fn buffer_full() -> Status {
+    Status::resource_exhausted("channel request buffer is full")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Code;
+    use futures_util::future::{self, poll_fn, Ready};
+    use std::time::{Duration, Instant};
+    use tower::buffer::Buffer;
+
+    /// A service that is never ready, so requests pile up in the buffer.
+    struct Stuck;
+
+    impl Service<()> for Stuck {
+        type Response = ();
+        type Error = crate::Error;
+        type Future = Ready<Result<(), crate::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Pending
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    type Pending = Vec<<Buffer<Stuck, ()> as Service<()>>::Future>;
+
+    /// Fill a buffer of size one in front of `Stuck` and wrap it, so that the
+    /// next request has to wait for space.
+    ///
+    /// The returned response futures have to be kept alive, the buffer worker
+    /// discards requests whose caller went away.
+    async fn saturated(
+        policy: BufferFullPolicy,
+        status: Option<Status>,
+    ) -> (Backpressure<Buffer<Stuck, ()>>, Pending) {
+        let buffer = Buffer::new(Stuck, 1);
+        let mut pending = Vec::new();
+
+        // The first request is held by the buffer worker and the second one
+        // occupies the only slot of the queue.
+        for _ in 0..2 {
+            let mut handle = buffer.clone();
+            poll_fn(|cx| handle.poll_ready(cx)).await.unwrap();
+            pending.push(handle.call(()));
+        }
+
+        (Backpressure::new(buffer, policy, status), pending)
+    }
+
+    fn code(err: crate::Error) -> Code {
+        Status::from_error(&*err).code()
+    }
+
+    #[tokio::test]
+    async fn fail_fast() {
+        let (mut svc, _pending) = saturated(BufferFullPolicy::FailFast, None).await;
+
+        let err = poll_fn(|cx| svc.poll_ready(cx)).await.unwrap_err();
+        assert_eq!(code(err), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn wait_timeout() {
+        let timeout = Duration::from_millis(50);
+        let (mut svc, _pending) = saturated(BufferFullPolicy::WaitTimeout(timeout), None).await;
+
+        let start = Instant::now();
+        let err = poll_fn(|cx| svc.poll_ready(cx)).await.unwrap_err();
+        assert_eq!(code(err), Code::ResourceExhausted);
+        assert!(start.elapsed() >= timeout);
+    }
+
+    #[tokio::test]
+    async fn wait() {
+        let (mut svc, _pending) = saturated(BufferFullPolicy::Wait, None).await;
+
+        let ready = poll_fn(|cx| Poll::Ready(svc.poll_ready(cx).is_ready())).await;
+        assert!(!ready);
+    }
+
+    #[tokio::test]
+    async fn custom_status() {
+        let status = Status::new(Code::Unavailable, "client overloaded");
+        let (mut svc, _pending) = saturated(BufferFullPolicy::FailFast, Some(status)).await;
+
+        let err = poll_fn(|cx| svc.poll_ready(cx)).await.unwrap_err();
+        let status = Status::from_error(&*err);
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "client overloaded");
+    }
+}
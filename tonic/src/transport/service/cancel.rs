@@ -0,0 +1,235 @@
+use crate::{body::BoxBody, transport::CancellationToken, Status};
+use http::{HeaderMap, Request, Response};
+use http_body::Body as HttpBody;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::sync::watch;
+use tower_service::Service;
+
+/// Inserts a [`CancellationToken`] into the extensions of each request, which
+/// is cancelled if the request is dropped before it finished.
+///
+/// A request is finished once its response future or body failed, its
+/// response is a trailers-only response, or its response body ended.
+/// The server drops the response future or body of a request that the client
+/// reset, and so does the `GrpcTimeout` of a request whose deadline elapsed.
+#[derive(Debug)]
+pub(crate) struct CancelOnDrop<S> {
+    inner: S,
+}
+
+impl<S> CancelOnDrop<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        CancelOnDrop { inner }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for CancelOnDrop<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let (tx, token) = CancellationToken::new();
+        req.extensions_mut().insert(token);
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            guard: Some(Guard(Some(tx))),
+        }
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    guard: Option<Guard>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.project();
+        let result = futures_util::ready!(me.inner.poll(cx));
+        let mut guard = me.guard.take().expect("polled after ready");
+
+        let response = match result {
+            Ok(response) => response,
+            Err(error) => {
+                guard.finish();
+                return Poll::Ready(Err(error));
+            }
+        };
+
+        if response.headers().contains_key("grpc-status") {
+            guard.finish();
+            return Poll::Ready(Ok(response));
+        }
+
+        let response = response.map(|body| BoxBody::new(CancelBody { inner: body, guard }));
+        Poll::Ready(Ok(response))
+    }
+}
+
+/// Cancels the token of a request when dropped, unless it was finished.
+#[derive(Debug)]
+struct Guard(Option<watch::Sender<bool>>);
+
+impl Guard {
+    fn finish(&mut self) {
+        self.0.take();
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(tx) = self.0.take() {
+            // every token may already be gone
+            let _ = tx.broadcast(true);
+        }
+    }
+}
+
+struct CancelBody {
+    inner: BoxBody,
+    guard: Guard,
+}
+
+impl HttpBody for CancelBody {
+    type Data = <BoxBody as HttpBody>::Data;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = futures_util::ready!(Pin::new(&mut self.inner).poll_data(cx));
+
+        // the trailers of a body that ends here are never polled, and a body
+        // that failed is not cancelled either
+        if self.inner.is_end_stream() || matches!(data, Some(Err(_))) {
+            self.guard.finish();
+        }
+
+        Poll::Ready(data)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = futures_util::ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+        self.guard.finish();
+        Poll::Ready(trailers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::{self, poll_fn, Ready};
+    use std::sync::{Arc, Mutex};
+
+    /// Responds with an empty body, and keeps the token of the request.
+    #[derive(Default)]
+    struct Svc(Arc<Mutex<Option<CancellationToken>>>);
+
+    impl Service<Request<()>> for Svc {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, req: Request<()>) -> Self::Future {
+            *self.0.lock().unwrap() = req.extensions().get::<CancellationToken>().cloned();
+            future::ok(Response::new(BoxBody::empty()))
+        }
+    }
+
+    fn call() -> (
+        ResponseFuture<Ready<Result<Response<BoxBody>, crate::Error>>>,
+        CancellationToken,
+    ) {
+        let svc = Svc::default();
+        let token = svc.0.clone();
+
+        let future = CancelOnDrop::new(svc).call(Request::new(()));
+        let token = token.lock().unwrap().take().unwrap();
+        (future, token)
+    }
+
+    #[tokio::test]
+    async fn cancels_dropped_response_future() {
+        let (future, token) = call();
+        assert!(!token.is_cancelled());
+
+        drop(future);
+        assert!(token.is_cancelled());
+        token.cancelled().await;
+    }
+
+    #[tokio::test]
+    async fn cancels_dropped_body() {
+        let (future, token) = call();
+
+        let body = future.await.unwrap().into_body();
+        assert!(!token.is_cancelled());
+
+        drop(body);
+        assert!(token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn ended_body_is_not_cancelled() {
+        let (future, token) = call();
+
+        // hyper does not poll the trailers of a body that ended
+        let mut body = future.await.unwrap().into_body();
+        assert!(poll_fn(|cx| Pin::new(&mut body).poll_data(cx))
+            .await
+            .is_none());
+
+        drop(body);
+        assert!(!token.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn finished_body_is_not_cancelled() {
+        let (future, token) = call();
+
+        let mut body = future.await.unwrap().into_body();
+        assert!(poll_fn(|cx| Pin::new(&mut body).poll_data(cx))
+            .await
+            .is_none());
+        poll_fn(|cx| Pin::new(&mut body).poll_trailers(cx))
+            .await
+            .unwrap();
+
+        drop(body);
+        assert!(!token.is_cancelled());
+    }
+}
@@ -1,13 +1,28 @@
-use super::{connector, layer::ServiceBuilderExt, reconnect::Reconnect, AddOrigin};
-use crate::{body::BoxBody, transport::Endpoint};
+use super::{
+    connector::Connector,
+    io::Io,
+    layer::ServiceBuilderExt,
+    reconnect::{Backoff, Reconnect},
+    AddOrigin,
+};
+use crate::{
+    body::BoxBody,
+    transport::{
+        channel::ConnectivityState,
+        channelz::{self, SubchannelNode},
+        ConnectionInfo, Endpoint,
+    },
+};
 use hyper::client::conn::Builder;
 use hyper::client::service::Connect as HyperConnect;
 use std::{
     fmt,
     future::Future,
     pin::Pin,
+    sync::{atomic::AtomicBool, Arc, Mutex},
     task::{Context, Poll},
 };
+use tokio::sync::watch;
 use tower::{
     layer::Layer,
     limit::{concurrency::ConcurrencyLimitLayer, rate::RateLimitLayer},
@@ -21,43 +36,130 @@ use tower_service::Service;
 pub(crate) type Request = http::Request<BoxBody>;
 pub(crate) type Response = http::Response<hyper::Body>;
 
+type MakeConnection<C> = HyperConnect<Connector<C>, BoxBody, http::Uri>;
+
 pub(crate) struct Connection {
     inner: BoxService<Request, Response, crate::Error>,
+    info: Arc<Mutex<Option<ConnectionInfo>>>,
+    state: watch::Receiver<ConnectivityState>,
+    reset_backoff: Arc<AtomicBool>,
+    subchannel: Option<Arc<SubchannelNode>>,
 }
 
 impl Connection {
-    pub(crate) async fn new(endpoint: Endpoint) -> Result<Self, crate::Error> {
-        #[cfg(feature = "tls")]
-        let connector = connector(endpoint.tls.clone());
+    pub(crate) async fn new<C>(
+        endpoint: Endpoint,
+        connector: Connector<C>,
+    ) -> Result<Self, crate::Error>
+    where
+        C: tower_make::MakeConnection<http::Uri> + Send + 'static,
+        C::Connection: Io,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let info = connector.connection_info();
+        let subchannel = connector.subchannel();
+        let mut connector = make_connection(&endpoint, connector);
 
-        #[cfg(not(feature = "tls"))]
-        let connector = connector();
+        let initial_conn = connector.call(endpoint.uri.clone()).await?;
+        let conn = Reconnect::new(
+            initial_conn,
+            connector,
+            endpoint.uri.clone(),
+            backoff(&endpoint),
+        );
+
+        Ok(Self::with_stack(&endpoint, conn, info, subchannel))
+    }
 
-        let settings = Builder::new()
-            .http2_initial_stream_window_size(endpoint.init_stream_window_size)
-            .http2_initial_connection_window_size(endpoint.init_connection_window_size)
-            .http2_only(true)
-            .clone();
+    /// Create a connection that is only established once it is first used.
+    pub(crate) fn lazy<C>(endpoint: Endpoint, connector: Connector<C>) -> Self
+    where
+        C: tower_make::MakeConnection<http::Uri> + Send + 'static,
+        C::Connection: Io,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
+        let info = connector.connection_info();
+        let subchannel = connector.subchannel();
+        let connector = make_connection(&endpoint, connector);
+
+        let conn = Reconnect::lazy(connector, endpoint.uri.clone(), backoff(&endpoint));
+
+        Self::with_stack(&endpoint, conn, info, subchannel)
+    }
 
+    fn with_stack<C>(
+        endpoint: &Endpoint,
+        conn: Reconnect<MakeConnection<C>, http::Uri>,
+        info: Arc<Mutex<Option<ConnectionInfo>>>,
+        subchannel: Option<Arc<SubchannelNode>>,
+    ) -> Self
+    where
+        C: tower_make::MakeConnection<http::Uri> + Send + 'static,
+        C::Connection: Io,
+        C::Future: Send + 'static,
+        C::Error: Into<crate::Error> + Send,
+    {
         let stack = ServiceBuilder::new()
-            .layer_fn(|s| AddOrigin::new(s, endpoint.uri.clone()))
+            .layer_fn(|s| AddOrigin::new(s, endpoint.origin_uri().clone()))
             .optional_layer(endpoint.timeout.map(TimeoutLayer::new))
             .optional_layer(endpoint.concurrency_limit.map(ConcurrencyLimitLayer::new))
             .optional_layer(endpoint.rate_limit.map(|(l, d)| RateLimitLayer::new(l, d)))
             .into_inner();
 
-        let mut connector = HyperConnect::new(connector, settings);
-        let initial_conn = connector.call(endpoint.uri.clone()).await?;
-        let conn = Reconnect::new(initial_conn, connector, endpoint.uri.clone());
-
+        let state = conn.connectivity_state();
+        let reset_backoff = conn.reset_backoff();
         let inner = stack.layer(conn);
 
-        Ok(Self {
+        if let Some(subchannel) = &subchannel {
+            subchannel.set_state(state.clone());
+        }
+
+        Self {
             inner: BoxService::new(inner),
-        })
+            info,
+            state,
+            reset_backoff,
+            subchannel,
+        }
+    }
+
+    /// A handle to the info of the most recently established connection.
+    pub(crate) fn connection_info(&self) -> Arc<Mutex<Option<ConnectionInfo>>> {
+        self.info.clone()
+    }
+
+    /// A handle to the connectivity state of the connection.
+    pub(crate) fn connectivity_state(&self) -> watch::Receiver<ConnectivityState> {
+        self.state.clone()
+    }
+
+    /// A handle to reset the reconnect backoff of the connection.
+    pub(crate) fn reset_backoff(&self) -> Arc<AtomicBool> {
+        self.reset_backoff.clone()
     }
 }
 
+fn make_connection<C>(endpoint: &Endpoint, connector: Connector<C>) -> MakeConnection<C>
+where
+    C: tower_make::MakeConnection<http::Uri>,
+{
+    let settings = Builder::new()
+        .http2_initial_stream_window_size(endpoint.init_stream_window_size)
+        .http2_initial_connection_window_size(endpoint.init_connection_window_size)
+        .http2_only(true)
+        .clone();
+
+    HyperConnect::new(connector, settings)
+}
+
+fn backoff(endpoint: &Endpoint) -> Option<Backoff> {
+    endpoint
+        .reconnect_backoff
+        .map(|(initial, max)| Backoff::new(initial, max, endpoint.reconnect_backoff_multiplier))
+}
+
 impl Service<Request> for Connection {
     type Response = Response;
     type Error = crate::Error;
@@ -70,7 +172,17 @@ impl Service<Request> for Connection {
     }
 
     fn call(&mut self, req: Request) -> Self::Future {
-        self.inner.call(req)
+        let call = match &self.subchannel {
+            Some(subchannel) => subchannel.calls().track(),
+            None => return self.inner.call(req),
+        };
+
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await;
+            call.finish(channelz::call_succeeded(&response));
+            response
+        })
     }
 }
 
@@ -1,43 +1,100 @@
-use super::io::BoxedIo;
+use super::io::{BoxedIo, Io};
+use super::keepalive::KeepAliveConfig;
+use super::proxy;
 #[cfg(feature = "tls")]
 use super::tls::TlsConnector;
+use crate::transport::{
+    channelz::SubchannelNode, ConnectError, ConnectionInfo, Endpoint, Proxy, Stage,
+};
+use futures_util::{try_future::MapErr, TryFutureExt};
 use http::Uri;
-use hyper::client::connect::HttpConnector;
+use hyper::client::connect::{
+    dns::{GaiResolver, Name, Resolve},
+    HttpConnector,
+};
 use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::timer::Timeout;
 use tower_make::MakeConnection;
 use tower_service::Service;
 
-#[cfg(not(feature = "tls"))]
-pub(crate) fn connector() -> HttpConnector {
-    let mut http = HttpConnector::new();
-    http.enforce_http(false);
-    http
-}
-
-#[cfg(feature = "tls")]
-pub(crate) fn connector(tls: Option<TlsConnector>) -> Connector {
-    Connector::new(tls)
-}
-
-pub(crate) struct Connector {
-    http: HttpConnector,
+/// Establishes connections via `C`, wrapping them in TLS if the endpoint is
+/// configured for it.
+pub(crate) struct Connector<C: MakeConnection<Uri>> {
+    inner: C,
+    remote_addr: fn(&C::Connection) -> Option<SocketAddr>,
     #[cfg(feature = "tls")]
     tls: Option<TlsConnector>,
+    keep_alive: Option<KeepAliveConfig>,
+    connect_timeout: Option<Duration>,
+    proxy: Option<Proxy>,
+    info: Arc<Mutex<Option<ConnectionInfo>>>,
+    subchannel: Option<Arc<SubchannelNode>>,
 }
 
-impl Connector {
-    #[cfg(feature = "tls")]
-    pub(crate) fn new(tls: Option<TlsConnector>) -> Self {
-        let mut http = HttpConnector::new();
+impl Connector<HttpConnector<DnsErrors<GaiResolver>>> {
+    /// A connector that connects over TCP, through the proxy of the endpoint
+    /// if it has one.
+    pub(crate) fn http(endpoint: &Endpoint) -> Self {
+        let mut http = HttpConnector::new_with_resolver(DnsErrors(GaiResolver::new()));
         http.enforce_http(false);
+        endpoint.tcp.apply_to_connector(&mut http);
 
-        Self { http, tls }
+        let mut connector = Self::new(http, |io: &TcpStream| io.peer_addr().ok(), endpoint);
+        connector.proxy = endpoint.proxy.clone();
+        connector
     }
 }
 
-impl Service<Uri> for Connector {
+impl<C: MakeConnection<Uri>> Connector<C> {
+    /// `remote_addr` extracts the address of the peer from a connection made
+    /// by `inner`, if it has one.
+    pub(crate) fn new(
+        inner: C,
+        remote_addr: fn(&C::Connection) -> Option<SocketAddr>,
+        endpoint: &Endpoint,
+    ) -> Self {
+        Self {
+            inner,
+            remote_addr,
+            #[cfg(feature = "tls")]
+            tls: endpoint.tls.clone(),
+            keep_alive: endpoint.keep_alive_config(),
+            connect_timeout: endpoint.connect_timeout,
+            proxy: None,
+            info: Arc::new(Mutex::new(None)),
+            subchannel: endpoint
+                .channelz_channel
+                .as_ref()
+                .map(|channel| channel.add_subchannel(endpoint.uri.to_string())),
+        }
+    }
+
+    /// A handle to the info of the most recently established connection.
+    pub(crate) fn connection_info(&self) -> Arc<Mutex<Option<ConnectionInfo>>> {
+        self.info.clone()
+    }
+
+    /// The channelz subchannel the connections are counted in, if the
+    /// channel is registered with channelz.
+    pub(crate) fn subchannel(&self) -> Option<Arc<SubchannelNode>> {
+        self.subchannel.clone()
+    }
+}
+
+impl<C> Service<Uri> for Connector<C>
+where
+    C: MakeConnection<Uri>,
+    C::Connection: Io,
+    C::Future: Send + 'static,
+    C::Error: Into<crate::Error>,
+{
     type Response = BoxedIo;
     type Error = crate::Error;
 
@@ -45,27 +102,77 @@ impl Service<Uri> for Connector {
         Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        MakeConnection::poll_ready(&mut self.http, cx).map_err(Into::into)
+        MakeConnection::poll_ready(&mut self.inner, cx).map_err(Into::into)
     }
 
     fn call(&mut self, uri: Uri) -> Self::Future {
-        let connect = MakeConnection::make_connection(&mut self.http, uri);
+        let proxy = self.proxy.clone();
+        let connect = match &proxy {
+            Some(proxy) => MakeConnection::make_connection(&mut self.inner, proxy.connect_uri()),
+            None => MakeConnection::make_connection(&mut self.inner, uri.clone()),
+        };
 
         #[cfg(feature = "tls")]
         let tls = self.tls.clone();
+        let remote_addr = self.remote_addr;
+        let keep_alive = self.keep_alive;
+        let info = self.info.clone();
+        let connect_timeout = self.connect_timeout;
+        let subchannel = self.subchannel.clone();
 
-        Box::pin(async move {
-            let io = connect.await?;
+        let connect = async move {
+            let mut io = connect.await.map_err(Into::into)?;
+            let remote_addr = remote_addr(&io);
+            let socket = subchannel.map(|subchannel| subchannel.add_socket(remote_addr));
+
+            if let Some(proxy) = proxy {
+                proxy::tunnel(&mut io, &proxy, &uri).await?;
+            }
 
             #[cfg(feature = "tls")]
             {
                 if let Some(tls) = tls {
-                    let conn = tls.connect(io).await?;
-                    return Ok(BoxedIo::new(conn));
+                    let (conn, conn_info) = tls
+                        .connect(io, remote_addr)
+                        .await
+                        .map_err(|e| ConnectError::new(Stage::Tls, e))?;
+                    *info.lock().unwrap() = Some(conn_info);
+                    return Ok(conn.with_socket(socket).with_keep_alive(keep_alive));
                 }
             }
 
-            Ok(BoxedIo::new(io))
+            *info.lock().unwrap() = Some(ConnectionInfo::new(remote_addr));
+            Ok(BoxedIo::new(io)
+                .with_socket(socket)
+                .with_keep_alive(keep_alive))
+        };
+
+        // the timeout covers the TCP, proxy and TLS handshakes
+        Box::pin(async move {
+            let result: Result<_, crate::Error> = match connect_timeout {
+                Some(timeout) => match Timeout::new(connect, timeout).await {
+                    Ok(result) => result,
+                    Err(elapsed) => Err(io::Error::from(elapsed).into()),
+                },
+                None => connect.await,
+            };
+            result.map_err(|e| ConnectError::new(Stage::Connect, e).into())
+        })
+    }
+}
+
+/// Resolves hosts via `R`, marking its errors as DNS failures.
+#[derive(Debug, Clone)]
+pub(crate) struct DnsErrors<R>(R);
+
+impl<R: Resolve> Resolve for DnsErrors<R> {
+    type Addrs = R::Addrs;
+    type Future = MapErr<R::Future, fn(io::Error) -> io::Error>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        self.0.resolve(name).map_err(|e| {
+            let kind = e.kind();
+            io::Error::new(kind, ConnectError::new(Stage::Dns, e))
         })
     }
 }
@@ -1,18 +1,20 @@
-use super::connection::Connection;
+use super::{connection::Connection, connector::Connector};
 use crate::transport::Endpoint;
+use futures_core::Stream;
 use std::{
     collections::VecDeque,
     fmt,
     future::Future,
+    hash::Hash,
     pin::Pin,
     task::{Context, Poll},
 };
 use tower::discover::{Change, Discover};
+use tracing::{debug, trace};
 
 pub(crate) struct ServiceList {
     list: VecDeque<Endpoint>,
-    connecting:
-        Option<Pin<Box<dyn Future<Output = Result<Connection, crate::Error>> + Send + 'static>>>,
+    connecting: Option<Connecting>,
     i: usize,
 }
 
@@ -37,7 +39,9 @@ impl Discover for ServiceList {
     ) -> Poll<Result<Change<Self::Key, Self::Service>, Self::Error>> {
         loop {
             if let Some(connecting) = &mut self.connecting {
-                let svc = futures_core::ready!(Pin::new(connecting).poll(cx))?;
+                let svc = futures_core::ready!(Pin::new(connecting).poll(cx));
+                self.connecting = None;
+                let svc = svc?;
 
                 let i = self.i;
                 self.i += 1;
@@ -48,7 +52,7 @@ impl Discover for ServiceList {
             }
 
             if let Some(endpoint) = self.list.pop_front() {
-                let fut = Connection::new(endpoint);
+                let fut = connect(endpoint);
                 self.connecting = Some(Box::pin(fut));
             } else {
                 return Poll::Pending;
@@ -64,3 +68,121 @@ impl fmt::Debug for ServiceList {
             .finish()
     }
 }
+
+/// Discovers endpoints from a stream of changes.
+///
+/// Inserted endpoints are connected to concurrently and only handed to the
+/// balancer once connected. Endpoints that fail to connect are skipped, so an
+/// unreachable replica does not take the whole channel down.
+pub(crate) struct DynamicServiceList<K, S> {
+    changes: Option<S>,
+    connecting: Vec<(K, Connecting)>,
+}
+
+type Connecting = Pin<Box<dyn Future<Output = Result<Connection, crate::Error>> + Send + 'static>>;
+
+impl<K, S> DynamicServiceList<K, S> {
+    pub(crate) fn new(changes: S) -> Self {
+        Self {
+            changes: Some(changes),
+            connecting: Vec::new(),
+        }
+    }
+}
+
+impl<K, S> Discover for DynamicServiceList<K, S>
+where
+    K: Hash + Eq + Clone + Unpin + fmt::Debug,
+    S: Stream<Item = Change<K, Endpoint>> + Unpin,
+{
+    type Key = K;
+    type Service = Connection;
+    type Error = crate::Error;
+
+    fn poll_discover(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Change<Self::Key, Self::Service>, Self::Error>> {
+        loop {
+            let change = match &mut self.changes {
+                Some(changes) => Pin::new(changes).poll_next(cx),
+                None => Poll::Pending,
+            };
+
+            match change {
+                Poll::Ready(Some(Change::Insert(key, endpoint))) => {
+                    trace!(message = "connecting to endpoint", ?key);
+                    self.connecting.retain(|(k, _)| *k != key);
+                    let fut = connect(endpoint);
+                    self.connecting.push((key, Box::pin(fut)));
+                }
+                Poll::Ready(Some(Change::Remove(key))) => {
+                    self.connecting.retain(|(k, _)| *k != key);
+                    return Poll::Ready(Ok(Change::Remove(key)));
+                }
+                // keep balancing over the known endpoints
+                Poll::Ready(None) => self.changes = None,
+                Poll::Pending => break,
+            }
+        }
+
+        let mut i = 0;
+        while i < self.connecting.len() {
+            match self.connecting[i].1.as_mut().poll(cx) {
+                Poll::Pending => i += 1,
+                Poll::Ready(result) => {
+                    let (key, _) = self.connecting.swap_remove(i);
+
+                    match result {
+                        Ok(svc) => return Poll::Ready(Ok(Change::Insert(key, svc))),
+                        Err(error) => {
+                            debug!(message = "failed to connect to endpoint", ?key, %error);
+                        }
+                    }
+                }
+            }
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<K: fmt::Debug, S> fmt::Debug for DynamicServiceList<K, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let connecting = self.connecting.iter().map(|(k, _)| k).collect::<Vec<_>>();
+        f.debug_struct("DynamicServiceList")
+            .field("connecting", &connecting)
+            .finish()
+    }
+}
+
+fn connect(endpoint: Endpoint) -> impl Future<Output = Result<Connection, crate::Error>> {
+    let connector = Connector::http(&endpoint);
+    Connection::new(endpoint, connector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::poll_fn;
+
+    fn unused_endpoint() -> Endpoint {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        Endpoint::from_shared(format!("http://{}", addr)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn moves_on_after_failed_connect() {
+        let mut list = ServiceList::new(vec![unused_endpoint(), unused_endpoint()]);
+
+        // each endpoint is connected to once, the finished attempt is not
+        // polled again
+        for _ in 0..2 {
+            let change = poll_fn(|cx| Pin::new(&mut list).poll_discover(cx)).await;
+            assert!(change.is_err());
+        }
+        assert!(list.connecting.is_none());
+        assert!(list.list.is_empty());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::{body::BoxBody, Status};
+use http::{header::HeaderName, HeaderMap, Request, Response};
+use http_body::Body as HttpBody;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// Copies the values of the configured metadata keys from each request into
+/// the trailers of its response.
+///
+/// Trailers-only responses, which carry the `grpc-status` in their headers and
+/// have no trailers, get the values added to their headers instead.
+#[derive(Debug)]
+pub(crate) struct EchoMetadata<S> {
+    inner: S,
+    keys: Arc<Vec<HeaderName>>,
+}
+
+impl<S> EchoMetadata<S> {
+    pub(crate) fn new(inner: S, keys: Arc<Vec<HeaderName>>) -> Self {
+        EchoMetadata { inner, keys }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for EchoMetadata<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let mut echo = HeaderMap::new();
+        for key in self.keys.iter() {
+            for value in req.headers().get_all(key) {
+                echo.append(key.clone(), value.clone());
+            }
+        }
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            echo: Some(echo).filter(|echo| !echo.is_empty()),
+        }
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    echo: Option<HeaderMap>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.project();
+        let response = futures_util::ready!(me.inner.poll(cx))?;
+
+        let echo = match me.echo.take() {
+            Some(echo) => echo,
+            None => return Poll::Ready(Ok(response)),
+        };
+
+        let (mut parts, body) = response.into_parts();
+
+        if parts.headers.contains_key("grpc-status") {
+            parts.headers.extend(echo);
+            return Poll::Ready(Ok(Response::from_parts(parts, body)));
+        }
+
+        let body = EchoBody {
+            inner: body,
+            echo: Some(echo),
+        };
+        Poll::Ready(Ok(Response::from_parts(parts, BoxBody::new(body))))
+    }
+}
+
+struct EchoBody {
+    inner: BoxBody,
+    echo: Option<HeaderMap>,
+}
+
+impl HttpBody for EchoBody {
+    type Data = <BoxBody as HttpBody>::Data;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.echo.is_none() && self.inner.is_end_stream()
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        Pin::new(&mut self.inner).poll_data(cx)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = futures_util::ready!(Pin::new(&mut self.inner).poll_trailers(cx))?;
+
+        let trailers = match (trailers, self.echo.take()) {
+            (Some(mut trailers), Some(echo)) => {
+                trailers.extend(echo);
+                Some(trailers)
+            }
+            (trailers, echo) => trailers.or(echo),
+        };
+
+        Poll::Ready(Ok(trailers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::{self, poll_fn, Ready};
+    use http::HeaderValue;
+
+    /// Responds with trailers, like a successful gRPC call.
+    struct WithTrailers;
+
+    impl Service<Request<()>> for WithTrailers {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<()>) -> Self::Future {
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+
+            // an empty body that ends with these trailers
+            let body = EchoBody {
+                inner: BoxBody::empty(),
+                echo: Some(trailers),
+            };
+            future::ok(Response::new(BoxBody::new(body)))
+        }
+    }
+
+    /// Responds with a trailers-only error.
+    struct TrailersOnly;
+
+    impl Service<Request<()>> for TrailersOnly {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<()>) -> Self::Future {
+            let mut response = Response::new(BoxBody::empty());
+            Status::unimplemented("")
+                .add_header(response.headers_mut())
+                .unwrap();
+
+            future::ok(response)
+        }
+    }
+
+    fn keys() -> Arc<Vec<HeaderName>> {
+        Arc::new(vec![HeaderName::from_static("x-request-id")])
+    }
+
+    fn request() -> Request<()> {
+        Request::builder()
+            .header("x-request-id", "abc")
+            .header("x-other", "1")
+            .body(())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn echoes_into_trailers() {
+        let mut svc = EchoMetadata::new(WithTrailers, keys());
+
+        let mut body = svc.call(request()).await.unwrap().into_body();
+        assert!(poll_fn(|cx| Pin::new(&mut body).poll_data(cx))
+            .await
+            .is_none());
+        let trailers = poll_fn(|cx| Pin::new(&mut body).poll_trailers(cx))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+        assert_eq!(trailers.get("x-request-id").unwrap(), "abc");
+        assert!(trailers.get("x-other").is_none());
+    }
+
+    #[tokio::test]
+    async fn echoes_into_trailers_only_headers() {
+        let mut svc = EchoMetadata::new(TrailersOnly, keys());
+
+        let response = svc.call(request()).await.unwrap();
+
+        assert_eq!(response.headers().get("x-request-id").unwrap(), "abc");
+        assert!(response.body().is_end_stream());
+    }
+}
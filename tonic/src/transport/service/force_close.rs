@@ -0,0 +1,72 @@
+use futures_core::Stream;
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
+
+/// Fails all reads and writes on a connection once `closed` turns `true`,
+/// which makes hyper drop the connection along with its in-flight requests.
+pub(crate) struct ForceClose<IO> {
+    io: IO,
+    closed: watch::Receiver<bool>,
+    is_closed: bool,
+}
+
+impl<IO> ForceClose<IO> {
+    pub(crate) fn new(io: IO, closed: watch::Receiver<bool>) -> Self {
+        ForceClose {
+            io,
+            closed,
+            is_closed: false,
+        }
+    }
+
+    fn poll_closed(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        while !self.is_closed {
+            match Pin::new(&mut self.closed).poll_next(cx) {
+                Poll::Ready(Some(closed)) => self.is_closed = closed,
+                // the sender is gone once the server stopped
+                Poll::Ready(None) | Poll::Pending => return Ok(()),
+            }
+        }
+
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "server shutdown grace period is over",
+        ))
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for ForceClose<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_closed(cx)?;
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for ForceClose<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.poll_closed(cx)?;
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_closed(cx)?;
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,292 @@
+//! Parsing and tracing of the HTTP/2 frames read and written on a connection.
+//!
+//! The frame headers are used to send keepalive pings and `GOAWAY` frames
+//! between the frames written by hyper and to tell whether a connection has
+//! open streams.
+//!
+//! With the `trace-frames` feature, every frame also emits a `TRACE` level
+//! event with the message `h2 frame` and the following fields:
+//!
+//! - `direction`: `read` or `write`
+//! - `frame_type`: the name of the frame type, e.g. `HEADERS` or `DATA`
+//! - `flags`: the frame flags
+//! - `stream_id`: the stream the frame belongs to, `0` for the connection
+//! - `len`: the length of the frame payload
+//!
+//! Frames are found by parsing the frame headers out of the plaintext bytes
+//! passing through the connection, so TLS connections are traced as well.
+//! This costs a small amount of work for every read and write, which is why
+//! it is opt-in.
+//!
+//! gRPC message boundaries are traced by the codec regardless of this feature,
+//! as `encoded message` and `decoded message` events with `len` and
+//! `compressed` fields.
+
+use std::cmp;
+#[cfg(feature = "trace-frames")]
+use tracing::trace;
+
+/// The connection preface sent by HTTP/2 clients before the first frame.
+pub(super) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+pub(super) const HEADER_LEN: usize = 9;
+
+pub(super) const DATA: u8 = 0x0;
+pub(super) const HEADERS: u8 = 0x1;
+pub(super) const RST_STREAM: u8 = 0x3;
+pub(super) const PUSH_PROMISE: u8 = 0x5;
+pub(super) const PING: u8 = 0x6;
+pub(super) const GOAWAY: u8 = 0x7;
+pub(super) const CONTINUATION: u8 = 0x9;
+
+pub(super) const END_STREAM: u8 = 0x1;
+pub(super) const ACK: u8 = 0x1;
+pub(super) const END_HEADERS: u8 = 0x4;
+
+/// The fixed size header that precedes every HTTP/2 frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct FrameHeader {
+    pub(super) len: usize,
+    pub(super) ty: u8,
+    pub(super) flags: u8,
+    pub(super) stream_id: u32,
+}
+
+impl FrameHeader {
+    pub(super) fn parse(header: [u8; HEADER_LEN]) -> Self {
+        FrameHeader {
+            len: u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize,
+            ty: header[3],
+            flags: header[4],
+            stream_id: u32::from_be_bytes([header[5], header[6], header[7], header[8]])
+                & !(1 << 31),
+        }
+    }
+}
+
+/// Finds the frame headers in the bytes read from or written to a connection.
+#[derive(Debug)]
+pub(super) struct Frames {
+    state: State,
+    /// Whether a header block was started but not ended yet, which only
+    /// `CONTINUATION` frames may follow.
+    header_block: bool,
+}
+
+#[derive(Debug)]
+enum State {
+    Preface(usize),
+    Header {
+        buf: [u8; HEADER_LEN],
+        filled: usize,
+    },
+    Payload(usize),
+}
+
+impl State {
+    fn header() -> Self {
+        State::Header {
+            buf: [0; HEADER_LEN],
+            filled: 0,
+        }
+    }
+}
+
+impl Frames {
+    pub(super) fn new() -> Self {
+        Frames {
+            state: State::Preface(0),
+            header_block: false,
+        }
+    }
+
+    /// Whether all bytes fed so far form complete frames, so that another
+    /// frame may be sent next.
+    ///
+    /// This is not the case before the first frame, as that must be the
+    /// preface of the side the bytes are sent by, nor in the middle of a
+    /// header block, as any other frame there is a protocol error.
+    pub(super) fn at_boundary(&self) -> bool {
+        match self.state {
+            State::Header { filled, .. } => filled == 0 && !self.header_block,
+            _ => false,
+        }
+    }
+
+    /// Feed bytes read from or written to the connection, calling `on_frame`
+    /// with the header of every frame found.
+    pub(super) fn feed(&mut self, mut data: &[u8], on_frame: &mut impl FnMut(FrameHeader)) {
+        while !data.is_empty() {
+            match &mut self.state {
+                State::Preface(matched) => {
+                    let n = cmp::min(PREFACE.len() - *matched, data.len());
+
+                    if data[..n] == PREFACE[*matched..*matched + n] {
+                        *matched += n;
+                        data = &data[n..];
+
+                        if *matched == PREFACE.len() {
+                            self.state = State::header();
+                        }
+                    } else {
+                        // This side does not start with a client preface, so
+                        // the bytes matched so far belong to the first frame.
+                        let matched = *matched;
+                        self.state = State::header();
+                        self.feed(&PREFACE[..matched], on_frame);
+                    }
+                }
+                State::Header { buf, filled } => {
+                    let n = cmp::min(HEADER_LEN - *filled, data.len());
+                    buf[*filled..*filled + n].copy_from_slice(&data[..n]);
+                    *filled += n;
+                    data = &data[n..];
+
+                    if *filled == HEADER_LEN {
+                        let header = FrameHeader::parse(*buf);
+                        match header.ty {
+                            HEADERS | PUSH_PROMISE | CONTINUATION => {
+                                self.header_block = header.flags & END_HEADERS == 0;
+                            }
+                            _ => {}
+                        }
+                        on_frame(header);
+                        self.state = if header.len == 0 {
+                            State::header()
+                        } else {
+                            State::Payload(header.len)
+                        };
+                    }
+                }
+                State::Payload(remaining) => {
+                    let n = cmp::min(*remaining, data.len());
+                    *remaining -= n;
+                    data = &data[n..];
+
+                    if *remaining == 0 {
+                        self.state = State::header();
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "trace-frames")]
+#[derive(Debug)]
+pub(super) struct FrameTracer {
+    direction: &'static str,
+    frames: Frames,
+}
+
+#[cfg(feature = "trace-frames")]
+impl FrameTracer {
+    pub(super) fn new(direction: &'static str) -> Self {
+        FrameTracer {
+            direction,
+            frames: Frames::new(),
+        }
+    }
+
+    /// Feed bytes read from or written to the connection.
+    pub(super) fn feed(&mut self, data: &[u8]) {
+        let direction = self.direction;
+        self.frames
+            .feed(data, &mut |header| trace_frame(direction, header));
+    }
+}
+
+/// Trace a complete frame header.
+#[cfg(feature = "trace-frames")]
+fn trace_frame(direction: &'static str, header: FrameHeader) {
+    trace!(
+        direction,
+        frame_type = frame_type(header.ty),
+        flags = header.flags,
+        stream_id = header.stream_id,
+        len = header.len as u32,
+        "h2 frame"
+    );
+}
+
+#[cfg(feature = "trace-frames")]
+fn frame_type(ty: u8) -> &'static str {
+    match ty {
+        0x0 => "DATA",
+        0x1 => "HEADERS",
+        0x2 => "PRIORITY",
+        0x3 => "RST_STREAM",
+        0x4 => "SETTINGS",
+        0x5 => "PUSH_PROMISE",
+        0x6 => "PING",
+        0x7 => "GOAWAY",
+        0x8 => "WINDOW_UPDATE",
+        0x9 => "CONTINUATION",
+        _ => "UNKNOWN",
+    }
+}
+
+#[cfg(all(test, feature = "trace-frames"))]
+mod tests {
+    use super::*;
+    use crate::test_util::{frame, Recorder};
+
+    type Frame = Vec<(String, String)>;
+
+    /// The fields of the `h2 frame` events recorded so far.
+    fn frames(recorder: &Recorder) -> Vec<Frame> {
+        recorder
+            .events()
+            .into_iter()
+            .map(|event| event.fields)
+            .collect()
+    }
+
+    fn fields(direction: &str, ty: &str, flags: u8, stream_id: u32, len: usize) -> Frame {
+        vec![
+            ("direction".to_string(), direction.to_string()),
+            ("frame_type".to_string(), ty.to_string()),
+            ("flags".to_string(), flags.to_string()),
+            ("stream_id".to_string(), stream_id.to_string()),
+            ("len".to_string(), len.to_string()),
+        ]
+    }
+
+    #[test]
+    fn traces_frames_split_across_writes() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let mut bytes = PREFACE.to_vec();
+        bytes.extend(frame(0x4, 0, 0, &[0; 6]));
+        bytes.extend(frame(0x1, 0x4, 1, &[0; 3]));
+        bytes.extend(frame(0x0, 0x1, 1, &[]));
+
+        let mut tracer = FrameTracer::new("write");
+        for chunk in bytes.chunks(5) {
+            tracer.feed(chunk);
+        }
+
+        assert_eq!(
+            frames(&recorder),
+            vec![
+                fields("write", "SETTINGS", 0, 0, 6),
+                fields("write", "HEADERS", 0x4, 1, 3),
+                fields("write", "DATA", 0x1, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn traces_frames_without_preface() {
+        let recorder = Recorder::default();
+        let _guard = tracing::subscriber::set_default(recorder.clone());
+
+        let mut tracer = FrameTracer::new("read");
+        tracer.feed(&frame(0x8, 0, 0, &[0; 4]));
+
+        assert_eq!(
+            frames(&recorder),
+            vec![fields("read", "WINDOW_UPDATE", 0, 0, 4)]
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use super::load_shed::overloaded;
+use crate::{body::BoxBody, Status};
+use futures_util::future::{self, poll_fn};
+use http::{Request, Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio_sync::semaphore::{Permit, Semaphore};
+use tower_service::Service;
+
+/// Limits the number of requests in flight across all connections of a
+/// server, sharing `semaphore` between them.
+///
+/// Unlike tower's `ConcurrencyLimit`, the permit is acquired per request in
+/// `call` rather than in `poll_ready`, as hyper polls a connection's service
+/// for readiness before a request arrives and idle connections would hold
+/// permits otherwise. A request that does not get a permit right away is
+/// rejected with the status in `shed` if it is set, else its response future
+/// waits for one before polling the inner future.
+#[derive(Debug)]
+pub(crate) struct GlobalConcurrencyLimit<S> {
+    inner: S,
+    semaphore: Arc<Semaphore>,
+    shed: Option<Status>,
+}
+
+impl<S> GlobalConcurrencyLimit<S> {
+    pub(crate) fn new(inner: S, semaphore: Arc<Semaphore>, shed: Option<Status>) -> Self {
+        GlobalConcurrencyLimit {
+            inner,
+            semaphore,
+            shed,
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for GlobalConcurrencyLimit<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+    S::Future: Send + 'static,
+    S::Error: Into<crate::Error>,
+{
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let mut permit = Held {
+            semaphore: self.semaphore.clone(),
+            permit: Permit::new(),
+        };
+        let acquired = permit.permit.try_acquire(&permit.semaphore).is_ok();
+
+        if let Some(status) = self.shed.as_ref().filter(|_| !acquired) {
+            return Box::pin(future::ok(overloaded(status)));
+        }
+
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            if !acquired {
+                let Held { semaphore, permit } = &mut permit;
+                poll_fn(|cx| permit.poll_acquire(cx, semaphore)).await?;
+            }
+
+            let response = response.await.map_err(Into::into);
+            drop(permit);
+            response
+        })
+    }
+}
+
+/// Releases the permit, or stops waiting for it, when dropped.
+#[derive(Debug)]
+struct Held {
+    semaphore: Arc<Semaphore>,
+    permit: Permit,
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        self.permit.release(&self.semaphore);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::service::load_shed::overloaded_status;
+    use crate::Code;
+    use futures_util::future::Ready;
+
+    struct Ok200;
+
+    impl Service<Request<()>> for Ok200 {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<()>) -> Self::Future {
+            future::ok(Response::new(BoxBody::empty()))
+        }
+    }
+
+    fn grpc_status(res: &Response<BoxBody>) -> Option<Code> {
+        Status::from_header_map(res.headers()).map(|s| s.code())
+    }
+
+    #[tokio::test]
+    async fn limit_is_shared() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let mut first = GlobalConcurrencyLimit::new(Ok200, semaphore.clone(), None);
+        let mut second = GlobalConcurrencyLimit::new(Ok200, semaphore.clone(), None);
+
+        let mut in_flight = first.call(Request::new(()));
+        let mut waiting = second.call(Request::new(()));
+
+        poll_fn(|cx| {
+            assert!(waiting.as_mut().poll(cx).is_pending());
+            Poll::Ready(())
+        })
+        .await;
+
+        // the permit is taken on `call`, not when polling the response
+        let res = (&mut in_flight).await.unwrap();
+        assert_eq!(grpc_status(&res), None);
+
+        let res = waiting.await.unwrap();
+        assert_eq!(grpc_status(&res), None);
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+
+    #[tokio::test]
+    async fn sheds_over_limit() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let mut svc =
+            GlobalConcurrencyLimit::new(Ok200, semaphore.clone(), Some(overloaded_status()));
+
+        let in_flight = svc.call(Request::new(()));
+
+        let res = svc.call(Request::new(())).await.unwrap();
+        assert_eq!(grpc_status(&res), Some(Code::ResourceExhausted));
+
+        in_flight.await.unwrap();
+        let res = svc.call(Request::new(())).await.unwrap();
+        assert_eq!(grpc_status(&res), None);
+    }
+
+    #[tokio::test]
+    async fn releases_permit_of_dropped_response() {
+        let semaphore = Arc::new(Semaphore::new(1));
+        let mut svc = GlobalConcurrencyLimit::new(Ok200, semaphore.clone(), None);
+
+        drop(svc.call(Request::new(())));
+        assert_eq!(semaphore.available_permits(), 1);
+    }
+}
@@ -0,0 +1,240 @@
+use super::rejection;
+use crate::{body::BoxBody, Code, Status};
+use http::{HeaderMap, HeaderValue, Request, Response};
+use http_body::Body as HttpBody;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::timer::Delay;
+use tower_service::Service;
+use tracing::trace;
+
+pub(crate) const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Enforces the deadline a client sent in the `grpc-timeout` header of a
+/// request, answering it with a `DEADLINE_EXCEEDED` status once the deadline
+/// passed and dropping the handler of the request.
+///
+/// The deadline also applies to the body of the response: a stream that is
+/// still sending messages once it passed is dropped and the response ends
+/// with `DEADLINE_EXCEEDED` trailers.
+///
+/// A `max_timeout` of the server bounds the handlers of all requests, even
+/// those without a deadline.
+#[derive(Debug)]
+pub(crate) struct GrpcTimeout<S> {
+    inner: S,
+    max_timeout: Option<Duration>,
+}
+
+impl<S> GrpcTimeout<S> {
+    pub(crate) fn new(inner: S, max_timeout: Option<Duration>) -> Self {
+        GrpcTimeout { inner, max_timeout }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for GrpcTimeout<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let timeout = req
+            .headers()
+            .get(GRPC_TIMEOUT_HEADER)
+            .and_then(|value| match parse_timeout(value) {
+                Some(timeout) => Some(timeout),
+                None => {
+                    trace!(?value, "ignoring invalid grpc-timeout header");
+                    None
+                }
+            });
+        let timeout = match (timeout, self.max_timeout) {
+            (Some(timeout), Some(max)) => Some(timeout.min(max)),
+            (timeout, max) => timeout.or(max),
+        };
+
+        ResponseFuture {
+            inner: self.inner.call(req),
+            deadline: timeout.map(tokio::timer::delay_for),
+        }
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    #[pin]
+    deadline: Option<Delay>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut me = self.project();
+
+        if let Poll::Ready(result) = me.inner.poll(cx) {
+            let response = result?;
+
+            // trailers-only responses have no body to bound
+            if response.headers().contains_key("grpc-status") {
+                return Poll::Ready(Ok(response));
+            }
+
+            let deadline = match me.deadline.take() {
+                Some(deadline) => deadline,
+                None => return Poll::Ready(Ok(response)),
+            };
+            let response = response.map(|body| {
+                BoxBody::new(DeadlineBody {
+                    inner: Some(body),
+                    deadline,
+                })
+            });
+            return Poll::Ready(Ok(response));
+        }
+
+        match me.deadline.as_pin_mut() {
+            Some(deadline) => {
+                futures_core::ready!(deadline.poll(cx));
+                trace!("deadline exceeded");
+                Poll::Ready(Ok(rejection(&deadline_exceeded())))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+fn deadline_exceeded() -> Status {
+    Status::new(Code::DeadlineExceeded, "Deadline exceeded")
+}
+
+/// The body of a response whose deadline did not pass before its handler
+/// returned it.
+#[pin_project]
+struct DeadlineBody {
+    /// The body, until the deadline passed.
+    inner: Option<BoxBody>,
+    #[pin]
+    deadline: Delay,
+}
+
+impl DeadlineBody {
+    /// Drop the body if the deadline passed.
+    fn poll_deadline(self: Pin<&mut Self>, cx: &mut Context<'_>) {
+        let me = self.project();
+        if me.inner.is_some() && me.deadline.poll(cx).is_ready() {
+            trace!("deadline exceeded while streaming the response");
+            *me.inner = None;
+        }
+    }
+}
+
+impl HttpBody for DeadlineBody {
+    type Data = <BoxBody as HttpBody>::Data;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        // the trailers of a body that was dropped are still to be sent
+        self.inner
+            .as_ref()
+            .is_some_and(|inner| inner.is_end_stream())
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        self.as_mut().poll_deadline(cx);
+
+        match self.project().inner {
+            Some(inner) => Pin::new(inner).poll_data(cx),
+            None => Poll::Ready(None),
+        }
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        self.as_mut().poll_deadline(cx);
+
+        match self.project().inner {
+            Some(inner) => Pin::new(inner).poll_trailers(cx),
+            None => Poll::Ready(deadline_exceeded().to_header_map().map(Some)),
+        }
+    }
+}
+
+/// Parse a `grpc-timeout` header value: a positive integer of at most eight
+/// digits followed by its unit.
+pub(crate) fn parse_timeout(value: &HeaderValue) -> Option<Duration> {
+    let value = value.to_str().ok()?;
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+
+    let (digits, unit) = value.split_at(value.len() - 1);
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount = digits.parse::<u64>().ok()?;
+
+    let timeout = match unit {
+        "H" => Duration::from_secs(amount * 60 * 60),
+        "M" => Duration::from_secs(amount * 60),
+        "S" => Duration::from_secs(amount),
+        "m" => Duration::from_millis(amount),
+        "u" => Duration::from_micros(amount),
+        "n" => Duration::from_nanos(amount),
+        _ => return None,
+    };
+    Some(timeout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(value: &'static str) -> Option<Duration> {
+        parse_timeout(&HeaderValue::from_static(value))
+    }
+
+    #[test]
+    fn parses_timeouts() {
+        assert_eq!(parse("2H"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse("3M"), Some(Duration::from_secs(3 * 60)));
+        assert_eq!(parse("5S"), Some(Duration::from_secs(5)));
+        assert_eq!(parse("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse("99999999u"), Some(Duration::from_micros(99_999_999)));
+        assert_eq!(parse("1n"), Some(Duration::from_nanos(1)));
+    }
+
+    #[test]
+    fn rejects_invalid_timeouts() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("m"), None);
+        assert_eq!(parse("10"), None);
+        assert_eq!(parse("10x"), None);
+        assert_eq!(parse("-1S"), None);
+        assert_eq!(parse("+1S"), None);
+        assert_eq!(parse("123456789S"), None);
+    }
+}
@@ -0,0 +1,575 @@
+use crate::{
+    body::{BoxBody, BytesBuf},
+    transport::server::GrpcWebConfig,
+    Status,
+};
+use bytes::{Buf, BufMut, Bytes, BytesMut, IntoBuf};
+use futures_core::Stream;
+use futures_util::future::{self, Either, Ready};
+use http::{
+    header::{self, HeaderMap, HeaderValue},
+    Method, Request, Response, StatusCode, Version,
+};
+use http_body::Body as HttpBody;
+use hyper::Body;
+use pin_project::pin_project;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+const GRPC: &str = "application/grpc";
+const GRPC_WEB: &str = "application/grpc-web";
+const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
+
+// a trailer frame is flagged by the most significant bit of its first byte
+const TRAILER_FRAME: u8 = 0x80;
+
+// response headers a browser client needs to read the status
+const EXPOSE_HEADERS: &str = "grpc-status,grpc-message,grpc-status-details-bin";
+
+/// The encoding of the messages of a grpc-web request or response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    /// `application/grpc-web`, framed like regular gRPC.
+    Binary,
+    /// `application/grpc-web-text`, the frames encoded as base64.
+    Text,
+}
+
+impl Encoding {
+    fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Binary => GRPC_WEB,
+            Encoding::Text => GRPC_WEB_TEXT,
+        }
+    }
+}
+
+/// Translates [grpc-web] requests into regular gRPC requests for the inner
+/// service and its responses back, so that browsers can call the server
+/// directly.
+///
+/// The trailers of a response are sent as a final frame of its body, and
+/// CORS requests are answered according to the [`GrpcWebConfig`]. Requests
+/// that aren't grpc-web are passed through untouched.
+///
+/// [grpc-web]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
+#[derive(Debug)]
+pub(crate) struct GrpcWeb<S> {
+    inner: S,
+    config: Arc<GrpcWebConfig>,
+}
+
+impl<S> GrpcWeb<S> {
+    pub(crate) fn new(inner: S, config: Arc<GrpcWebConfig>) -> Self {
+        GrpcWeb { inner, config }
+    }
+
+    /// The origin of a cross-origin request, if it is allowed.
+    fn allowed_origin(&self, req: &Request<Body>) -> Option<HeaderValue> {
+        let origin = req.headers().get(header::ORIGIN)?;
+        if self.config.allows_origin(origin) {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    fn preflight(&self, req: &Request<Body>) -> Response<BoxBody> {
+        let mut response = Response::new(BoxBody::empty());
+
+        let origin = match self.allowed_origin(req) {
+            Some(origin) => origin,
+            None => {
+                *response.status_mut() = StatusCode::FORBIDDEN;
+                return response;
+            }
+        };
+
+        *response.status_mut() = StatusCode::NO_CONTENT;
+        let headers = response.headers_mut();
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        headers.insert(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            HeaderValue::from_static("POST"),
+        );
+        if let Some(request_headers) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                request_headers.clone(),
+            );
+        }
+        if let Some(max_age) = self.config.max_age {
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, max_age.as_secs().into());
+        }
+        headers.insert(header::VARY, HeaderValue::from_static("origin"));
+
+        response
+    }
+}
+
+impl<S> Service<Request<Body>> for GrpcWeb<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Either<ResponseFuture<S::Future>, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if is_preflight(&req) {
+            return Either::Right(future::ok(self.preflight(&req)));
+        }
+
+        let (encoding, content_type) = match grpc_web_content_type(req.headers()) {
+            Some(content_type) => content_type,
+            None => {
+                return Either::Left(ResponseFuture {
+                    inner: self.inner.call(req),
+                    web: None,
+                })
+            }
+        };
+
+        let web = WebResponse {
+            encoding,
+            version: req.version(),
+            origin: self.allowed_origin(&req),
+        };
+
+        let (mut parts, body) = req.into_parts();
+        parts.headers.insert(header::CONTENT_TYPE, content_type);
+        let body = match encoding {
+            Encoding::Binary => body,
+            Encoding::Text => Body::wrap_stream(decode_text(body)),
+        };
+
+        Either::Left(ResponseFuture {
+            inner: self.inner.call(Request::from_parts(parts, body)),
+            web: Some(web),
+        })
+    }
+}
+
+/// How to translate the response of a grpc-web request.
+#[derive(Debug)]
+struct WebResponse {
+    encoding: Encoding,
+    version: Version,
+    origin: Option<HeaderValue>,
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    web: Option<WebResponse>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.project();
+        let response = futures_core::ready!(me.inner.poll(cx))?;
+
+        let web = match me.web.take() {
+            Some(web) => web,
+            None => return Poll::Ready(Ok(response)),
+        };
+
+        let (mut parts, body) = response.into_parts();
+        parts.version = web.version;
+
+        let content_type = parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| value.starts_with(GRPC))
+            .map(|value| format!("{}{}", web.encoding.content_type(), &value[GRPC.len()..]))
+            .and_then(|value| HeaderValue::from_shared(value.into()).ok())
+            .unwrap_or_else(|| HeaderValue::from_static(web.encoding.content_type()));
+        parts.headers.insert(header::CONTENT_TYPE, content_type);
+
+        if let Some(origin) = web.origin {
+            parts
+                .headers
+                .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+            parts.headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSE_HEADERS),
+            );
+            parts
+                .headers
+                .insert(header::VARY, HeaderValue::from_static("origin"));
+        }
+
+        let body = BoxBody::new(GrpcWebBody::new(body, web.encoding));
+        Poll::Ready(Ok(Response::from_parts(parts, body)))
+    }
+}
+
+/// A response body with the trailers of the inner body appended as a
+/// trailer frame.
+struct GrpcWebBody {
+    inner: BoxBody,
+    encoding: Encoding,
+    done: bool,
+}
+
+impl GrpcWebBody {
+    fn new(inner: BoxBody, encoding: Encoding) -> Self {
+        GrpcWebBody {
+            inner,
+            encoding,
+            done: false,
+        }
+    }
+
+    fn encode(&self, data: Bytes) -> BytesBuf {
+        match self.encoding {
+            Encoding::Binary => data.into_buf(),
+            Encoding::Text => Bytes::from(base64::encode(&data)).into_buf(),
+        }
+    }
+}
+
+impl HttpBody for GrpcWebBody {
+    type Data = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.done
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+
+        let trailers = match futures_core::ready!(Pin::new(&mut self.inner).poll_data(cx)) {
+            Some(Ok(data)) => {
+                let data = self.encode(data.collect());
+                return Poll::Ready(Some(Ok(data)));
+            }
+            Some(Err(status)) => status.to_header_map()?,
+            None => match futures_core::ready!(Pin::new(&mut self.inner).poll_trailers(cx))? {
+                Some(trailers) => trailers,
+                None => {
+                    self.done = true;
+                    return Poll::Ready(None);
+                }
+            },
+        };
+
+        self.done = true;
+        let frame = encode_trailers(&trailers);
+        Poll::Ready(Some(Ok(self.encode(frame))))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+}
+
+fn is_preflight(req: &Request<Body>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(header::ORIGIN)
+        && req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// The encoding of a grpc-web request along with the content type of the
+/// gRPC request it is translated to, e.g. `application/grpc+proto` for
+/// `application/grpc-web-text+proto`.
+fn grpc_web_content_type(headers: &HeaderMap) -> Option<(Encoding, HeaderValue)> {
+    let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+
+    let (encoding, rest) = if let Some(rest) = content_type.strip_prefix(GRPC_WEB_TEXT) {
+        (Encoding::Text, rest)
+    } else {
+        (Encoding::Binary, content_type.strip_prefix(GRPC_WEB)?)
+    };
+
+    // a subtype or parameters may follow, but not e.g. `grpc-webfoo`
+    if !(rest.is_empty() || rest.starts_with('+') || rest.starts_with(';')) {
+        return None;
+    }
+
+    let content_type = HeaderValue::from_shared(format!("{}{}", GRPC, rest).into()).ok()?;
+    Some((encoding, content_type))
+}
+
+/// Decode the base64 encoded body of a `grpc-web-text` request.
+///
+/// Clients may encode each message separately, so padding can occur in the
+/// middle of the body; every padded quantum is decoded on its own.
+fn decode_text(mut body: Body) -> impl Stream<Item = Result<Bytes, crate::Error>> {
+    async_stream::try_stream! {
+        let mut buf = BytesMut::new();
+
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(crate::Error::from)?;
+            buf.extend_from_slice(&chunk);
+
+            loop {
+                let complete = buf.len() - buf.len() % 4;
+                let len = match buf[..complete].iter().position(|&b| b == b'=') {
+                    Some(padding) => padding - padding % 4 + 4,
+                    None => complete,
+                };
+                if len == 0 {
+                    break;
+                }
+
+                let decoded = base64::decode(&buf.split_to(len)).map_err(crate::Error::from)?;
+                yield Bytes::from(decoded);
+            }
+        }
+
+        if !buf.is_empty() {
+            let status = Status::new(crate::Code::Internal, "Incomplete grpc-web-text request body");
+            Err(crate::Error::from(status))?;
+        }
+    }
+}
+
+/// Encode `trailers` as a grpc-web trailer frame.
+fn encode_trailers(trailers: &HeaderMap) -> Bytes {
+    let len = trailers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 3)
+        .sum::<usize>();
+
+    let mut frame = BytesMut::with_capacity(len + 5);
+    frame.put_u8(TRAILER_FRAME);
+    frame.put_u32_be(len as u32);
+    for (name, value) in trailers {
+        frame.put_slice(name.as_str().as_bytes());
+        frame.put_u8(b':');
+        frame.put_slice(value.as_bytes());
+        frame.put_slice(b"\r\n");
+    }
+
+    frame.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(content_type: &str, body: impl Into<Body>) -> Request<Body> {
+        Request::builder()
+            .method(Method::POST)
+            .uri("/test.Svc/Method")
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::ORIGIN, "https://example.com")
+            .body(body.into())
+            .unwrap()
+    }
+
+    /// Responds with the request message, the content type of the request
+    /// and a `grpc-status` trailer.
+    fn echo(req: Request<Body>) -> impl Future<Output = Result<Response<BoxBody>, Status>> {
+        async move {
+            let content_type = req.headers()[header::CONTENT_TYPE].clone();
+            let mut body = req.into_body();
+            let mut message = Vec::new();
+            while let Some(chunk) = body.next().await {
+                message.extend_from_slice(&chunk.unwrap());
+            }
+
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+            let body = async_stream::try_stream! {
+                yield Bytes::from(message);
+            };
+            let body = TrailersBody {
+                data: Box::pin(body),
+                trailers: Some(trailers),
+            };
+
+            let mut response = Response::new(BoxBody::new(body));
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, content_type);
+            Ok(response)
+        }
+    }
+
+    type DataStream = Pin<Box<dyn Stream<Item = Result<Bytes, Status>> + Send + Sync>>;
+
+    struct TrailersBody {
+        data: DataStream,
+        trailers: Option<HeaderMap>,
+    }
+
+    impl HttpBody for TrailersBody {
+        type Data = BytesBuf;
+        type Error = Status;
+
+        fn poll_data(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            self.data
+                .as_mut()
+                .poll_next(cx)
+                .map(|data| data.map(|data| data.map(IntoBuf::into_buf)))
+        }
+
+        fn poll_trailers(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            Poll::Ready(Ok(self.trailers.take()))
+        }
+    }
+
+    type BoxService = tower::util::BoxService<Request<Body>, Response<BoxBody>, Status>;
+
+    fn grpc_web(config: GrpcWebConfig) -> GrpcWeb<BoxService> {
+        let echo = BoxService::new(tower::service_fn(echo));
+        GrpcWeb::new(echo, Arc::new(config))
+    }
+
+    async fn collect(mut body: BoxBody) -> Vec<u8> {
+        let mut collected = Vec::new();
+        while let Some(data) = future::poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+            collected.extend_from_slice(data.unwrap().bytes());
+        }
+        collected
+    }
+
+    #[tokio::test]
+    async fn binary() {
+        let mut svc = grpc_web(GrpcWebConfig::new());
+
+        let response = svc
+            .call(request("application/grpc-web+proto", "message"))
+            .await
+            .unwrap();
+
+        // the inner service sees, and echoes, a regular gRPC content type
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/grpc-web+proto"
+        );
+        assert!(!response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert_eq!(
+            collect(response.into_body()).await,
+            b"message\x80\x00\x00\x00\x0fgrpc-status:0\r\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn text() {
+        let mut svc = grpc_web(GrpcWebConfig::new());
+
+        // two separately encoded parts, the first one padded
+        let body = format!("{}{}", base64::encode("mess"), base64::encode("age"));
+        let response = svc
+            .call(request("application/grpc-web-text", body))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/grpc-web-text"
+        );
+        let body = String::from_utf8(collect(response.into_body()).await).unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "{}{}",
+                base64::encode("message"),
+                base64::encode(&b"\x80\x00\x00\x00\x0fgrpc-status:0\r\n"[..])
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn passes_through_grpc() {
+        let mut svc = grpc_web(GrpcWebConfig::new());
+
+        let response = svc
+            .call(request("application/grpc", "message"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/grpc");
+        assert_eq!(collect(response.into_body()).await, b"message");
+    }
+
+    #[tokio::test]
+    async fn cors() {
+        let config = GrpcWebConfig::new()
+            .allow_origin("https://example.com")
+            .max_age(Duration::from_secs(60));
+        let mut svc = grpc_web(config);
+
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/test.Svc/Method")
+            .header(header::ORIGIN, "https://example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "x-grpc-web")
+            .body(Body::empty())
+            .unwrap();
+        let response = svc.call(preflight).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_HEADERS], "x-grpc-web");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "60");
+
+        let response = svc
+            .call(request("application/grpc-web", "message"))
+            .await
+            .unwrap();
+        let headers = response.headers();
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_EXPOSE_HEADERS],
+            EXPOSE_HEADERS
+        );
+
+        let preflight = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/test.Svc/Method")
+            .header(header::ORIGIN, "https://evil.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .body(Body::empty())
+            .unwrap();
+        let response = svc.call(preflight).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+}
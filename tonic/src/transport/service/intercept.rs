@@ -0,0 +1,40 @@
+use super::rejection;
+use crate::{body::BoxBody, Interceptor};
+use futures_util::future::{self, Either, Ready};
+use http::{Request, Response};
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// Runs an [`Interceptor`] on every request, answering the requests it
+/// rejects with their status instead of calling the inner service.
+#[derive(Debug)]
+pub(crate) struct Intercept<S> {
+    inner: S,
+    interceptor: Interceptor,
+}
+
+impl<S> Intercept<S> {
+    pub(crate) fn new(inner: S, interceptor: Interceptor) -> Self {
+        Intercept { inner, interceptor }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Intercept<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        match self.interceptor.call(req) {
+            Ok(req) => Either::Left(self.inner.call(req)),
+            Err(status) => Either::Right(future::ok(rejection(&status))),
+        }
+    }
+}
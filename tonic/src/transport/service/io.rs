@@ -1,20 +1,99 @@
+use super::force_close::ForceClose;
+#[cfg(feature = "trace-frames")]
+use super::frames::FrameTracer;
+use super::keepalive::{KeepAlive, KeepAliveConfig};
+use super::lifetime::{Lifetime, LifetimeConfig};
+use super::streams::CountStreams;
+use crate::transport::{channelz::SocketNode, ConnectionInfo};
 use std::io;
+use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::watch;
 
-pub(in crate::transport) trait Io:
-    AsyncRead + AsyncWrite + Send + Unpin + 'static
-{
-}
+pub(crate) trait Io: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
 
 impl<T> Io for T where T: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
 
-pub(crate) struct BoxedIo(Pin<Box<dyn Io>>);
+pub(crate) struct BoxedIo {
+    io: Pin<Box<dyn Io>>,
+    info: ConnectionInfo,
+    #[cfg(feature = "trace-frames")]
+    read_frames: FrameTracer,
+    #[cfg(feature = "trace-frames")]
+    write_frames: FrameTracer,
+}
 
 impl BoxedIo {
     pub(in crate::transport) fn new<I: Io>(io: I) -> Self {
-        BoxedIo(Box::pin(io))
+        BoxedIo {
+            io: Box::pin(io),
+            info: ConnectionInfo::new(None),
+            #[cfg(feature = "trace-frames")]
+            read_frames: FrameTracer::new("read"),
+            #[cfg(feature = "trace-frames")]
+            write_frames: FrameTracer::new("write"),
+        }
+    }
+
+    /// Record the address of the peer on the other end of this connection.
+    pub(in crate::transport) fn with_remote_addr(mut self, addr: SocketAddr) -> Self {
+        self.info.remote_addr = Some(addr);
+        self
+    }
+
+    /// Record what was negotiated while establishing this connection.
+    #[cfg(feature = "tls")]
+    pub(in crate::transport) fn with_connection_info(mut self, info: ConnectionInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    /// Count the streams of this connection in its channelz `socket`, if it
+    /// has one.
+    pub(in crate::transport) fn with_socket(mut self, socket: Option<Arc<SocketNode>>) -> Self {
+        if let Some(socket) = socket {
+            self.io = Box::pin(CountStreams::new(self.io, socket));
+        }
+        self
+    }
+
+    /// Send keepalive pings on this connection if `config` is set.
+    pub(in crate::transport) fn with_keep_alive(mut self, config: Option<KeepAliveConfig>) -> Self {
+        if let Some(config) = config {
+            self.io = Box::pin(KeepAlive::new(self.io, config));
+        }
+        self
+    }
+
+    /// Close this connection once it is idle or too old, as set by `config`.
+    pub(in crate::transport) fn with_lifetime(mut self, config: Option<LifetimeConfig>) -> Self {
+        if let Some(config) = config {
+            self.io = Box::pin(Lifetime::new(self.io, config));
+        }
+        self
+    }
+
+    /// Fail all reads and writes on this connection once `closed` turns
+    /// `true`.
+    pub(in crate::transport) fn with_force_close(
+        mut self,
+        closed: Option<watch::Receiver<bool>>,
+    ) -> Self {
+        if let Some(closed) = closed {
+            self.io = Box::pin(ForceClose::new(self.io, closed));
+        }
+        self
+    }
+
+    pub(in crate::transport) fn remote_addr(&self) -> Option<SocketAddr> {
+        self.info.remote_addr
+    }
+
+    pub(in crate::transport) fn connection_info(&self) -> &ConnectionInfo {
+        &self.info
     }
 }
 
@@ -24,7 +103,16 @@ impl AsyncRead for BoxedIo {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_read(cx, buf)
+        let poll = Pin::new(&mut self.io).poll_read(cx, buf);
+
+        #[cfg(feature = "trace-frames")]
+        {
+            if let Poll::Ready(Ok(n)) = poll {
+                self.read_frames.feed(&buf[..n]);
+            }
+        }
+
+        poll
     }
 }
 
@@ -34,14 +122,23 @@ impl AsyncWrite for BoxedIo {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<io::Result<usize>> {
-        Pin::new(&mut self.0).poll_write(cx, buf)
+        let poll = Pin::new(&mut self.io).poll_write(cx, buf);
+
+        #[cfg(feature = "trace-frames")]
+        {
+            if let Poll::Ready(Ok(n)) = poll {
+                self.write_frames.feed(&buf[..n]);
+            }
+        }
+
+        poll
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_flush(cx)
+        Pin::new(&mut self.io).poll_flush(cx)
     }
 
     fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
-        Pin::new(&mut self.0).poll_shutdown(cx)
+        Pin::new(&mut self.io).poll_shutdown(cx)
     }
 }
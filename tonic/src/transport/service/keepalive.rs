@@ -0,0 +1,528 @@
+//! HTTP/2 keepalive pings.
+//!
+//! hyper does not send pings on its own, so [`KeepAlive`] sends them from
+//! below hyper: it wraps the plaintext side of a connection and writes a
+//! `PING` frame in between the frames written by hyper whenever nothing was
+//! read from the peer for the keepalive interval. If nothing is read within
+//! the keepalive timeout after that, the connection fails with a `TimedOut`
+//! error, which closes it.
+//!
+//! The acknowledgements of these pings are removed from the bytes read before
+//! hyper sees them, as it never sent the pings they acknowledge.
+
+use super::frames::{
+    FrameHeader, Frames, ACK, DATA, END_STREAM, HEADERS, HEADER_LEN, PING, PREFACE, RST_STREAM,
+};
+use bytes::BytesMut;
+use std::{
+    cmp,
+    collections::HashMap,
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::{delay, Delay};
+use tracing::{debug, trace};
+
+/// The payload of the pings sent by [`KeepAlive`], which tells their
+/// acknowledgements apart from those of pings sent by the peer.
+const PING_PAYLOAD: [u8; 8] = *b"tonic-ka";
+const PING_FRAME: [u8; HEADER_LEN + 8] = [
+    0, 0, 8, PING, 0, 0, 0, 0, 0, b't', b'o', b'n', b'i', b'c', b'-', b'k', b'a',
+];
+
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// How long to wait for a response to a ping unless configured otherwise.
+pub(crate) const DEFAULT_KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// How often and when keepalive pings are sent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct KeepAliveConfig {
+    pub(crate) interval: Duration,
+    pub(crate) timeout: Duration,
+    /// Whether to also send pings while there are no open streams.
+    pub(crate) while_idle: bool,
+}
+
+pub(crate) struct KeepAlive<IO> {
+    io: IO,
+    config: KeepAliveConfig,
+    delay: Delay,
+    last_read: Instant,
+    /// Whether a ping was sent and nothing has been read since.
+    awaiting_ack: bool,
+    /// The number of bytes of a due ping that were written so far.
+    ping: Option<usize>,
+    /// Whether a ping is due but hyper is in the middle of writing a frame.
+    ping_blocked: bool,
+    flush: bool,
+    written: Frames,
+    reader: Reader,
+    streams: Streams,
+}
+
+impl<IO> KeepAlive<IO> {
+    pub(crate) fn new(io: IO, config: KeepAliveConfig) -> Self {
+        let now = Instant::now();
+
+        KeepAlive {
+            io,
+            config,
+            delay: delay(now + config.interval),
+            last_read: now,
+            awaiting_ack: false,
+            ping: None,
+            ping_blocked: false,
+            flush: false,
+            written: Frames::new(),
+            reader: Reader::new(PING_PAYLOAD),
+            streams: Streams::default(),
+        }
+    }
+}
+
+impl<IO> KeepAlive<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    /// Send a ping if one is due, failing if the last one timed out.
+    fn poll_keep_alive(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        loop {
+            if let Some(written) = self.ping {
+                match Pin::new(&mut self.io).poll_write(cx, &PING_FRAME[written..])? {
+                    Poll::Ready(n) if written + n == PING_FRAME.len() => {
+                        self.ping = None;
+                        self.flush = true;
+                    }
+                    Poll::Ready(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Poll::Ready(n) => {
+                        self.ping = Some(written + n);
+                        continue;
+                    }
+                    Poll::Pending => return Ok(()),
+                }
+            }
+
+            if self.flush {
+                match Pin::new(&mut self.io).poll_flush(cx)? {
+                    Poll::Ready(()) => self.flush = false,
+                    Poll::Pending => return Ok(()),
+                }
+            }
+
+            if Pin::new(&mut self.delay).poll(cx).is_pending() {
+                return Ok(());
+            }
+
+            if self.awaiting_ack {
+                debug!("keepalive ping timed out");
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "keepalive ping timed out",
+                ));
+            }
+
+            let now = Instant::now();
+            let next = self.last_read + self.config.interval;
+            if next > now {
+                self.delay.reset(next);
+                continue;
+            }
+
+            if !self.config.while_idle && self.streams.is_idle() {
+                self.delay.reset(now + self.config.interval);
+                continue;
+            }
+
+            // hyper is in the middle of writing a frame or a header block and
+            // will write the rest of it once the connection is writable again,
+            // after which the ping is sent.
+            if !self.written.at_boundary() {
+                self.ping_blocked = true;
+                return Ok(());
+            }
+            self.ping_blocked = false;
+
+            trace!("sending keepalive ping");
+            self.ping = Some(0);
+            self.awaiting_ack = true;
+            self.delay.reset(now + self.config.timeout);
+        }
+    }
+}
+
+impl<IO> AsyncRead for KeepAlive<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        me.poll_keep_alive(cx)?;
+
+        loop {
+            let streams = &mut me.streams;
+            let n = me.reader.take(buf, &mut |header| {
+                streams.on_frame(header, false);
+            });
+            if n > 0 || buf.is_empty() {
+                return Poll::Ready(Ok(n));
+            }
+
+            me.reader.buf.reserve(READ_BUFFER_SIZE);
+            let n =
+                futures_util::ready!(Pin::new(&mut me.io).poll_read_buf(cx, &mut me.reader.buf))?;
+            if n == 0 {
+                return Poll::Ready(Ok(me.reader.take_remaining(buf)));
+            }
+
+            me.last_read = Instant::now();
+            if me.awaiting_ack {
+                me.awaiting_ack = false;
+                me.delay.reset(me.last_read + me.config.interval);
+            }
+        }
+    }
+}
+
+impl<IO> AsyncWrite for KeepAlive<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        me.poll_keep_alive(cx)?;
+
+        // a ping that was started must be written in full first
+        if me.ping.is_some() {
+            return Poll::Pending;
+        }
+
+        let n = futures_util::ready!(Pin::new(&mut me.io).poll_write(cx, buf))?;
+
+        let streams = &mut me.streams;
+        me.written.feed(&buf[..n], &mut |header| {
+            streams.on_frame(header, true);
+        });
+
+        if me.ping_blocked && me.written.at_boundary() {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// Passes on the bytes read from the peer, except for the acknowledgements
+/// of our own pings, which are told apart by their payload.
+#[derive(Debug)]
+pub(super) struct Reader {
+    pub(super) buf: BytesMut,
+    state: ReadState,
+    payload: [u8; 8],
+    /// The number of acknowledgements removed so far.
+    pub(super) acks: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum ReadState {
+    Start,
+    Header,
+    Forward(usize),
+}
+
+impl Reader {
+    pub(super) fn new(payload: [u8; 8]) -> Self {
+        Reader {
+            buf: BytesMut::new(),
+            state: ReadState::Start,
+            payload,
+            acks: 0,
+        }
+    }
+
+    /// Move as many bytes into `dst` as are known not to belong to a ping
+    /// acknowledgement, calling `on_frame` with the header of every frame.
+    pub(super) fn take(&mut self, dst: &mut [u8], on_frame: &mut impl FnMut(FrameHeader)) -> usize {
+        let mut n = 0;
+
+        while n < dst.len() {
+            match self.state {
+                ReadState::Start => {
+                    if self.buf.is_empty() {
+                        break;
+                    }
+
+                    // frames never start with the first byte of the preface
+                    self.state = if self.buf[0] == PREFACE[0] {
+                        ReadState::Forward(PREFACE.len())
+                    } else {
+                        ReadState::Header
+                    };
+                }
+                ReadState::Header => {
+                    if self.buf.len() < HEADER_LEN {
+                        break;
+                    }
+
+                    let mut header = [0; HEADER_LEN];
+                    header.copy_from_slice(&self.buf[..HEADER_LEN]);
+                    let header = FrameHeader::parse(header);
+
+                    if header.ty == PING && header.flags & ACK != 0 && header.len == 8 {
+                        if self.buf.len() < PING_FRAME.len() {
+                            break;
+                        }
+
+                        if self.buf[HEADER_LEN..PING_FRAME.len()] == self.payload {
+                            trace!(payload = ?self.payload, "ping acknowledged");
+                            self.buf.advance(PING_FRAME.len());
+                            self.acks += 1;
+                            continue;
+                        }
+                    }
+
+                    on_frame(header);
+                    self.state = ReadState::Forward(HEADER_LEN + header.len);
+                }
+                ReadState::Forward(remaining) => {
+                    if self.buf.is_empty() {
+                        break;
+                    }
+
+                    let len = cmp::min(cmp::min(remaining, self.buf.len()), dst.len() - n);
+                    dst[n..n + len].copy_from_slice(&self.buf[..len]);
+                    self.buf.advance(len);
+                    n += len;
+
+                    self.state = if remaining == len {
+                        ReadState::Header
+                    } else {
+                        ReadState::Forward(remaining - len)
+                    };
+                }
+            }
+        }
+
+        n
+    }
+
+    /// Move the bytes left over at the end of the connection into `dst`.
+    pub(super) fn take_remaining(&mut self, dst: &mut [u8]) -> usize {
+        let len = cmp::min(self.buf.len(), dst.len());
+        dst[..len].copy_from_slice(&self.buf[..len]);
+        self.buf.advance(len);
+        len
+    }
+}
+
+/// Tracks which streams are open on a connection.
+#[derive(Debug, Default)]
+pub(super) struct Streams {
+    /// Whether the local and the remote side ended each open stream.
+    open: HashMap<u32, (bool, bool)>,
+    last_stream_id: u32,
+}
+
+/// How a frame changed the streams of a connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) enum StreamEvent {
+    Opened,
+    /// Both sides ended the stream.
+    Ended,
+    /// Either side reset the stream.
+    Reset,
+}
+
+impl Streams {
+    pub(super) fn is_idle(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    pub(super) fn on_frame(&mut self, header: FrameHeader, local: bool) -> Option<StreamEvent> {
+        let id = header.stream_id;
+        let mut event = None;
+
+        match header.ty {
+            // stream ids only ever increase, so a frame for a lower id than
+            // the last one belongs to a stream that was already closed
+            HEADERS if id > self.last_stream_id => {
+                self.last_stream_id = id;
+                self.open.insert(id, (false, false));
+                event = Some(StreamEvent::Opened);
+            }
+            RST_STREAM => {
+                return self.open.remove(&id).map(|_| StreamEvent::Reset);
+            }
+            HEADERS | DATA => {}
+            _ => return None,
+        }
+
+        if header.flags & END_STREAM != 0 {
+            if let Some(ended) = self.open.get_mut(&id) {
+                if local {
+                    ended.0 = true;
+                } else {
+                    ended.1 = true;
+                }
+
+                if *ended == (true, true) {
+                    self.open.remove(&id);
+                    return Some(StreamEvent::Ended);
+                }
+            }
+        }
+
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{frame, write, WriteRecorder};
+    use crate::transport::service::frames::{CONTINUATION, END_HEADERS};
+
+    fn read_all(reader: &mut Reader, bytes: &[u8], chunk: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut dst = [0; 4];
+
+        for chunk in bytes.chunks(chunk) {
+            reader.buf.extend_from_slice(chunk);
+            loop {
+                let n = reader.take(&mut dst, &mut |_| {});
+                if n == 0 {
+                    break;
+                }
+                out.extend_from_slice(&dst[..n]);
+            }
+        }
+
+        out
+    }
+
+    #[test]
+    fn ping_frame() {
+        assert_eq!(PING_FRAME.to_vec(), frame(PING, 0, 0, &PING_PAYLOAD));
+    }
+
+    #[test]
+    fn removes_own_ping_acks() {
+        let settings = frame(0x4, 0, 0, &[0; 6]);
+        let data = frame(DATA, 0, 1, b"hello");
+        let other_ack = frame(PING, ACK, 0, b"12345678");
+
+        let mut bytes = settings.clone();
+        bytes.extend(frame(PING, ACK, 0, &PING_PAYLOAD));
+        bytes.extend(data.clone());
+        bytes.extend(other_ack.clone());
+        bytes.extend(frame(PING, ACK, 0, &PING_PAYLOAD));
+
+        let mut expected = settings;
+        expected.extend(data);
+        expected.extend(other_ack);
+
+        for chunk in 1..bytes.len() {
+            let mut reader = Reader::new(PING_PAYLOAD);
+            assert_eq!(read_all(&mut reader, &bytes, chunk), expected);
+        }
+    }
+
+    #[test]
+    fn passes_client_preface() {
+        let mut bytes = PREFACE.to_vec();
+        bytes.extend(frame(0x4, 0, 0, &[]));
+        bytes.extend(frame(PING, ACK, 0, &PING_PAYLOAD));
+
+        let mut reader = Reader::new(PING_PAYLOAD);
+        assert_eq!(
+            read_all(&mut reader, &bytes, 7),
+            bytes[..PREFACE.len() + HEADER_LEN].to_vec()
+        );
+    }
+
+    fn header(ty: u8, flags: u8, stream_id: u32) -> FrameHeader {
+        FrameHeader {
+            len: 0,
+            ty,
+            flags,
+            stream_id,
+        }
+    }
+
+    #[test]
+    fn streams_close_when_both_sides_end() {
+        let mut streams = Streams::default();
+        assert!(streams.is_idle());
+
+        let opened = streams.on_frame(header(HEADERS, 0, 1), true);
+        assert_eq!(opened, Some(StreamEvent::Opened));
+        assert_eq!(streams.on_frame(header(DATA, END_STREAM, 1), true), None);
+        assert!(!streams.is_idle());
+
+        assert_eq!(streams.on_frame(header(HEADERS, 0, 1), false), None);
+        let ended = streams.on_frame(header(HEADERS, END_STREAM, 1), false);
+        assert_eq!(ended, Some(StreamEvent::Ended));
+        assert!(streams.is_idle());
+    }
+
+    #[test]
+    fn streams_close_on_reset() {
+        let mut streams = Streams::default();
+
+        streams.on_frame(header(HEADERS, 0, 3), true);
+        let reset = streams.on_frame(header(RST_STREAM, 0, 3), false);
+        assert_eq!(reset, Some(StreamEvent::Reset));
+        assert!(streams.is_idle());
+
+        // trailers still in flight when the stream was reset
+        streams.on_frame(header(HEADERS, END_STREAM, 3), false);
+        assert!(streams.is_idle());
+    }
+
+    #[tokio::test]
+    async fn pings_wait_for_the_end_of_header_blocks() {
+        let config = KeepAliveConfig {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_secs(10),
+            while_idle: true,
+        };
+        let mut io = KeepAlive::new(WriteRecorder::default(), config);
+
+        let headers = frame(HEADERS, 0, 1, &[0; 3]);
+        let continuation = frame(CONTINUATION, END_HEADERS, 1, &[0; 3]);
+        let data = frame(DATA, 0, 1, &[]);
+
+        write(&mut io, PREFACE).await;
+        write(&mut io, &headers).await;
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+        write(&mut io, &continuation).await;
+        write(&mut io, &data).await;
+
+        let mut expected = PREFACE.to_vec();
+        expected.extend(headers);
+        expected.extend(continuation);
+        expected.extend_from_slice(&PING_FRAME);
+        expected.extend(data);
+        assert_eq!(io.io.written, expected);
+    }
+}
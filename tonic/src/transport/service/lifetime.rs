@@ -0,0 +1,378 @@
+//! Closing server connections that are idle or too old.
+//!
+//! hyper cannot be told to close a single connection of a server gracefully,
+//! so [`Lifetime`] does so from below hyper, like the keepalive pings: once a
+//! connection had no open streams for too long, or was open for too long, it
+//! writes a `GOAWAY` frame that tells the client not to open new streams on
+//! it, followed by a `PING`. Once the ping is acknowledged, every stream the
+//! client opened before it saw the `GOAWAY` was read, so the connection is
+//! closed as soon as it has no open streams, by ending the bytes read by
+//! hyper. Streams still open on a connection past its maximum age fail once
+//! the grace period is over.
+
+use super::frames::{Frames, GOAWAY, HEADER_LEN, PING};
+use super::keepalive::{Reader, Streams};
+use rand::Rng;
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::timer::{delay, Delay};
+use tracing::debug;
+
+/// The payload of the ping sent after the `GOAWAY` frame, which tells its
+/// acknowledgement apart from those of other pings.
+const PING_PAYLOAD: [u8; 8] = *b"tonic-ga";
+const GOING_AWAY: [u8; 2 * (HEADER_LEN + 8)] = [
+    // GOAWAY with the highest possible stream id and NO_ERROR, which leaves
+    // the streams the client already opened alone
+    0, 0, 8, GOAWAY, 0, 0, 0, 0, 0, 0x7f, 0xff, 0xff, 0xff, 0, 0, 0, 0, // PING
+    0, 0, 8, PING, 0, 0, 0, 0, 0, b't', b'o', b'n', b'i', b'c', b'-', b'g', b'a',
+];
+
+/// How long to wait for the ping to be acknowledged before treating it as if
+/// it was.
+const ACK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// When server connections are closed.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct LifetimeConfig {
+    pub(crate) max_idle: Option<Duration>,
+    pub(crate) max_age: Option<Duration>,
+    pub(crate) max_age_grace: Option<Duration>,
+}
+
+impl LifetimeConfig {
+    /// Whether connections are ever closed.
+    pub(crate) fn is_bounded(&self) -> bool {
+        self.max_idle.is_some() || self.max_age.is_some()
+    }
+}
+
+pub(crate) struct Lifetime<IO> {
+    io: IO,
+    max_idle: Option<Duration>,
+    max_age_grace: Option<Duration>,
+    /// When the connection reaches its maximum age, if it has one.
+    expires: Option<Instant>,
+    /// Since when the connection has no open streams.
+    idle_since: Option<Instant>,
+    delay: Delay,
+    /// The number of bytes of the `GOAWAY` and `PING` frames that were
+    /// written so far.
+    going_away: Option<usize>,
+    /// Whether the `GOAWAY` frame is due but hyper is in the middle of
+    /// writing a frame or a header block.
+    going_away_blocked: bool,
+    flush: bool,
+    draining: Option<Draining>,
+    /// Whether the bytes read by hyper were ended.
+    closed: bool,
+    written: Frames,
+    reader: Reader,
+    streams: Streams,
+}
+
+/// The state of a connection once its `GOAWAY` frame was sent.
+#[derive(Debug, Clone, Copy)]
+struct Draining {
+    ack_deadline: Instant,
+    grace_deadline: Option<Instant>,
+}
+
+impl<IO> Lifetime<IO> {
+    pub(crate) fn new(io: IO, config: LifetimeConfig) -> Self {
+        let now = Instant::now();
+
+        // spread out the reconnects of clients that connected at once
+        let expires = config.max_age.map(|age| {
+            let jitter = rand::thread_rng().gen_range(0, age.as_millis() as u64 / 10 + 1);
+            now + age + Duration::from_millis(jitter)
+        });
+
+        Lifetime {
+            io,
+            max_idle: config.max_idle,
+            max_age_grace: config.max_age_grace,
+            expires,
+            idle_since: Some(now),
+            delay: delay(now),
+            going_away: None,
+            going_away_blocked: false,
+            flush: false,
+            draining: None,
+            closed: false,
+            written: Frames::new(),
+            reader: Reader::new(PING_PAYLOAD),
+            streams: Streams::default(),
+        }
+    }
+
+    /// Record whether the connection has open streams after a frame was read
+    /// or written.
+    fn update_idle(&mut self, cx: &mut Context<'_>) {
+        match (self.streams.is_idle(), self.idle_since) {
+            (true, None) => {
+                self.idle_since = Some(Instant::now());
+                // the connection may be closed now or has a new deadline
+                cx.waker().wake_by_ref();
+            }
+            (false, Some(_)) => self.idle_since = None,
+            _ => {}
+        }
+    }
+
+    /// When the connection is due to go away.
+    fn deadline(&self) -> Option<Instant> {
+        let idle = self
+            .idle_since
+            .and_then(|since| Some(since + self.max_idle?));
+
+        match (idle, self.expires) {
+            (Some(idle), Some(expires)) => Some(idle.min(expires)),
+            (idle, expires) => idle.or(expires),
+        }
+    }
+}
+
+impl<IO> Lifetime<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    /// Send the `GOAWAY` frame once it is due, and close the connection once
+    /// its streams ended.
+    fn poll_lifetime(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        loop {
+            if let Some(written) = self.going_away {
+                match Pin::new(&mut self.io).poll_write(cx, &GOING_AWAY[written..])? {
+                    Poll::Ready(n) if written + n == GOING_AWAY.len() => {
+                        self.going_away = None;
+                        self.flush = true;
+                    }
+                    Poll::Ready(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Poll::Ready(n) => {
+                        self.going_away = Some(written + n);
+                        continue;
+                    }
+                    Poll::Pending => return Ok(()),
+                }
+            }
+
+            if self.flush {
+                match Pin::new(&mut self.io).poll_flush(cx)? {
+                    Poll::Ready(()) => self.flush = false,
+                    Poll::Pending => return Ok(()),
+                }
+            }
+
+            if self.closed {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+
+            let draining = match self.draining {
+                Some(draining) => draining,
+                None => {
+                    let deadline = match self.deadline() {
+                        Some(deadline) => deadline,
+                        None => return Ok(()),
+                    };
+
+                    if deadline > now {
+                        self.delay.reset(deadline);
+                        if Pin::new(&mut self.delay).poll(cx).is_pending() {
+                            return Ok(());
+                        }
+                    }
+
+                    // hyper is in the middle of writing a frame or a header
+                    // block and will write the rest of it once the connection
+                    // is writable again, after which the frames are sent.
+                    if !self.written.at_boundary() {
+                        self.going_away_blocked = true;
+                        return Ok(());
+                    }
+                    self.going_away_blocked = false;
+
+                    let expired = self.expires.is_some_and(|expires| expires <= now);
+                    debug!(expired, "closing connection");
+                    self.going_away = Some(0);
+                    self.draining = Some(Draining {
+                        ack_deadline: now + ACK_TIMEOUT,
+                        grace_deadline: self
+                            .max_age_grace
+                            .filter(|_| expired)
+                            .map(|grace| now + grace),
+                    });
+                    continue;
+                }
+            };
+
+            let acked = self.reader.acks > 0 || draining.ack_deadline <= now;
+            if acked && self.streams.is_idle() {
+                debug!("connection closed");
+                self.closed = true;
+                cx.waker().wake_by_ref();
+                return Ok(());
+            }
+
+            let next = match draining.grace_deadline {
+                Some(grace_deadline) if grace_deadline <= now => {
+                    debug!("max connection age grace period is over");
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "max connection age grace period is over",
+                    ));
+                }
+                Some(grace_deadline) if !acked => grace_deadline.min(draining.ack_deadline),
+                Some(grace_deadline) => grace_deadline,
+                None if !acked => draining.ack_deadline,
+                None => return Ok(()),
+            };
+
+            self.delay.reset(next);
+            if Pin::new(&mut self.delay).poll(cx).is_pending() {
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<IO> AsyncRead for Lifetime<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        me.poll_lifetime(cx)?;
+
+        loop {
+            if me.closed {
+                return Poll::Ready(Ok(0));
+            }
+
+            let streams = &mut me.streams;
+            let n = me.reader.take(buf, &mut |header| {
+                streams.on_frame(header, false);
+            });
+            if n > 0 || buf.is_empty() {
+                me.update_idle(cx);
+                return Poll::Ready(Ok(n));
+            }
+
+            // hyper never sees the acknowledgement of the ping, so reading it
+            // may have to close the connection right away
+            if me.draining.is_some() {
+                me.poll_lifetime(cx)?;
+                if me.closed {
+                    continue;
+                }
+            }
+
+            me.reader.buf.reserve(READ_BUFFER_SIZE);
+            let n =
+                futures_util::ready!(Pin::new(&mut me.io).poll_read_buf(cx, &mut me.reader.buf))?;
+            if n == 0 {
+                return Poll::Ready(Ok(me.reader.take_remaining(buf)));
+            }
+        }
+    }
+}
+
+impl<IO> AsyncWrite for Lifetime<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        me.poll_lifetime(cx)?;
+
+        // the frames that were started must be written in full first
+        if me.going_away.is_some() {
+            return Poll::Pending;
+        }
+
+        let n = futures_util::ready!(Pin::new(&mut me.io).poll_write(cx, buf))?;
+
+        let streams = &mut me.streams;
+        me.written.feed(&buf[..n], &mut |header| {
+            streams.on_frame(header, true);
+        });
+        me.update_idle(cx);
+
+        if me.going_away_blocked && me.written.at_boundary() {
+            cx.waker().wake_by_ref();
+        }
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{frame, write, WriteRecorder};
+    use crate::transport::service::frames::{CONTINUATION, DATA, END_HEADERS, HEADERS, PREFACE};
+
+    #[test]
+    fn going_away_frames() {
+        let mut frames = Frames::new();
+        let mut headers = Vec::new();
+        frames.feed(&GOING_AWAY, &mut |header| headers.push(header));
+
+        assert!(frames.at_boundary());
+        assert_eq!(headers.len(), 2);
+        assert_eq!((headers[0].ty, headers[0].len), (GOAWAY, 8));
+        assert_eq!((headers[1].ty, headers[1].len), (PING, 8));
+        assert_eq!(GOING_AWAY[HEADER_LEN + 8 + HEADER_LEN..], PING_PAYLOAD);
+    }
+
+    #[tokio::test]
+    async fn goes_away_after_header_blocks() {
+        let config = LifetimeConfig {
+            max_age: Some(Duration::from_millis(50)),
+            ..LifetimeConfig::default()
+        };
+        let mut io = Lifetime::new(WriteRecorder::default(), config);
+
+        let headers = frame(HEADERS, 0, 1, &[0; 3]);
+        let continuation = frame(CONTINUATION, END_HEADERS, 1, &[0; 3]);
+        let data = frame(DATA, 0, 1, &[]);
+
+        write(&mut io, PREFACE).await;
+        write(&mut io, &headers).await;
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+        write(&mut io, &continuation).await;
+        write(&mut io, &data).await;
+
+        let mut expected = PREFACE.to_vec();
+        expected.extend(headers);
+        expected.extend(continuation);
+        expected.extend_from_slice(&GOING_AWAY);
+        expected.extend(data);
+        assert_eq!(io.io.written, expected);
+    }
+}
@@ -0,0 +1,127 @@
+use super::rejection;
+use crate::{body::BoxBody, Status};
+use futures_util::future::{self, Either};
+use http::{Request, Response};
+use std::task::{Context, Poll};
+use tower_service::Service;
+use tracing::debug;
+
+/// Rejects requests with `status` while the inner service is not ready,
+/// instead of waiting for it to become ready.
+#[derive(Debug)]
+pub(crate) struct LoadShed<S> {
+    inner: S,
+    status: Status,
+    overloaded: bool,
+}
+
+impl<S> LoadShed<S> {
+    pub(crate) fn new(inner: S, status: Status) -> Self {
+        LoadShed {
+            inner,
+            status,
+            overloaded: false,
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for LoadShed<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Either<S::Future, future::Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        match self.inner.poll_ready(cx) {
+            Poll::Ready(result) => {
+                self.overloaded = false;
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                self.overloaded = true;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if self.overloaded {
+            return Either::Right(future::ok(overloaded(&self.status)));
+        }
+
+        Either::Left(self.inner.call(req))
+    }
+}
+
+/// The status of requests that are shed unless configured otherwise.
+pub(crate) fn overloaded_status() -> Status {
+    Status::resource_exhausted("server overloaded")
+}
+
+/// The response to a request that is shed.
+pub(crate) fn overloaded(status: &Status) -> Response<BoxBody> {
+    debug!("server overloaded, shedding request");
+    rejection(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Code;
+    use futures_util::future::{poll_fn, Ready};
+
+    /// Ready only once `ready` is set.
+    struct Gate {
+        ready: bool,
+    }
+
+    impl Service<Request<()>> for Gate {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            if self.ready {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        }
+
+        fn call(&mut self, _: Request<()>) -> Self::Future {
+            future::ok(Response::new(BoxBody::empty()))
+        }
+    }
+
+    fn grpc_status(res: &Response<BoxBody>) -> Option<Code> {
+        Status::from_header_map(res.headers()).map(|s| s.code())
+    }
+
+    #[tokio::test]
+    async fn sheds_while_not_ready() {
+        let mut svc = LoadShed::new(Gate { ready: false }, overloaded_status());
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        let res = svc.call(Request::new(())).await.unwrap();
+        assert_eq!(grpc_status(&res), Some(Code::ResourceExhausted));
+
+        svc.inner.ready = true;
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        let res = svc.call(Request::new(())).await.unwrap();
+        assert_eq!(grpc_status(&res), None);
+    }
+
+    #[tokio::test]
+    async fn sheds_with_custom_status() {
+        let status = Status::new(Code::Unavailable, "try again later");
+        let mut svc = LoadShed::new(Gate { ready: false }, status);
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        let res = svc.call(Request::new(())).await.unwrap();
+        let status = Status::from_header_map(res.headers()).unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "try again later");
+    }
+}
@@ -1,21 +1,80 @@
+mod add_extension;
 mod add_origin;
+mod backpressure;
+mod cancel;
 mod connection;
 mod connector;
 mod discover;
+mod echo;
 mod either;
+mod force_close;
+mod frames;
+mod global_limit;
+mod grpc_timeout;
+#[cfg(feature = "grpc-web")]
+mod grpc_web;
+mod intercept;
 mod io;
+mod keepalive;
 mod layer;
+mod lifetime;
+mod load_shed;
+mod peer_limit;
+mod proxy;
 mod reconnect;
+mod requeue;
+mod retry;
 mod router;
+mod stats;
+mod streams;
+mod tcp;
 #[cfg(feature = "tls")]
 mod tls;
 
+use crate::{body::BoxBody, Status};
+use http::Response;
+
+pub(crate) use self::add_extension::AddExtension;
 pub(crate) use self::add_origin::AddOrigin;
+pub(crate) use self::backpressure::Backpressure;
+pub(crate) use self::cancel::CancelOnDrop;
 pub(crate) use self::connection::Connection;
-pub(crate) use self::connector::connector;
-pub(crate) use self::discover::ServiceList;
+pub(crate) use self::connector::Connector;
+pub(crate) use self::discover::{DynamicServiceList, ServiceList};
+pub(crate) use self::echo::EchoMetadata;
+pub(crate) use self::global_limit::GlobalConcurrencyLimit;
+pub(crate) use self::grpc_timeout::GrpcTimeout;
+#[cfg(feature = "grpc-web")]
+pub(crate) use self::grpc_web::GrpcWeb;
+pub(crate) use self::intercept::Intercept;
 pub(crate) use self::io::BoxedIo;
+pub(crate) use self::keepalive::{KeepAliveConfig, DEFAULT_KEEP_ALIVE_TIMEOUT};
 pub(crate) use self::layer::{layer_fn, ServiceBuilderExt};
+pub(crate) use self::lifetime::LifetimeConfig;
+pub(crate) use self::load_shed::{overloaded_status, LoadShed};
+pub(crate) use self::peer_limit::{PeerRateLimit, PeerRateLimiter};
+pub(crate) use self::proxy::bypasses_proxy;
+pub(crate) use self::reconnect::{ConnectFailed, NotReady};
+pub(crate) use self::requeue::Requeue;
+pub(crate) use self::retry::Retry;
 pub(crate) use self::router::{Or, Routes};
+pub(crate) use self::stats::Stats;
+pub(crate) use self::tcp::TcpConfig;
+#[cfg(feature = "rustls")]
+pub(crate) use self::tls::RustlsRoots;
 #[cfg(feature = "tls")]
 pub(crate) use self::tls::{TlsAcceptor, TlsConnector};
+
+/// A trailers-only response carrying `status`, for requests that are
+/// rejected before they reach the service.
+pub(crate) fn rejection(status: &Status) -> Response<BoxBody> {
+    let mut response = Response::new(BoxBody::empty());
+
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::header::HeaderValue::from_static("application/grpc"),
+    );
+    status.add_header(response.headers_mut()).unwrap();
+
+    response
+}
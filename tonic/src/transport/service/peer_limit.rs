@@ -0,0 +1,232 @@
+use super::rejection;
+use crate::{body::BoxBody, Status};
+use futures_util::future::{self, Either};
+use http::{Request, Response};
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower_service::Service;
+use tracing::debug;
+
+/// A rate limiter that keeps a separate token bucket for every peer address.
+///
+/// Each peer may issue `limit` requests per `per` window. The bucket of a
+/// peer is refilled once its window has elapsed.
+///
+/// Buckets of peers that have been idle for at least a full window are
+/// evicted, since they would be refilled on their next request anyway. This
+/// bounds memory to the number of peers seen within the last window.
+pub(crate) struct PeerRateLimiter {
+    limit: u64,
+    per: Duration,
+    state: Mutex<Buckets>,
+}
+
+struct Buckets {
+    peers: HashMap<IpAddr, Bucket>,
+    last_sweep: Instant,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    until: Instant,
+    rem: u64,
+}
+
+impl PeerRateLimiter {
+    pub(crate) fn new(limit: u64, per: Duration) -> Self {
+        PeerRateLimiter {
+            limit,
+            per,
+            state: Mutex::new(Buckets {
+                peers: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take a token from `peer`'s bucket, returning `false` if it is empty.
+    pub(crate) fn try_acquire(&self, peer: IpAddr) -> bool {
+        self.try_acquire_at(peer, Instant::now())
+    }
+
+    fn try_acquire_at(&self, peer: IpAddr, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Buckets { peers, last_sweep } = &mut *state;
+
+        if now >= *last_sweep + self.per {
+            peers.retain(|_, bucket| now < bucket.until);
+            *last_sweep = now;
+        }
+
+        let limit = self.limit;
+        let per = self.per;
+        let bucket = peers.entry(peer).or_insert(Bucket {
+            until: now + per,
+            rem: limit,
+        });
+
+        if now >= bucket.until {
+            bucket.until = now + per;
+            bucket.rem = limit;
+        }
+
+        if bucket.rem > 0 {
+            bucket.rem -= 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    #[cfg(test)]
+    fn tracked_peers(&self) -> usize {
+        self.state.lock().unwrap().peers.len()
+    }
+}
+
+impl fmt::Debug for PeerRateLimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PeerRateLimiter")
+            .field("limit", &self.limit)
+            .field("per", &self.per)
+            .finish()
+    }
+}
+
+/// Rejects requests with `status` once the peer of the connection has used
+/// up its tokens in the shared [`PeerRateLimiter`].
+///
+/// The status defaults to `RESOURCE_EXHAUSTED`.
+#[derive(Debug)]
+pub(crate) struct PeerRateLimit<S> {
+    inner: S,
+    limiter: Arc<PeerRateLimiter>,
+    peer: Option<IpAddr>,
+    status: Status,
+}
+
+impl<S> PeerRateLimit<S> {
+    pub(crate) fn new(
+        inner: S,
+        limiter: Arc<PeerRateLimiter>,
+        peer: Option<IpAddr>,
+        status: Option<Status>,
+    ) -> Self {
+        PeerRateLimit {
+            inner,
+            limiter,
+            peer,
+            status: status
+                .unwrap_or_else(|| Status::resource_exhausted("peer rate limit exceeded")),
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for PeerRateLimit<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<BoxBody>>,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = Either<S::Future, future::Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        match self.peer {
+            Some(peer) if !self.limiter.try_acquire(peer) => {
+                debug!(message = "peer exceeded its rate limit", %peer);
+                Either::Right(future::ok(rejection(&self.status)))
+            }
+            _ => Either::Left(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Code;
+    use futures_util::future::Ready;
+
+    const NOISY: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const QUIET: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    #[derive(Clone)]
+    struct Ok200;
+
+    impl Service<Request<()>> for Ok200 {
+        type Response = Response<BoxBody>;
+        type Error = crate::Error;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Request<()>) -> Self::Future {
+            future::ok(Response::new(BoxBody::empty()))
+        }
+    }
+
+    fn grpc_status(res: &Response<BoxBody>) -> Option<Code> {
+        Status::from_header_map(res.headers()).map(|s| s.code())
+    }
+
+    #[tokio::test]
+    async fn noisy_peer_does_not_affect_quiet_peer() {
+        let limiter = Arc::new(PeerRateLimiter::new(3, Duration::from_secs(60)));
+        let mut noisy = PeerRateLimit::new(Ok200, limiter.clone(), Some(NOISY), None);
+        let mut quiet = PeerRateLimit::new(Ok200, limiter.clone(), Some(QUIET), None);
+
+        for _ in 0..3 {
+            let res = noisy.call(Request::new(())).await.unwrap();
+            assert_eq!(grpc_status(&res), None);
+        }
+
+        let res = noisy.call(Request::new(())).await.unwrap();
+        assert_eq!(grpc_status(&res), Some(Code::ResourceExhausted));
+
+        let res = quiet.call(Request::new(())).await.unwrap();
+        assert_eq!(grpc_status(&res), None);
+    }
+
+    #[tokio::test]
+    async fn custom_rejection_status() {
+        let limiter = Arc::new(PeerRateLimiter::new(1, Duration::from_secs(60)));
+        let status = Status::new(Code::Unavailable, "try again later");
+        let mut svc = PeerRateLimit::new(Ok200, limiter, Some(NOISY), Some(status));
+
+        svc.call(Request::new(())).await.unwrap();
+
+        let res = svc.call(Request::new(())).await.unwrap();
+        let status = Status::from_header_map(res.headers()).unwrap();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(status.message(), "try again later");
+    }
+
+    #[test]
+    fn buckets_refill_and_idle_peers_are_evicted() {
+        let per = Duration::from_secs(1);
+        let limiter = PeerRateLimiter::new(1, per);
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire_at(NOISY, start));
+        assert!(!limiter.try_acquire_at(NOISY, start));
+        assert!(limiter.try_acquire_at(QUIET, start));
+        assert_eq!(limiter.tracked_peers(), 2);
+
+        // After a full window both peers are idle and get evicted, and the
+        // returning peer starts with a full bucket.
+        assert!(limiter.try_acquire_at(NOISY, start + per * 2));
+        assert_eq!(limiter.tracked_peers(), 1);
+    }
+}
@@ -1,14 +1,25 @@
-use crate::Error;
+use crate::{transport::channel::ConnectivityState, Code, Error, Status};
 use pin_project::pin_project;
 use std::fmt;
 use std::{
+    cmp,
+    error::Error as StdError,
     future::Future,
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    sync::watch,
+    timer::{delay_for, Delay},
 };
 use tower_make::MakeService;
 use tower_service::Service;
-use tracing::trace;
+use tracing::{field, trace, Span};
 
 pub(crate) struct Reconnect<M, Target>
 where
@@ -17,6 +28,12 @@ where
     mk_service: M,
     state: State<M::Future, M::Response>,
     target: Target,
+    backoff: Option<Backoff>,
+    lazy: bool,
+    connect_error: Option<Error>,
+    connectivity: watch::Sender<ConnectivityState>,
+    connectivity_rx: watch::Receiver<ConnectivityState>,
+    reset_backoff: Arc<AtomicBool>,
 }
 
 #[derive(Debug)]
@@ -24,24 +41,147 @@ enum State<F, S> {
     Idle,
     Connecting(F),
     Connected(S),
+    Backoff(Delay),
+}
+
+impl<F, S> State<F, S> {
+    fn connectivity(&self) -> ConnectivityState {
+        match self {
+            State::Idle => ConnectivityState::Idle,
+            State::Connecting(_) => ConnectivityState::Connecting,
+            State::Connected(_) => ConnectivityState::Ready,
+            State::Backoff(_) => ConnectivityState::TransientFailure,
+        }
+    }
+
+    /// The connectivity state as recorded on request spans.
+    fn name(&self) -> &'static str {
+        match self {
+            State::Idle => "idle",
+            State::Connecting(_) => "connecting",
+            State::Connected(_) => "ready",
+            State::Backoff(_) => "transient_failure",
+        }
+    }
+}
+
+/// Exponential backoff between failed reconnect attempts.
+///
+/// The delay starts at `initial`, grows by `multiplier` after every failed
+/// attempt up to `max` and is reset once a connection has been established.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    current: Duration,
+}
+
+impl Backoff {
+    pub(crate) fn new(initial: Duration, max: Duration, multiplier: f64) -> Self {
+        Backoff {
+            initial,
+            max,
+            multiplier,
+            current: initial,
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = grow(self.current, self.multiplier, self.max);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+/// Multiply `delay` by `multiplier`, saturating at `max`.
+///
+/// The product is computed in seconds, so that a large multiplier or `max`
+/// cannot overflow a `Duration`, and an invalid product falls back to `max`.
+pub(crate) fn grow(delay: Duration, multiplier: f64, max: Duration) -> Duration {
+    let grown = delay.as_secs_f64() * multiplier;
+    Duration::try_from_secs_f64(grown).map_or(max, |grown| cmp::min(grown, max))
 }
 
 impl<M, Target> Reconnect<M, Target>
 where
     M: Service<Target>,
 {
-    pub(crate) fn new<S, Request>(initial_connection: S, mk_service: M, target: Target) -> Self
+    pub(crate) fn new<S, Request>(
+        initial_connection: S,
+        mk_service: M,
+        target: Target,
+        backoff: Option<Backoff>,
+    ) -> Self
     where
         M: Service<Target, Response = S>,
         S: Service<Request>,
         Error: From<M::Error> + From<S::Error>,
         Target: Clone,
     {
+        Self::with_state(
+            mk_service,
+            State::Connected(initial_connection),
+            target,
+            backoff,
+        )
+    }
+
+    /// Create a service that connects on first use.
+    ///
+    /// Unlike a service created with `new`, a failed connection attempt
+    /// without a backoff does not fail `poll_ready`. It fails the request that
+    /// triggered it with `UNAVAILABLE` instead, and the next request makes a
+    /// new attempt.
+    pub(crate) fn lazy(mk_service: M, target: Target, backoff: Option<Backoff>) -> Self {
+        let mut reconnect = Self::with_state(mk_service, State::Idle, target, backoff);
+        reconnect.lazy = true;
+        reconnect
+    }
+
+    fn with_state(
+        mk_service: M,
+        state: State<M::Future, M::Response>,
+        target: Target,
+        backoff: Option<Backoff>,
+    ) -> Self {
+        let (connectivity, connectivity_rx) = watch::channel(state.connectivity());
         Reconnect {
             mk_service,
-            state: State::Connected(initial_connection),
+            state,
             target,
+            backoff,
+            lazy: false,
+            connect_error: None,
+            connectivity,
+            connectivity_rx,
+            reset_backoff: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// A handle to the connectivity state, which is updated on every state
+    /// change and set to `Shutdown` once this service is dropped.
+    pub(crate) fn connectivity_state(&self) -> watch::Receiver<ConnectivityState> {
+        self.connectivity_rx.clone()
+    }
+
+    /// A handle to cut the backoff short: once set, the backoff is reset and
+    /// the next request makes a new connection attempt right away.
+    pub(crate) fn reset_backoff(&self) -> Arc<AtomicBool> {
+        self.reset_backoff.clone()
+    }
+
+    fn set_state(&mut self, state: State<M::Future, M::Response>) {
+        let connectivity = state.connectivity();
+        if connectivity != self.state.connectivity() {
+            trace!("connectivity state changed to {:?}", connectivity);
+            let _ = self.connectivity.broadcast(connectivity);
         }
+        self.state = state;
     }
 }
 
@@ -51,7 +191,8 @@ where
     S: Service<Request>,
     M::Future: Unpin,
     Error: From<M::Error> + From<S::Error>,
-    Target: Clone,
+    Target: Clone + fmt::Display,
+    Request: Send + Sync + 'static,
 {
     type Response = S::Response;
     type Error = Error;
@@ -61,6 +202,16 @@ where
         let ret;
         let mut state;
 
+        if self.reset_backoff.swap(false, Ordering::SeqCst) {
+            trace!("poll_ready; resetting backoff");
+            if let Some(backoff) = &mut self.backoff {
+                backoff.reset();
+            }
+            if let State::Backoff(_) = self.state {
+                self.set_state(State::Idle);
+            }
+        }
+
         loop {
             match self.state {
                 State::Idle => {
@@ -74,13 +225,16 @@ where
                     }
 
                     let fut = self.mk_service.make_service(self.target.clone());
-                    self.state = State::Connecting(fut);
+                    self.set_state(State::Connecting(fut));
                     continue;
                 }
                 State::Connecting(ref mut f) => {
                     trace!("poll_ready; connecting");
                     match Pin::new(f).poll(cx) {
                         Poll::Ready(Ok(service)) => {
+                            if let Some(backoff) = &mut self.backoff {
+                                backoff.reset();
+                            }
                             state = State::Connected(service);
                         }
                         Poll::Pending => {
@@ -89,10 +243,36 @@ where
                         }
                         Poll::Ready(Err(e)) => {
                             trace!("poll_ready; error");
+                            match &mut self.backoff {
+                                Some(backoff) => {
+                                    let delay = backoff.next_delay();
+                                    trace!("poll_ready; backing off for {:?}", delay);
+                                    state = State::Backoff(delay_for(delay));
+                                }
+                                None if self.lazy => {
+                                    state = State::Idle;
+                                    self.connect_error = Some(e.into());
+                                    ret = Ok(());
+                                    break;
+                                }
+                                None => {
+                                    state = State::Idle;
+                                    ret = Err(e.into());
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                }
+                State::Backoff(ref mut delay) => {
+                    trace!("poll_ready; backoff");
+                    match Pin::new(delay).poll(cx) {
+                        Poll::Ready(()) => {
                             state = State::Idle;
-                            ret = Err(e.into());
-                            break;
                         }
+                        // Requests issued while backing off are failed in
+                        // `call` instead of waiting for the next attempt.
+                        Poll::Pending => return Poll::Ready(Ok(())),
                     }
                 }
                 State::Connected(ref mut inner) => {
@@ -114,16 +294,29 @@ where
                 }
             }
 
-            self.state = state;
+            self.set_state(state);
         }
 
-        self.state = state;
+        self.set_state(state);
         Poll::Ready(ret)
     }
 
     fn call(&mut self, request: Request) -> Self::Future {
+        let span = Span::current();
+        span.record("grpc.endpoint", field::display(&self.target));
+        span.record("grpc.connectivity_state", self.state.name());
+
         let service = match self.state {
             State::Connected(ref mut service) => service,
+            State::Backoff(ref delay) => {
+                let status = Status::new(Code::Unavailable, "connection lost, reconnecting");
+                let error = NotReady::new(status, request, delay.deadline());
+                return ResponseFuture::error(error.into());
+            }
+            State::Idle if self.connect_error.is_some() => {
+                let error = self.connect_error.take().unwrap();
+                return ResponseFuture::error(ConnectFailed::new(error).into());
+            }
             _ => panic!("service not ready; poll_ready must be called first"),
         };
 
@@ -132,6 +325,15 @@ where
     }
 }
 
+impl<M, Target> Drop for Reconnect<M, Target>
+where
+    M: Service<Target>,
+{
+    fn drop(&mut self) {
+        let _ = self.connectivity.broadcast(ConnectivityState::Shutdown);
+    }
+}
+
 impl<M, Target> fmt::Debug for Reconnect<M, Target>
 where
     M: Service<Target> + fmt::Debug,
@@ -144,20 +346,126 @@ where
             .field("mk_service", &self.mk_service)
             .field("state", &self.state)
             .field("target", &self.target)
+            .field("backoff", &self.backoff)
+            .field("lazy", &self.lazy)
             .finish()
     }
 }
 
+/// The error of a request on a lazy channel whose connection failed.
+///
+/// Its source is the `UNAVAILABLE` status the request fails with. It also
+/// keeps the error of the connection, to classify the failure.
+pub(crate) struct ConnectFailed {
+    status: Status,
+    error: Error,
+}
+
+impl ConnectFailed {
+    fn new(error: Error) -> Self {
+        let status = Status::new(Code::Unavailable, format!("connect failed: {}", error));
+        ConnectFailed { status, error }
+    }
+
+    pub(crate) fn connect_error(&self) -> &(dyn StdError + 'static) {
+        &*self.error
+    }
+}
+
+impl fmt::Debug for ConnectFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConnectFailed")
+            .field("status", &self.status)
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+impl fmt::Display for ConnectFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.status, f)
+    }
+}
+
+impl StdError for ConnectFailed {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.status)
+    }
+}
+
+/// The error of a request issued while backing off before reconnecting.
+///
+/// Its source is the `UNAVAILABLE` status the request fails with. It also
+/// carries the request, which can be sent again once the backoff has elapsed
+/// instead, to wait for the channel to become ready.
+pub(crate) struct NotReady<R> {
+    status: Status,
+    request: R,
+    retry_at: Instant,
+}
+
+impl<R> NotReady<R> {
+    fn new(status: Status, request: R, retry_at: Instant) -> Self {
+        NotReady {
+            status,
+            request,
+            retry_at,
+        }
+    }
+
+    /// Split the error into the request and the time of the next connection
+    /// attempt.
+    pub(crate) fn into_request(self) -> (R, Instant) {
+        (self.request, self.retry_at)
+    }
+
+    pub(crate) fn into_status(self) -> Status {
+        self.status
+    }
+}
+
+impl<R> fmt::Debug for NotReady<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotReady")
+            .field("status", &self.status)
+            .field("retry_at", &self.retry_at)
+            .finish()
+    }
+}
+
+impl<R> fmt::Display for NotReady<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.status, f)
+    }
+}
+
+impl<R: Send + Sync + 'static> StdError for NotReady<R> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        Some(&self.status)
+    }
+}
+
 #[pin_project]
 #[derive(Debug)]
 pub(crate) struct ResponseFuture<F> {
     #[pin]
-    inner: F,
+    inner: Option<F>,
+    error: Option<Error>,
 }
 
 impl<F> ResponseFuture<F> {
     pub(crate) fn new(inner: F) -> Self {
-        ResponseFuture { inner }
+        ResponseFuture {
+            inner: Some(inner),
+            error: None,
+        }
+    }
+
+    pub(crate) fn error(error: Error) -> Self {
+        ResponseFuture {
+            inner: None,
+            error: Some(error),
+        }
     }
 }
 
@@ -169,6 +477,194 @@ where
     type Output = Result<T, Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        self.project().inner.poll(cx).map_err(Into::into)
+        let me = self.project();
+
+        if let Some(error) = me.error.take() {
+            return Poll::Ready(Err(error));
+        }
+
+        me.inner
+            .as_pin_mut()
+            .expect("polled after completion")
+            .poll(cx)
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::{self, poll_fn, Ready};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    /// A backend that can be taken down and brought back up.
+    struct Backend {
+        up: Arc<AtomicBool>,
+    }
+
+    /// A connection to `Backend` that is dropped as soon as the backend goes down.
+    struct Conn {
+        up: Arc<AtomicBool>,
+    }
+
+    impl Service<&'static str> for Backend {
+        type Response = Conn;
+        type Error = Error;
+        type Future = Ready<Result<Conn, Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: &'static str) -> Self::Future {
+            if self.up.load(Ordering::SeqCst) {
+                future::ok(Conn {
+                    up: self.up.clone(),
+                })
+            } else {
+                future::err("connection refused".into())
+            }
+        }
+    }
+
+    impl Service<()> for Conn {
+        type Response = ();
+        type Error = Error;
+        type Future = Ready<Result<(), Error>>;
+
+        fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            if self.up.load(Ordering::SeqCst) {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Ready(Err("connection dropped".into()))
+            }
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            future::ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_backoff() {
+        let up = Arc::new(AtomicBool::new(true));
+        let backend = Backend { up: up.clone() };
+        let initial = Conn { up: up.clone() };
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(50), 2.0);
+        let mut svc = Reconnect::new(initial, backend, "backend", Some(backoff));
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        svc.call(()).await.unwrap();
+
+        // Drop the connection while the backend is unreachable.
+        up.store(false, Ordering::SeqCst);
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        let err = svc.call(()).await.unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::Unavailable);
+
+        // Once the backend is back the next attempt after the backoff succeeds.
+        up.store(true, Ordering::SeqCst);
+        delay_for(Duration::from_millis(20)).await;
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        svc.call(()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn publishes_connectivity_state() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend = Backend { up: up.clone() };
+        let backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(10), 2.0);
+        let mut svc: Reconnect<_, _> = Reconnect::lazy(backend, "backend", Some(backoff));
+        let mut state = svc.connectivity_state();
+        assert_eq!(*state.get_ref(), ConnectivityState::Idle);
+
+        // The request carries the time of the next attempt to send it again.
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        assert_eq!(*state.get_ref(), ConnectivityState::TransientFailure);
+        let err = svc.call(()).await.unwrap_err();
+        let not_ready = err.downcast::<NotReady<()>>().unwrap();
+        let ((), retry_at) = not_ready.into_request();
+        assert!(retry_at > Instant::now());
+
+        up.store(true, Ordering::SeqCst);
+        delay_for(Duration::from_millis(20)).await;
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        assert_eq!(*state.get_ref(), ConnectivityState::Ready);
+
+        drop(svc);
+        assert_eq!(state.recv().await, Some(ConnectivityState::Shutdown));
+    }
+
+    #[tokio::test]
+    async fn lazy_connects_on_first_use() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend = Backend { up: up.clone() };
+        let mut svc: Reconnect<_, _> = Reconnect::lazy(backend, "backend", None);
+
+        // The failed attempt only fails the request that triggered it.
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        let err = svc.call(()).await.unwrap_err();
+        assert_eq!(Status::from_error(&*err).code(), Code::Unavailable);
+
+        up.store(true, Ordering::SeqCst);
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        svc.call(()).await.unwrap();
+    }
+
+    #[test]
+    fn backoff_is_capped_and_reset() {
+        let mut backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(25), 2.0);
+
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(20));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(25));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(25));
+
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+
+        let mut backoff = Backoff::new(Duration::from_millis(10), Duration::from_secs(1), 1.5);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(10));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(15));
+    }
+
+    #[test]
+    fn backoff_saturates_at_max() {
+        let max = Duration::new(u64::MAX, 999_999_999);
+        let mut backoff = Backoff::new(Duration::from_secs(1), max, 1e300);
+        assert_eq!(backoff.next_delay(), Duration::from_secs(1));
+        assert_eq!(backoff.next_delay(), max);
+        assert_eq!(backoff.next_delay(), max);
+
+        let max = Duration::from_secs(1);
+        for &multiplier in &[f64::INFINITY, f64::NAN, -2.0] {
+            let mut backoff = Backoff::new(Duration::from_millis(10), max, multiplier);
+            backoff.next_delay();
+            assert_eq!(backoff.next_delay(), max);
+        }
+    }
+
+    #[tokio::test]
+    async fn reset_backoff_reconnects_right_away() {
+        let up = Arc::new(AtomicBool::new(false));
+        let backend = Backend { up: up.clone() };
+        let backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(60), 2.0);
+        let mut svc: Reconnect<_, _> = Reconnect::lazy(backend, "backend", Some(backoff));
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        svc.call(()).await.unwrap_err();
+
+        up.store(true, Ordering::SeqCst);
+        svc.reset_backoff().store(true, Ordering::SeqCst);
+
+        poll_fn(|cx| svc.poll_ready(cx)).await.unwrap();
+        svc.call(()).await.unwrap();
     }
 }
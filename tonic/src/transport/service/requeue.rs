@@ -0,0 +1,93 @@
+use super::{
+    grpc_timeout::{parse_timeout, GRPC_TIMEOUT_HEADER},
+    reconnect::NotReady,
+};
+use crate::{body::BoxBody, Code, Status};
+use futures_util::future::poll_fn;
+use http::{Request, Response};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+use tokio::timer::delay;
+use tower_service::Service;
+use tracing::trace;
+
+/// Sends the requests that failed because the channel was backing off before
+/// reconnecting again once the next connection attempt is due, instead of
+/// failing them with `UNAVAILABLE`.
+///
+/// Requests keep being sent again until the channel is ready or their
+/// `grpc-timeout` has elapsed.
+#[derive(Debug, Clone)]
+pub(crate) struct Requeue<S> {
+    inner: S,
+}
+
+impl<S> Requeue<S> {
+    pub(crate) fn new(inner: S) -> Self {
+        Requeue { inner }
+    }
+}
+
+impl<S> Service<Request<BoxBody>> for Requeue<S>
+where
+    S: Service<Request<BoxBody>, Response = Response<hyper::Body>, Error = crate::Error>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<hyper::Body>;
+    type Error = crate::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        let deadline = request
+            .headers()
+            .get(GRPC_TIMEOUT_HEADER)
+            .and_then(parse_timeout)
+            .map(|timeout| Instant::now() + timeout);
+
+        let first = self.inner.call(request);
+        // the request is sent again through a clone, which waits for its own
+        // slot in the buffer
+        let mut svc = self.inner.clone();
+
+        Box::pin(async move {
+            let mut result = first.await;
+
+            loop {
+                let error = match result {
+                    Ok(response) => return Ok(response),
+                    Err(error) => error,
+                };
+                let (request, retry_at) = match error.downcast::<NotReady<Request<BoxBody>>>() {
+                    Ok(not_ready) => not_ready.into_request(),
+                    Err(error) => return Err(error),
+                };
+
+                if deadline.is_some_and(|deadline| retry_at >= deadline) {
+                    let status = Status::new(
+                        Code::DeadlineExceeded,
+                        "deadline exceeded while waiting for the channel to become ready",
+                    );
+                    return Err(status.into());
+                }
+
+                trace!("waiting for the channel to become ready");
+                delay(retry_at).await;
+
+                poll_fn(|cx| svc.poll_ready(cx)).await?;
+                result = svc.call(request).await;
+            }
+        })
+    }
+}
@@ -0,0 +1,656 @@
+//! Automatic retries of failed requests.
+//!
+//! [`Retry`] sends every attempt of a request through a clone of the inner
+//! service. The body of the request is read through a [`ReplayBuffer`], which
+//! keeps the chunks read so far so that the next attempt can send them again,
+//! and continues with the rest of the original body after that.
+
+use super::grpc_timeout::{parse_timeout, GRPC_TIMEOUT_HEADER};
+use super::reconnect::grow;
+use crate::{
+    body::{BoxBody, BytesBuf},
+    request::duration_to_grpc_timeout,
+    transport::RetryPolicy,
+    Code, Status,
+};
+use bytes::{Buf, Bytes, IntoBuf};
+use futures_core::ready;
+use http::{HeaderMap, HeaderValue, Request, Response};
+use http_body::Body as HttpBody;
+use std::{
+    cmp,
+    error::Error,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::timer::delay_for;
+use tower::util::Oneshot;
+use tower_service::Service;
+use tracing::trace;
+
+const PUSHBACK_HEADER: &str = "grpc-retry-pushback-ms";
+
+/// Retries the requests sent through `inner` according to a [`RetryPolicy`].
+#[derive(Debug, Clone)]
+pub(crate) struct Retry<S> {
+    inner: S,
+    policy: Arc<RetryPolicy>,
+}
+
+impl<S> Retry<S> {
+    pub(crate) fn new(inner: S, policy: Arc<RetryPolicy>) -> Self {
+        Retry { inner, policy }
+    }
+}
+
+impl<S> Service<Request<BoxBody>> for Retry<S>
+where
+    S: Service<Request<BoxBody>, Response = Response<hyper::Body>, Error = crate::Error>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send,
+{
+    type Response = Response<hyper::Body>;
+    type Error = crate::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // every attempt waits for its own clone of `inner` to become ready
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+        Box::pin(retry(self.inner.clone(), self.policy.clone(), request))
+    }
+}
+
+async fn retry<S>(
+    svc: S,
+    policy: Arc<RetryPolicy>,
+    request: Request<BoxBody>,
+) -> Result<Response<hyper::Body>, crate::Error>
+where
+    S: Service<Request<BoxBody>, Response = Response<hyper::Body>, Error = crate::Error> + Clone,
+{
+    let policy = policy.for_method(request.uri().path());
+    let (parts, body) = request.into_parts();
+
+    let deadline = parts
+        .headers
+        .get(GRPC_TIMEOUT_HEADER)
+        .and_then(parse_timeout)
+        .map(|timeout| Instant::now() + timeout);
+    let buffer = ReplayBuffer::new(body, policy.buffer_limit);
+    let mut backoff = Backoff::new(policy);
+    // extensions can't be cloned, only the first attempt carries them
+    let mut extensions = Some(parts.extensions);
+    let mut attempt = 1;
+
+    loop {
+        let mut request = Request::new(buffer.replay());
+        *request.method_mut() = parts.method.clone();
+        *request.uri_mut() = parts.uri.clone();
+        *request.version_mut() = parts.version;
+        *request.headers_mut() = parts.headers.clone();
+        if let Some(extensions) = extensions.take() {
+            *request.extensions_mut() = extensions;
+        }
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout = HeaderValue::from_str(&duration_to_grpc_timeout(remaining)).unwrap();
+            request.headers_mut().insert(GRPC_TIMEOUT_HEADER, timeout);
+        }
+
+        let result = Oneshot::new(svc.clone(), request).await;
+
+        let pushback = match &result {
+            Ok(response) => match Status::from_header_map(response.headers()) {
+                // a trailers-only response, the server did not start responding
+                Some(status) if policy.retries(status.code()) => {
+                    pushback(response.headers(), &status)
+                }
+                _ => return result,
+            },
+            // the request was never sent
+            Err(error) if policy.retries(local_error_code(&**error)) => Pushback::None,
+            Err(_) => return result,
+        };
+
+        if attempt >= policy.max_attempts || buffer.is_committed() {
+            return result;
+        }
+
+        let delay = match pushback {
+            Pushback::None => backoff.next_delay(),
+            Pushback::Delay(delay) => {
+                backoff.reset();
+                delay
+            }
+            Pushback::Stop => return result,
+        };
+
+        if let Some(deadline) = deadline {
+            if Instant::now() + delay >= deadline {
+                return result;
+            }
+        }
+
+        trace!(message = "retrying request", attempt, ?delay);
+        drop(result);
+        delay_for(delay).await;
+        attempt += 1;
+    }
+}
+
+/// How the server asked to space out the next attempt.
+#[derive(Debug, PartialEq)]
+enum Pushback {
+    /// Use the backoff of the policy.
+    None,
+    /// Wait for the given delay.
+    Delay(Duration),
+    /// Don't retry.
+    Stop,
+}
+
+fn pushback(headers: &HeaderMap, status: &Status) -> Pushback {
+    if let Some(value) = headers.get(PUSHBACK_HEADER) {
+        return match value.to_str().ok().and_then(|value| value.parse().ok()) {
+            Some(millis) => Pushback::Delay(Duration::from_millis(millis)),
+            None => Pushback::Stop,
+        };
+    }
+
+    match retry_info::retry_delay(status.details()) {
+        Some(delay) => Pushback::Delay(delay),
+        None => Pushback::None,
+    }
+}
+
+/// The code of an error that occurred before a request was sent, or
+/// `UNKNOWN` if it might have been sent.
+fn local_error_code(error: &(dyn Error + 'static)) -> Code {
+    let mut source = Some(error);
+    while let Some(error) = source {
+        if let Some(status) = error.downcast_ref::<Status>() {
+            return status.code();
+        }
+        if let Some(error) = error.downcast_ref::<hyper::Error>() {
+            if error.is_connect() {
+                return Code::Unavailable;
+            }
+        }
+        source = error.source();
+    }
+
+    Code::Unknown
+}
+
+#[cfg(feature = "prost")]
+mod retry_info {
+    use prost::Message;
+    use std::time::Duration;
+
+    const RETRY_INFO_TYPE_URL: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+    // The parts of the `google.rpc` messages needed to read a `RetryInfo`.
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Status {
+        #[prost(message, repeated, tag = "3")]
+        details: Vec<Any>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Any {
+        #[prost(string, tag = "1")]
+        type_url: String,
+        #[prost(bytes, tag = "2")]
+        value: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct RetryInfo {
+        #[prost(message, optional, tag = "1")]
+        retry_delay: Option<ProtoDuration>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct ProtoDuration {
+        #[prost(int64, tag = "1")]
+        seconds: i64,
+        #[prost(int32, tag = "2")]
+        nanos: i32,
+    }
+
+    /// The retry delay of the `RetryInfo` in the `google.rpc.Status` encoded
+    /// in `details`, if there is one.
+    pub(super) fn retry_delay(details: &[u8]) -> Option<Duration> {
+        if details.is_empty() {
+            return None;
+        }
+
+        let status = Status::decode(details).ok()?;
+        let any = status
+            .details
+            .into_iter()
+            .find(|any| any.type_url == RETRY_INFO_TYPE_URL)?;
+        let delay = RetryInfo::decode(&any.value[..]).ok()?.retry_delay?;
+
+        if delay.seconds < 0 || delay.nanos < 0 {
+            return None;
+        }
+        Some(Duration::new(delay.seconds as u64, delay.nanos as u32))
+    }
+
+    #[cfg(test)]
+    pub(super) fn encode_retry_delay(delay: Duration) -> Vec<u8> {
+        let retry_info = RetryInfo {
+            retry_delay: Some(ProtoDuration {
+                seconds: delay.as_secs() as i64,
+                nanos: delay.subsec_nanos() as i32,
+            }),
+        };
+        let mut value = Vec::new();
+        retry_info.encode(&mut value).unwrap();
+
+        let status = Status {
+            details: vec![Any {
+                type_url: RETRY_INFO_TYPE_URL.to_string(),
+                value,
+            }],
+        };
+        let mut details = Vec::new();
+        status.encode(&mut details).unwrap();
+        details
+    }
+}
+
+#[cfg(not(feature = "prost"))]
+mod retry_info {
+    use std::time::Duration;
+
+    pub(super) fn retry_delay(_details: &[u8]) -> Option<Duration> {
+        None
+    }
+}
+
+/// Exponential backoff with full jitter between attempts.
+#[derive(Debug)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    multiplier: f64,
+    current: Duration,
+}
+
+impl Backoff {
+    fn new(policy: &RetryPolicy) -> Self {
+        Backoff {
+            initial: policy.initial_backoff,
+            max: policy.max_backoff,
+            multiplier: policy.backoff_multiplier,
+            current: cmp::min(policy.initial_backoff, policy.max_backoff),
+        }
+    }
+
+    fn next_delay(&mut self) -> Duration {
+        let ceiling = self.current;
+        self.current = grow(self.current, self.multiplier, self.max);
+        ceiling.mul_f64(rand::random::<f64>())
+    }
+
+    fn reset(&mut self) {
+        self.current = cmp::min(self.initial, self.max);
+    }
+}
+
+/// The body of a request shared by all of its attempts.
+#[derive(Clone)]
+struct ReplayBuffer {
+    shared: Arc<Mutex<Replay>>,
+}
+
+struct Replay {
+    body: BoxBody,
+    /// The chunks read from `body`, unless the call is committed.
+    chunks: Vec<Bytes>,
+    chunks_read: usize,
+    len: usize,
+    limit: usize,
+    /// Whether more than `limit` bytes were read, or reading failed, so the
+    /// request can't be sent again.
+    committed: bool,
+    trailers: Option<Option<HeaderMap>>,
+    /// The number of the attempt currently reading the body.
+    attempt: usize,
+}
+
+impl ReplayBuffer {
+    fn new(body: BoxBody, limit: usize) -> Self {
+        let replay = Replay {
+            body,
+            chunks: Vec::new(),
+            chunks_read: 0,
+            len: 0,
+            limit,
+            committed: false,
+            trailers: None,
+            attempt: 0,
+        };
+
+        ReplayBuffer {
+            shared: Arc::new(Mutex::new(replay)),
+        }
+    }
+
+    /// A body for the next attempt, which ends the bodies of earlier
+    /// attempts.
+    fn replay(&self) -> BoxBody {
+        let mut replay = self.shared.lock().unwrap();
+        replay.attempt += 1;
+
+        BoxBody::new(ReplayBody {
+            shared: self.shared.clone(),
+            attempt: replay.attempt,
+            position: 0,
+        })
+    }
+
+    fn is_committed(&self) -> bool {
+        self.shared.lock().unwrap().committed
+    }
+}
+
+struct ReplayBody {
+    shared: Arc<Mutex<Replay>>,
+    attempt: usize,
+    position: usize,
+}
+
+impl HttpBody for ReplayBody {
+    type Data = BytesBuf;
+    type Error = Status;
+
+    fn poll_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let me = self.get_mut();
+        let mut replay = me.shared.lock().unwrap();
+
+        if replay.attempt != me.attempt {
+            return Poll::Ready(None);
+        }
+
+        if me.position < replay.chunks_read {
+            let chunk = replay.chunks[me.position].clone();
+            me.position += 1;
+            return Poll::Ready(Some(Ok(chunk.into_buf())));
+        }
+
+        let chunk = match ready!(Pin::new(&mut replay.body).poll_data(cx)) {
+            Some(Ok(data)) => data.collect::<Bytes>(),
+            Some(Err(status)) => {
+                replay.committed = true;
+                return Poll::Ready(Some(Err(status)));
+            }
+            None => return Poll::Ready(None),
+        };
+
+        if !replay.committed {
+            if replay.len + chunk.len() > replay.limit {
+                trace!("request exceeds the retry buffer limit, committing");
+                replay.committed = true;
+                replay.chunks = Vec::new();
+            } else {
+                replay.len += chunk.len();
+                replay.chunks.push(chunk.clone());
+            }
+        }
+        replay.chunks_read += 1;
+        me.position += 1;
+
+        Poll::Ready(Some(Ok(chunk.into_buf())))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let mut replay = self.shared.lock().unwrap();
+
+        if replay.attempt != self.attempt {
+            return Poll::Ready(Ok(None));
+        }
+
+        if let Some(trailers) = &replay.trailers {
+            return Poll::Ready(Ok(trailers.clone()));
+        }
+
+        let trailers = ready!(Pin::new(&mut replay.body).poll_trailers(cx))?;
+        replay.trailers = Some(trailers.clone());
+        Poll::Ready(Ok(trailers))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::future::poll_fn;
+
+    type Responses = Vec<Option<(Code, Option<&'static str>)>>;
+
+    /// A service recording the body of every attempt and answering them with
+    /// the given statuses in turn, `None` meaning a successful response.
+    #[derive(Clone, Default)]
+    struct Svc {
+        attempts: Arc<Mutex<Vec<Bytes>>>,
+        responses: Arc<Mutex<Responses>>,
+    }
+
+    impl Svc {
+        fn new(mut responses: Responses) -> Self {
+            responses.reverse();
+            Svc {
+                attempts: Default::default(),
+                responses: Arc::new(Mutex::new(responses)),
+            }
+        }
+
+        fn attempts(&self) -> Vec<Bytes> {
+            self.attempts.lock().unwrap().clone()
+        }
+    }
+
+    impl Service<Request<BoxBody>> for Svc {
+        type Response = Response<hyper::Body>;
+        type Error = crate::Error;
+        type Future =
+            Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send + 'static>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<BoxBody>) -> Self::Future {
+            let attempts = self.attempts.clone();
+            let response = self.responses.lock().unwrap().pop().unwrap();
+
+            Box::pin(async move {
+                let body = collect(request.into_body()).await;
+                attempts.lock().unwrap().push(body);
+
+                let mut http = Response::new(hyper::Body::empty());
+                if let Some((code, pushback)) = response {
+                    Status::new(code, "failed")
+                        .add_header(http.headers_mut())
+                        .unwrap();
+                    if let Some(pushback) = pushback {
+                        http.headers_mut()
+                            .insert(PUSHBACK_HEADER, HeaderValue::from_static(pushback));
+                    }
+                }
+                Ok(http)
+            })
+        }
+    }
+
+    async fn collect(mut body: BoxBody) -> Bytes {
+        let mut collected = Vec::new();
+        while let Some(data) = poll_fn(|cx| Pin::new(&mut body).poll_data(cx)).await {
+            collected.extend_from_slice(data.unwrap().bytes());
+        }
+        Bytes::from(collected)
+    }
+
+    fn request(body: &'static str) -> Request<BoxBody> {
+        let chunks = body
+            .split(' ')
+            .map(|chunk| Ok::<_, Status>(Bytes::from(chunk)))
+            .collect::<Vec<_>>();
+        let body = BoxBody::map_from(hyper::Body::wrap_stream(futures_util::stream::iter(chunks)));
+        Request::builder()
+            .uri("http://example.com/test.Svc/Method")
+            .body(body)
+            .unwrap()
+    }
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(10))
+    }
+
+    async fn call(svc: &Svc, policy: &RetryPolicy, request: Request<BoxBody>) -> Option<Code> {
+        let response = Retry::new(svc.clone(), Arc::new(policy.clone()))
+            .call(request)
+            .await
+            .unwrap();
+        Status::from_header_map(response.headers()).map(|status| status.code())
+    }
+
+    #[tokio::test]
+    async fn retries_and_replays_request() {
+        let svc = Svc::new(vec![Some((Code::Unavailable, None)), None]);
+
+        let code = call(&svc, &policy(), request("a b c")).await;
+
+        assert_eq!(code, None);
+        assert_eq!(svc.attempts(), vec!["abc", "abc"]);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let unavailable = Some((Code::Unavailable, None));
+        let svc = Svc::new(vec![unavailable; 3]);
+
+        let code = call(&svc, &policy(), request("a")).await;
+
+        assert_eq!(code, Some(Code::Unavailable));
+        assert_eq!(svc.attempts().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn only_retries_retryable_codes() {
+        let svc = Svc::new(vec![Some((Code::Internal, None))]);
+
+        let code = call(&svc, &policy(), request("a")).await;
+
+        assert_eq!(code, Some(Code::Internal));
+        assert_eq!(svc.attempts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn method_overrides() {
+        let svc = Svc::new(vec![Some((Code::Unavailable, None))]);
+
+        let policy = policy().method("test.Svc", RetryPolicy::new().max_attempts(1));
+        let code = call(&svc, &policy, request("a")).await;
+
+        assert_eq!(code, Some(Code::Unavailable));
+        assert_eq!(svc.attempts().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn honors_pushback() {
+        let svc = Svc::new(vec![Some((Code::Unavailable, Some("-1")))]);
+        let code = call(&svc, &policy(), request("a")).await;
+        assert_eq!(code, Some(Code::Unavailable));
+        assert_eq!(svc.attempts().len(), 1);
+
+        let svc = Svc::new(vec![Some((Code::Unavailable, Some("10"))), None]);
+        let code = call(&svc, &policy(), request("a")).await;
+        assert_eq!(code, None);
+        assert_eq!(svc.attempts().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_past_buffer_limit() {
+        let svc = Svc::new(vec![Some((Code::Unavailable, None)), None]);
+
+        let policy = policy().buffer_limit(2);
+        let code = call(&svc, &policy, request("a b c")).await;
+
+        assert_eq!(code, Some(Code::Unavailable));
+        assert_eq!(svc.attempts(), vec!["abc"]);
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_past_deadline() {
+        let svc = Svc::new(vec![Some((Code::Unavailable, Some("1000"))), None]);
+
+        let mut request = request("a");
+        request
+            .headers_mut()
+            .insert(GRPC_TIMEOUT_HEADER, HeaderValue::from_static("100m"));
+        let code = call(&svc, &policy(), request).await;
+
+        assert_eq!(code, Some(Code::Unavailable));
+        assert_eq!(svc.attempts().len(), 1);
+    }
+
+    #[cfg(feature = "prost")]
+    #[test]
+    fn reads_retry_info() {
+        let details = retry_info::encode_retry_delay(Duration::from_millis(1500));
+        let status = Status::with_details(Code::Unavailable, "", Bytes::from(details));
+
+        assert_eq!(
+            pushback(&HeaderMap::new(), &status),
+            Pushback::Delay(Duration::from_millis(1500))
+        );
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let mut backoff = Backoff::new(&policy());
+        for _ in 0..10 {
+            assert!(backoff.next_delay() <= Duration::from_millis(10));
+        }
+        assert_eq!(backoff.current, Duration::from_millis(10));
+
+        backoff.reset();
+        assert_eq!(backoff.current, Duration::from_millis(1));
+    }
+
+    #[test]
+    fn backoff_saturates_at_max() {
+        let max = Duration::new(u64::MAX, 999_999_999);
+        let policy = RetryPolicy::default()
+            .backoff(Duration::from_secs(1), max)
+            .backoff_multiplier(1e300);
+        let mut backoff = Backoff::new(&policy);
+        backoff.next_delay();
+        backoff.next_delay();
+        assert_eq!(backoff.current, max);
+
+        for &multiplier in &[-2.0, f64::NAN] {
+            let policy = RetryPolicy::default().backoff_multiplier(multiplier);
+            assert_eq!(policy.backoff_multiplier, 1.0);
+        }
+    }
+}
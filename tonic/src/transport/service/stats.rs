@@ -0,0 +1,323 @@
+use crate::{
+    body::{BoxBody, BytesBuf},
+    transport::{RpcStats, StatsHandler},
+    Code, Status,
+};
+use bytes::Buf;
+use futures_util::TryStreamExt;
+use http::{HeaderMap, Request, Response};
+use http_body::Body as HttpBody;
+use hyper::Body;
+use pin_project::pin_project;
+use std::{
+    cmp, fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Instant,
+};
+use tower_service::Service;
+use tracing::{debug, field, info_span, Span};
+
+/// Traces every request in a `grpc_request` span and reports its statistics
+/// to an optional [`StatsHandler`].
+///
+/// The span has the field `grpc.method` and, once the request is finished,
+/// `grpc.code`, `grpc.latency_ms`, `grpc.messages_received` and
+/// `grpc.messages_sent`. It is entered while the inner service is called and
+/// inserted into the request extensions, where the codec picks it up, unless
+/// the request already carries a span.
+///
+/// Messages are counted by their gRPC framing in the request and response
+/// bodies. A request is finished once its request and response body are
+/// dropped, which happens after the response was sent or when the request is
+/// cancelled.
+pub(crate) struct Stats<S> {
+    inner: S,
+    handler: Option<Arc<dyn StatsHandler>>,
+}
+
+impl<S> Stats<S> {
+    pub(crate) fn new(inner: S, handler: Option<Arc<dyn StatsHandler>>) -> Self {
+        Stats { inner, handler }
+    }
+}
+
+impl<S> Service<Request<Body>> for Stats<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Error: Into<crate::Error>,
+{
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let method = req.uri().path().to_string();
+        if let Some(handler) = &self.handler {
+            handler.begin(&method);
+        }
+
+        let span = info_span!(
+            "grpc_request",
+            grpc.method = %method,
+            grpc.code = field::Empty,
+            grpc.latency_ms = field::Empty,
+            grpc.messages_received = field::Empty,
+            grpc.messages_sent = field::Empty,
+        );
+        let rpc = Arc::new(Rpc {
+            method,
+            span,
+            start: Instant::now(),
+            code: Mutex::new(None),
+            received: AtomicUsize::new(0),
+            sent: AtomicUsize::new(0),
+            handler: self.handler.clone(),
+        });
+
+        let (mut parts, body) = req.into_parts();
+        if parts.extensions.get::<Span>().is_none() {
+            parts.extensions.insert(rpc.span.clone());
+        }
+
+        let received = rpc.clone();
+        let mut messages = Messages::default();
+        let body = Body::wrap_stream(body.map_ok(move |chunk| {
+            let count = messages.feed(&chunk);
+            received.received.fetch_add(count, Ordering::Relaxed);
+            chunk
+        }));
+
+        let inner = {
+            let _enter = rpc.span.enter();
+            self.inner.call(Request::from_parts(parts, body))
+        };
+
+        ResponseFuture {
+            inner,
+            rpc: Some(rpc),
+        }
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for Stats<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stats")
+            .field("inner", &self.inner)
+            .field("handler", &self.handler)
+            .finish()
+    }
+}
+
+#[pin_project]
+#[derive(Debug)]
+pub(crate) struct ResponseFuture<F> {
+    #[pin]
+    inner: F,
+    rpc: Option<Arc<Rpc>>,
+}
+
+impl<F, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<Response<BoxBody>, E>>,
+    E: Into<crate::Error>,
+{
+    type Output = Result<Response<BoxBody>, crate::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let me = self.project();
+
+        let res = {
+            let _enter = me.rpc.as_ref().map(|rpc| rpc.span.enter());
+            futures_core::ready!(me.inner.poll(cx)).map_err(Into::into)?
+        };
+
+        let rpc = me.rpc.take().expect("polled after ready");
+        rpc.set_code(res.headers());
+
+        let res = res.map(|body| {
+            BoxBody::new(CountBody {
+                inner: body,
+                rpc,
+                messages: Messages::default(),
+            })
+        });
+        Poll::Ready(Ok(res))
+    }
+}
+
+/// Counts the messages of a response body and takes the status from its
+/// trailers.
+struct CountBody {
+    inner: BoxBody,
+    rpc: Arc<Rpc>,
+    messages: Messages,
+}
+
+impl HttpBody for CountBody {
+    type Data = BytesBuf;
+    type Error = Status;
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = futures_core::ready!(Pin::new(&mut self.inner).poll_data(cx));
+
+        match &data {
+            Some(Ok(data)) => {
+                let count = self.messages.feed(data.bytes());
+                self.rpc.sent.fetch_add(count, Ordering::Relaxed);
+            }
+            Some(Err(status)) => *self.rpc.code.lock().unwrap() = Some(status.code()),
+            None => {}
+        }
+
+        Poll::Ready(data)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = futures_core::ready!(Pin::new(&mut self.inner).poll_trailers(cx));
+
+        if let Ok(Some(trailers)) = &trailers {
+            self.rpc.set_code(trailers);
+        }
+
+        Poll::Ready(trailers)
+    }
+}
+
+/// The state of a request, reported once it is dropped by the request body,
+/// the response future and the response body.
+#[derive(Debug)]
+struct Rpc {
+    method: String,
+    span: Span,
+    start: Instant,
+    code: Mutex<Option<Code>>,
+    received: AtomicUsize,
+    sent: AtomicUsize,
+    handler: Option<Arc<dyn StatsHandler>>,
+}
+
+impl Rpc {
+    fn set_code(&self, headers: &HeaderMap) {
+        if let Some(status) = Status::from_header_map(headers) {
+            *self.code.lock().unwrap() = Some(status.code());
+        }
+    }
+}
+
+impl Drop for Rpc {
+    fn drop(&mut self) {
+        let stats = RpcStats {
+            method: &self.method,
+            code: *self.code.get_mut().unwrap(),
+            duration: self.start.elapsed(),
+            messages_received: *self.received.get_mut(),
+            messages_sent: *self.sent.get_mut(),
+        };
+
+        if let Some(code) = stats.code {
+            self.span.record("grpc.code", code as i64);
+        }
+        self.span
+            .record("grpc.latency_ms", stats.duration.as_millis() as u64);
+        self.span
+            .record("grpc.messages_received", stats.messages_received as u64);
+        self.span
+            .record("grpc.messages_sent", stats.messages_sent as u64);
+        debug!(parent: &self.span, "request finished");
+
+        if let Some(handler) = &self.handler {
+            handler.end(&stats);
+        }
+    }
+}
+
+// every gRPC message is prefixed by a compression flag and its length
+const PREFIX_LEN: usize = 5;
+
+/// Counts the gRPC messages in the bytes of a body.
+#[derive(Debug, Default)]
+struct Messages {
+    prefix: [u8; PREFIX_LEN],
+    filled: usize,
+    remaining: usize,
+}
+
+impl Messages {
+    /// Feed the next bytes of a body, returning how many messages start in
+    /// them.
+    fn feed(&mut self, mut data: &[u8]) -> usize {
+        let mut count = 0;
+
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let n = cmp::min(self.remaining, data.len());
+                self.remaining -= n;
+                data = &data[n..];
+                continue;
+            }
+
+            let n = cmp::min(PREFIX_LEN - self.filled, data.len());
+            self.prefix[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+
+            if self.filled == PREFIX_LEN {
+                let p = &self.prefix;
+                self.remaining = u32::from_be_bytes([p[1], p[2], p[3], p[4]]) as usize;
+                self.filled = 0;
+                count += 1;
+            }
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: u32) -> Vec<u8> {
+        let mut frame = vec![0];
+        frame.extend_from_slice(&len.to_be_bytes());
+        frame.extend(std::iter::repeat(1).take(len as usize));
+        frame
+    }
+
+    #[test]
+    fn counts_messages() {
+        let mut data = frame(3);
+        data.extend(frame(0));
+        data.extend(frame(10));
+
+        let mut messages = Messages::default();
+        assert_eq!(messages.feed(&data), 3);
+
+        // split at every possible position
+        for at in 0..data.len() {
+            let mut messages = Messages::default();
+            let count = messages.feed(&data[..at]) + messages.feed(&data[at..]);
+            assert_eq!(count, 3, "split at {}", at);
+        }
+    }
+}
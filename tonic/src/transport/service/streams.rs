@@ -0,0 +1,90 @@
+use super::frames::Frames;
+use super::keepalive::{StreamEvent, Streams};
+use crate::transport::channelz::SocketNode;
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Counts the streams opened on a connection in its channelz socket.
+pub(crate) struct CountStreams<IO> {
+    io: IO,
+    socket: Arc<SocketNode>,
+    read: Frames,
+    written: Frames,
+    streams: Streams,
+}
+
+impl<IO> CountStreams<IO> {
+    pub(crate) fn new(io: IO, socket: Arc<SocketNode>) -> Self {
+        CountStreams {
+            io,
+            socket,
+            read: Frames::new(),
+            written: Frames::new(),
+            streams: Streams::default(),
+        }
+    }
+}
+
+fn count(socket: &SocketNode, event: Option<StreamEvent>) {
+    match event {
+        Some(StreamEvent::Opened) => socket.streams().start(),
+        Some(StreamEvent::Ended) => socket.streams().finish(true),
+        Some(StreamEvent::Reset) => socket.streams().finish(false),
+        None => {}
+    }
+}
+
+impl<IO> AsyncRead for CountStreams<IO>
+where
+    IO: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        let n = futures_util::ready!(Pin::new(&mut me.io).poll_read(cx, buf))?;
+
+        let (socket, streams) = (&me.socket, &mut me.streams);
+        me.read.feed(&buf[..n], &mut |header| {
+            count(socket, streams.on_frame(header, false))
+        });
+
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<IO> AsyncWrite for CountStreams<IO>
+where
+    IO: AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let me = &mut *self;
+        let n = futures_util::ready!(Pin::new(&mut me.io).poll_write(cx, buf))?;
+
+        let (socket, streams) = (&me.socket, &mut me.streams);
+        me.written.feed(&buf[..n], &mut |header| {
+            count(socket, streams.on_frame(header, true))
+        });
+
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
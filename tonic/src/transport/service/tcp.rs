@@ -0,0 +1,37 @@
+//! TCP socket options.
+
+use hyper::client::connect::HttpConnector;
+use std::{io, time::Duration};
+use tokio::net::TcpStream;
+
+/// Socket options applied to the TCP connections of a client or server.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct TcpConfig {
+    pub(crate) nodelay: bool,
+    pub(crate) keepalive: Option<Duration>,
+    pub(crate) send_buffer_size: Option<usize>,
+    pub(crate) recv_buffer_size: Option<usize>,
+}
+
+impl TcpConfig {
+    /// Apply the options to the connections made by `http`.
+    pub(crate) fn apply_to_connector<R>(&self, http: &mut HttpConnector<R>) {
+        http.set_nodelay(self.nodelay);
+        http.set_keepalive(self.keepalive);
+        http.set_send_buffer_size(self.send_buffer_size);
+        http.set_recv_buffer_size(self.recv_buffer_size);
+    }
+
+    /// Apply the options to an accepted connection.
+    pub(crate) fn apply_to_stream(&self, io: &TcpStream) -> io::Result<()> {
+        io.set_nodelay(self.nodelay)?;
+        io.set_keepalive(self.keepalive)?;
+        if let Some(size) = self.send_buffer_size {
+            io.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            io.set_recv_buffer_size(size)?;
+        }
+        Ok(())
+    }
+}
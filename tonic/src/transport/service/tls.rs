@@ -1,17 +1,24 @@
-use super::io::BoxedIo;
-use crate::transport::{Certificate, Identity};
+use super::io::{BoxedIo, Io};
+use crate::transport::{Certificate, ConnectionInfo, Error, ErrorKind, Identity};
 #[cfg(feature = "openssl")]
 use openssl1::{
     pkey::PKey,
     ssl::{select_next_proto, AlpnError, SslAcceptor, SslConnector, SslMethod, SslVerifyMode},
     x509::{store::X509StoreBuilder, X509},
 };
-use std::{fmt, sync::Arc};
-use tokio::net::TcpStream;
+use std::{fmt, net::SocketAddr, sync::Arc};
+#[cfg(feature = "openssl")]
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+#[cfg(feature = "openssl")]
+use tokio::io::{AsyncRead, AsyncWrite};
 #[cfg(feature = "rustls")]
 use tokio_rustls::{
     rustls::{ClientConfig, NoClientAuth, ServerConfig, Session},
-    webpki::DNSNameRef,
+    webpki::{DNSName, DNSNameRef},
     TlsAcceptor as RustlsAcceptor, TlsConnector as RustlsConnector,
 };
 
@@ -37,6 +44,18 @@ enum TlsError {
     CertificateParseError,
     #[cfg(feature = "rustls")]
     PrivateKeyParseError,
+    #[cfg(feature = "rustls")]
+    InvalidDnsName(String),
+}
+
+/// The root certificates to trust with Rustls besides the CA certificate.
+#[cfg(feature = "rustls")]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct RustlsRoots {
+    #[cfg(feature = "tls-roots")]
+    pub(crate) native: bool,
+    #[cfg(feature = "tls-webpki-roots")]
+    pub(crate) webpki: bool,
 }
 
 #[derive(Clone)]
@@ -59,20 +78,22 @@ impl TlsConnector {
         cert: Option<Certificate>,
         identity: Option<Identity>,
         domain: String,
-    ) -> Result<Self, crate::Error> {
-        let mut config = SslConnector::builder(SslMethod::tls())?;
-        config.set_alpn_protos(ALPN_H2_WIRE)?;
+    ) -> Result<Self, Error> {
+        let mut config =
+            SslConnector::builder(SslMethod::tls()).map_err(tls_error(ErrorKind::Client))?;
 
         if let Some(cert) = cert {
-            let ca = X509::from_pem(&cert.pem[..])?;
-            config.cert_store_mut().add_cert(ca)?;
+            let invalid_ca = tls_error(ErrorKind::InvalidCaCertificate);
+            let ca = X509::from_pem(&cert.pem[..]).map_err(&invalid_ca)?;
+            config.cert_store_mut().add_cert(ca).map_err(&invalid_ca)?;
         }
 
         if let Some(identity) = identity {
-            let key = PKey::private_key_from_pem(&identity.key[..])?;
-            let cert = X509::from_pem(&identity.cert.pem[..])?;
-            config.set_certificate(&cert)?;
-            config.set_private_key(&key)?;
+            let invalid_identity = tls_error(ErrorKind::InvalidIdentity);
+            let key = PKey::private_key_from_pem(&identity.key[..]).map_err(&invalid_identity)?;
+            let cert = X509::from_pem(&identity.cert.pem[..]).map_err(&invalid_identity)?;
+            config.set_certificate(&cert).map_err(&invalid_identity)?;
+            config.set_private_key(&key).map_err(&invalid_identity)?;
         }
 
         Ok(Self {
@@ -85,7 +106,7 @@ impl TlsConnector {
     pub(crate) fn new_with_openssl_raw(
         ssl_connector: openssl1::ssl::SslConnector,
         domain: String,
-    ) -> Result<Self, crate::Error> {
+    ) -> Result<Self, Error> {
         Ok(Self {
             inner: Connector::Openssl(ssl_connector),
             domain: Arc::new(domain),
@@ -93,22 +114,53 @@ impl TlsConnector {
     }
 
     #[cfg(feature = "rustls")]
+    #[cfg_attr(
+        not(any(feature = "tls-roots", feature = "tls-webpki-roots")),
+        allow(unused_variables)
+    )]
     pub(crate) fn new_with_rustls_cert(
         ca_cert: Option<Certificate>,
         identity: Option<Identity>,
+        roots: RustlsRoots,
         domain: String,
-    ) -> Result<Self, crate::Error> {
+    ) -> Result<Self, Error> {
         let mut config = ClientConfig::new();
         config.set_protocols(&[Vec::from(&ALPN_H2[..])]);
 
+        #[cfg(feature = "tls-roots")]
+        {
+            if roots.native {
+                config.root_store = rustls_native_certs::load_native_certs()
+                    .map_err(tls_error(ErrorKind::Client))?;
+            }
+        }
+
+        #[cfg(feature = "tls-webpki-roots")]
+        {
+            if roots.webpki {
+                config
+                    .root_store
+                    .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            }
+        }
+
         if let Some(identity) = identity {
-            let (client_cert, client_key) = rustls_keys::load_identity(identity)?;
+            let (client_cert, client_key) = rustls_keys::load_identity(identity)
+                .map_err(tls_error(ErrorKind::InvalidIdentity))?;
             config.set_single_client_cert(client_cert, client_key);
         }
 
         if let Some(cert) = ca_cert {
             let mut buf = std::io::Cursor::new(&cert.pem[..]);
-            config.root_store.add_pem_file(&mut buf).unwrap();
+            match config.root_store.add_pem_file(&mut buf) {
+                Ok((valid, _)) if valid > 0 => {}
+                _ => {
+                    return Err(Error::from_source(
+                        ErrorKind::InvalidCaCertificate,
+                        Box::new(TlsError::CertificateParseError),
+                    ))
+                }
+            }
         }
 
         Ok(Self {
@@ -119,45 +171,80 @@ impl TlsConnector {
 
     #[cfg(feature = "rustls")]
     pub(crate) fn new_with_rustls_raw(
-        config: tokio_rustls::rustls::ClientConfig,
+        mut config: tokio_rustls::rustls::ClientConfig,
         domain: String,
-    ) -> Result<Self, crate::Error> {
+    ) -> Result<Self, Error> {
+        config.set_protocols(&[Vec::from(ALPN_H2)]);
+
         Ok(Self {
             inner: Connector::Rustls(Arc::new(config)),
             domain: Arc::new(domain),
         })
     }
 
-    pub(crate) async fn connect(&self, io: TcpStream) -> Result<BoxedIo, crate::Error> {
+    /// Check that `domain` is a valid DNS name, as required by Rustls.
+    #[cfg(feature = "rustls")]
+    pub(crate) fn check_domain(domain: &str) -> Result<(), Error> {
+        dns_name(domain).map(drop)
+    }
+
+    /// Use the connector for another domain, e.g. after the origin of an
+    /// endpoint changed.
+    pub(crate) fn with_domain(mut self, domain: String) -> Self {
+        self.domain = Arc::new(domain);
+        self
+    }
+
+    pub(crate) async fn connect<I: Io>(
+        &self,
+        io: I,
+        remote_addr: Option<SocketAddr>,
+    ) -> Result<(BoxedIo, ConnectionInfo), crate::Error> {
+        let mut info = ConnectionInfo::new(remote_addr);
+
         let tls_io = match &self.inner {
             #[cfg(feature = "openssl")]
             Connector::Openssl(connector) => {
-                let config = connector.configure()?;
-                let tls = tokio_openssl::connect(config, &self.domain, io).await?;
-
-                match tls.ssl().selected_alpn_protocol() {
-                    Some(b) if b == b"h2" => tracing::trace!("HTTP/2 succesfully negotiated."),
-                    _ => return Err(TlsError::H2NotNegotiated.into()),
+                // set per connection, as raw connectors can't be changed
+                let mut config = connector.configure()?;
+                config.set_alpn_protos(ALPN_H2_WIRE)?;
+                let tls = tokio_openssl::connect(config, &self.domain, OpaqueIo(io))
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                check_alpn(tls.ssl().selected_alpn_protocol())?;
+
+                info.alpn_protocol = tls.ssl().selected_alpn_protocol().map(Vec::from);
+                info.peer_certificates = match tls.ssl().peer_cert_chain() {
+                    Some(chain) => Some(
+                        chain
+                            .iter()
+                            .map(|cert| cert.to_pem().map(Certificate::from_pem))
+                            .collect::<Result<_, _>>()?,
+                    ),
+                    None => None,
                 };
 
                 BoxedIo::new(tls)
             }
             #[cfg(feature = "rustls")]
             Connector::Rustls(config) => {
-                let dns = DNSNameRef::try_from_ascii_str(self.domain.as_str())
-                    .unwrap()
-                    .to_owned();
+                let dns = dns_name(&self.domain)?;
 
                 let io = RustlsConnector::from(config.clone())
                     .connect(dns.as_ref(), io)
                     .await?;
 
                 let (_, session) = io.get_ref();
+                check_alpn(session.get_alpn_protocol())?;
 
-                match session.get_alpn_protocol() {
-                    Some(b) if b == b"h2" => (),
-                    _ => return Err(TlsError::H2NotNegotiated.into()),
-                };
+                info.alpn_protocol = session.get_alpn_protocol().map(Vec::from);
+                info.peer_certificates = session.get_peer_certificates().map(|certs| {
+                    certs
+                        .iter()
+                        .map(|cert| Certificate::from_der(&cert.0))
+                        .collect()
+                });
 
                 BoxedIo::new(io)
             }
@@ -166,7 +253,48 @@ impl TlsConnector {
             _ => unreachable!("Reached a tls config point with neither feature enabled!"),
         };
 
-        Ok(tls_io)
+        Ok((tls_io, info))
+    }
+}
+
+/// Attribute the errors of a TLS input to the input of `kind`.
+#[cfg_attr(not(any(feature = "openssl", feature = "rustls")), allow(dead_code))]
+fn tls_error<E: Into<crate::Error>>(kind: ErrorKind) -> impl Fn(E) -> Error {
+    move |e| Error::from_source(kind, e.into())
+}
+
+/// Parse the domain the server must be valid for.
+#[cfg(feature = "rustls")]
+fn dns_name(domain: &str) -> Result<DNSName, Error> {
+    DNSNameRef::try_from_ascii_str(domain)
+        .map(|dns| dns.to_owned())
+        .map_err(|_| {
+            Error::from_source(
+                ErrorKind::InvalidDomainName,
+                Box::new(TlsError::InvalidDnsName(domain.to_string())),
+            )
+        })
+}
+
+/// Fail unless the server selected `h2` via ALPN.
+#[cfg_attr(not(any(feature = "openssl", feature = "rustls")), allow(dead_code))]
+fn check_alpn(protocol: Option<&[u8]>) -> Result<(), crate::Error> {
+    match protocol {
+        Some(b"h2") => {
+            tracing::trace!("HTTP/2 successfully negotiated.");
+            Ok(())
+        }
+        Some(protocol) => {
+            tracing::debug!(
+                "server selected {:?} instead of h2",
+                String::from_utf8_lossy(protocol)
+            );
+            Err(TlsError::H2NotNegotiated.into())
+        }
+        None => {
+            tracing::debug!("server did not select a protocol via ALPN");
+            Err(TlsError::H2NotNegotiated.into())
+        }
     }
 }
 
@@ -207,26 +335,33 @@ impl TlsAcceptor {
     pub(crate) fn new_with_openssl_identity(
         identity: Identity,
         client_ca_root: Option<Certificate>,
-    ) -> Result<Self, crate::Error> {
-        let key = PKey::private_key_from_pem(&identity.key[..])?;
-        let cert = X509::from_pem(&identity.cert.pem[..])?;
-
-        let mut config = SslAcceptor::mozilla_modern(SslMethod::tls())?;
-
-        config.set_private_key(&key)?;
-        config.set_certificate(&cert)?;
-        config.set_alpn_protos(ALPN_H2_WIRE)?;
+    ) -> Result<Self, Error> {
+        let server_error = tls_error(ErrorKind::Server);
+        let invalid_identity = tls_error(ErrorKind::InvalidIdentity);
+        let key = PKey::private_key_from_pem(&identity.key[..]).map_err(&invalid_identity)?;
+        let cert = X509::from_pem(&identity.cert.pem[..]).map_err(&invalid_identity)?;
+
+        let mut config = SslAcceptor::mozilla_modern(SslMethod::tls()).map_err(&server_error)?;
+
+        config.set_private_key(&key).map_err(&invalid_identity)?;
+        config.set_certificate(&cert).map_err(&invalid_identity)?;
+        config
+            .set_alpn_protos(ALPN_H2_WIRE)
+            .map_err(&server_error)?;
         config.set_alpn_select_callback(|_ssl, alpn| {
             select_next_proto(ALPN_H2_WIRE, alpn).ok_or(AlpnError::NOACK)
         });
 
         if let Some(cert) = client_ca_root {
-            let ca_cert = X509::from_pem(&cert.pem[..])?;
-            let mut store = X509StoreBuilder::new()?;
-            store.add_cert(ca_cert.clone())?;
-
-            config.add_client_ca(&ca_cert)?;
-            config.set_verify_cert_store(store.build())?;
+            let invalid_ca = tls_error(ErrorKind::InvalidCaCertificate);
+            let ca_cert = X509::from_pem(&cert.pem[..]).map_err(&invalid_ca)?;
+            let mut store = X509StoreBuilder::new().map_err(&server_error)?;
+            store.add_cert(ca_cert.clone()).map_err(&invalid_ca)?;
+
+            config.add_client_ca(&ca_cert).map_err(&invalid_ca)?;
+            config
+                .set_verify_cert_store(store.build())
+                .map_err(&server_error)?;
             config.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
         }
 
@@ -238,7 +373,7 @@ impl TlsAcceptor {
     #[cfg(feature = "openssl")]
     pub(crate) fn new_with_openssl_raw(
         acceptor: openssl1::ssl::SslAcceptor,
-    ) -> Result<Self, crate::Error> {
+    ) -> Result<Self, Error> {
         Ok(Self {
             inner: Acceptor::Openssl(acceptor),
         })
@@ -248,8 +383,9 @@ impl TlsAcceptor {
     pub(crate) fn new_with_rustls_identity(
         identity: Identity,
         client_ca_root: Option<Certificate>,
-    ) -> Result<Self, crate::Error> {
-        let (cert, key) = rustls_keys::load_identity(identity)?;
+    ) -> Result<Self, Error> {
+        let (cert, key) =
+            rustls_keys::load_identity(identity).map_err(tls_error(ErrorKind::InvalidIdentity))?;
 
         let mut config = match client_ca_root {
             None => ServerConfig::new(NoClientAuth::new()),
@@ -258,8 +394,13 @@ impl TlsAcceptor {
 
                 let mut client_root_cert_store = tokio_rustls::rustls::RootCertStore::empty();
                 match client_root_cert_store.add_pem_file(&mut cert) {
-                    Err(_) => return Err(Box::new(TlsError::CertificateParseError)),
-                    _ => (),
+                    Ok((valid, _)) if valid > 0 => {}
+                    _ => {
+                        return Err(Error::from_source(
+                            ErrorKind::InvalidCaCertificate,
+                            Box::new(TlsError::CertificateParseError),
+                        ))
+                    }
                 };
 
                 let client_auth =
@@ -267,7 +408,9 @@ impl TlsAcceptor {
                 ServerConfig::new(client_auth)
             }
         };
-        config.set_single_cert(cert, key)?;
+        config
+            .set_single_cert(cert, key)
+            .map_err(tls_error(ErrorKind::InvalidIdentity))?;
         config.set_protocols(&[Vec::from(&ALPN_H2[..])]);
 
         Ok(Self {
@@ -278,17 +421,42 @@ impl TlsAcceptor {
     #[cfg(feature = "rustls")]
     pub(crate) fn new_with_rustls_raw(
         config: tokio_rustls::rustls::ServerConfig,
-    ) -> Result<Self, crate::Error> {
+    ) -> Result<Self, Error> {
         Ok(Self {
             inner: Acceptor::Rustls(Arc::new(config)),
         })
     }
 
-    pub(crate) async fn connect(&self, io: TcpStream) -> Result<BoxedIo, crate::Error> {
+    /// Accept a TLS connection on `io`.
+    ///
+    /// The returned io carries the negotiated protocol and the certificates
+    /// the client presented, which were verified if a client CA is set.
+    pub(crate) async fn connect<I: Io>(&self, io: I) -> Result<BoxedIo, crate::Error> {
+        let mut info = ConnectionInfo::new(None);
+
         let io = match &self.inner {
             #[cfg(feature = "openssl")]
             Acceptor::Openssl(acceptor) => {
-                let tls = tokio_openssl::accept(&acceptor, io).await?;
+                let tls = tokio_openssl::accept(&acceptor, OpaqueIo(io))
+                    .await
+                    .map_err(|e| e.to_string())?;
+
+                info.alpn_protocol = tls.ssl().selected_alpn_protocol().map(Vec::from);
+                // unlike on the client, the chain on the server side does not
+                // include the peer's own certificate
+                info.peer_certificates = match tls.ssl().peer_certificate() {
+                    Some(cert) => {
+                        let mut certs = vec![Certificate::from_pem(cert.to_pem()?)];
+                        if let Some(chain) = tls.ssl().peer_cert_chain() {
+                            for cert in chain {
+                                certs.push(Certificate::from_pem(cert.to_pem()?));
+                            }
+                        }
+                        Some(certs)
+                    }
+                    None => None,
+                };
+
                 BoxedIo::new(tls)
             }
 
@@ -296,6 +464,16 @@ impl TlsAcceptor {
             Acceptor::Rustls(config) => {
                 let acceptor = RustlsAcceptor::from(config.clone());
                 let tls = acceptor.accept(io).await?;
+
+                let (_, session) = tls.get_ref();
+                info.alpn_protocol = session.get_alpn_protocol().map(Vec::from);
+                info.peer_certificates = session.get_peer_certificates().map(|certs| {
+                    certs
+                        .iter()
+                        .map(|cert| Certificate::from_der(&cert.0))
+                        .collect()
+                });
+
                 BoxedIo::new(tls)
             }
 
@@ -303,7 +481,7 @@ impl TlsAcceptor {
             _ => unreachable!("Reached a tls config point with neither feature enabled!"),
         };
 
-        Ok(io)
+        Ok(io.with_connection_info(info))
     }
 }
 
@@ -336,12 +514,56 @@ impl fmt::Display for TlsError {
                 f,
                 "Error parsing TLS private key - no RSA or PKCS8-encoded keys found."
             ),
+            #[cfg(feature = "rustls")]
+            TlsError::InvalidDnsName(domain) => write!(f, "{:?} is not a valid DNS name.", domain),
         }
     }
 }
 
 impl std::error::Error for TlsError {}
 
+/// A connection that can be formatted with `Debug`, which is required to
+/// report openssl handshake errors for arbitrary connections.
+#[cfg(feature = "openssl")]
+struct OpaqueIo<I>(I);
+
+#[cfg(feature = "openssl")]
+impl<I> fmt::Debug for OpaqueIo<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OpaqueIo").finish()
+    }
+}
+
+#[cfg(feature = "openssl")]
+impl<I: Io> AsyncRead for OpaqueIo<I> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+#[cfg(feature = "openssl")]
+impl<I: Io> AsyncWrite for OpaqueIo<I> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
 #[cfg(feature = "rustls")]
 mod rustls_keys {
     use tokio_rustls::rustls::{internal::pemfile, Certificate, PrivateKey};
@@ -377,8 +599,8 @@ mod rustls_keys {
         let cert = {
             let mut cert = std::io::Cursor::new(&identity.cert.pem[..]);
             match pemfile::certs(&mut cert) {
-                Ok(certs) => certs,
-                Err(_) => return Err(Box::new(TlsError::CertificateParseError)),
+                Ok(certs) if !certs.is_empty() => certs,
+                _ => return Err(Box::new(TlsError::CertificateParseError)),
             }
         };
 
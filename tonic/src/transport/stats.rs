@@ -0,0 +1,110 @@
+use crate::Code;
+use std::{fmt, sync::Arc, time::Duration};
+
+/// A handler for per request statistics of a [`Server`].
+///
+/// This is called for every request the server receives, similar to the
+/// `StatsHandler` of grpc-go, e.g. to export latency metrics per method
+/// without wrapping every handler. Set it via [`Server::stats_handler`].
+///
+/// Closures taking the [`RpcStats`] of a finished request implement this
+/// trait:
+///
+/// ```
+/// # use tonic::transport::{RpcStats, Server};
+/// # let builder = Server::builder();
+/// builder.stats_handler(|stats: &RpcStats<'_>| {
+///     println!("{} took {:?}", stats.method(), stats.duration());
+/// });
+/// ```
+///
+/// [`Server`]: struct.Server.html
+/// [`Server::stats_handler`]: struct.Server.html#method.stats_handler
+pub trait StatsHandler: Send + Sync + 'static {
+    /// Called when a request is received, before any interceptor or service
+    /// sees it.
+    ///
+    /// `method` is the path of the request, e.g. `/helloworld.Greeter/SayHello`.
+    fn begin(&self, method: &str) {
+        let _ = method;
+    }
+
+    /// Called once a request is finished, i.e. when its response has been
+    /// sent or it was cancelled.
+    fn end(&self, stats: &RpcStats<'_>);
+}
+
+impl<F> StatsHandler for F
+where
+    F: Fn(&RpcStats<'_>) + Send + Sync + 'static,
+{
+    fn end(&self, stats: &RpcStats<'_>) {
+        self(stats)
+    }
+}
+
+impl fmt::Debug for dyn StatsHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatsHandler").finish()
+    }
+}
+
+/// Reports to two handlers, the first of which is called first.
+pub(crate) struct BothHandlers(
+    pub(crate) Arc<dyn StatsHandler>,
+    pub(crate) Arc<dyn StatsHandler>,
+);
+
+impl StatsHandler for BothHandlers {
+    fn begin(&self, method: &str) {
+        self.0.begin(method);
+        self.1.begin(method);
+    }
+
+    fn end(&self, stats: &RpcStats<'_>) {
+        self.0.end(stats);
+        self.1.end(stats);
+    }
+}
+
+/// Statistics of a finished request, passed to [`StatsHandler::end`].
+///
+/// [`StatsHandler::end`]: trait.StatsHandler.html#tymethod.end
+#[derive(Debug, Clone)]
+pub struct RpcStats<'a> {
+    pub(crate) method: &'a str,
+    pub(crate) code: Option<Code>,
+    pub(crate) duration: Duration,
+    pub(crate) messages_received: usize,
+    pub(crate) messages_sent: usize,
+}
+
+impl<'a> RpcStats<'a> {
+    /// The path of the request, e.g. `/helloworld.Greeter/SayHello`.
+    pub fn method(&self) -> &'a str {
+        self.method
+    }
+
+    /// The status code sent to the client.
+    ///
+    /// This is `None` if the request finished without a status, e.g. because
+    /// the client cancelled it or the connection was closed.
+    pub fn code(&self) -> Option<Code> {
+        self.code
+    }
+
+    /// The time from receiving the request until it finished.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The number of messages received from the client.
+    pub fn messages_received(&self) -> usize {
+        self.messages_received
+    }
+
+    /// The number of messages sent to the client.
+    pub fn messages_sent(&self) -> usize {
+        self.messages_sent
+    }
+}
@@ -0,0 +1,2512 @@
+use super::{
+    Addresses, BufferFullPolicy, CancellationToken, Channel, ConnectionInfo, ConnectivityState,
+    DnsResolver, Endpoint, Proxy, Resolver, RetryPolicy, Server, ServiceName,
+};
+use crate::{body::BoxBody, client::GrpcService, test_util::Recorder};
+use futures_util::{future, stream};
+use http::{Request, Response};
+use hyper::Body;
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_service::Service;
+use tracing::field;
+
+#[derive(Debug, Clone, Default)]
+struct Svc;
+
+impl Service<Request<Body>> for Svc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, _req: Request<Body>) -> Self::Future {
+        let res = Response::builder()
+            .header("grpc-status", "0")
+            .body(BoxBody::empty())
+            .unwrap();
+
+        future::ok(res)
+    }
+}
+
+impl ServiceName for Svc {
+    const NAME: &'static str = "test.Svc";
+}
+
+/// Responds like [`Svc`], but only after a delay.
+#[derive(Debug, Clone)]
+struct SlowSvc(Duration);
+
+impl Service<Request<Body>> for SlowSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let delay = tokio::timer::delay_for(self.0);
+        let res = Service::call(&mut Svc, req);
+        Box::pin(async move {
+            delay.await;
+            res.await
+        })
+    }
+}
+
+impl ServiceName for SlowSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+/// Keeps the cancellation token of the last request, then responds like
+/// [`SlowSvc`].
+#[derive(Debug, Clone)]
+struct TokenSvc {
+    delay: Duration,
+    token: Arc<Mutex<Option<CancellationToken>>>,
+}
+
+impl Service<Request<Body>> for TokenSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let req = crate::Request::from_http(req);
+        *self.token.lock().unwrap() = req.cancellation_token();
+        Service::call(&mut SlowSvc(self.delay), Request::new(Body::empty()))
+    }
+}
+
+impl ServiceName for TokenSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+/// Fails the first `failures` requests with `UNAVAILABLE`, then responds like
+/// [`Svc`].
+#[derive(Debug, Clone)]
+struct FlakySvc {
+    failures: usize,
+    calls: Arc<Mutex<usize>>,
+}
+
+impl Service<Request<Body>> for FlakySvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let mut calls = self.calls.lock().unwrap();
+        *calls += 1;
+        if *calls > self.failures {
+            return Service::call(&mut Svc, req);
+        }
+
+        let res = Response::builder()
+            .header("grpc-status", "14")
+            .body(BoxBody::empty())
+            .unwrap();
+
+        future::ok(res)
+    }
+}
+
+impl ServiceName for FlakySvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+/// Responds with what it knows about the connection of the request, the
+/// address of the peer in `x-remote-addr` and the number of certificates it
+/// presented in `x-peer-certificates`.
+#[derive(Debug, Clone)]
+struct InfoSvc;
+
+impl Service<Request<Body>> for InfoSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let authority = req.uri().authority_part().map(|a| a.to_string());
+        let req = crate::Request::from_http(req);
+        assert!(req.extensions().get::<ConnectionInfo>().is_some());
+        let remote_addr = req.remote_addr().map(|addr| addr.to_string());
+        let peer_certificates = req.peer_certificates().map_or(0, |certs| certs.len());
+
+        let res = Response::builder()
+            .header("grpc-status", "0")
+            .header("x-remote-addr", remote_addr.unwrap_or_default())
+            .header("x-peer-certificates", peer_certificates)
+            .header("x-authority", authority.unwrap_or_default())
+            .body(BoxBody::empty())
+            .unwrap();
+
+        future::ok(res)
+    }
+}
+
+impl ServiceName for InfoSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+/// Responds to unary calls with an empty message, with initial and trailing
+/// metadata.
+#[derive(Debug, Clone)]
+struct TrailersSvc;
+
+impl Service<Request<Body>> for TrailersSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let mut grpc = crate::server::Grpc::new(crate::codec::ProstCodec::<(), ()>::default());
+        let handler = tower::service_fn(|_req: crate::Request<()>| {
+            let mut response = crate::Response::new(());
+            response
+                .metadata_mut()
+                .insert("x-initial", "1".parse().unwrap());
+            response
+                .trailers_mut()
+                .insert("x-trailing", "2".parse().unwrap());
+            future::ok::<_, crate::Status>(response)
+        });
+
+        Box::pin(async move { Ok(grpc.unary(handler, req).await) })
+    }
+}
+
+impl ServiceName for TrailersSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+/// Responds with the `grpc-encoding` of the request in `x-grpc-encoding`.
+#[derive(Debug, Clone)]
+struct EncodingSvc;
+
+impl Service<Request<Body>> for EncodingSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let encoding = req.headers().get("grpc-encoding").cloned();
+
+        let mut res = Response::builder();
+        res.header("grpc-status", "0");
+        if let Some(encoding) = encoding {
+            res.header("x-grpc-encoding", encoding);
+        }
+
+        future::ok(res.body(BoxBody::empty()).unwrap())
+    }
+}
+
+impl ServiceName for EncodingSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+#[cfg(feature = "rustls")]
+const CA: &str = include_str!("../../../tonic-examples/data/tls/ca.pem");
+#[cfg(feature = "rustls")]
+const SERVER_CERT: &str = include_str!("../../../tonic-examples/data/tls/server.pem");
+#[cfg(feature = "rustls")]
+const SERVER_KEY: &str = include_str!("../../../tonic-examples/data/tls/server.key");
+#[cfg(feature = "rustls")]
+const CLIENT_CA: &str = include_str!("../../../tonic-examples/data/tls/client_ca.pem");
+#[cfg(feature = "rustls")]
+const CLIENT_CERT: &str = include_str!("../../../tonic-examples/data/tls/client1.pem");
+#[cfg(feature = "rustls")]
+const CLIENT_KEY: &str = include_str!("../../../tonic-examples/data/tls/client1.key");
+
+fn unused_addr() -> SocketAddr {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap()
+}
+
+async fn spawn_server(server: Server, addr: SocketAddr) {
+    tokio::spawn(async move {
+        server.clone().add_service(Svc).serve(addr).await.unwrap();
+    });
+
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+}
+
+#[tokio::test]
+async fn connection_info() {
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+
+    let uri = format!("http://{}", addr);
+    let channel = Channel::from_shared(uri).unwrap().connect().await.unwrap();
+
+    let info = channel.connection_info().unwrap();
+    assert_eq!(info.remote_addr(), Some(addr));
+    assert!(info.alpn_protocol().is_none());
+    assert!(info.peer_certificates().is_none());
+
+    let balanced = Channel::balance_list(
+        vec![Channel::from_shared(format!("http://{}", addr)).unwrap()].into_iter(),
+    );
+    assert!(balanced.connection_info().is_none());
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn connection_info_tls() {
+    use super::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+    let addr = unused_addr();
+    let server = Server::builder()
+        .tls_config(
+            ServerTlsConfig::with_rustls().identity(Identity::from_pem(SERVER_CERT, SERVER_KEY)),
+        )
+        .unwrap();
+    spawn_server(server, addr).await;
+
+    let tls = ClientTlsConfig::with_rustls()
+        .ca_certificate(Certificate::from_pem(CA))
+        .domain_name("localhost");
+    let channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let info = channel.connection_info().unwrap();
+    assert_eq!(info.remote_addr(), Some(addr));
+    assert_eq!(info.alpn_protocol(), Some(&b"h2"[..]));
+
+    let certs = info.peer_certificates().unwrap();
+    assert_eq!(certs.len(), 1);
+    assert_eq!(certs[0].get_ref(), SERVER_CERT.as_bytes());
+}
+
+#[tokio::test]
+async fn dispatch_records_span_fields() {
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+
+    let recorder = Recorder::default();
+    let _guard = tracing::subscriber::set_default(recorder.clone());
+
+    let uri = format!("http://{}", addr);
+    let endpoint = Channel::from_shared(uri.clone()).unwrap();
+    let mut channel = Channel::balance_list(vec![endpoint].into_iter());
+
+    future::poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx))
+        .await
+        .unwrap();
+
+    let span = tracing::info_span!(
+        "rpc",
+        grpc.endpoint = field::Empty,
+        grpc.connectivity_state = field::Empty
+    );
+    let request = Request::builder()
+        .uri("/test.Svc/Method")
+        .body(BoxBody::empty())
+        .unwrap();
+    let response = {
+        let _enter = span.enter();
+        GrpcService::call(&mut channel, request)
+    };
+    response.await.unwrap();
+
+    let fields = recorder.span_fields();
+    assert!(fields.contains(&("grpc.endpoint".to_string(), format!("{}/", uri))));
+    assert!(fields.contains(&("grpc.connectivity_state".to_string(), "ready".to_string())));
+}
+
+#[cfg(feature = "rustls")]
+async fn spawn_tls_server(addr: SocketAddr) {
+    use super::{Identity, ServerTlsConfig};
+
+    let server = Server::builder()
+        .tls_config(
+            ServerTlsConfig::with_rustls().identity(Identity::from_pem(SERVER_CERT, SERVER_KEY)),
+        )
+        .unwrap();
+    spawn_server(server, addr).await;
+}
+
+async fn unary(channel: &mut Channel) -> Result<(), super::Error> {
+    future::poll_fn(|cx| GrpcService::poll_ready(channel, cx)).await?;
+
+    let request = Request::builder()
+        .uri("/test.Svc/Method")
+        .body(BoxBody::empty())
+        .unwrap();
+    GrpcService::call(channel, request).await?;
+
+    Ok(())
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn tls_domain_defaults_to_host() {
+    use super::{Certificate, ClientTlsConfig};
+
+    let addr = unused_addr();
+    spawn_tls_server(addr).await;
+
+    let tls = ClientTlsConfig::with_rustls().ca_certificate(Certificate::from_pem(CA));
+    let mut channel = Channel::from_shared(format!("https://localhost:{}", addr.port()))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn tls_domain_defaults_to_origin() {
+    use super::{Certificate, ClientTlsConfig};
+
+    let addr = unused_addr();
+    spawn_tls_server(addr).await;
+
+    let tls = ClientTlsConfig::with_rustls().ca_certificate(Certificate::from_pem(CA));
+    let origin = format!("https://localhost:{}", addr.port());
+    let mut channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .origin(origin.parse().unwrap())
+        .connect()
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn balance_ips_with_shared_tls_domain() {
+    use super::{Certificate, ClientTlsConfig};
+
+    let addrs = vec![unused_addr(), unused_addr()];
+    for addr in &addrs {
+        spawn_tls_server(*addr).await;
+    }
+
+    let tls = ClientTlsConfig::with_rustls()
+        .ca_certificate(Certificate::from_pem(CA))
+        .domain_name("localhost");
+    let endpoints = addrs.iter().map(|addr| {
+        Channel::from_shared(format!("https://{}", addr))
+            .unwrap()
+            .tls_config(tls.clone())
+            .unwrap()
+    });
+    let mut channel = Channel::balance_list(endpoints);
+
+    for _ in 0..4 {
+        unary(&mut channel).await.unwrap();
+    }
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn reload_server_identity() {
+    use super::{Certificate, ClientTlsConfig, FileIdentityProvider, ServerTlsConfig};
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("tonic-identity-{}", rand::random::<u64>()));
+    fs::create_dir_all(&dir).unwrap();
+    let (cert, key) = (dir.join("tls.crt"), dir.join("tls.key"));
+    fs::write(&cert, SERVER_CERT).unwrap();
+    fs::write(&key, SERVER_KEY).unwrap();
+
+    let addr = unused_addr();
+    let identities = FileIdentityProvider::new(&cert, &key).interval(Duration::from_millis(50));
+    let server = Server::builder()
+        .tls_config(ServerTlsConfig::with_rustls().identity_provider(identities))
+        .unwrap();
+    spawn_server(server, addr).await;
+
+    let tls = ClientTlsConfig::with_rustls()
+        .ca_certificate(Certificate::from_pem(CA))
+        .domain_name("localhost");
+    let endpoint = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap();
+    let mut channel = endpoint.connect().await.unwrap();
+    unary(&mut channel).await.unwrap();
+
+    // rotate to a certificate the client doesn't trust
+    fs::write(&cert, CLIENT_CERT).unwrap();
+    fs::write(&key, CLIENT_KEY).unwrap();
+    tokio::timer::delay_for(Duration::from_millis(200)).await;
+
+    assert!(endpoint.connect().await.is_err());
+    // the established connection is kept
+    unary(&mut channel).await.unwrap();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn rustls_client_config_advertises_h2() {
+    use super::ClientTlsConfig;
+    use tokio_rustls::rustls::ClientConfig;
+
+    let addr = unused_addr();
+    spawn_tls_server(addr).await;
+
+    // a config without any ALPN protocols
+    let mut config = ClientConfig::new();
+    config
+        .root_store
+        .add_pem_file(&mut std::io::Cursor::new(CA))
+        .unwrap();
+    let tls = ClientTlsConfig::with_rustls()
+        .rustls_client_config(config)
+        .domain_name("localhost");
+
+    let mut channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let info = channel.connection_info().unwrap();
+    assert_eq!(info.alpn_protocol(), Some(&b"h2"[..]));
+    unary(&mut channel).await.unwrap();
+}
+
+#[cfg(feature = "tls-webpki-roots")]
+#[tokio::test]
+async fn webpki_roots() {
+    use super::{Certificate, ClientTlsConfig};
+
+    let addr = unused_addr();
+    spawn_tls_server(addr).await;
+    let endpoint = |tls: &ClientTlsConfig| {
+        Channel::from_shared(format!("https://{}", addr))
+            .unwrap()
+            .tls_config(tls.clone().domain_name("localhost"))
+            .unwrap()
+    };
+
+    // the test CA is not one of the bundled roots
+    let tls = ClientTlsConfig::with_rustls().webpki_roots();
+    assert!(endpoint(&tls).connect().await.is_err());
+
+    let tls = tls.clone().ca_certificate(Certificate::from_pem(CA));
+    let mut channel = endpoint(&tls).connect().await.unwrap();
+    unary(&mut channel).await.unwrap();
+}
+
+#[cfg(feature = "tls-roots")]
+#[tokio::test]
+async fn native_roots_keep_ca_certificate() {
+    use super::{Certificate, ClientTlsConfig};
+
+    let addr = unused_addr();
+    spawn_tls_server(addr).await;
+
+    let tls = ClientTlsConfig::with_rustls()
+        .native_roots()
+        .ca_certificate(Certificate::from_pem(CA))
+        .domain_name("localhost");
+    let mut channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+}
+
+#[cfg(feature = "rustls")]
+#[test]
+fn invalid_tls_config() {
+    use super::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+    let client_error = |uri: &'static str, tls: ClientTlsConfig| match Endpoint::from_static(uri)
+        .tls_config(tls)
+    {
+        Ok(_) => panic!("TLS config is valid"),
+        Err(error) => error.to_string(),
+    };
+    let server_error = |tls: ServerTlsConfig| match Server::builder().tls_config(tls) {
+        Ok(_) => panic!("TLS config is valid"),
+        Err(error) => error.to_string(),
+    };
+
+    let tls = ClientTlsConfig::with_rustls().ca_certificate(Certificate::from_pem("garbage"));
+    let error = client_error("https://example.com", tls);
+    assert!(error.starts_with("InvalidCaCertificate"), "{}", error);
+
+    let tls = ClientTlsConfig::with_rustls().identity(Identity::from_pem(CLIENT_CERT, "garbage"));
+    let error = client_error("https://example.com", tls);
+    assert!(error.starts_with("InvalidIdentity"), "{}", error);
+
+    let tls = ClientTlsConfig::with_rustls().domain_name("127.0.0.1");
+    let error = client_error("https://example.com", tls);
+    assert!(error.starts_with("InvalidDomainName"), "{}", error);
+
+    let error = server_error(ServerTlsConfig::with_rustls());
+    assert!(error.starts_with("InvalidIdentity"), "{}", error);
+
+    let tls = ServerTlsConfig::with_rustls()
+        .identity(Identity::from_pem(SERVER_CERT, SERVER_KEY))
+        .client_ca_root(Certificate::from_pem("garbage"));
+    let error = server_error(tls);
+    assert!(error.starts_with("InvalidCaCertificate"), "{}", error);
+}
+
+#[test]
+fn endpoint_config_summary() {
+    let endpoint = Endpoint::from_static("http://example.com");
+
+    let config = endpoint.config_summary();
+    assert_eq!(config.origin(), None);
+    assert_eq!(config.timeout(), None);
+    assert_eq!(config.connect_timeout(), None);
+    assert_eq!(config.buffer_size(), 1024);
+    assert_eq!(config.buffer_full_policy(), BufferFullPolicy::Wait);
+    assert!(config.buffer_full_status().is_none());
+    assert_eq!(config.keep_alive_timeout(), Duration::from_secs(20));
+    assert!(!config.keep_alive_while_idle());
+    assert!(config.retry_policy().is_none());
+    assert!(!config.tls());
+    assert!(!config.tcp_nodelay());
+    assert_eq!(config.tcp_keepalive(), None);
+
+    let endpoint = endpoint
+        .timeout(Duration::from_secs(5))
+        .connect_timeout(Duration::from_secs(1))
+        .concurrency_limit(32)
+        .rate_limit(100, Duration::from_secs(1))
+        .buffer_size(64)
+        .buffer_full_policy(BufferFullPolicy::FailFast)
+        .buffer_full_status(crate::Status::new(
+            crate::Code::Unavailable,
+            "client overloaded",
+        ))
+        .initial_stream_window_size(1 << 20)
+        .reconnect_backoff(Duration::from_millis(100), Duration::from_secs(10))
+        .reconnect_backoff_multiplier(1.5)
+        .http2_keep_alive_interval(Duration::from_secs(30))
+        .keep_alive_timeout(Duration::from_secs(5))
+        .keep_alive_while_idle(true)
+        .retry_policy(RetryPolicy::new().max_attempts(4))
+        .tcp_nodelay(true)
+        .tcp_keepalive(Duration::from_secs(60))
+        .tcp_recv_buffer_size(1 << 16);
+
+    let config = endpoint.config_summary();
+    assert_eq!(config.uri(), "http://example.com/");
+    assert_eq!(config.timeout(), Some(Duration::from_secs(5)));
+    assert_eq!(config.connect_timeout(), Some(Duration::from_secs(1)));
+    assert_eq!(config.concurrency_limit(), Some(32));
+    assert_eq!(config.rate_limit(), Some((100, Duration::from_secs(1))));
+    assert_eq!(config.buffer_size(), 64);
+    assert_eq!(config.buffer_full_policy(), BufferFullPolicy::FailFast);
+    let status = config.buffer_full_status().unwrap();
+    assert_eq!(status.code(), crate::Code::Unavailable);
+    assert_eq!(status.message(), "client overloaded");
+    assert_eq!(config.initial_stream_window_size(), Some(1 << 20));
+    assert_eq!(config.initial_connection_window_size(), None);
+    assert_eq!(
+        config.reconnect_backoff(),
+        Some((Duration::from_millis(100), Duration::from_secs(10)))
+    );
+    assert_eq!(config.reconnect_backoff_multiplier(), 1.5);
+    assert_eq!(
+        config.http2_keep_alive_interval(),
+        Some(Duration::from_secs(30))
+    );
+    assert_eq!(config.keep_alive_timeout(), Duration::from_secs(5));
+    assert!(config.keep_alive_while_idle());
+    assert_eq!(config.retry_policy().unwrap().max_attempts, 4);
+    assert!(config.tcp_nodelay());
+    assert_eq!(config.tcp_keepalive(), Some(Duration::from_secs(60)));
+    assert_eq!(config.tcp_send_buffer_size(), None);
+    assert_eq!(config.tcp_recv_buffer_size(), Some(1 << 16));
+
+    // a backoff never shrinks
+    for &multiplier in &[0.5, -1.0, f64::NAN] {
+        let config = endpoint
+            .clone()
+            .reconnect_backoff_multiplier(multiplier)
+            .config_summary();
+        assert_eq!(config.reconnect_backoff_multiplier(), 1.0);
+    }
+}
+
+#[tokio::test]
+async fn connect_lazy() {
+    let addr = unused_addr();
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect_lazy();
+
+    // nothing is listening yet, which only fails this request
+    let err = unary(&mut channel).await.unwrap_err();
+    let status = crate::Status::from_error(&err);
+    assert_eq!(status.code(), crate::Code::Unavailable);
+
+    spawn_server(Server::builder(), addr).await;
+
+    unary(&mut channel).await.unwrap();
+    assert_eq!(channel.connection_info().unwrap().remote_addr(), Some(addr));
+}
+
+#[tokio::test]
+async fn wait_for_ready() {
+    let addr = unused_addr();
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .reconnect_backoff(Duration::from_millis(20), Duration::from_millis(20))
+        .connect_lazy();
+    assert_eq!(channel.state(), Some(ConnectivityState::Idle));
+
+    // requests fail right away while the channel backs off
+    let err = unary(&mut channel).await.unwrap_err();
+    let status = crate::Status::from_error(&err);
+    assert_eq!(status.code(), crate::Code::Unavailable);
+    assert_eq!(channel.state(), Some(ConnectivityState::TransientFailure));
+
+    let mut request = crate::Request::new(());
+    request.set_wait_for_ready(true);
+    let request = request
+        .into_http("/test.Svc/Method".parse().unwrap())
+        .map(|()| BoxBody::empty());
+
+    future::poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx))
+        .await
+        .unwrap();
+    let call = GrpcService::call(&mut channel, request);
+    let (response, ()) = future::join(call, async {
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+        spawn_server(Server::builder(), addr).await;
+    })
+    .await;
+
+    response.unwrap();
+    assert_eq!(channel.state(), Some(ConnectivityState::Ready));
+    assert_eq!(
+        channel
+            .wait_for_state_change(ConnectivityState::TransientFailure)
+            .await,
+        Some(ConnectivityState::Ready)
+    );
+}
+
+#[tokio::test]
+async fn connect_with_connector() {
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+
+    // the endpoint's uri is never dialed, the connector decides where to go
+    let connector = tower::service_fn(move |_| tokio::net::TcpStream::connect(addr));
+    let mut channel = Endpoint::from_static("http://example.com")
+        .connect_with_connector(connector)
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+    assert_eq!(channel.connection_info().unwrap().remote_addr(), None);
+}
+
+#[tokio::test]
+async fn client_over_hyper_connection() {
+    use crate::{client::Grpc, codec::ProstCodec};
+    use http::uri::PathAndQuery;
+
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+
+    // any service can carry the requests of a client, not just a `Channel`
+    let io = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (send_request, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(io)
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        let _ = connection.await;
+    });
+
+    let origin = format!("http://{}", addr).parse().unwrap();
+    let mut client = Grpc::new(send_request).origin(origin);
+    client.ready().await.unwrap();
+    let path = PathAndQuery::from_static("/test.Svc/Method");
+    let mut messages = client
+        .server_streaming(
+            crate::Request::new(()),
+            path,
+            ProstCodec::<(), ()>::default(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+
+    assert!(messages.message().await.unwrap().is_none());
+}
+
+/// Accept a single connection and tunnel it to the target of its `CONNECT`
+/// request.
+#[tokio::test]
+async fn response_trailers() {
+    use crate::{client::Grpc, codec::ProstCodec};
+    use http::uri::PathAndQuery;
+
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(TrailersSvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let channel = Endpoint::new(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let path = PathAndQuery::from_static("/test.Svc/Method");
+    let response = Grpc::new(channel)
+        .unary(
+            crate::Request::new(()),
+            path,
+            ProstCodec::<(), ()>::default(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.metadata().get("x-initial").unwrap(), "1");
+    assert!(response.metadata().get("x-trailing").is_none());
+    assert_eq!(response.trailers().get("x-trailing").unwrap(), "2");
+    assert!(response.trailers().get("x-initial").is_none());
+}
+
+/// Open an HTTP/2 connection to `addr`, returning a client that sends its
+/// requests on it and a receiver that resolves once it is closed.
+async fn connection(
+    addr: SocketAddr,
+) -> (
+    crate::client::Grpc<hyper::client::conn::SendRequest<BoxBody>>,
+    tokio::sync::oneshot::Receiver<()>,
+) {
+    let io = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let (send_request, connection) = hyper::client::conn::Builder::new()
+        .http2_only(true)
+        .handshake(io)
+        .await
+        .unwrap();
+
+    let (closed_tx, closed) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let _ = connection.await;
+        let _ = closed_tx.send(());
+    });
+
+    let origin = format!("http://{}", addr).parse().unwrap();
+    (
+        crate::client::Grpc::new(send_request).origin(origin),
+        closed,
+    )
+}
+
+async fn call(
+    client: &mut crate::client::Grpc<hyper::client::conn::SendRequest<BoxBody>>,
+) -> Result<(), crate::Status> {
+    let path = http::uri::PathAndQuery::from_static("/test.Svc/Method");
+    let codec = crate::codec::ProstCodec::<(), ()>::default();
+    client.ready().await.unwrap();
+    let mut messages = client
+        .server_streaming(crate::Request::new(()), path, codec)
+        .await?
+        .into_inner();
+    while messages.message().await?.is_some() {}
+    Ok(())
+}
+
+#[tokio::test]
+async fn max_connection_idle() {
+    let addr = unused_addr();
+    let server = Server::builder().max_connection_idle(Duration::from_millis(300));
+    spawn_server(server, addr).await;
+
+    let (mut client, mut closed) = connection(addr).await;
+    call(&mut client).await.unwrap();
+
+    // the request kept the connection open
+    tokio::timer::delay_for(Duration::from_millis(200)).await;
+    call(&mut client).await.unwrap();
+    assert!(closed.try_recv().is_err());
+
+    tokio::timer::Timeout::new(closed, Duration::from_secs(2))
+        .await
+        .expect("idle connection was not closed")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn max_connection_age() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .max_connection_age(Duration::from_millis(100))
+            .add_service(SlowSvc(Duration::from_millis(500)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    // the request in flight completes before the connection is closed
+    let (mut client, closed) = connection(addr).await;
+    call(&mut client).await.unwrap();
+
+    tokio::timer::Timeout::new(closed, Duration::from_secs(2))
+        .await
+        .expect("old connection was not closed")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn max_connection_age_grace() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .max_connection_age(Duration::from_millis(100))
+            .max_connection_age_grace(Duration::from_millis(100))
+            .add_service(SlowSvc(Duration::from_secs(5)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let (mut client, closed) = connection(addr).await;
+    let call = tokio::timer::Timeout::new(call(&mut client), Duration::from_secs(2));
+    assert!(call.await.expect("request was not failed").is_err());
+
+    tokio::timer::Timeout::new(closed, Duration::from_secs(2))
+        .await
+        .expect("old connection was not closed")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn server_timeout() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .timeout(Duration::from_millis(100))
+            .add_service(SlowSvc(Duration::from_secs(5)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Endpoint::new(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let headers = response_headers(&mut channel).await;
+
+    assert_eq!(headers.get("grpc-status").unwrap(), "4");
+}
+
+async fn spawn_http_proxy() -> SocketAddr {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    };
+
+    let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        let (mut client, _) = listener.accept().await.unwrap();
+
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            client.read_exact(&mut byte).await.unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        let target: SocketAddr = head.split(' ').nth(1).unwrap().parse().unwrap();
+
+        let mut server = TcpStream::connect(&target).await.unwrap();
+        client
+            .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+            .await
+            .unwrap();
+
+        let (mut client_read, mut client_write) = client.split();
+        let (mut server_read, mut server_write) = server.split();
+        let _ = future::join(
+            client_read.copy(&mut server_write),
+            server_read.copy(&mut client_write),
+        )
+        .await;
+    });
+
+    addr
+}
+
+#[tokio::test]
+async fn http_proxy() {
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+    let proxy = spawn_http_proxy().await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .proxy(Proxy::http(format!("http://{}", proxy).parse().unwrap()))
+        .connect()
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+    assert_eq!(
+        channel.connection_info().unwrap().remote_addr(),
+        Some(proxy)
+    );
+}
+
+#[tokio::test]
+async fn connect_timeout() {
+    // a connector whose connection attempts never complete
+    let connector =
+        tower::service_fn(|_| future::pending::<Result<tokio::net::TcpStream, std::io::Error>>());
+    let endpoint =
+        Endpoint::from_static("http://[::]:50051").connect_timeout(Duration::from_millis(100));
+
+    let connect = endpoint.connect_with_connector(connector);
+    let connect = tokio::timer::Timeout::new(connect, Duration::from_secs(5));
+    let error = connect
+        .await
+        .expect("connect did not time out")
+        .unwrap_err();
+    assert!(error.is_timeout());
+    assert!(error.is_connect());
+
+    let mut source = std::error::Error::source(&error);
+    while let Some(error) = source {
+        if let Some(error) = error.downcast_ref::<std::io::Error>() {
+            assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+            return;
+        }
+        source = error.source();
+    }
+    panic!("not an I/O error: {:?}", error);
+}
+
+#[tokio::test]
+async fn connect_error_classes() {
+    // nothing listens on the address
+    let uri = format!("http://{}", unused_addr());
+    let error = Endpoint::from_shared(uri.clone())
+        .unwrap()
+        .connect()
+        .await
+        .unwrap_err();
+    assert!(error.is_connect());
+    assert!(!error.is_dns() && !error.is_tls() && !error.is_timeout());
+
+    // lazy channels fail the calls
+    let mut channel = Endpoint::from_shared(uri).unwrap().connect_lazy();
+    let error = unary(&mut channel).await.unwrap_err();
+    assert!(error.is_connect());
+    assert!(!error.is_dns());
+
+    let error = Endpoint::from_static("http://does-not-exist.invalid:50051")
+        .connect()
+        .await
+        .unwrap_err();
+    assert!(error.is_connect());
+    assert!(error.is_dns());
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn tls_handshake_error() {
+    use super::{Certificate, ClientTlsConfig};
+
+    // the server does not speak TLS
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+
+    let tls = ClientTlsConfig::with_rustls()
+        .ca_certificate(Certificate::from_pem(CA))
+        .domain_name("localhost");
+    let error = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap_err();
+    assert!(error.is_tls());
+    assert!(error.is_connect());
+    assert!(!error.is_dns());
+}
+
+#[tokio::test]
+async fn request_timeout_error() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(SlowSvc(Duration::from_secs(5)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .timeout(Duration::from_millis(100))
+        .connect()
+        .await
+        .unwrap();
+
+    let error = unary(&mut channel).await.unwrap_err();
+    assert!(error.is_timeout());
+    assert!(!error.is_connect());
+}
+
+#[tokio::test]
+async fn cancellation_token() {
+    let addr = unused_addr();
+    let token = Arc::new(Mutex::new(None));
+    let svc = TokenSvc {
+        delay: Duration::from_millis(300),
+        token: token.clone(),
+    };
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(svc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    unary(&mut channel).await.unwrap();
+
+    let finished = token.lock().unwrap().take().unwrap();
+    assert!(!finished.is_cancelled());
+
+    // the client gives up on the request before it is answered
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .timeout(Duration::from_millis(100))
+        .connect()
+        .await
+        .unwrap();
+    assert!(unary(&mut channel).await.unwrap_err().is_timeout());
+
+    let cancelled = token.lock().unwrap().take().unwrap();
+    tokio::timer::Timeout::new(cancelled.cancelled(), Duration::from_secs(1))
+        .await
+        .unwrap();
+    assert!(!finished.is_cancelled());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serve_with_incoming() {
+    let (client, server) = tokio::net::UnixStream::pair().unwrap();
+
+    let incoming = futures_util::stream::iter(vec![Ok::<_, std::io::Error>(server)]);
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(Svc)
+            .serve_with_incoming(incoming)
+            .await
+            .unwrap();
+    });
+
+    let mut client = Some(client);
+    let connector = tower::service_fn(move |_| future::ready(client.take().ok_or("already used")));
+    let mut channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(connector)
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+    unary(&mut channel).await.unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn cancellation_on_connection_close() {
+    use std::{net::Shutdown, os::unix::net::UnixStream};
+
+    let (client, server) = UnixStream::pair().unwrap();
+    let close = client.try_clone().unwrap();
+    let client = tokio::net::UnixStream::from_std(client, &Default::default()).unwrap();
+    let server = tokio::net::UnixStream::from_std(server, &Default::default()).unwrap();
+
+    let token = Arc::new(Mutex::new(None));
+    let svc = TokenSvc {
+        delay: Duration::from_secs(5),
+        token: token.clone(),
+    };
+    let incoming = futures_util::stream::iter(vec![Ok::<_, std::io::Error>(server)]);
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(svc)
+            .serve_with_incoming(incoming)
+            .await
+            .unwrap();
+    });
+
+    let mut client = Some(client);
+    let connector = tower::service_fn(move |_| future::ready(client.take().ok_or("already used")));
+    let mut channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(connector)
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        let _ = unary(&mut channel).await;
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    // the connection goes away while the request is in flight
+    close.shutdown(Shutdown::Both).unwrap();
+
+    let cancelled = token.lock().unwrap().take().unwrap();
+    tokio::timer::Timeout::new(cancelled.cancelled(), Duration::from_secs(1))
+        .await
+        .unwrap();
+}
+
+async fn response_headers(channel: &mut Channel) -> http::HeaderMap {
+    future::poll_fn(|cx| GrpcService::poll_ready(channel, cx))
+        .await
+        .unwrap();
+
+    let request = Request::builder()
+        .uri("/test.Svc/Method")
+        .body(BoxBody::empty())
+        .unwrap();
+    let response = GrpcService::call(channel, request).await.unwrap();
+    response.headers().clone()
+}
+
+#[tokio::test]
+async fn set_compression() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(EncodingSvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let headers = response_headers(&mut channel).await;
+    assert!(headers.get("x-grpc-encoding").is_none());
+
+    // the setting is shared with the clones of the channel
+    channel.clone().set_compression(true);
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers.get("x-grpc-encoding").unwrap(), "gzip");
+
+    channel.set_compression(false);
+    let headers = response_headers(&mut channel).await;
+    assert!(headers.get("x-grpc-encoding").is_none());
+}
+
+#[tokio::test]
+async fn call_credentials() {
+    use crate::{metadata::MetadataMap, AccessToken, CallCredentials, RequestMetadata};
+
+    struct Insecure;
+
+    impl CallCredentials for Insecure {
+        fn request_metadata(&self, method: &str) -> RequestMetadata {
+            assert_eq!(method, "/test.Svc/Method");
+            let mut metadata = MetadataMap::new();
+            metadata.insert("authorization", "Bearer local".parse().unwrap());
+            Box::pin(future::ok(metadata))
+        }
+
+        fn allow_insecure(&self) -> bool {
+            true
+        }
+    }
+
+    let addr = unused_addr();
+    // keys are normalized to lowercase
+    let key = http::header::HeaderName::from_bytes(b"Authorization").unwrap();
+    let server = Server::builder().echo_metadata(vec![key]);
+    spawn_server(server, addr).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    fn request(credentials: impl CallCredentials) -> Request<BoxBody> {
+        let mut request = crate::Request::new(BoxBody::empty());
+        request.set_credentials(credentials);
+        request.into_http("/test.Svc/Method".parse().unwrap())
+    }
+
+    let response = GrpcService::call(&mut channel, request(Insecure))
+        .await
+        .unwrap();
+    assert_eq!(response.headers()["authorization"], "Bearer local");
+
+    // not sent without TLS
+    let token = AccessToken::new("secret").unwrap();
+    let err = GrpcService::call(&mut channel, request(token))
+        .await
+        .unwrap_err();
+    let status = crate::Status::from_error(&err);
+    assert_eq!(status.code(), crate::Code::Unauthenticated);
+}
+
+#[tokio::test]
+async fn retry_policy() {
+    let addr = unused_addr();
+    let calls = Arc::new(Mutex::new(0));
+    let svc = FlakySvc {
+        failures: 2,
+        calls: calls.clone(),
+    };
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(svc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let policy = RetryPolicy::new().backoff(Duration::from_millis(1), Duration::from_millis(10));
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .retry_policy(policy.clone())
+        .connect()
+        .await
+        .unwrap();
+
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers["grpc-status"], "0");
+    assert_eq!(*calls.lock().unwrap(), 3);
+
+    // without retries the failure is returned right away
+    *calls.lock().unwrap() = 0;
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .retry_policy(policy.max_attempts(1))
+        .connect()
+        .await
+        .unwrap();
+
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers["grpc-status"], "14");
+    assert_eq!(*calls.lock().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn server_connection_info() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(InfoSvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let headers = response_headers(&mut channel).await;
+    let remote_addr: SocketAddr = headers["x-remote-addr"].to_str().unwrap().parse().unwrap();
+    assert_eq!(remote_addr.ip(), addr.ip());
+    assert_eq!(headers["x-peer-certificates"], "0");
+}
+
+#[tokio::test]
+async fn origin() {
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(InfoSvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .origin("http://greeter.example.com".parse().unwrap())
+        .connect()
+        .await
+        .unwrap();
+
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers["x-authority"], "greeter.example.com");
+    assert_eq!(channel.connection_info().unwrap().remote_addr(), Some(addr));
+}
+
+#[cfg(feature = "rustls")]
+#[tokio::test]
+async fn server_connection_info_client_auth() {
+    use super::{Certificate, ClientTlsConfig, Identity, ServerTlsConfig};
+
+    let addr = unused_addr();
+    let tls = ServerTlsConfig::with_rustls()
+        .identity(Identity::from_pem(SERVER_CERT, SERVER_KEY))
+        .client_ca_root(Certificate::from_pem(CLIENT_CA));
+    tokio::spawn(async move {
+        Server::builder()
+            .tls_config(tls)
+            .unwrap()
+            .add_service(InfoSvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let tls = ClientTlsConfig::with_rustls()
+        .ca_certificate(Certificate::from_pem(CA))
+        .identity(Identity::from_pem(CLIENT_CERT, CLIENT_KEY))
+        .domain_name("localhost");
+    let mut channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers["x-peer-certificates"], "1");
+
+    // clients without a certificate are turned away
+    let tls = ClientTlsConfig::with_rustls()
+        .ca_certificate(Certificate::from_pem(CA))
+        .domain_name("localhost");
+    let mut channel = Channel::from_shared(format!("https://{}", addr))
+        .unwrap()
+        .tls_config(tls)
+        .unwrap()
+        .connect_lazy();
+    assert!(unary(&mut channel).await.is_err());
+}
+
+#[tokio::test]
+async fn interceptors() {
+    use crate::{metadata::MetadataValue, Code, Status};
+
+    let addr = unused_addr();
+    let server = Server::builder().interceptor(|req: crate::Request<()>| {
+        match req.metadata().get("authorization") {
+            Some(token) if token == "Bearer secret" => Ok(req),
+            _ => Err(Status::new(Code::Unauthenticated, "invalid token")),
+        }
+    });
+    spawn_server(server, addr).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .intercept(|mut req: crate::Request<()>| {
+            let token = MetadataValue::from_static("Bearer secret");
+            req.metadata_mut().insert("authorization", token);
+            Ok(req)
+        })
+        .connect()
+        .await
+        .unwrap();
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers["grpc-status"], "0");
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let headers = response_headers(&mut channel).await;
+    let status = Status::from_header_map(&headers).unwrap();
+    assert_eq!(status.code(), Code::Unauthenticated);
+    assert_eq!(status.message(), "invalid token");
+
+    // a rejected request is never sent
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .intercept(|_| Err(Status::new(Code::PermissionDenied, "not allowed")))
+        .connect()
+        .await
+        .unwrap();
+    let error = unary(&mut channel).await.unwrap_err();
+    let status = Status::from_error(&error);
+    assert_eq!(status.code(), Code::PermissionDenied);
+    assert_eq!(status.message(), "not allowed");
+}
+
+#[tokio::test]
+async fn async_interceptor() {
+    use crate::{metadata::MetadataValue, Code, Status};
+
+    let addr = unused_addr();
+    let server = Server::builder().interceptor(|req: crate::Request<()>| {
+        match req.metadata().get("authorization") {
+            Some(token) if token == "Bearer refreshed" => Ok(req),
+            _ => Err(Status::new(Code::Unauthenticated, "invalid token")),
+        }
+    });
+    spawn_server(server, addr).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .intercept_async(|mut req: crate::Request<()>| {
+            async move {
+                // like refreshing a token
+                tokio::timer::delay_for(Duration::from_millis(10)).await;
+                let token = MetadataValue::from_static("Bearer refreshed");
+                req.metadata_mut().insert("authorization", token);
+                Ok(req)
+            }
+        })
+        .connect()
+        .await
+        .unwrap();
+    for _ in 0..3 {
+        let headers = response_headers(&mut channel).await;
+        assert_eq!(headers["grpc-status"], "0");
+    }
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .intercept_async(|_| async { Err(Status::new(Code::Unavailable, "token service is down")) })
+        .connect()
+        .await
+        .unwrap();
+    let error = unary(&mut channel).await.unwrap_err();
+    let status = Status::from_error(&error);
+    assert_eq!(status.code(), Code::Unavailable);
+    assert_eq!(status.message(), "token service is down");
+}
+
+#[tokio::test]
+async fn routes_by_service_name() {
+    let addr = unused_addr();
+    spawn_server(Server::builder(), addr).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let mut status = |path: &'static str| {
+        let request = Request::builder().uri(path).body(BoxBody::empty()).unwrap();
+        let response = GrpcService::call(&mut channel, request);
+        async move { response.await.unwrap().headers()["grpc-status"].clone() }
+    };
+
+    assert_eq!(status("/test.Svc/Method").await, "0");
+    // a service whose name only starts with a registered one is unknown
+    assert_eq!(status("/test.SvcV2/Method").await, "12");
+    assert_eq!(status("/test.Sv/Method").await, "12");
+}
+
+#[tokio::test]
+async fn fallback() {
+    let addr = unused_addr();
+    let fallback = tower::service_fn(|req: Request<Body>| {
+        let code = if req.uri().path() == "/healthz" {
+            "0"
+        } else {
+            "5"
+        };
+        let res = Response::builder()
+            .header("grpc-status", code)
+            .body(BoxBody::empty());
+        future::ready(res)
+    });
+    spawn_server(Server::builder().fallback(fallback), addr).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let mut status = |path: &'static str| {
+        let request = Request::builder().uri(path).body(BoxBody::empty()).unwrap();
+        let response = GrpcService::call(&mut channel, request);
+        async move { response.await.unwrap().headers()["grpc-status"].clone() }
+    };
+
+    assert_eq!(status("/test.Svc/Method").await, "0");
+    assert_eq!(status("/healthz").await, "0");
+    assert_eq!(status("/other.Svc/Method").await, "5");
+}
+
+#[test]
+fn router_service_names() {
+    let router = Server::builder().add_service(Svc).add_service(Svc);
+    assert_eq!(router.service_names(), ["test.Svc", "test.Svc"]);
+}
+
+#[tokio::test]
+async fn serve_with_listener() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (signal_tx, signal) = tokio::sync::oneshot::channel();
+    let (done_tx, done) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        let result = Server::builder()
+            .add_service(Svc)
+            .serve_with_listener_and_shutdown(listener, async {
+                signal.await.ok();
+            })
+            .await;
+        done_tx.send(result).unwrap();
+    });
+
+    // the listener is bound, so connecting cannot race the server
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    let request = Request::builder()
+        .uri("/test.Svc/Method")
+        .body(BoxBody::empty())
+        .unwrap();
+    let response = GrpcService::call(&mut channel, request).await.unwrap();
+    assert_eq!(response.headers()["grpc-status"], "0");
+
+    drop(channel);
+    signal_tx.send(()).unwrap();
+    done.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn balance_stream() {
+    use super::channel::Change;
+
+    let live = unused_addr();
+    spawn_server(Server::builder(), live).await;
+    let endpoint = |addr: SocketAddr| Channel::from_shared(format!("http://{}", addr)).unwrap();
+
+    let (tx, rx) = futures_channel::mpsc::unbounded();
+    let mut channel = Channel::balance_stream(rx);
+
+    // the unreachable endpoint is skipped instead of failing the channel
+    tx.unbounded_send(Change::Insert("down", endpoint(unused_addr())))
+        .unwrap();
+    tx.unbounded_send(Change::Insert("live", endpoint(live)))
+        .unwrap();
+
+    for _ in 0..4 {
+        unary(&mut channel).await.unwrap();
+    }
+}
+
+/// Resolves to the address sets sent on a channel.
+struct ChannelResolver(Mutex<Option<futures_channel::mpsc::UnboundedReceiver<Vec<SocketAddr>>>>);
+
+impl Resolver for ChannelResolver {
+    fn resolve(&self, _uri: &http::Uri) -> Addresses {
+        use futures_util::StreamExt;
+
+        let addresses = self.0.lock().unwrap().take().unwrap();
+        Box::pin(addresses.map(Ok))
+    }
+}
+
+#[tokio::test]
+async fn resolver() {
+    let live = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(InfoSvc)
+            .serve(live)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let (tx, rx) = futures_channel::mpsc::unbounded();
+    tx.unbounded_send(vec![live]).unwrap();
+
+    let mut channel = Channel::from_static("http://backend.test:50051")
+        .resolver(ChannelResolver(Mutex::new(Some(rx))))
+        .connect()
+        .await
+        .unwrap();
+
+    // the authority is still the one of the endpoint
+    let headers = response_headers(&mut channel).await;
+    assert_eq!(headers["x-authority"], "backend.test:50051");
+
+    // an unreachable address is skipped
+    tx.unbounded_send(vec![unused_addr(), live]).unwrap();
+    for _ in 0..4 {
+        unary(&mut channel).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn dns_resolver() {
+    use futures_util::StreamExt;
+
+    let uri = "http://127.0.0.1:50051".parse().unwrap();
+    let mut addresses = DnsResolver::new().resolve(&uri);
+    let addresses = addresses.next().await.unwrap().unwrap();
+    assert_eq!(addresses, vec!["127.0.0.1:50051".parse().unwrap()]);
+
+    let uri = "https://localhost".parse().unwrap();
+    let mut addresses = DnsResolver::new().resolve(&uri);
+    let addresses = addresses.next().await.unwrap().unwrap();
+    assert!(addresses
+        .iter()
+        .all(|addr| addr.ip().is_loopback() && addr.port() == 443));
+}
+
+/// Records the raw bytes read and written on a connection.
+struct Recorded<IO> {
+    io: IO,
+    read: Arc<Mutex<Vec<u8>>>,
+    written: Arc<Mutex<Vec<u8>>>,
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for Recorded<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let n = futures_util::ready!(Pin::new(&mut self.io).poll_read(cx, buf))?;
+        self.read.lock().unwrap().extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for Recorded<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let n = futures_util::ready!(Pin::new(&mut self.io).poll_write(cx, buf))?;
+        self.written.lock().unwrap().extend_from_slice(&buf[..n]);
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// The number of keepalive `PING` frames in `bytes`, or of their
+/// acknowledgements if `ack` is set.
+fn count_pings(bytes: &[u8], ack: bool) -> usize {
+    let mut frame = vec![0, 0, 8, 0x6, ack as u8, 0, 0, 0, 0];
+    frame.extend_from_slice(b"tonic-ka");
+
+    bytes
+        .windows(frame.len())
+        .filter(|w| *w == &frame[..])
+        .count()
+}
+
+#[tokio::test]
+async fn keep_alive() {
+    let addr = unused_addr();
+    let server = Server::builder().http2_keep_alive_interval(Duration::from_millis(50));
+    spawn_server(server, addr).await;
+
+    let read = Arc::new(Mutex::new(Vec::new()));
+    let written = Arc::new(Mutex::new(Vec::new()));
+    let (r, w) = (read.clone(), written.clone());
+    let connector = tower::service_fn(move |_| {
+        let (read, written) = (r.clone(), w.clone());
+        async move {
+            let io = tokio::net::TcpStream::connect(addr).await?;
+            Ok::<_, std::io::Error>(Recorded { io, read, written })
+        }
+    });
+    let mut channel = Endpoint::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .http2_keep_alive_interval(Duration::from_millis(50))
+        .keep_alive_while_idle(true)
+        .connect_with_connector(connector)
+        .await
+        .unwrap();
+
+    // both sides ping the idle connection in between the requests
+    for _ in 0..3 {
+        unary(&mut channel).await.unwrap();
+        tokio::timer::delay_for(Duration::from_millis(200)).await;
+    }
+
+    let read = read.lock().unwrap();
+    let written = written.lock().unwrap();
+
+    // the client's pings were acked by the server
+    assert!(count_pings(&written, false) > 0);
+    assert!(count_pings(&read, true) > 0);
+
+    // and the server's by the client
+    assert!(count_pings(&read, false) > 0);
+    assert!(count_pings(&written, true) > 0);
+}
+
+#[tokio::test]
+async fn tcp_options() {
+    let addr = unused_addr();
+    let server = Server::builder()
+        .tcp_nodelay(true)
+        .tcp_keepalive(Duration::from_secs(60))
+        .tcp_send_buffer_size(1 << 16)
+        .tcp_recv_buffer_size(1 << 16);
+    spawn_server(server, addr).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .tcp_nodelay(true)
+        .tcp_keepalive(Duration::from_secs(60))
+        .tcp_send_buffer_size(1 << 16)
+        .tcp_recv_buffer_size(1 << 16)
+        .connect()
+        .await
+        .unwrap();
+
+    unary(&mut channel).await.unwrap();
+}
+
+#[tokio::test]
+async fn keep_alive_timeout_closes_connection() {
+    // a peer that accepts connections but never responds
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .http2_keep_alive_interval(Duration::from_millis(50))
+        .keep_alive_timeout(Duration::from_millis(50))
+        .connect()
+        .await
+        .unwrap();
+
+    let request = tokio::timer::Timeout::new(unary(&mut channel), Duration::from_secs(5));
+    assert!(request.await.expect("request did not fail").is_err());
+
+    drop(listener);
+}
+
+/// Serve `SlowSvc` until `signal` resolves, sending the result on `done`.
+async fn spawn_server_with_shutdown(
+    server: Server,
+    addr: SocketAddr,
+    delay: Duration,
+    signal: tokio::sync::oneshot::Receiver<()>,
+    done: tokio::sync::oneshot::Sender<Result<(), super::Error>>,
+) {
+    tokio::spawn(async move {
+        let result = server
+            .clone()
+            .add_service(SlowSvc(delay))
+            .serve_with_shutdown(addr, async {
+                signal.await.ok();
+            })
+            .await;
+        done.send(result).unwrap();
+    });
+
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+}
+
+#[tokio::test]
+async fn serve_with_shutdown_waits_for_requests() {
+    let addr = unused_addr();
+    let (signal_tx, signal) = tokio::sync::oneshot::channel();
+    let (done_tx, done) = tokio::sync::oneshot::channel();
+    let delay = Duration::from_millis(300);
+    spawn_server_with_shutdown(Server::builder(), addr, delay, signal, done_tx).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let (result_tx, result) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        result_tx.send(unary(&mut channel).await).unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+    signal_tx.send(()).unwrap();
+
+    // the server waits for the request in flight
+    result.await.unwrap().unwrap();
+    done.await.unwrap().unwrap();
+
+    // and no longer accepts connections
+    let channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await;
+    assert!(channel.is_err());
+}
+
+#[tokio::test]
+async fn serve_with_shutdown_grace_period() {
+    let addr = unused_addr();
+    let (signal_tx, signal) = tokio::sync::oneshot::channel();
+    let (done_tx, done) = tokio::sync::oneshot::channel();
+    let server = Server::builder().shutdown_grace_period(Duration::from_millis(100));
+    let delay = Duration::from_secs(30);
+    spawn_server_with_shutdown(server, addr, delay, signal, done_tx).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let (result_tx, result) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        result_tx.send(unary(&mut channel).await).unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+    signal_tx.send(()).unwrap();
+
+    let done = tokio::timer::Timeout::new(done, Duration::from_secs(5));
+    done.await.expect("server did not stop").unwrap().unwrap();
+
+    // the request in flight was cut off
+    assert!(result.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn grpc_timeout() {
+    use crate::{Code, Status};
+
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(SlowSvc(Duration::from_secs(30)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    future::poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx))
+        .await
+        .unwrap();
+
+    let mut request = crate::Request::new(BoxBody::empty());
+    request.set_timeout(Duration::from_millis(100));
+    let request = request.into_http("/test.Svc/Method".parse().unwrap());
+
+    let response = tokio::timer::Timeout::new(
+        GrpcService::call(&mut channel, request),
+        Duration::from_secs(5),
+    );
+    let response = response.await.expect("deadline was not enforced").unwrap();
+    let status = Status::from_header_map(response.headers()).unwrap();
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+}
+
+/// Streams a message to every request every 20 milliseconds, forever.
+#[derive(Debug, Clone, Default)]
+struct TickSvc;
+
+impl Service<Request<Body>> for TickSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, _req: Request<Body>) -> Self::Future {
+        use crate::{
+            codec::{Codec, ProstCodec},
+            Status,
+        };
+
+        let messages = stream::unfold((), |()| async {
+            tokio::timer::delay_for(Duration::from_millis(20)).await;
+            Some((Ok::<_, Status>("tick".to_string()), ()))
+        });
+        let encoder = ProstCodec::<String, ()>::default().encoder();
+        let body =
+            crate::codec::encode_server(encoder, messages, tracing::Span::none(), None, None);
+
+        future::ok(Response::new(BoxBody::new(body)))
+    }
+}
+
+impl ServiceName for TickSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+#[tokio::test]
+async fn grpc_timeout_ends_response_stream() {
+    use crate::{client::Grpc, codec::ProstCodec, Code};
+    use http::uri::PathAndQuery;
+
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(TickSvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    let mut request = crate::Request::new(());
+    request.set_timeout(Duration::from_millis(200));
+    let path = PathAndQuery::from_static("/test.Svc/Method");
+    let mut messages = Grpc::new(channel)
+        .server_streaming(request, path, ProstCodec::<(), String>::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let read = async {
+        let mut read = 0;
+        loop {
+            match messages.message().await {
+                Ok(Some(_)) => read += 1,
+                Ok(None) => panic!("stream ended without a status"),
+                Err(status) => return (read, status),
+            }
+        }
+    };
+    let read = tokio::timer::Timeout::new(read, Duration::from_secs(5));
+    let (read, status) = read.await.expect("deadline was not enforced");
+
+    // the handler responded in time, so messages were streamed until then
+    assert!(read > 0);
+    assert_eq!(status.code(), Code::DeadlineExceeded);
+}
+
+#[tokio::test]
+async fn load_shed_global_concurrency_limit() {
+    use crate::{Code, Status};
+
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .global_concurrency_limit(1)
+            .load_shed(true)
+            .add_service(SlowSvc(Duration::from_millis(500)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let endpoint = Channel::from_shared(format!("http://{}", addr)).unwrap();
+    let mut first = endpoint.connect().await.unwrap();
+    let mut second = endpoint.connect().await.unwrap();
+
+    let shed = async {
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+        response_headers(&mut second).await
+    };
+    let (in_flight, shed) = future::join(response_headers(&mut first), shed).await;
+
+    let status = Status::from_header_map(&in_flight).unwrap();
+    assert_eq!(status.code(), Code::Ok);
+    // the limit is shared across connections, so this is shed
+    let status = Status::from_header_map(&shed).unwrap();
+    assert_eq!(status.code(), Code::ResourceExhausted);
+
+    let headers = response_headers(&mut second).await;
+    let status = Status::from_header_map(&headers).unwrap();
+    assert_eq!(status.code(), Code::Ok);
+}
+
+#[tokio::test]
+async fn load_shed_status() {
+    use crate::{Code, Status};
+
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .global_concurrency_limit(1)
+            .load_shed(true)
+            .load_shed_status(Status::new(Code::Unavailable, "try again later"))
+            .add_service(SlowSvc(Duration::from_millis(500)))
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let endpoint = Channel::from_shared(format!("http://{}", addr)).unwrap();
+    let mut first = endpoint.connect().await.unwrap();
+    let mut second = endpoint.connect().await.unwrap();
+
+    let shed = async {
+        tokio::timer::delay_for(Duration::from_millis(100)).await;
+        response_headers(&mut second).await
+    };
+    let (_, shed) = future::join(response_headers(&mut first), shed).await;
+
+    let status = Status::from_header_map(&shed).unwrap();
+    assert_eq!(status.code(), Code::Unavailable);
+    assert_eq!(status.message(), "try again later");
+}
+
+/// Counts the requests passing through the services it wraps.
+#[derive(Debug, Clone, Default)]
+struct CountLayer(Arc<Mutex<usize>>);
+
+impl<S> tower::layer::Layer<S> for CountLayer {
+    type Service = Counted<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Counted(inner, self.0.clone())
+    }
+}
+
+#[derive(Debug)]
+struct Counted<S>(S, Arc<Mutex<usize>>);
+
+impl<S, R> Service<R> for Counted<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        *self.1.lock().unwrap() += 1;
+        self.0.call(req)
+    }
+}
+
+#[tokio::test]
+async fn layers() {
+    use crate::{Code, Status};
+
+    let server_layer = CountLayer::default();
+    let addr = unused_addr();
+    let server = Server::builder()
+        .interceptor(|_| Err(Status::new(Code::Unauthenticated, "invalid token")))
+        .layer(server_layer.clone());
+    spawn_server(server, addr).await;
+
+    let client_layer = CountLayer::default();
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .layer(client_layer.clone())
+        .layer(tower::timeout::TimeoutLayer::new(Duration::from_secs(5)))
+        .connect()
+        .await
+        .unwrap();
+
+    for _ in 0..2 {
+        let headers = response_headers(&mut channel).await;
+        let status = Status::from_header_map(&headers).unwrap();
+        assert_eq!(status.code(), Code::Unauthenticated);
+    }
+
+    assert_eq!(*client_layer.0.lock().unwrap(), 2);
+    // server layers wrap the interceptor and see rejected requests
+    assert_eq!(*server_layer.0.lock().unwrap(), 2);
+}
+
+/// Responds with the body of the request.
+#[derive(Debug, Clone)]
+struct EchoBodySvc;
+
+impl Service<Request<Body>> for EchoBodySvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let mut res = Response::new(BoxBody::map_from(req.into_body()));
+        res.headers_mut()
+            .insert("grpc-status", http::HeaderValue::from_static("0"));
+        future::ok(res)
+    }
+}
+
+impl ServiceName for EchoBodySvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+#[tokio::test]
+async fn stats_handler() {
+    use super::RpcStats;
+    use crate::Code;
+
+    let stats = Arc::new(Mutex::new(Vec::new()));
+    let handler_stats = stats.clone();
+
+    let addr = unused_addr();
+    tokio::spawn(async move {
+        Server::builder()
+            .stats_handler(move |stats: &RpcStats<'_>| {
+                handler_stats.lock().unwrap().push((
+                    stats.method().to_string(),
+                    stats.code(),
+                    stats.messages_received(),
+                    stats.messages_sent(),
+                ));
+            })
+            .add_service(EchoBodySvc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Channel::from_shared(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+    future::poll_fn(|cx| GrpcService::poll_ready(&mut channel, cx))
+        .await
+        .unwrap();
+
+    // two empty messages
+    let body = hyper::Body::from(vec![0u8; 10]);
+    let request = Request::builder()
+        .uri("/test.Svc/Method")
+        .body(BoxBody::map_from(body))
+        .unwrap();
+    let mut response = GrpcService::call(&mut channel, request).await.unwrap();
+    while let Some(chunk) = response.body_mut().next().await {
+        chunk.unwrap();
+    }
+    drop(response);
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let stats = stats.lock().unwrap();
+    assert_eq!(
+        *stats,
+        vec![("/test.Svc/Method".to_string(), Some(Code::Ok), 2, 2)]
+    );
+}
+
+#[tokio::test]
+async fn channelz() {
+    use super::Channelz;
+
+    let channelz = Channelz::new();
+
+    let addr = unused_addr();
+    let mut server = Server::builder().channelz(channelz.clone());
+    tokio::spawn(async move {
+        server.add_service(Svc).serve(addr).await.unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let mut channel = Endpoint::new(format!("http://{}", addr))
+        .unwrap()
+        .channelz(channelz.clone())
+        .connect()
+        .await
+        .unwrap();
+    response_headers(&mut channel).await;
+    response_headers(&mut channel).await;
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let servers = channelz.servers();
+    assert_eq!(servers.len(), 1);
+    assert_eq!(servers[0].calls().started(), 2);
+    assert_eq!(servers[0].calls().succeeded(), 2);
+    assert_eq!(servers[0].sockets().len(), 1);
+    assert_eq!(servers[0].sockets()[0].streams().started(), 2);
+    assert_eq!(servers[0].sockets()[0].streams().succeeded(), 2);
+
+    let channels = channelz.channels();
+    assert_eq!(channels.len(), 1);
+    assert_eq!(channels[0].target(), format!("http://{}/", addr));
+    assert_eq!(channels[0].state(), ConnectivityState::Ready);
+    assert_eq!(channels[0].calls().succeeded(), 2);
+
+    let subchannels = channels[0].subchannels();
+    assert_eq!(subchannels.len(), 1);
+    assert_eq!(subchannels[0].state(), ConnectivityState::Ready);
+    assert_eq!(subchannels[0].calls().succeeded(), 2);
+    assert_eq!(subchannels[0].sockets().len(), 1);
+    assert_eq!(subchannels[0].sockets()[0].remote_addr(), Some(addr));
+    assert_eq!(subchannels[0].sockets()[0].streams().started(), 2);
+
+    drop(channel);
+    assert!(channelz.channels().is_empty());
+}
+
+/// Streams messages of 1000 bytes to every request for as long as they are
+/// read, counting the messages it produced.
+#[derive(Debug, Clone, Default)]
+struct FloodSvc(Arc<AtomicUsize>);
+
+impl Service<Request<Body>> for FloodSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, _req: Request<Body>) -> Self::Future {
+        use crate::{
+            codec::{Codec, ProstCodec},
+            Status,
+        };
+
+        let produced = self.0.clone();
+        let messages = stream::iter((0..).map(move |_| {
+            produced.fetch_add(1, Ordering::SeqCst);
+            Ok::<_, Status>("x".repeat(1000))
+        }));
+        let encoder = ProstCodec::<String, ()>::default().encoder();
+        let body =
+            crate::codec::encode_server(encoder, messages, tracing::Span::none(), None, None);
+
+        future::ok(Response::new(BoxBody::new(body)))
+    }
+}
+
+impl ServiceName for FloodSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+#[tokio::test]
+async fn response_stream_backpressure() {
+    use crate::{client::Grpc, codec::ProstCodec};
+    use http::uri::PathAndQuery;
+
+    let produced = Arc::new(AtomicUsize::new(0));
+    let addr = unused_addr();
+    let svc = FloodSvc(produced.clone());
+    tokio::spawn(async move {
+        Server::builder()
+            .add_service(svc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let channel = Endpoint::new(format!("http://{}", addr))
+        .unwrap()
+        .initial_stream_window_size(32 * 1024)
+        .initial_connection_window_size(64 * 1024)
+        .connect()
+        .await
+        .unwrap();
+    let path = PathAndQuery::from_static("/test.Svc/Method");
+    let mut messages = Grpc::new(channel)
+        .server_streaming(
+            crate::Request::new(()),
+            path,
+            ProstCodec::<(), String>::default(),
+        )
+        .await
+        .unwrap()
+        .into_inner();
+
+    // the client reads one message and then stops reading
+    assert_eq!(messages.message().await.unwrap().unwrap().len(), 1000);
+    tokio::timer::delay_for(Duration::from_millis(500)).await;
+
+    // the handler stalls once the 32 KiB stream window is full
+    let stalled = produced.load(Ordering::SeqCst);
+    assert!(stalled > 1 && stalled < 40, "produced {} messages", stalled);
+    tokio::timer::delay_for(Duration::from_millis(200)).await;
+    assert_eq!(produced.load(Ordering::SeqCst), stalled);
+
+    // reading more lets the handler continue
+    for _ in 0..stalled {
+        messages.message().await.unwrap().unwrap();
+    }
+    tokio::timer::delay_for(Duration::from_millis(200)).await;
+    assert!(produced.load(Ordering::SeqCst) > stalled);
+}
+
+/// Holds on to the bodies of requests without reading them, and never
+/// responds.
+#[derive(Debug, Clone, Default)]
+struct StallSvc(Arc<Mutex<Vec<Body>>>);
+
+impl Service<Request<Body>> for StallSvc {
+    type Response = Response<BoxBody>;
+    type Error = crate::Error;
+    type Future = future::Pending<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Ok(()).into()
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        self.0.lock().unwrap().push(req.into_body());
+        future::pending()
+    }
+}
+
+impl ServiceName for StallSvc {
+    const NAME: &'static str = "test.Svc";
+}
+
+#[tokio::test]
+async fn request_stream_backpressure() {
+    use crate::{client::Grpc, codec::ProstCodec};
+    use http::uri::PathAndQuery;
+
+    let addr = unused_addr();
+    let svc = StallSvc::default();
+    tokio::spawn(async move {
+        Server::builder()
+            .initial_stream_window_size(16 * 1024)
+            .initial_connection_window_size(64 * 1024)
+            .add_service(svc)
+            .serve(addr)
+            .await
+            .unwrap();
+    });
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    let channel = Endpoint::new(format!("http://{}", addr))
+        .unwrap()
+        .connect()
+        .await
+        .unwrap();
+
+    // the client has to learn the window of the server before it streams
+    tokio::timer::delay_for(Duration::from_millis(100)).await;
+
+    // the client streams messages of 1000 bytes as fast as it can
+    let produced = Arc::new(AtomicUsize::new(0));
+    let counter = produced.clone();
+    let messages = stream::iter((0..).map(move |_| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        "x".repeat(1000)
+    }));
+    tokio::spawn(async move {
+        let path = PathAndQuery::from_static("/test.Svc/Method");
+        let _ = Grpc::new(channel)
+            .client_streaming(
+                crate::Request::new(messages),
+                path,
+                ProstCodec::<String, ()>::default(),
+            )
+            .await;
+    });
+    tokio::timer::delay_for(Duration::from_millis(500)).await;
+
+    // the client stalls once the 16 KiB stream window of the server is full
+    let stalled = produced.load(Ordering::SeqCst);
+    assert!(stalled > 1 && stalled < 24, "produced {} messages", stalled);
+    tokio::timer::delay_for(Duration::from_millis(200)).await;
+    assert_eq!(produced.load(Ordering::SeqCst), stalled);
+}
@@ -30,6 +30,27 @@ impl Certificate {
         let pem = pem.as_ref().into();
         Self { pem }
     }
+
+    /// Build a PEM encoded certificate out of a DER encoded one.
+    #[cfg(feature = "rustls")]
+    pub(crate) fn from_der(der: &[u8]) -> Self {
+        let encoded = base64::encode(der);
+
+        let mut pem = String::from("-----BEGIN CERTIFICATE-----\n");
+        for line in encoded.as_bytes().chunks(64) {
+            // base64 output is always ascii
+            pem.push_str(std::str::from_utf8(line).unwrap());
+            pem.push('\n');
+        }
+        pem.push_str("-----END CERTIFICATE-----\n");
+
+        Self::from_pem(pem)
+    }
+
+    /// Get the PEM encoded bytes of this certificate.
+    pub fn get_ref(&self) -> &[u8] {
+        &self.pem
+    }
 }
 
 impl Identity {